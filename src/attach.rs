@@ -8,6 +8,8 @@ use alloc::vec::Vec;
 use core::sync::atomic::{AtomicBool, Ordering};
 use spin::Mutex;
 
+use crate::section::{AttachTarget, ProgramType};
+
 /// Global verbose mode switch for real-time eBPF output
 static VERBOSE_MODE: AtomicBool = AtomicBool::new(false);
 
@@ -36,6 +38,14 @@ pub enum Error {
     AlreadyAttached(String),
     /// Tracepoint has no attached program.
     NotAttached(String),
+    /// Program section name carries no attach target.
+    NoAttachTarget(u32),
+    /// Attach target needs a probe feature that is not compiled in.
+    Unsupported(String),
+    /// The probe subsystem rejected the attachment.
+    ProbeFailed(&'static str),
+    /// Replacement program has a different type than the one it replaces.
+    IncompatibleProgram(u32),
 }
 
 impl core::fmt::Display for Error {
@@ -47,6 +57,12 @@ impl core::fmt::Display for Error {
                 write!(f, "Tracepoint already has attached program: {}", name)
             }
             Self::NotAttached(name) => write!(f, "No program attached to tracepoint: {}", name),
+            Self::NoAttachTarget(id) => write!(f, "Program {} has no attach target", id),
+            Self::Unsupported(target) => write!(f, "Attach target not supported: {}", target),
+            Self::ProbeFailed(e) => write!(f, "Probe attach failed: {}", e),
+            Self::IncompatibleProgram(id) => {
+                write!(f, "Program {} does not match the type it replaces", id)
+            }
         }
     }
 }
@@ -65,6 +81,16 @@ pub struct AttachmentInfo {
 /// Global attachment registry: tracepoint name -> attachment info
 static ATTACHMENTS: Mutex<BTreeMap<String, AttachmentInfo>> = Mutex::new(BTreeMap::new());
 
+/// Held from the check that a program is loaded until its new attachment is
+/// in place, and by `runtime::unload_program` from its attachment check
+/// until the program is removed, so no attachment can point at an unloaded
+/// program.
+///
+/// A separate lock rather than the program registry: probe handlers look
+/// programs up with their probe registry locked, so holding the program
+/// registry while listing probes could deadlock.
+pub(crate) static ATTACH_LOCK: Mutex<()> = Mutex::new(());
+
 /// Attach a program to a tracepoint.
 ///
 /// # Arguments
@@ -75,11 +101,16 @@ static ATTACHMENTS: Mutex<BTreeMap<String, AttachmentInfo>> = Mutex::new(BTreeMa
 /// # Returns
 /// Ok(()) on success, Error if tracepoint already has attachment or program not found.
 pub fn attach(tracepoint: &str, prog_id: u32, prog_name: &str) -> Result<(), Error> {
+    let _attach = ATTACH_LOCK.lock();
     // Verify program exists
     if crate::runtime::get_program(prog_id).is_none() {
         return Err(Error::ProgramNotFound(prog_id));
     }
+    attach_tracepoint(tracepoint, prog_id, prog_name)
+}
 
+/// Add a tracepoint attachment for a program known to be loaded.
+fn attach_tracepoint(tracepoint: &str, prog_id: u32, prog_name: &str) -> Result<(), Error> {
     let mut attachments = ATTACHMENTS.lock();
 
     if attachments.contains_key(tracepoint) {
@@ -123,6 +154,98 @@ pub fn detach(tracepoint: &str) -> Result<AttachmentInfo, Error> {
     }
 }
 
+/// Detach a program from every tracepoint and probe it is attached to.
+///
+/// Hprobe slots cannot be removed while an hprobe handler is running (e.g.
+/// when called from the program's own probe hit); those are removed once
+/// the hit is complete and are not counted.
+///
+/// # Returns
+/// The number of attachments removed.
+pub fn detach_program(prog_id: u32) -> usize {
+    let tracepoints: Vec<String> = ATTACHMENTS
+        .lock()
+        .iter()
+        .filter(|(_, info)| info.prog_id == prog_id)
+        .map(|(tp, _)| tp.clone())
+        .collect();
+    let detached = tracepoints.iter().filter(|tp| detach(tp).is_ok()).count();
+
+    detached + detach_hprobes(prog_id) + detach_guest(prog_id)
+}
+
+#[cfg(feature = "hprobe")]
+fn detach_hprobes(prog_id: u32) -> usize {
+    crate::probe::hprobe::manager::try_detach_program(prog_id).unwrap_or_else(|e| {
+        log::warn!("Program {}: hprobes not detached: {}", prog_id, e);
+        0
+    })
+}
+
+#[cfg(not(feature = "hprobe"))]
+fn detach_hprobes(_prog_id: u32) -> usize {
+    0
+}
+
+/// Remove the hprobe slots [`detach_program`] could not remove from inside
+/// a probe handler.
+#[cfg(feature = "hprobe")]
+pub(crate) fn run_pending_detaches() {
+    crate::probe::hprobe::manager::run_pending_detaches();
+}
+
+#[cfg(not(feature = "hprobe"))]
+pub(crate) fn run_pending_detaches() {}
+
+#[cfg(feature = "guest-kprobe")]
+fn detach_guest(prog_id: u32) -> usize {
+    crate::probe::kprobe::manager::detach_program(prog_id)
+}
+
+#[cfg(not(feature = "guest-kprobe"))]
+fn detach_guest(_prog_id: u32) -> usize {
+    0
+}
+
+/// List every tracepoint and probe a program is attached to.
+pub fn program_attachments(prog_id: u32) -> Vec<AttachTarget> {
+    let mut targets: Vec<AttachTarget> = ATTACHMENTS
+        .lock()
+        .iter()
+        .filter(|(_, info)| info.prog_id == prog_id)
+        .map(|(tp, _)| AttachTarget::Tracepoint(tp.clone()))
+        .collect();
+    hprobe_targets(prog_id, &mut targets);
+    guest_targets(prog_id, &mut targets);
+    targets
+}
+
+#[cfg(feature = "hprobe")]
+fn hprobe_targets(prog_id: u32, targets: &mut Vec<AttachTarget>) {
+    // An entry and a return probe on the same symbol are two slots.
+    for (name, _, _, _, _, id) in crate::probe::hprobe::manager::list_all() {
+        let target = AttachTarget::Symbol(name);
+        if id == prog_id && !targets.contains(&target) {
+            targets.push(target);
+        }
+    }
+}
+
+#[cfg(not(feature = "hprobe"))]
+fn hprobe_targets(_prog_id: u32, _targets: &mut Vec<AttachTarget>) {}
+
+#[cfg(feature = "guest-kprobe")]
+fn guest_targets(prog_id: u32, targets: &mut Vec<AttachTarget>) {
+    for (vm_id, gva, _, _, _, _, id, _) in crate::probe::kprobe::manager::list_all() {
+        if id == prog_id {
+            targets.push(AttachTarget::Guest { vm_id, gva });
+        }
+    }
+}
+
+#[cfg(not(feature = "guest-kprobe"))]
+fn guest_targets(_prog_id: u32, _targets: &mut Vec<AttachTarget>) {}
+
 /// Get the program attached to a tracepoint.
 ///
 /// # Returns
@@ -149,3 +272,161 @@ pub fn attachment_count() -> usize {
     let attachments = ATTACHMENTS.lock();
     attachments.len()
 }
+
+// =============================================================================
+// Auto-Attach
+// =============================================================================
+
+/// Attach a program to the target encoded in its ELF section name.
+///
+/// - `tracepoint/<sub>/<event>` uses [`attach`]
+/// - `kprobe/`, `kretprobe/`, `hprobe/`, `hretprobe/` use `hprobe_manager::attach`
+/// - `gkprobe/`, `gkretprobe/` use the guest `manager::attach` in `BrkInject` mode
+///
+/// # Returns
+/// The target the program was attached to.
+pub fn auto_attach(prog_id: u32) -> Result<AttachTarget, Error> {
+    let _attach = ATTACH_LOCK.lock();
+    let program = crate::runtime::get_program(prog_id).ok_or(Error::ProgramNotFound(prog_id))?;
+    let target = program
+        .attach_target()
+        .cloned()
+        .ok_or(Error::NoAttachTarget(prog_id))?;
+    let is_ret = program.prog_type().is_return_probe();
+
+    match &target {
+        AttachTarget::Tracepoint(tp) => attach_tracepoint(tp, prog_id, program.name())?,
+        AttachTarget::Symbol(symbol) => attach_hprobe(symbol, prog_id, is_ret)?,
+        AttachTarget::Guest { vm_id, gva } => attach_guest(*vm_id, *gva, prog_id, is_ret)?,
+    }
+
+    log::info!(
+        "Auto-attached program {} ({}) to {:?}",
+        prog_id,
+        program.section(),
+        target
+    );
+    Ok(target)
+}
+
+#[cfg(feature = "hprobe")]
+fn attach_hprobe(symbol: &str, prog_id: u32, is_ret: bool) -> Result<(), Error> {
+    crate::probe::hprobe::manager::attach_loaded(symbol, prog_id, is_ret)
+        .map(|_| ())
+        .map_err(Error::ProbeFailed)
+}
+
+#[cfg(not(feature = "hprobe"))]
+fn attach_hprobe(symbol: &str, _prog_id: u32, _is_ret: bool) -> Result<(), Error> {
+    Err(Error::Unsupported(alloc::format!("hprobe {}", symbol)))
+}
+
+#[cfg(feature = "guest-kprobe")]
+fn attach_guest(vm_id: u32, gva: u64, prog_id: u32, is_ret: bool) -> Result<(), Error> {
+    use crate::probe::kprobe::manager::{self, KprobeMode};
+    manager::attach_loaded(vm_id, gva, prog_id, is_ret, KprobeMode::BrkInject)
+        .map_err(Error::ProbeFailed)
+}
+
+#[cfg(not(feature = "guest-kprobe"))]
+fn attach_guest(vm_id: u32, gva: u64, _prog_id: u32, _is_ret: bool) -> Result<(), Error> {
+    Err(Error::Unsupported(alloc::format!(
+        "guest kprobe vm{}:{:#x}",
+        vm_id,
+        gva
+    )))
+}
+
+// =============================================================================
+// Program Replacement
+// =============================================================================
+
+/// Replace the program behind an existing attachment, like Linux
+/// `bpf_link_update` with `BPF_F_REPLACE`.
+///
+/// `target` must currently run `old_prog_id`. The switch is a single store
+/// under the target's registry lock: invocations that already started finish
+/// on the old program and every later hit runs `new_prog_id`, with no window
+/// in which the target runs nothing. The old program stays loaded.
+///
+/// To keep map contents across the swap, load the new program with
+/// `ObjectLoader::reuse_maps(old_prog_id)`.
+///
+/// Both programs must have the same [`ProgramType`]
+/// unless one of them is raw bytecode.
+pub fn replace(target: &AttachTarget, old_prog_id: u32, new_prog_id: u32) -> Result<(), Error> {
+    let _attach = ATTACH_LOCK.lock();
+    let program = |id| crate::runtime::get_program(id).ok_or(Error::ProgramNotFound(id));
+    let (old, new) = (program(old_prog_id)?, program(new_prog_id)?);
+    if !same_type(old.prog_type(), new.prog_type()) {
+        return Err(Error::IncompatibleProgram(new_prog_id));
+    }
+
+    match target {
+        AttachTarget::Tracepoint(tp) => {
+            replace_tracepoint(tp, old_prog_id, new_prog_id, new.name())?
+        }
+        AttachTarget::Symbol(symbol) => replace_hprobe(symbol, old_prog_id, new_prog_id)?,
+        AttachTarget::Guest { vm_id, gva } => {
+            replace_guest(*vm_id, *gva, old_prog_id, new_prog_id)?
+        }
+    }
+
+    log::info!(
+        "Replaced program {} with {} on {:?}",
+        old_prog_id,
+        new_prog_id,
+        target
+    );
+    Ok(())
+}
+
+/// Whether a program of type `new` may replace one of type `old`.
+fn same_type(old: ProgramType, new: ProgramType) -> bool {
+    old == new || old == ProgramType::Unknown || new == ProgramType::Unknown
+}
+
+fn replace_tracepoint(
+    tracepoint: &str,
+    old_prog_id: u32,
+    new_prog_id: u32,
+    new_name: &str,
+) -> Result<(), Error> {
+    let mut attachments = ATTACHMENTS.lock();
+    let info = attachments
+        .get_mut(tracepoint)
+        .filter(|info| info.prog_id == old_prog_id)
+        .ok_or_else(|| Error::NotAttached(tracepoint.to_string()))?;
+    info.prog_id = new_prog_id;
+    if !new_name.is_empty() {
+        info.prog_name = new_name.to_string();
+    }
+    Ok(())
+}
+
+#[cfg(feature = "hprobe")]
+fn replace_hprobe(symbol: &str, old_prog_id: u32, new_prog_id: u32) -> Result<(), Error> {
+    crate::probe::hprobe::manager::replace_program(symbol, old_prog_id, new_prog_id)
+        .map(|_| ())
+        .map_err(Error::ProbeFailed)
+}
+
+#[cfg(not(feature = "hprobe"))]
+fn replace_hprobe(symbol: &str, _old_prog_id: u32, _new_prog_id: u32) -> Result<(), Error> {
+    Err(Error::Unsupported(alloc::format!("hprobe {}", symbol)))
+}
+
+#[cfg(feature = "guest-kprobe")]
+fn replace_guest(vm_id: u32, gva: u64, old_prog_id: u32, new_prog_id: u32) -> Result<(), Error> {
+    crate::probe::kprobe::manager::replace_program(vm_id, gva, old_prog_id, new_prog_id)
+        .map_err(Error::ProbeFailed)
+}
+
+#[cfg(not(feature = "guest-kprobe"))]
+fn replace_guest(vm_id: u32, gva: u64, _old_prog_id: u32, _new_prog_id: u32) -> Result<(), Error> {
+    Err(Error::Unsupported(alloc::format!(
+        "guest kprobe vm{}:{:#x}",
+        vm_id,
+        gva
+    )))
+}
@@ -0,0 +1,133 @@
+//! Bloom filter maps.
+//!
+//! A [`MapType::BloomFilter`] map answers "was this value pushed?" with no
+//! false negatives and a small rate of false positives. Values are added
+//! with `bpf_map_push_elem` and tested with `bpf_map_peek_elem`; they cannot
+//! be listed or removed.
+//!
+//! Filters are kept here rather than in the map registry, whose maps are
+//! locked for each operation. The bits are atomics, so programs on all CPUs
+//! can test and set them at the same time.
+//!
+//! [`MapType::BloomFilter`]: crate::maps::MapType::BloomFilter
+
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::RwLock;
+
+use crate::maps::Error;
+
+/// Hash functions per value (the Linux default when `map_extra` is 0).
+pub const BLOOM_NR_HASH_FUNCS: u32 = 5;
+
+/// Smallest bit array, in bits.
+const MIN_BITS: u64 = 64;
+
+struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    value_size: u32,
+}
+
+impl BloomFilter {
+    /// Size the bit array as Linux does: about `max_entries * hashes / ln 2`
+    /// bits, rounded up to a power of two.
+    fn new(value_size: u32, max_entries: u32) -> Self {
+        let nr_bits = (max_entries as u64 * BLOOM_NR_HASH_FUNCS as u64 * 7 / 5)
+            .max(MIN_BITS)
+            .next_power_of_two();
+        Self {
+            bits: (0..nr_bits / 64).map(|_| AtomicU64::new(0)).collect(),
+            value_size,
+        }
+    }
+
+    /// Bit index of each hash function, by double hashing with the two
+    /// halves of one 64-bit hash of the value.
+    fn bit_indexes(&self, value: &[u8]) -> impl Iterator<Item = u64> {
+        let mask = self.bits.len() as u64 * 64 - 1;
+        let hash = hash64(value);
+        let (h1, h2) = (hash & 0xffff_ffff, (hash >> 32) | 1);
+        (0..BLOOM_NR_HASH_FUNCS as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) & mask)
+    }
+
+    fn insert(&self, value: &[u8]) {
+        for bit in self.bit_indexes(value) {
+            self.bits[(bit / 64) as usize].fetch_or(1 << (bit % 64), Ordering::Relaxed);
+        }
+    }
+
+    fn contains(&self, value: &[u8]) -> bool {
+        self.bit_indexes(value).all(|bit| {
+            let word = self.bits[(bit / 64) as usize].load(Ordering::Relaxed);
+            word & (1 << (bit % 64)) != 0
+        })
+    }
+}
+
+/// FNV-1a, finished with the MurmurHash3 mixer so the low bits used for
+/// bit indexes depend on every input byte.
+fn hash64(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+/// Filters by map ID.
+static BLOOM_FILTERS: RwLock<BTreeMap<u32, Arc<BloomFilter>>> = RwLock::new(BTreeMap::new());
+
+fn filter(map_id: u32) -> Option<Arc<BloomFilter>> {
+    BLOOM_FILTERS.read().get(&map_id).cloned()
+}
+
+// =============================================================================
+// Map Lifecycle
+// =============================================================================
+
+/// Allocate the filter of map `map_id`.
+pub(crate) fn create(map_id: u32, value_size: u32, max_entries: u32) {
+    let filter = Arc::new(BloomFilter::new(value_size, max_entries));
+    BLOOM_FILTERS.write().insert(map_id, filter);
+}
+
+/// Free the filter of map `map_id`.
+pub(crate) fn destroy(map_id: u32) {
+    BLOOM_FILTERS.write().remove(&map_id);
+}
+
+// =============================================================================
+// Push and Peek
+// =============================================================================
+
+/// Add `value` to the filter of map `map_id`. `flags` must be 0 (BPF_ANY).
+pub(crate) fn push(map_id: u32, value: &[u8], flags: u64) -> Result<(), Error> {
+    let filter = filter(map_id).ok_or(Error::InvalidArgument)?;
+    if flags != 0 || value.len() != filter.value_size as usize {
+        return Err(Error::InvalidArgument);
+    }
+    filter.insert(value);
+    Ok(())
+}
+
+/// Whether `value` may have been added to the filter of map `map_id`.
+pub(crate) fn contains(map_id: u32, value: &[u8]) -> Result<bool, Error> {
+    let filter = filter(map_id).ok_or(Error::InvalidArgument)?;
+    if value.len() != filter.value_size as usize {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(filter.contains(value))
+}
+
+/// Value size of the filter of map `map_id`, or None if the map is not a
+/// bloom filter. Lets the helpers size the program's value without locking
+/// the map.
+pub(crate) fn value_size(map_id: u32) -> Option<u32> {
+    let filters = BLOOM_FILTERS.read();
+    filters.get(&map_id).map(|filter| filter.value_size)
+}
@@ -0,0 +1,225 @@
+//! eBPF disassembler.
+//!
+//! Renders bytecode in the syntax of the Linux verifier log and
+//! `bpftool prog dump xlated`, e.g.
+//!
+//! ```text
+//!    0: (b7) r1 = 0
+//!    1: (7b) *(u64 *)(r10 -8) = r1
+//!    2: (18) r1 = map[counts]
+//!    4: (85) call bpf_map_lookup_elem#1
+//!    5: (15) if r0 == 0x0 goto pc+2
+//! ```
+//!
+//! Map loads are shown with the map name when the FD is in the given map
+//! list and helper calls with the helper name when it is known.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::verifier::{
+    BPF_ADD, BPF_ALU, BPF_ALU64, BPF_AND, BPF_ARSH, BPF_ATOMIC, BPF_CALL, BPF_DIV, BPF_END,
+    BPF_EXIT, BPF_JA, BPF_JEQ, BPF_JGE, BPF_JGT, BPF_JLE, BPF_JLT, BPF_JMP, BPF_JMP32, BPF_JNE,
+    BPF_JSET, BPF_JSGE, BPF_JSGT, BPF_JSLE, BPF_JSLT, BPF_LDX, BPF_LSH, BPF_MEM, BPF_MOD, BPF_MOV,
+    BPF_MUL, BPF_NEG, BPF_OR, BPF_RSH, BPF_ST, BPF_STX, BPF_SUB, BPF_XOR, INSN_SIZE, Insn,
+    LD_DW_IMM, MODE_MASK, OP_MASK, PSEUDO_CALL, PSEUDO_FUNC, PSEUDO_MAP_FD, PSEUDO_MAP_VALUE,
+    access_size,
+};
+
+/// Disassemble `code`, one line per instruction.
+///
+/// Each line starts with the instruction index and opcode. `lddw` takes two
+/// slots, so the index after it is skipped. `map_fds` is the program's
+/// (map_name, map_fd) list, used to name map loads.
+pub fn disassemble(code: &[u8], map_fds: &[(String, u32)]) -> Vec<String> {
+    let len = code.len() / INSN_SIZE;
+    let mut lines = Vec::with_capacity(len);
+
+    let mut idx = 0;
+    while idx < len {
+        let insn = Insn::decode(code, idx);
+        let text = if insn.op == LD_DW_IMM && idx + 1 < len {
+            let next = Insn::decode(code, idx + 1);
+            format_lddw(&insn, &next, map_fds)
+        } else {
+            format_insn(&insn)
+        };
+        lines.push(format!("{:4}: ({:02x}) {}", idx, insn.op, text));
+        idx += if insn.op == LD_DW_IMM { 2 } else { 1 };
+    }
+    lines
+}
+
+/// Name of helper `id`, if it is one the runtime knows.
+pub fn helper_name(id: u32) -> Option<&'static str> {
+    crate::helpers::helper_name(id).or_else(|| hypervisor_helper_name(id))
+}
+
+#[cfg(feature = "tracepoint-support")]
+fn hypervisor_helper_name(id: u32) -> Option<&'static str> {
+    crate::tracepoints::hypervisor_helpers::hypervisor_helper_name(id)
+}
+
+#[cfg(not(feature = "tracepoint-support"))]
+fn hypervisor_helper_name(_id: u32) -> Option<&'static str> {
+    None
+}
+
+fn format_lddw(insn: &Insn, next: &Insn, map_fds: &[(String, u32)]) -> String {
+    let fd = insn.imm as u32;
+    let map = || match map_fds.iter().find(|(_, f)| *f == fd) {
+        Some((name, _)) => format!("map[{}]", name),
+        None => format!("map[fd:{}]", fd),
+    };
+    match insn.src {
+        PSEUDO_MAP_FD => format!("r{} = {}", insn.dst, map()),
+        PSEUDO_MAP_VALUE => format!("r{} = {}[0]+{}", insn.dst, map(), next.imm as u32),
+        PSEUDO_FUNC => format!("r{} = subprog[{}]", insn.dst, signed(insn.imm as i64)),
+        _ => {
+            let value = (insn.imm as u32 as u64) | ((next.imm as u32 as u64) << 32);
+            format!("r{} = {:#x}", insn.dst, value)
+        }
+    }
+}
+
+fn format_insn(insn: &Insn) -> String {
+    match insn.class() {
+        BPF_ALU | BPF_ALU64 => format_alu(insn),
+        BPF_LDX if insn.op & MODE_MASK == BPF_MEM => format!(
+            "r{} = *({} *)(r{} {})",
+            insn.dst,
+            size_name(insn.op),
+            insn.src,
+            signed(insn.off as i64)
+        ),
+        BPF_ST if insn.op & MODE_MASK == BPF_MEM => format!(
+            "*({} *)(r{} {}) = {}",
+            size_name(insn.op),
+            insn.dst,
+            signed(insn.off as i64),
+            insn.imm
+        ),
+        BPF_STX => match insn.op & MODE_MASK {
+            BPF_MEM => format!(
+                "*({} *)(r{} {}) = r{}",
+                size_name(insn.op),
+                insn.dst,
+                signed(insn.off as i64),
+                insn.src
+            ),
+            BPF_ATOMIC if insn.imm == 0 => format!(
+                "lock *({} *)(r{} {}) += r{}",
+                size_name(insn.op),
+                insn.dst,
+                signed(insn.off as i64),
+                insn.src
+            ),
+            _ => unknown(insn),
+        },
+        BPF_JMP | BPF_JMP32 => format_jmp(insn),
+        _ => unknown(insn),
+    }
+}
+
+fn format_alu(insn: &Insn) -> String {
+    let is64 = insn.class() == BPF_ALU64;
+    let reg = if is64 { 'r' } else { 'w' };
+    let src = if insn.uses_reg_src() {
+        format!("{}{}", reg, insn.src)
+    } else {
+        format!("{}", insn.imm)
+    };
+
+    let op = match insn.op & OP_MASK {
+        BPF_ADD => "+=",
+        BPF_SUB => "-=",
+        BPF_MUL => "*=",
+        BPF_DIV => "/=",
+        BPF_OR => "|=",
+        BPF_AND => "&=",
+        BPF_LSH => "<<=",
+        BPF_RSH => ">>=",
+        BPF_MOD => "%=",
+        BPF_XOR => "^=",
+        BPF_MOV => "=",
+        BPF_ARSH => "s>>=",
+        BPF_NEG => return format!("{}{} = -{}{}", reg, insn.dst, reg, insn.dst),
+        // The source bit selects big-endian conversion.
+        BPF_END if !is64 => {
+            let order = if insn.uses_reg_src() { "be" } else { "le" };
+            return format!("r{} = {}{} r{}", insn.dst, order, insn.imm, insn.dst);
+        }
+        _ => return unknown(insn),
+    };
+    format!("{}{} {} {}", reg, insn.dst, op, src)
+}
+
+fn format_jmp(insn: &Insn) -> String {
+    let is32 = insn.class() == BPF_JMP32;
+    let op = match insn.op & OP_MASK {
+        BPF_JA if !is32 => return format!("goto pc{}", signed(insn.off as i64)),
+        BPF_CALL if !is32 => {
+            return match insn.src {
+                PSEUDO_CALL => format!("call pc{}", signed(insn.imm as i64)),
+                _ => match helper_name(insn.imm as u32) {
+                    Some(name) => format!("call {}#{}", name, insn.imm),
+                    None => format!("call #{}", insn.imm),
+                },
+            };
+        }
+        BPF_EXIT if !is32 => return String::from("exit"),
+        BPF_JEQ => "==",
+        BPF_JGT => ">",
+        BPF_JGE => ">=",
+        BPF_JSET => "&",
+        BPF_JNE => "!=",
+        BPF_JSGT => "s>",
+        BPF_JSGE => "s>=",
+        BPF_JLT => "<",
+        BPF_JLE => "<=",
+        BPF_JSLT => "s<",
+        BPF_JSLE => "s<=",
+        _ => return unknown(insn),
+    };
+
+    let reg = if is32 { 'w' } else { 'r' };
+    let src = if insn.uses_reg_src() {
+        format!("{}{}", reg, insn.src)
+    } else {
+        format!("{:#x}", insn.imm)
+    };
+    format!(
+        "if {}{} {} {} goto pc{}",
+        reg,
+        insn.dst,
+        op,
+        src,
+        signed(insn.off as i64)
+    )
+}
+
+fn size_name(op: u8) -> &'static str {
+    match access_size(op) {
+        1 => "u8",
+        2 => "u16",
+        4 => "u32",
+        _ => "u64",
+    }
+}
+
+/// `+8` / `-8`, as offsets are written in the verifier log.
+fn signed(value: i64) -> String {
+    if value < 0 {
+        format!("{}", value)
+    } else {
+        format!("+{}", value)
+    }
+}
+
+fn unknown(insn: &Insn) -> String {
+    format!(
+        "invalid insn (dst=r{} src=r{} off={} imm={})",
+        insn.dst, insn.src, insn.off, insn.imm
+    )
+}
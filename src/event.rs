@@ -1,8 +1,8 @@
 //! Unified trace event format and built-in event pipeline.
 //!
 //! All probe sources write `TraceEvent` records through `emit_event()`.
-//! Events are best-effort written to RingBuf, while a local fallback queue
-//! guarantees shell consumption even when RingBuf map push/pop is unavailable.
+//! Events are written to the trace RingBuf; a local fallback queue takes the
+//! ones it cannot (not initialized or full), so the shell still sees them.
 
 use alloc::boxed::Box;
 use alloc::collections::{BTreeMap, VecDeque};
@@ -159,7 +159,7 @@ fn remember_event_name(event_id: u32, name_offset: u16) {
 /// Global trace RingBuf map FD (`None` means uninitialized).
 static RINGBUF_FD: Mutex<Option<u32>> = Mutex::new(None);
 
-/// Fallback software queue used when RingBuf map operations are unavailable.
+/// Fallback software queue for events the RingBuf cannot take.
 static FALLBACK_EVENTS: Mutex<VecDeque<TraceEvent>> = Mutex::new(VecDeque::new());
 
 /// Initialize the global trace RingBuf with default size.
@@ -189,6 +189,7 @@ pub fn init_ringbuf_with_size(size_kb: u32) {
         key_size: 0,
         value_size: 0,
         max_entries: size_bytes,
+        map_flags: 0,
     };
 
     match maps::create(&def) {
@@ -232,25 +233,13 @@ fn fallback_pop(max_events: usize) -> Vec<TraceEvent> {
 
 /// Write one event into the global stream.
 ///
-/// Returns `true` if RingBuf map write succeeded. The fallback queue is always
-/// updated so shell commands can still read recent events.
+/// Returns `true` if RingBuf map write succeeded. Events the RingBuf cannot
+/// take go to the fallback queue so shell commands can still read them.
 pub fn ringbuf_push(event: &TraceEvent) -> bool {
-    let pushed = if let Some(fd) = ringbuf_fd() {
-        use crate::map_ops::AxKernelAuxOps;
-        use kbpf_basic::KernelAuxiliaryOps;
-
-        matches!(
-            AxKernelAuxOps::get_unified_map_from_fd(fd, |unified_map| {
-                unified_map.map_mut().push_elem(event.as_bytes(), 0)
-            }),
-            Ok(())
-        )
-    } else {
-        false
-    };
-
-    // Keep a software copy for shell-side consumption.
-    fallback_push(*event);
+    let pushed = ringbuf_fd().is_some_and(|fd| maps::ringbuf_output(fd, event.as_bytes()).is_ok());
+    if !pushed {
+        fallback_push(*event);
+    }
     pushed
 }
 
@@ -262,17 +251,7 @@ pub fn consume_events(max_events: usize) -> Vec<TraceEvent> {
     let limit = if max_events == 0 { usize::MAX } else { max_events };
 
     if let Some(fd) = ringbuf_fd() {
-        use crate::map_ops::AxKernelAuxOps;
-        use kbpf_basic::KernelAuxiliaryOps;
-
-        while events.len() < limit {
-            let mut raw = [0u8; core::mem::size_of::<TraceEvent>()];
-            let res = AxKernelAuxOps::get_unified_map_from_fd(fd, |unified_map| {
-                unified_map.map_mut().pop_elem(&mut raw)
-            });
-            if !matches!(res, Ok(())) {
-                break;
-            }
+        for raw in maps::ringbuf_consume(fd, max_events) {
             if let Some(ev) = TraceEvent::from_bytes(&raw) {
                 events.push(ev);
             }
@@ -27,6 +27,7 @@ pub fn example_with_map() {
         key_size: 8,
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
 
     let map_id = crate::maps::create(&map_def).expect("Failed to create map");
@@ -5,13 +5,23 @@
 
 use crate::map_ops;
 use crate::maps;
+use crate::ringbuf;
 use spin::Mutex;
 
-/// Static buffer for returning lookup results.
-/// Linux BPF returns a pointer to map-internal storage; we simulate this
-/// with a static buffer. Max value size supported: 512 bytes.
+/// Static buffers for returning copies of lookup results, one per CPU and
+/// nesting depth, so a program interrupted by another one (e.g. from a probe
+/// hit in a helper) keeps its copy. Each lookup that copies overwrites the
+/// previous copy of the same program.
+/// Like Linux, lookups in array and hash maps return a pointer to the value
+/// in the map (see `maps::value_regions`); other maps return a copy.
+/// Max value size supported for copies: 512 bytes.
 pub const MAX_VALUE_SIZE: usize = 512;
-static LOOKUP_BUFFER: Mutex<[u8; MAX_VALUE_SIZE]> = Mutex::new([0u8; MAX_VALUE_SIZE]);
+const LOOKUP_CPUS: usize = crate::platform::MAX_CPUS as usize;
+/// Nested programs per CPU with a lookup buffer of their own. Deeper ones
+/// get NULL from lookups that would return a copy.
+pub const MAX_LOOKUP_DEPTH: usize = 4;
+static LOOKUP_BUFFER: Mutex<[[[u8; MAX_VALUE_SIZE]; MAX_LOOKUP_DEPTH]; LOOKUP_CPUS]> =
+    Mutex::new([[[0u8; MAX_VALUE_SIZE]; MAX_LOOKUP_DEPTH]; LOOKUP_CPUS]);
 
 /// Static buffer for returning tracepoint names.
 pub const MAX_NAME_SIZE: usize = 64;
@@ -23,11 +33,12 @@ static NAME_BUFFER: Mutex<[u8; MAX_NAME_SIZE]> = Mutex::new([0u8; MAX_NAME_SIZE]
 /// Must be called and registered before VM execution.
 ///
 /// # Returns
-/// Memory range (start..end) of the static LOOKUP_BUFFER.
+/// Memory range (start..end) of the static LOOKUP_BUFFER, covering the
+/// buffers of all CPUs and depths.
 pub fn get_lookup_buffer_range() -> core::ops::Range<u64> {
     let buffer = LOOKUP_BUFFER.lock();
     let start = buffer.as_ptr() as u64;
-    let end = start + MAX_VALUE_SIZE as u64;
+    let end = start + (MAX_VALUE_SIZE * MAX_LOOKUP_DEPTH * LOOKUP_CPUS) as u64;
     start..end
 }
 
@@ -62,11 +73,43 @@ pub mod id {
     pub const GET_SMP_PROCESSOR_ID: u32 = 8;
     /// bpf_get_tracepoint_name(tracepoint_id) -> name_ptr or 0
     pub const GET_TRACEPOINT_NAME: u32 = 10;
+    /// bpf_tail_call(ctx, prog_array, index) -> does not return on success
+    pub const TAIL_CALL: u32 = 12;
+    /// bpf_perf_event_output(ctx, perf_map, flags, data, size) -> 0 or error
+    pub const PERF_EVENT_OUTPUT: u32 = 25;
+    /// bpf_get_stackid(ctx, stack_map, flags) -> stack ID or error
+    pub const GET_STACKID: u32 = 27;
+    /// bpf_get_stack(ctx, buf, size, flags) -> bytes written or error
+    pub const GET_STACK: u32 = 67;
+    /// bpf_map_push_elem(map_id, value, flags) -> 0 or error
+    pub const MAP_PUSH_ELEM: u32 = 87;
+    /// bpf_map_pop_elem(map_id, value) -> 0 or error
+    pub const MAP_POP_ELEM: u32 = 88;
+    /// bpf_map_peek_elem(map_id, value) -> 0 or error
+    pub const MAP_PEEK_ELEM: u32 = 89;
     /// bpf_probe_read_kernel(dst, size, src) -> 0 or error
     /// Same semantics as PROBE_READ, but uses the Linux kernel helper ID.
     pub const PROBE_READ_KERNEL: u32 = 113;
+    /// bpf_ringbuf_output(ringbuf, data, size, flags) -> 0 or error
+    pub const RINGBUF_OUTPUT: u32 = 130;
+    /// bpf_ringbuf_reserve(ringbuf, size, flags) -> record_ptr or 0
+    pub const RINGBUF_RESERVE: u32 = 131;
+    /// bpf_ringbuf_submit(record, flags)
+    pub const RINGBUF_SUBMIT: u32 = 132;
+    /// bpf_ringbuf_discard(record, flags)
+    pub const RINGBUF_DISCARD: u32 = 133;
+    /// bpf_ringbuf_query(ringbuf, flags) -> value
+    pub const RINGBUF_QUERY: u32 = 134;
+    /// bpf_loop(nr_loops, callback, ctx, flags) -> iterations run or error
+    /// Linked into a loop around the callback at load time; there is no
+    /// helper function to register.
+    pub const LOOP: u32 = 181;
 }
 
+/// Most iterations a single `bpf_loop` call runs (`BPF_MAX_LOOPS` in Linux).
+/// Larger counts fail with `-E2BIG` without calling the callback.
+pub const MAX_LOOPS: u32 = 1 << 23;
+
 // =============================================================================
 // Helper Implementations
 // =============================================================================
@@ -77,7 +120,10 @@ pub mod id {
 /// - r1 = map_fd (from ld_map_fd instruction)
 /// - r2 = pointer to key (on eBPF stack)
 ///
-/// Returns: pointer to value in static buffer, or 0 if not found.
+/// Returns: pointer to the value in the map, or to a copy in the static
+/// buffer of this CPU and nesting depth for maps whose values have no fixed
+/// address, or 0 if not found or nested deeper than [`MAX_LOOKUP_DEPTH`].
+/// Writes through a pointer into the map update the value in place.
 ///
 /// SAFETY: Assumes eBPF program is trusted and pointers are valid.
 fn bpf_map_lookup_elem(map_fd: u64, key_ptr: u64, _r3: u64, _r4: u64, _r5: u64) -> u64 {
@@ -93,11 +139,24 @@ fn bpf_map_lookup_elem(map_fd: u64, key_ptr: u64, _r3: u64, _r4: u64, _r5: u64)
         core::slice::from_raw_parts(ptr, key_size as usize)
     };
 
+    if map_ops::get_map_type(map_fd as u32).is_some_and(maps::has_fixed_values) {
+        return maps::prog_lookup_ptr(map_fd as u32, key_bytes).map_or(0, |ptr| ptr as u64);
+    }
+
     // Lookup in map
-    match maps::lookup_elem(map_fd as u32, key_bytes) {
+    match maps::prog_lookup_elem(map_fd as u32, key_bytes) {
         Some(value) => {
-            // Copy value to static buffer and return pointer
-            let mut buffer = LOOKUP_BUFFER.lock();
+            // Copy value to the buffer of this invocation and return pointer
+            let depth = crate::runtime::run_depth().saturating_sub(1);
+            let mut buffers = LOOKUP_BUFFER.lock();
+            let cpu = &mut buffers[crate::platform::cpu_id() as usize % LOOKUP_CPUS];
+            let Some(buffer) = cpu.get_mut(depth) else {
+                log::warn!(
+                    "bpf_map_lookup_elem: no lookup buffer at depth {}",
+                    depth + 1
+                );
+                return 0;
+            };
             let len = value.len().min(MAX_VALUE_SIZE);
             buffer[..len].copy_from_slice(&value[..len]);
             buffer.as_ptr() as u64
@@ -114,7 +173,8 @@ fn bpf_map_lookup_elem(map_fd: u64, key_ptr: u64, _r3: u64, _r4: u64, _r5: u64)
 /// - r3 = pointer to value
 /// - r4 = flags (0 = create or update)
 ///
-/// Returns: 0 on success, negative on error.
+/// Returns: 0 on success, negative on error, including for maps created
+/// with `BPF_F_RDONLY_PROG`.
 ///
 /// SAFETY: Assumes eBPF program is trusted and pointers are valid.
 fn bpf_map_update_elem(map_fd: u64, key_ptr: u64, value_ptr: u64, flags: u64, _r5: u64) -> u64 {
@@ -131,7 +191,7 @@ fn bpf_map_update_elem(map_fd: u64, key_ptr: u64, value_ptr: u64, flags: u64, _r
         (key, value)
     };
 
-    match maps::update_elem(map_fd as u32, key_bytes, value_bytes, flags) {
+    match maps::prog_update_elem(map_fd as u32, key_bytes, value_bytes, flags) {
         Ok(()) => 0,
         Err(_) => (-1i64) as u64,
     }
@@ -156,12 +216,99 @@ fn bpf_map_delete_elem(map_fd: u64, key_ptr: u64, _r3: u64, _r4: u64, _r5: u64)
     // Read key from pointer (UNSAFE: trusting eBPF program)
     let key_bytes = unsafe { core::slice::from_raw_parts(key_ptr as *const u8, key_size as usize) };
 
-    match maps::delete_elem(map_fd as u32, key_bytes) {
+    match maps::prog_delete_elem(map_fd as u32, key_bytes) {
+        Ok(()) => 0,
+        Err(_) => (-1i64) as u64,
+    }
+}
+
+/// bpf_map_push_elem - push an element onto a Queue or Stack map, or add it
+/// to a BloomFilter map.
+///
+/// Linux/Aya semantics:
+/// - r1 = map_fd
+/// - r2 = pointer to value
+/// - r3 = flags (BPF_EXIST = drop the oldest element if full)
+///
+/// Returns: 0 on success, negative on error.
+///
+/// SAFETY: Assumes eBPF program is trusted and pointers are valid.
+fn bpf_map_push_elem(map_fd: u64, value_ptr: u64, flags: u64, _r4: u64, _r5: u64) -> u64 {
+    // Bloom filters are tested and set without locking the map
+    if let Some(value_size) = crate::bloom::value_size(map_fd as u32) {
+        if !maps::prog_may_write(map_fd as u32) {
+            return (-1i64) as u64;
+        }
+        let value =
+            unsafe { core::slice::from_raw_parts(value_ptr as *const u8, value_size as usize) };
+        return match crate::bloom::push(map_fd as u32, value, flags) {
+            Ok(()) => 0,
+            Err(_) => (-1i64) as u64,
+        };
+    }
+    let Some((_key_size, value_size)) = map_ops::get_map_sizes(map_fd as u32) else {
+        log::warn!("bpf_map_push_elem: map {} not found", map_fd);
+        return (-1i64) as u64;
+    };
+
+    // Read value from pointer (UNSAFE: trusting eBPF program)
+    let value_bytes =
+        unsafe { core::slice::from_raw_parts(value_ptr as *const u8, value_size as usize) };
+
+    match maps::prog_push_elem(map_fd as u32, value_bytes, flags) {
         Ok(()) => 0,
         Err(_) => (-1i64) as u64,
     }
 }
 
+/// bpf_map_pop_elem - remove the next element of a Queue or Stack map.
+///
+/// Linux/Aya semantics:
+/// - r1 = map_fd
+/// - r2 = pointer to value_size bytes receiving the element
+///
+/// Returns: 0 on success, negative if the map is empty.
+///
+/// SAFETY: Assumes eBPF program is trusted and pointers are valid.
+fn bpf_map_pop_elem(map_fd: u64, value_ptr: u64, _r3: u64, _r4: u64, _r5: u64) -> u64 {
+    copy_out(maps::prog_pop_elem(map_fd as u32), value_ptr)
+}
+
+/// bpf_map_peek_elem - read the next element of a Queue or Stack map
+/// without removing it.
+///
+/// Same arguments and return value as `bpf_map_pop_elem`. For a BloomFilter
+/// map, r2 points to the value to test instead; returns 0 if it was
+/// probably pushed and -ENOENT if it was not.
+fn bpf_map_peek_elem(map_fd: u64, value_ptr: u64, _r3: u64, _r4: u64, _r5: u64) -> u64 {
+    if let Some(value_size) = crate::bloom::value_size(map_fd as u32) {
+        if !maps::prog_may_read(map_fd as u32) {
+            return (-1i64) as u64;
+        }
+        let value =
+            unsafe { core::slice::from_raw_parts(value_ptr as *const u8, value_size as usize) };
+        return match crate::bloom::contains(map_fd as u32, value) {
+            Ok(true) => 0,
+            Ok(false) => (-2i64) as u64,
+            Err(_) => (-1i64) as u64,
+        };
+    }
+    copy_out(maps::prog_peek_elem(map_fd as u32), value_ptr)
+}
+
+/// Copy a popped or peeked element to the program's buffer.
+fn copy_out(value: Option<alloc::vec::Vec<u8>>, value_ptr: u64) -> u64 {
+    match value {
+        Some(value) => {
+            unsafe {
+                core::ptr::copy_nonoverlapping(value.as_ptr(), value_ptr as *mut u8, value.len());
+            }
+            0
+        }
+        None => (-1i64) as u64,
+    }
+}
+
 /// bpf_ktime_get_ns - get current time in nanoseconds.
 ///
 /// Returns: current monotonic time in nanoseconds.
@@ -240,6 +387,148 @@ fn bpf_get_tracepoint_name(id: u64, _r2: u64, _r3: u64, _r4: u64, _r5: u64) -> u
     buffer.as_ptr() as u64
 }
 
+/// bpf_tail_call - jump to another program.
+///
+/// r1 = context pointer of the current program
+/// r2 = ProgArray map_fd
+/// r3 = index into the program array
+///
+/// Returns: 0 if the jump was scheduled, negative if the slot is empty, the
+/// map is not a ProgArray, or the tail-call limit is reached. The runtime
+/// rewrites each call site to exit on success, so the calling program does
+/// not continue after a successful tail call.
+fn bpf_tail_call(_ctx: u64, map_fd: u64, index: u64, _r4: u64, _r5: u64) -> u64 {
+    match crate::runtime::tail_call(map_fd as u32, index as u32) {
+        Ok(()) => 0,
+        Err(e) => {
+            log::debug!("bpf_tail_call: map {} index {}: {}", map_fd, index, e);
+            (-1i64) as u64
+        }
+    }
+}
+
+/// bpf_get_stackid - record the current hypervisor stack.
+///
+/// r1 = context pointer (the `PtRegs` of an hprobe)
+/// r2 = StackTrace map_fd
+/// r3 = flags (skip count in the low 8 bits, `BPF_F_REUSE_STACKID`)
+///
+/// Returns: stack ID, or negative on error. See `stack::get_stackid`.
+fn bpf_get_stackid(ctx: u64, map_fd: u64, flags: u64, _r4: u64, _r5: u64) -> u64 {
+    crate::stack::get_stackid(ctx, map_fd as u32, flags) as u64
+}
+
+/// bpf_get_stack - copy the current hypervisor stack into a buffer.
+///
+/// r1 = context pointer (the `PtRegs` of an hprobe)
+/// r2 = destination pointer
+/// r3 = buffer size
+/// r4 = flags (skip count in the low 8 bits)
+///
+/// Returns: bytes written, or negative on error. See `stack::get_stack`.
+///
+/// SAFETY: Assumes eBPF program is trusted and pointers are valid.
+fn bpf_get_stack(ctx: u64, buf: u64, size: u64, flags: u64, _r5: u64) -> u64 {
+    unsafe { crate::stack::get_stack(ctx, buf as *mut u8, size as u32, flags) as u64 }
+}
+
+/// bpf_perf_event_output - append a sample to a perf event array ring.
+///
+/// Linux/Aya semantics:
+/// - r1 = context pointer
+/// - r2 = map_fd (PerfEventArray)
+/// - r3 = flags (CPU index, or BPF_F_CURRENT_CPU)
+/// - r4 = pointer to data
+/// - r5 = data size
+///
+/// Returns: 0 on success, negative on error. See `perf_event::output`.
+///
+/// SAFETY: Assumes eBPF program is trusted and pointers are valid.
+fn bpf_perf_event_output(_ctx: u64, map_fd: u64, flags: u64, data_ptr: u64, size: u64) -> u64 {
+    let data = unsafe { core::slice::from_raw_parts(data_ptr as *const u8, size as usize) };
+    crate::perf_event::output(map_fd as u32, flags, data) as u64
+}
+
+/// Wakeup flags accepted by the ring buffer helpers.
+const RINGBUF_WAKEUP_FLAGS: u64 = ringbuf::BPF_RB_NO_WAKEUP | ringbuf::BPF_RB_FORCE_WAKEUP;
+
+/// bpf_ringbuf_output - copy data into a new ring buffer record.
+///
+/// Linux/Aya semantics:
+/// - r1 = map_fd (RingBuf)
+/// - r2 = pointer to data
+/// - r3 = data size
+/// - r4 = flags (BPF_RB_NO_WAKEUP / BPF_RB_FORCE_WAKEUP, ignored)
+///
+/// Returns: 0 on success, negative if the ring is full.
+///
+/// SAFETY: Assumes eBPF program is trusted and pointers are valid.
+fn bpf_ringbuf_output(map_fd: u64, data_ptr: u64, size: u64, flags: u64, _r5: u64) -> u64 {
+    if flags & !RINGBUF_WAKEUP_FLAGS != 0 {
+        return (-1i64) as u64;
+    }
+    let data = unsafe { core::slice::from_raw_parts(data_ptr as *const u8, size as usize) };
+    match ringbuf::output(map_fd as u32, data) {
+        Ok(()) => 0,
+        Err(_) => (-1i64) as u64,
+    }
+}
+
+/// bpf_ringbuf_reserve - reserve a record to fill in place.
+///
+/// Linux/Aya semantics:
+/// - r1 = map_fd (RingBuf)
+/// - r2 = record size
+/// - r3 = flags (must be 0)
+///
+/// Returns: pointer to the record, or 0 if the ring is full. The record
+/// must be passed to `bpf_ringbuf_submit` or `bpf_ringbuf_discard`.
+fn bpf_ringbuf_reserve(map_fd: u64, size: u64, flags: u64, _r4: u64, _r5: u64) -> u64 {
+    let Ok(size) = u32::try_from(size) else {
+        return 0;
+    };
+    if flags != 0 {
+        return 0;
+    }
+    ringbuf::reserve(map_fd as u32, size).map_or(0, |ptr| ptr as u64)
+}
+
+/// bpf_ringbuf_submit - make a reserved record visible to the consumer.
+///
+/// Linux/Aya semantics:
+/// - r1 = record pointer from `bpf_ringbuf_reserve`
+/// - r2 = flags (BPF_RB_NO_WAKEUP / BPF_RB_FORCE_WAKEUP, ignored)
+///
+/// Returns: 0.
+fn bpf_ringbuf_submit(record: u64, _flags: u64, _r3: u64, _r4: u64, _r5: u64) -> u64 {
+    if !ringbuf::commit(record, false) {
+        log::warn!("bpf_ringbuf_submit: {:#x} is not reserved", record);
+    }
+    0
+}
+
+/// bpf_ringbuf_discard - drop a reserved record.
+///
+/// Same arguments and return value as `bpf_ringbuf_submit`.
+fn bpf_ringbuf_discard(record: u64, _flags: u64, _r3: u64, _r4: u64, _r5: u64) -> u64 {
+    if !ringbuf::commit(record, true) {
+        log::warn!("bpf_ringbuf_discard: {:#x} is not reserved", record);
+    }
+    0
+}
+
+/// bpf_ringbuf_query - query ring buffer positions.
+///
+/// Linux/Aya semantics:
+/// - r1 = map_fd (RingBuf)
+/// - r2 = BPF_RB_AVAIL_DATA, BPF_RB_RING_SIZE, BPF_RB_CONS_POS or
+///   BPF_RB_PROD_POS
+///
+/// Returns: the requested value, 0 for unknown flags.
+fn bpf_ringbuf_query(map_fd: u64, flags: u64, _r3: u64, _r4: u64, _r5: u64) -> u64 {
+    ringbuf::query(map_fd as u32, flags).unwrap_or(0)
+}
+
 // =============================================================================
 // Helper Registration
 // =============================================================================
@@ -261,7 +550,48 @@ pub fn get_helper(id: u32) -> Option<HelperFn> {
         id::TRACE_PRINTK => Some(bpf_trace_printk),
         id::GET_SMP_PROCESSOR_ID => Some(bpf_get_smp_processor_id),
         id::GET_TRACEPOINT_NAME => Some(bpf_get_tracepoint_name),
+        id::TAIL_CALL => Some(bpf_tail_call),
+        id::PERF_EVENT_OUTPUT => Some(bpf_perf_event_output),
+        id::GET_STACKID => Some(bpf_get_stackid),
+        id::GET_STACK => Some(bpf_get_stack),
+        id::MAP_PUSH_ELEM => Some(bpf_map_push_elem),
+        id::MAP_POP_ELEM => Some(bpf_map_pop_elem),
+        id::MAP_PEEK_ELEM => Some(bpf_map_peek_elem),
         id::PROBE_READ_KERNEL => Some(bpf_probe_read),
+        id::RINGBUF_OUTPUT => Some(bpf_ringbuf_output),
+        id::RINGBUF_RESERVE => Some(bpf_ringbuf_reserve),
+        id::RINGBUF_SUBMIT => Some(bpf_ringbuf_submit),
+        id::RINGBUF_DISCARD => Some(bpf_ringbuf_discard),
+        id::RINGBUF_QUERY => Some(bpf_ringbuf_query),
+        _ => None,
+    }
+}
+
+/// Get the Linux name of a helper by ID.
+pub fn helper_name(id: u32) -> Option<&'static str> {
+    match id {
+        id::MAP_LOOKUP_ELEM => Some("bpf_map_lookup_elem"),
+        id::MAP_UPDATE_ELEM => Some("bpf_map_update_elem"),
+        id::MAP_DELETE_ELEM => Some("bpf_map_delete_elem"),
+        id::PROBE_READ => Some("bpf_probe_read"),
+        id::KTIME_GET_NS => Some("bpf_ktime_get_ns"),
+        id::TRACE_PRINTK => Some("bpf_trace_printk"),
+        id::GET_SMP_PROCESSOR_ID => Some("bpf_get_smp_processor_id"),
+        id::GET_TRACEPOINT_NAME => Some("bpf_get_tracepoint_name"),
+        id::TAIL_CALL => Some("bpf_tail_call"),
+        id::PERF_EVENT_OUTPUT => Some("bpf_perf_event_output"),
+        id::GET_STACKID => Some("bpf_get_stackid"),
+        id::GET_STACK => Some("bpf_get_stack"),
+        id::MAP_PUSH_ELEM => Some("bpf_map_push_elem"),
+        id::MAP_POP_ELEM => Some("bpf_map_pop_elem"),
+        id::MAP_PEEK_ELEM => Some("bpf_map_peek_elem"),
+        id::PROBE_READ_KERNEL => Some("bpf_probe_read_kernel"),
+        id::RINGBUF_OUTPUT => Some("bpf_ringbuf_output"),
+        id::RINGBUF_RESERVE => Some("bpf_ringbuf_reserve"),
+        id::RINGBUF_SUBMIT => Some("bpf_ringbuf_submit"),
+        id::RINGBUF_DISCARD => Some("bpf_ringbuf_discard"),
+        id::RINGBUF_QUERY => Some("bpf_ringbuf_query"),
+        id::LOOP => Some("bpf_loop"),
         _ => None,
     }
 }
@@ -276,7 +606,20 @@ pub const SUPPORTED_HELPERS: &[u32] = &[
     id::TRACE_PRINTK,
     id::GET_SMP_PROCESSOR_ID,
     id::GET_TRACEPOINT_NAME,
+    id::TAIL_CALL,
+    id::PERF_EVENT_OUTPUT,
+    id::GET_STACKID,
+    id::GET_STACK,
+    id::MAP_PUSH_ELEM,
+    id::MAP_POP_ELEM,
+    id::MAP_PEEK_ELEM,
     id::PROBE_READ_KERNEL,
+    id::RINGBUF_OUTPUT,
+    id::RINGBUF_RESERVE,
+    id::RINGBUF_SUBMIT,
+    id::RINGBUF_DISCARD,
+    id::RINGBUF_QUERY,
+    id::LOOP,
 ];
 
 /// Register all standard helpers to an rbpf VM.
@@ -0,0 +1,166 @@
+//! JIT compilation support for eBPF programs.
+//!
+//! rbpf can translate programs into native code on x86_64. The native image
+//! needs executable memory, which is carved out of a page pool placed in
+//! `.text` (the same approach `insn_slot` uses for kprobe slots). Pages are
+//! made writable while rbpf emits code and restored to read-only afterwards.
+//!
+//! On targets rbpf cannot JIT for, or where `page_table` cannot make the pool
+//! writable, compilation is skipped and programs run in the interpreter.
+
+// The page pool is only used by targets with a JIT backend.
+#![cfg_attr(not(target_arch = "x86_64"), allow(dead_code))]
+
+use core::ptr::addr_of;
+
+use rbpf::EbpfVmRaw;
+use spin::Mutex;
+
+use crate::page_table;
+
+/// Size of one JIT pool page in bytes.
+pub const JIT_PAGE_SIZE: usize = 0x1000;
+
+/// Number of pages in the JIT pool.
+/// 32 pages = 128KB, enough for several thousand instructions.
+pub const JIT_POOL_PAGES: usize = 32;
+
+/// Upper bound of native bytes emitted per eBPF instruction.
+const MAX_NATIVE_BYTES_PER_INSN: usize = 64;
+
+/// Native bytes reserved for the prologue and epilogue.
+const JIT_OVERHEAD: usize = 256;
+
+#[repr(C, align(4096))]
+struct JitPool([[u8; JIT_PAGE_SIZE]; JIT_POOL_PAGES]);
+
+/// Pre-allocated JIT pages in the .text section.
+/// These are executable by virtue of being in .text.
+#[unsafe(link_section = ".text.ebpf_jit")]
+#[used]
+static mut JIT_POOL: JitPool = JitPool([[0u8; JIT_PAGE_SIZE]; JIT_POOL_PAGES]);
+
+/// Bitmap tracking which pages are allocated.
+/// Bit N = 1 means page N is in use.
+static PAGE_BITMAP: Mutex<u32> = Mutex::new(0);
+
+/// Whether rbpf can JIT-compile on the current target.
+pub const fn is_supported() -> bool {
+    cfg!(target_arch = "x86_64")
+}
+
+/// Number of free pages in the JIT pool.
+pub fn free_pages() -> usize {
+    JIT_POOL_PAGES - PAGE_BITMAP.lock().count_ones() as usize
+}
+
+fn pool_base() -> usize {
+    addr_of!(JIT_POOL) as usize
+}
+
+/// Pages needed for the native image of `prog_len` bytes of bytecode.
+fn image_pages(prog_len: usize) -> usize {
+    let size = (prog_len / 8) * MAX_NATIVE_BYTES_PER_INSN + JIT_OVERHEAD;
+    size.div_ceil(JIT_PAGE_SIZE)
+}
+
+/// Bitmap mask covering `pages` pages starting at page 0 (1..=32).
+fn page_mask(pages: usize) -> u32 {
+    u32::MAX >> (32 - pages)
+}
+
+/// A run of contiguous pages from the JIT pool holding one native image.
+///
+/// The pages are returned to the pool when the region is dropped.
+#[derive(Debug)]
+pub(crate) struct ExecRegion {
+    first_page: usize,
+    pages: usize,
+}
+
+impl ExecRegion {
+    /// Allocate `pages` contiguous pages from the pool.
+    fn alloc(pages: usize) -> Option<Self> {
+        if pages == 0 || pages > JIT_POOL_PAGES {
+            return None;
+        }
+        let mask = page_mask(pages);
+
+        let mut bitmap = PAGE_BITMAP.lock();
+        for first_page in 0..=(JIT_POOL_PAGES - pages) {
+            if *bitmap & (mask << first_page) == 0 {
+                *bitmap |= mask << first_page;
+                let region = Self { first_page, pages };
+                log::debug!("jit: allocated {} pages at {:#x}", pages, region.addr());
+                return Some(region);
+            }
+        }
+
+        log::warn!("jit: no {} contiguous free pages in JIT pool", pages);
+        None
+    }
+
+    fn addr(&self) -> usize {
+        pool_base() + self.first_page * JIT_PAGE_SIZE
+    }
+
+    fn len(&self) -> usize {
+        self.pages * JIT_PAGE_SIZE
+    }
+
+    fn set_writable(&self, writable: bool) -> bool {
+        page_table::set_kernel_text_writable(self.addr(), self.len(), writable)
+    }
+}
+
+impl Drop for ExecRegion {
+    fn drop(&mut self) {
+        *PAGE_BITMAP.lock() &= !(page_mask(self.pages) << self.first_page);
+        log::debug!("jit: freed {} pages at {:#x}", self.pages, self.addr());
+    }
+}
+
+/// JIT-compile the program held by `vm` into a fresh executable region.
+///
+/// Returns `None` if JIT is unsupported or compilation fails; the VM then
+/// keeps running the program in the interpreter.
+#[cfg(target_arch = "x86_64")]
+pub(crate) fn compile(vm: &mut EbpfVmRaw<'static>, prog_len: usize) -> Option<ExecRegion> {
+    let region = ExecRegion::alloc(image_pages(prog_len))?;
+    if !region.set_writable(true) {
+        log::warn!("jit: failed to make region {:#x} writable", region.addr());
+        return None;
+    }
+
+    // SAFETY: the pages belong exclusively to `region`, which the caller keeps
+    // alive next to `vm` for as long as the compiled image may run.
+    let mem: &'static mut [u8] =
+        unsafe { core::slice::from_raw_parts_mut(region.addr() as *mut u8, region.len()) };
+    let result = vm.set_jit_exec_memory(mem).and_then(|_| vm.jit_compile());
+
+    region.set_writable(false);
+    crate::cache::flush_icache_range(region.addr(), region.addr() + region.len());
+
+    match result {
+        Ok(()) => {
+            log::debug!("jit: compiled {} instructions", prog_len / 8);
+            Some(region)
+        }
+        Err(e) => {
+            log::warn!(
+                "jit: compilation failed, falling back to interpreter: {:?}",
+                e
+            );
+            None
+        }
+    }
+}
+
+/// JIT-compile the program held by `vm` into a fresh executable region.
+///
+/// rbpf has no JIT backend for this target, so the interpreter is used.
+#[cfg(not(target_arch = "x86_64"))]
+pub(crate) fn compile(_vm: &mut EbpfVmRaw<'static>, _prog_len: usize) -> Option<ExecRegion> {
+    log::debug!("jit: not supported on this target, using interpreter");
+    None
+}
@@ -8,6 +8,7 @@
 //! - `symbols` - Kernel symbol table lookup (default)
 //! - `tracepoint-support` - Tracepoint framework (default, requires symbols)
 //! - `runtime` - eBPF bytecode execution engine (default)
+//! - `jit` - Optional native compilation of loaded programs (x86_64 only)
 //!
 //! # Quick Start
 //!
@@ -53,13 +54,13 @@ pub mod symbols;
 // Tracepoint Module
 // =============================================================================
 
-#[cfg(feature = "tracepoint-support")]
+#[cfg(any(feature = "tracepoint-support", feature = "jit"))]
 pub mod cache;
 
 #[cfg(feature = "tracepoint-support")]
 pub mod insn_slot;
 
-#[cfg(feature = "tracepoint-support")]
+#[cfg(any(feature = "tracepoint-support", feature = "jit"))]
 pub mod page_table;
 
 #[cfg(feature = "hprobe")]
@@ -87,6 +88,9 @@ pub mod tracepoints;
 #[cfg(feature = "runtime")]
 pub mod map_ops;
 
+#[cfg(feature = "runtime")]
+pub mod map_storage;
+
 #[cfg(feature = "runtime")]
 pub mod vmap;
 
@@ -96,12 +100,39 @@ pub mod event;
 #[cfg(feature = "runtime")]
 pub mod maps;
 
+#[cfg(feature = "runtime")]
+pub mod ringbuf;
+
+#[cfg(feature = "runtime")]
+pub mod perf_event;
+
+#[cfg(feature = "runtime")]
+pub mod bloom;
+
 #[cfg(feature = "runtime")]
 pub mod helpers;
 
+#[cfg(feature = "runtime")]
+pub mod verifier;
+
+#[cfg(feature = "runtime")]
+pub mod disasm;
+
+#[cfg(feature = "runtime")]
+pub mod section;
+
+#[cfg(feature = "runtime")]
+pub mod watchdog;
+
+#[cfg(feature = "runtime")]
+pub mod stack;
+
 #[cfg(feature = "runtime")]
 pub mod runtime;
 
+#[cfg(feature = "jit")]
+pub mod jit;
+
 #[cfg(feature = "runtime")]
 pub mod attach;
 
@@ -119,7 +150,15 @@ pub mod output;
 pub use maps::{Error as MapError, MapDef, MapType, iter_entries};
 
 #[cfg(feature = "runtime")]
-pub use runtime::{EbpfProgram, Error as RuntimeError, get_program_map_fds};
+pub use runtime::{
+    EbpfProgram, Error as RuntimeError, ExecMode, ObjectLoader, get_program_map_fds,
+};
+
+#[cfg(feature = "runtime")]
+pub use verifier::VerifierError;
+
+#[cfg(feature = "runtime")]
+pub use watchdog::Budget;
 
 #[cfg(feature = "runtime")]
 pub use context::TraceContext;
@@ -128,10 +167,13 @@ pub use context::TraceContext;
 pub use programs::{PrecompiledProgram, ProgramRegistry};
 
 #[cfg(feature = "runtime")]
-pub use attach::{AttachmentInfo, is_verbose, set_verbose};
+pub use attach::{AttachmentInfo, auto_attach, is_verbose, set_verbose};
+
+#[cfg(feature = "runtime")]
+pub use section::{AttachTarget, ProgramType};
 
 #[cfg(feature = "runtime")]
-pub use output::{print_ebpf_result, print_if_verbose};
+pub use output::{format_stack_trace, print_ebpf_result, print_if_verbose, print_stack_trace};
 
 #[cfg(all(feature = "runtime", feature = "tracepoint-support"))]
 pub use event::{
@@ -179,7 +221,9 @@ pub fn init() {
     #[cfg(feature = "runtime")]
     {
         info!("  - runtime module enabled");
-        info!("    - maps: Array, HashMap, LRU, Queue, RingBuf");
+        info!(
+            "    - maps: Array, BloomFilter, HashMap, LRU, LpmTrie, PerCpuArray, PerCpuHash, PerfEventArray, Queue, RingBuf, Stack, StackTrace"
+        );
         info!(
             "    - helpers: {} standard functions",
             helpers::SUPPORTED_HELPERS.len()
@@ -248,7 +292,9 @@ pub fn init_with_symbols(kallsyms_data: &'static [u8], stext: u64, etext: u64) {
     #[cfg(feature = "runtime")]
     {
         info!("  - runtime module enabled");
-        info!("    - maps: Array, HashMap, LRU, Queue, RingBuf");
+        info!(
+            "    - maps: Array, BloomFilter, HashMap, LRU, LpmTrie, PerCpuArray, PerCpuHash, PerfEventArray, Queue, RingBuf, Stack, StackTrace"
+        );
         info!(
             "    - helpers: {} standard functions",
             helpers::SUPPORTED_HELPERS.len()
@@ -1,25 +1,61 @@
 //! kbpf-basic KernelAuxiliaryOps implementation for AxVisor.
 //!
 //! Provides the minimal kernel operations required by kbpf-basic Map types.
+//!
+//! The registry maps IDs to maps under a read-mostly lock that is only
+//! written when maps are created or destroyed. Each map has a lock of its
+//! own, so operations on different maps run in parallel.
 
 use alloc::boxed::Box;
 use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::cell::UnsafeCell;
 use core::ffi::c_void;
 use core::fmt::Debug;
+use core::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
 
 use kbpf_basic::map::{PerCpuVariants, PerCpuVariantsOps, UnifiedMap};
 use kbpf_basic::{BpfError, KernelAuxiliaryOps, Result};
-use spin::Mutex;
+use spin::{Mutex, RwLock};
+
+use crate::map_storage::MapStorage;
+use crate::maps::{MapDef, MapType};
+
+/// A map in the registry together with the type it was created as.
+///
+/// Some map types (e.g. `ProgArray`) are stored in a kbpf-basic map of a
+/// different type, so the original type is kept alongside.
+pub struct RegisteredMap {
+    /// Storage of the map's data, locked for each operation.
+    pub map: Mutex<MapStorage>,
+    /// Type requested at creation.
+    pub map_type: MapType,
+    /// Definition the map was created with, readable without the map lock.
+    pub def: MapDef,
+    /// Set by `maps::freeze`; the user side can no longer change the map.
+    /// User-side writes hold the read lock, see [`with_frozen_flag`].
+    pub frozen: RwLock<bool>,
+    /// Loaded programs using the map, see [`hold_map`].
+    pub prog_refs: AtomicUsize,
+}
 
 /// Global Map registry storing all created UnifiedMaps.
 /// Maps are accessed by index (map_fd).
-pub static MAP_REGISTRY: Mutex<Vec<Option<UnifiedMap>>> = Mutex::new(Vec::new());
+pub static MAP_REGISTRY: RwLock<Vec<Option<Arc<RegisteredMap>>>> = RwLock::new(Vec::new());
+
+/// Get the registered map `map_fd`. The registry lock is only held for the
+/// lookup; the map stays alive while the caller uses it, even if it is
+/// destroyed in the meantime.
+fn registered_map(map_fd: u32) -> Option<Arc<RegisteredMap>> {
+    let registry = MAP_REGISTRY.read();
+    registry.get(map_fd as usize)?.clone()
+}
 
 /// AxVisor implementation of KernelAuxiliaryOps.
 ///
 /// Provides minimal implementation for basic Map operations.
-/// Advanced features (RingBuf, PerCpu, Perf) return NotSupported.
+/// Perf event output returns NotSupported.
 pub struct AxKernelAuxOps;
 
 #[inline]
@@ -58,23 +94,14 @@ impl KernelAuxiliaryOps for AxKernelAuxOps {
     where
         F: FnOnce(&mut UnifiedMap) -> Result<R>,
     {
-        let mut registry = MAP_REGISTRY.lock();
-        let map = registry
-            .get_mut(map_fd as usize)
-            .ok_or(BpfError::NotFound)?
-            .as_mut()
-            .ok_or(BpfError::NotFound)?;
-        func(map)
+        let map = registered_map(map_fd).ok_or(BpfError::NotFound)?;
+        let mut storage = map.map.lock();
+        func(&mut storage.map)
     }
 
     fn get_unified_map_ptr_from_fd(map_fd: u32) -> Result<*const u8> {
-        let registry = MAP_REGISTRY.lock();
-        let map = registry
-            .get(map_fd as usize)
-            .ok_or(BpfError::NotFound)?
-            .as_ref()
-            .ok_or(BpfError::NotFound)?;
-        Ok(map as *const UnifiedMap as *const u8)
+        let map = registered_map(map_fd).ok_or(BpfError::NotFound)?;
+        Ok(Arc::as_ptr(&map) as *const u8)
     }
 
     fn copy_from_user(_src: *const u8, _size: usize, _dst: &mut [u8]) -> Result<()> {
@@ -88,7 +115,7 @@ impl KernelAuxiliaryOps for AxKernelAuxOps {
     }
 
     fn current_cpu_id() -> u32 {
-        crate::platform::cpu_id()
+        current_cpu()
     }
 
     fn perf_event_output(_ctx: *mut c_void, _fd: u32, _flags: u32, _data: &[u8]) -> Result<()> {
@@ -138,10 +165,17 @@ impl KernelAuxiliaryOps for AxKernelAuxOps {
     }
 }
 
-/// Register a new UnifiedMap in the registry.
+/// Register a new UnifiedMap, created for `def`, in the registry.
 /// Returns the map_fd (index).
-pub fn register_map(map: UnifiedMap) -> u32 {
-    let mut registry = MAP_REGISTRY.lock();
+pub fn register_map(map: UnifiedMap, def: &MapDef) -> u32 {
+    let map = Arc::new(RegisteredMap {
+        map: Mutex::new(MapStorage::new(map, def)),
+        map_type: def.map_type,
+        def: def.clone(),
+        frozen: RwLock::new(false),
+        prog_refs: AtomicUsize::new(0),
+    });
+    let mut registry = MAP_REGISTRY.write();
 
     // Find empty slot or append
     for (i, slot) in registry.iter_mut().enumerate() {
@@ -157,99 +191,274 @@ pub fn register_map(map: UnifiedMap) -> u32 {
 }
 
 /// Unregister a map from the registry.
+///
+/// Fails with `TryAgain` while a loaded program holds the map.
 pub fn unregister_map(map_fd: u32) -> Result<()> {
-    let mut registry = MAP_REGISTRY.lock();
+    let mut registry = MAP_REGISTRY.write();
     let slot = registry
         .get_mut(map_fd as usize)
         .ok_or(BpfError::NotFound)?;
-    if slot.is_none() {
+    let Some(map) = slot else {
         return Err(BpfError::NotFound);
+    };
+    if map.prog_refs.load(Ordering::Acquire) > 0 {
+        return Err(BpfError::TryAgain);
     }
     *slot = None;
     Ok(())
 }
 
+/// Take a reference on map `map_fd` for a loaded program, which keeps
+/// [`unregister_map`] from removing it. Returns false if there is no such
+/// map.
+pub(crate) fn hold_map(map_fd: u32) -> bool {
+    // The registry lock orders this against `unregister_map`
+    let registry = MAP_REGISTRY.read();
+    match registry.get(map_fd as usize) {
+        Some(Some(map)) => {
+            map.prog_refs.fetch_add(1, Ordering::AcqRel);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Drop a reference taken by [`hold_map`].
+pub(crate) fn release_map(map_fd: u32) {
+    if let Some(map) = registered_map(map_fd) {
+        map.prog_refs.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
 /// Get the number of active maps in the registry.
 pub fn map_count() -> usize {
-    let registry = MAP_REGISTRY.lock();
+    let registry = MAP_REGISTRY.read();
     registry.iter().filter(|s| s.is_some()).count()
 }
 
 /// Get Map metadata (key_size, value_size) by FD.
 pub fn get_map_sizes(map_fd: u32) -> Option<(u32, u32)> {
-    let registry = MAP_REGISTRY.lock();
-    let map = registry.get(map_fd as usize)?.as_ref()?;
-    let meta = map.map_meta();
-    Some((meta.key_size, meta.value_size))
+    get_map_def(map_fd).map(|def| (def.key_size, def.value_size))
+}
+
+/// Get the definition a map was created with.
+pub fn get_map_def(map_fd: u32) -> Option<MapDef> {
+    Some(registered_map(map_fd)?.def.clone())
+}
+
+/// Get the type a map was created as.
+pub fn get_map_type(map_fd: u32) -> Option<MapType> {
+    Some(registered_map(map_fd)?.map_type)
+}
+
+/// Freeze map `map_fd`. Returns false if there is no such map.
+///
+/// Waits for the writes running under [`with_frozen_flag`], so none of
+/// them changes the map after this returns.
+pub(crate) fn freeze_map(map_fd: u32) -> bool {
+    let Some(map) = registered_map(map_fd) else {
+        return false;
+    };
+    *map.frozen.write() = true;
+    true
+}
+
+/// Whether map `map_fd` has been frozen.
+pub fn is_map_frozen(map_fd: u32) -> bool {
+    registered_map(map_fd).is_some_and(|map| *map.frozen.read())
+}
+
+/// Run `f` with the frozen flag of map `map_fd`, which cannot change until
+/// `f` returns. `f` gets false if there is no such map.
+pub(crate) fn with_frozen_flag<R>(map_fd: u32, f: impl FnOnce(bool) -> R) -> R {
+    match registered_map(map_fd) {
+        Some(map) => {
+            let frozen = map.frozen.read();
+            f(*frozen)
+        }
+        None => f(false),
+    }
+}
+
+/// Run `f` on map `map_fd` with the map locked, so that several operations
+/// on the map see no changes from other CPUs in between.
+pub(crate) fn with_locked_map<R>(map_fd: u32, f: impl FnOnce(&mut MapStorage) -> R) -> Option<R> {
+    let map = registered_map(map_fd)?;
+    let mut storage = map.map.lock();
+    Some(f(&mut storage))
 }
 
 /// Iterate all keys in a map.
 ///
+/// Per-CPU hash maps keep a key set per CPU; their keys are the union.
+///
 /// # Arguments
 /// * `map_fd` - Map ID returned by create().
 ///
 /// # Returns
 /// Vector of key byte arrays.
 pub fn iter_map_keys(map_fd: u32) -> Vec<Vec<u8>> {
-    let mut keys = Vec::new();
-
     // Get map metadata to know key size
-    let key_size = match get_map_sizes(map_fd) {
-        Some((ks, _)) => ks as usize,
-        None => return keys,
+    let Some(def) = get_map_def(map_fd) else {
+        return Vec::new();
     };
+    with_locked_map(map_fd, |store| {
+        map_keys(&mut store.map, def.map_type, def.key_size, None, usize::MAX)
+    })
+    .unwrap_or_default()
+}
 
-    let mut registry = MAP_REGISTRY.lock();
-    let map = match registry.get_mut(map_fd as usize) {
-        Some(Some(m)) => m,
-        _ => return keys,
-    };
+/// Up to `max` keys of a locked map, in `get_next_key` order, starting
+/// after `start` (at the first key if None).
+///
+/// Per-CPU hash maps list the keys of CPU 0 first, then those only other
+/// CPUs hold.
+pub(crate) fn map_keys(
+    map: &mut UnifiedMap,
+    map_type: MapType,
+    key_size: u32,
+    start: Option<&[u8]>,
+    max: usize,
+) -> Vec<Vec<u8>> {
+    if map_type != MapType::PerCpuHash {
+        return walk_keys(map, key_size, start, max);
+    }
 
-    // Start with None to get first key
-    let mut current_key: Option<Vec<u8>> = None;
-    let mut next_key_buf = alloc::vec![0u8; key_size];
-
-    loop {
-        let result = match &current_key {
-            None => map.map_mut().get_next_key(None, &mut next_key_buf),
-            Some(key) => map
-                .map_mut()
-                .get_next_key(Some(key.as_slice()), &mut next_key_buf),
-        };
-
-        match result {
-            Ok(()) => {
-                let next_key = next_key_buf.clone();
-                keys.push(next_key.clone());
-                current_key = Some(next_key);
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    for cpu in 0..crate::platform::num_cpus() {
+        let cpu_keys = with_cpu_values(cpu, || walk_keys(map, key_size, None, usize::MAX));
+        for key in cpu_keys {
+            if cpu == 0 || !keys.contains(&key) {
+                keys.push(key);
             }
-            Err(_) => break,
         }
     }
+    let skip = start
+        .and_then(|start| keys.iter().position(|key| key == start))
+        .map_or(0, |i| i + 1);
+    keys.into_iter().skip(skip).take(max).collect()
+}
 
+/// Follow `get_next_key` from `start` for up to `max` keys.
+fn walk_keys(
+    map: &mut UnifiedMap,
+    key_size: u32,
+    start: Option<&[u8]>,
+    max: usize,
+) -> Vec<Vec<u8>> {
+    let mut keys: Vec<Vec<u8>> = Vec::new();
+    let mut next_key_buf = alloc::vec![0u8; key_size as usize];
+    let mut current_key = start.map(<[u8]>::to_vec);
+    while keys.len() < max
+        && map
+            .map_mut()
+            .get_next_key(current_key.as_deref(), &mut next_key_buf)
+            .is_ok()
+    {
+        keys.push(next_key_buf.clone());
+        current_key = Some(next_key_buf.clone());
+    }
     keys
 }
 
 // =============================================================================
-// PerCpuVariantsOps Placeholder Implementation
+// Per-CPU Storage
 // =============================================================================
 
-/// Dummy PerCpu implementation.
+/// Redirect target meaning "no redirect".
+const NO_REDIRECT: u32 = u32::MAX;
+
+/// CPU whose per-CPU values are used on each CPU, see [`with_cpu_values`].
+static CPU_REDIRECT: [AtomicU32; crate::platform::MAX_CPUS as usize] =
+    [const { AtomicU32::new(NO_REDIRECT) }; crate::platform::MAX_CPUS as usize];
+
+/// CPU whose per-CPU map values the current CPU accesses.
+pub(crate) fn current_cpu() -> u32 {
+    let cpu = crate::platform::cpu_id();
+    match CPU_REDIRECT.get(cpu as usize) {
+        Some(redirect) => match redirect.load(Ordering::Relaxed) {
+            NO_REDIRECT => cpu,
+            target => target,
+        },
+        None => cpu,
+    }
+}
+
+/// Run `f` with per-CPU maps on this CPU using the values of `cpu`.
 ///
-/// Returns None for all create calls, effectively disabling PerCpu Map types.
-/// This is acceptable for Phase 1 as we only support basic Map types.
-#[derive(Debug)]
-pub struct DummyPerCpuOps;
+/// User-side operations use this to read and write the values of other
+/// CPUs through kbpf-basic maps, which only access the current CPU's.
+pub(crate) fn with_cpu_values<R>(cpu: u32, f: impl FnOnce() -> R) -> R {
+    let Some(redirect) = CPU_REDIRECT.get(crate::platform::cpu_id() as usize) else {
+        return f();
+    };
+    let previous = redirect.swap(cpu, Ordering::Relaxed);
+    let result = f();
+    redirect.store(previous, Ordering::Relaxed);
+    result
+}
+
+/// One value per CPU.
+///
+/// Each CPU only touches its own value while a program runs, so the values
+/// need no lock. User-side reads of other CPUs' values may see a value that
+/// is being updated, as in Linux.
+pub struct PerCpuData<T> {
+    values: Box<[UnsafeCell<T>]>,
+}
+
+// SAFETY: values are only accessed through `PerCpuVariants`, whose callers
+// keep accesses to one CPU's value from overlapping (see above).
+unsafe impl<T: Send> Send for PerCpuData<T> {}
+unsafe impl<T: Send + Sync> Sync for PerCpuData<T> {}
 
-impl PerCpuVariantsOps for DummyPerCpuOps {
-    fn create<T: Clone + Sync + Send + 'static>(_value: T) -> Option<Box<dyn PerCpuVariants<T>>> {
-        // PerCpu Maps not supported in this phase
-        None
+impl<T> PerCpuData<T> {
+    fn slot(&self, cpu: u32) -> *mut T {
+        self.values[cpu as usize % self.values.len()].get()
+    }
+}
+
+impl<T> Debug for PerCpuData<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("PerCpuData")
+            .field("cpus", &self.values.len())
+            .finish()
+    }
+}
+
+impl<T: Clone + Sync + Send> PerCpuVariants<T> for PerCpuData<T> {
+    fn get(&self) -> &T {
+        unsafe { &*self.slot(current_cpu()) }
+    }
+
+    fn get_mut(&self) -> &mut T {
+        unsafe { &mut *self.slot(current_cpu()) }
+    }
+
+    unsafe fn force_get(&self, cpu: u32) -> &T {
+        unsafe { &*self.slot(cpu) }
+    }
+
+    unsafe fn force_get_mut(&self, cpu: u32) -> &mut T {
+        unsafe { &mut *self.slot(cpu) }
+    }
+}
+
+/// Per-CPU storage for kbpf-basic's per-CPU map types, with one value per
+/// CPU up to `platform::num_cpus`.
+#[derive(Debug)]
+pub struct PerCpuOps;
+
+impl PerCpuVariantsOps for PerCpuOps {
+    fn create<T: Clone + Sync + Send + 'static>(value: T) -> Option<Box<dyn PerCpuVariants<T>>> {
+        let values = (0..Self::num_cpus())
+            .map(|_| UnsafeCell::new(value.clone()))
+            .collect();
+        Some(Box::new(PerCpuData { values }))
     }
 
     fn num_cpus() -> u32 {
-        // Return 1 as fallback
-        1
+        crate::platform::num_cpus()
     }
 }
 
@@ -257,8 +466,7 @@ impl PerCpuVariantsOps for DummyPerCpuOps {
 // PollWaker for RingBuf
 // =============================================================================
 
-use alloc::sync::Arc;
-use core::sync::atomic::{AtomicBool, Ordering};
+use core::sync::atomic::AtomicBool;
 use kbpf_basic::PollWaker;
 
 /// Simple PollWaker that sets an atomic flag when data is available.
@@ -0,0 +1,313 @@
+//! Storage of registered maps.
+//!
+//! `bpf_map_lookup_elem` gives programs a pointer to the value in the map,
+//! which they may update in place, so values need a fixed address that the
+//! VM can allow when the program is loaded. kbpf-basic array maps allocate
+//! their values up front. Its hash maps allocate a value on each insert, so
+//! for `HashMap`, `LruHash` and `PerCpuHash` maps the kbpf-basic map only
+//! stores a slot number per key, and the values live in slots allocated
+//! here when the map is created, as in Linux's preallocated hash maps.
+//!
+//! A deleted key's slot is reused by a later insert; a program still
+//! holding a pointer to it then sees the new value, as in Linux.
+//!
+//! LRU hash maps are plain kbpf-basic hash maps here: when all slots are
+//! used, an insert evicts the key least recently looked up or updated.
+
+use alloc::boxed::Box;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use kbpf_basic::map::UnifiedMap;
+
+use crate::maps::{Error, MapDef, MapType};
+
+/// `update_elem` flags: create or update.
+const BPF_ANY: u64 = 0;
+/// `update_elem` flags: create only.
+const BPF_NOEXIST: u64 = 1;
+/// `update_elem` flags: update only.
+const BPF_EXIST: u64 = 2;
+
+/// Whether maps of `map_type` keep their values in slots allocated here.
+pub(crate) fn has_value_slots(map_type: MapType) -> bool {
+    matches!(
+        map_type,
+        MapType::HashMap | MapType::LruHash | MapType::PerCpuHash
+    )
+}
+
+/// Size of the values the kbpf-basic map stores: slot numbers for maps with
+/// value slots.
+pub(crate) fn stored_value_size(def: &MapDef) -> u32 {
+    if has_value_slots(def.map_type) {
+        4
+    } else {
+        def.value_size
+    }
+}
+
+/// The data of a registered map: its kbpf-basic map and, for hash maps, the
+/// value slots the kbpf-basic map refers to.
+pub struct MapStorage {
+    /// kbpf-basic map. Maps with value slots store slot numbers in it.
+    pub(crate) map: UnifiedMap,
+    slots: Option<ValueSlots>,
+}
+
+impl MapStorage {
+    pub(crate) fn new(map: UnifiedMap, def: &MapDef) -> Self {
+        Self {
+            map,
+            slots: has_value_slots(def.map_type).then(|| ValueSlots::new(def)),
+        }
+    }
+
+    /// Value of `key`, the current CPU's for per-CPU maps.
+    pub(crate) fn lookup(&mut self, key: &[u8]) -> Result<Option<&[u8]>, Error> {
+        let Some(slots) = &mut self.slots else {
+            return Ok(self.map.map_mut().lookup_elem(key)?);
+        };
+        let Some(slot) = self.map.map_mut().lookup_elem(key)?.and_then(slot_of) else {
+            return Ok(None);
+        };
+        slots.touch(slot);
+        Ok(Some(slots.value_mut(slot)))
+    }
+
+    /// Store `value` under `key`, the current CPU's value for per-CPU maps.
+    /// The slot of an existing key is updated in place. Like kbpf-basic
+    /// maps, shorter values are accepted and zero-padded.
+    pub(crate) fn update(&mut self, key: &[u8], value: &[u8], flags: u64) -> Result<(), Error> {
+        let Some(slots) = &mut self.slots else {
+            return Ok(self.map.map_mut().update_elem(key, value, flags)?);
+        };
+        if value.len() > slots.value_size || flags > BPF_EXIST {
+            return Err(Error::InvalidArgument);
+        }
+        let slot = match self.map.map_mut().lookup_elem(key)?.and_then(slot_of) {
+            Some(_) if flags == BPF_NOEXIST => return Err(Error::KeyExists),
+            Some(slot) => slot,
+            None if flags == BPF_EXIST => return Err(Error::KeyNotFound),
+            None => {
+                let slot = match slots.alloc() {
+                    Some(slot) => slot,
+                    None => {
+                        let (slot, old_key) = slots.evict().ok_or(Error::NoSpace)?;
+                        if let Err(e) = self.map.map_mut().delete_elem(&old_key) {
+                            // The old key still owns the slot
+                            slots.restore(slot, old_key);
+                            return Err(e.into());
+                        }
+                        slot
+                    }
+                };
+                let stored = slot.to_le_bytes();
+                if let Err(e) = self.map.map_mut().update_elem(key, &stored, BPF_ANY) {
+                    slots.release(slot);
+                    return Err(e.into());
+                }
+                slot
+            }
+        };
+        slots.store(slot, key, value);
+        Ok(())
+    }
+
+    /// Delete `key`, on the current CPU for per-CPU maps.
+    pub(crate) fn delete(&mut self, key: &[u8]) -> Result<(), Error> {
+        let Some(slots) = &mut self.slots else {
+            return Ok(self.map.map_mut().delete_elem(key)?);
+        };
+        let slot = self.map.map_mut().lookup_elem(key)?.and_then(slot_of);
+        self.map.map_mut().delete_elem(key)?;
+        if let Some(slot) = slot {
+            slots.release(slot);
+        }
+        Ok(())
+    }
+
+    /// Address range of the value slots, of all CPUs for per-CPU maps.
+    pub(crate) fn slots_range(&self) -> Option<Range<u64>> {
+        self.slots.as_ref().map(ValueSlots::range)
+    }
+}
+
+fn slot_of(stored: &[u8]) -> Option<u32> {
+    stored.try_into().ok().map(u32::from_le_bytes)
+}
+
+/// Preallocated values of a hash map.
+struct ValueSlots {
+    /// Values of all slots, CPU after CPU for per-CPU maps. Stored as u64
+    /// so values are 8-byte aligned.
+    data: Box<[u64]>,
+    /// Bytes from one slot to the next: the value size rounded up to 8.
+    stride: usize,
+    value_size: usize,
+    /// Slots per CPU.
+    slots: u32,
+    /// CPUs with slots of their own, 1 unless the map is per-CPU.
+    cpus: u32,
+    /// Unused slots of each CPU.
+    free: Vec<Vec<u32>>,
+    /// Eviction state of LRU maps.
+    lru: Option<LruSlots>,
+}
+
+/// End of the list of an LRU map's used slots.
+const NO_SLOT: u32 = u32::MAX;
+
+/// Key of each slot of an LRU map, and the used slots in order of last use,
+/// linked through their indices so evicting and touching are O(1).
+struct LruSlots {
+    keys: Vec<Vec<u8>>,
+    /// Next more recently used slot of each slot.
+    newer: Vec<u32>,
+    /// Next less recently used slot of each slot.
+    older: Vec<u32>,
+    /// Most recently used slot.
+    newest: u32,
+    /// Least recently used slot, the next to be evicted.
+    oldest: u32,
+}
+
+impl LruSlots {
+    fn new(slots: u32) -> Self {
+        Self {
+            keys: vec![Vec::new(); slots as usize],
+            newer: vec![NO_SLOT; slots as usize],
+            older: vec![NO_SLOT; slots as usize],
+            newest: NO_SLOT,
+            oldest: NO_SLOT,
+        }
+    }
+
+    fn is_linked(&self, slot: u32) -> bool {
+        self.newest == slot || self.newer[slot as usize] != NO_SLOT
+    }
+
+    /// Take `slot` out of the list, if it is in it.
+    fn unlink(&mut self, slot: u32) {
+        if !self.is_linked(slot) {
+            return;
+        }
+        let newer = self.newer[slot as usize];
+        let older = self.older[slot as usize];
+        match newer {
+            NO_SLOT => self.newest = older,
+            newer => self.older[newer as usize] = older,
+        }
+        match older {
+            NO_SLOT => self.oldest = newer,
+            older => self.newer[older as usize] = newer,
+        }
+        self.newer[slot as usize] = NO_SLOT;
+        self.older[slot as usize] = NO_SLOT;
+    }
+
+    /// Make `slot` the most recently used.
+    fn push_newest(&mut self, slot: u32) {
+        self.unlink(slot);
+        self.older[slot as usize] = self.newest;
+        match self.newest {
+            NO_SLOT => self.oldest = slot,
+            newest => self.newer[newest as usize] = slot,
+        }
+        self.newest = slot;
+    }
+}
+
+impl ValueSlots {
+    fn new(def: &MapDef) -> Self {
+        let cpus = match def.map_type {
+            MapType::PerCpuHash => crate::platform::num_cpus(),
+            _ => 1,
+        };
+        let stride = (def.value_size as usize).next_multiple_of(8);
+        let slots = def.max_entries;
+        let words = cpus as usize * slots as usize * stride / 8;
+        Self {
+            data: vec![0u64; words].into_boxed_slice(),
+            stride,
+            value_size: def.value_size as usize,
+            slots,
+            cpus,
+            // Reversed so slot 0 is used first
+            free: (0..cpus).map(|_| (0..slots).rev().collect()).collect(),
+            lru: (def.map_type == MapType::LruHash).then(|| LruSlots::new(slots)),
+        }
+    }
+
+    /// CPU whose slots the current access uses.
+    fn cpu(&self) -> usize {
+        crate::map_ops::current_cpu() as usize % self.cpus as usize
+    }
+
+    fn value_mut(&mut self, slot: u32) -> &mut [u8] {
+        let start = (self.cpu() * self.slots as usize + slot as usize) * self.stride;
+        // SAFETY: the u64s of `data` are initialized, and any bytes are
+        // valid u8s.
+        let bytes = unsafe {
+            core::slice::from_raw_parts_mut(self.data.as_mut_ptr() as *mut u8, self.data.len() * 8)
+        };
+        &mut bytes[start..start + self.value_size]
+    }
+
+    fn range(&self) -> Range<u64> {
+        let start = self.data.as_ptr() as u64;
+        start..start + self.data.len() as u64 * 8
+    }
+
+    fn alloc(&mut self) -> Option<u32> {
+        let cpu = self.cpu();
+        self.free[cpu].pop()
+    }
+
+    fn release(&mut self, slot: u32) {
+        if let Some(lru) = &mut self.lru {
+            lru.unlink(slot);
+        }
+        let cpu = self.cpu();
+        self.free[cpu].push(slot);
+    }
+
+    /// Take the least recently used slot of a full LRU map, together with
+    /// the key stored in it.
+    fn evict(&mut self) -> Option<(u32, Vec<u8>)> {
+        let lru = self.lru.as_mut()?;
+        let slot = lru.oldest;
+        if slot == NO_SLOT {
+            return None;
+        }
+        lru.unlink(slot);
+        Some((slot, core::mem::take(&mut lru.keys[slot as usize])))
+    }
+
+    /// Give an evicted slot back to `key`, whose entry could not be removed.
+    fn restore(&mut self, slot: u32, key: Vec<u8>) {
+        if let Some(lru) = &mut self.lru {
+            lru.keys[slot as usize] = key;
+            lru.push_newest(slot);
+        }
+    }
+
+    fn touch(&mut self, slot: u32) {
+        if let Some(lru) = &mut self.lru {
+            lru.push_newest(slot);
+        }
+    }
+
+    /// Write `value` to `slot`, zeroing the rest of the value, and record
+    /// `key` as its owner for eviction.
+    fn store(&mut self, slot: u32, key: &[u8], value: &[u8]) {
+        let dst = self.value_mut(slot);
+        dst[..value.len()].copy_from_slice(value);
+        dst[value.len()..].fill(0);
+        if let Some(lru) = &mut self.lru {
+            lru.keys[slot as usize] = key.to_vec();
+        }
+        self.touch(slot);
+    }
+}
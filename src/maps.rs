@@ -3,13 +3,19 @@
 //! Wraps kbpf-basic to provide Map storage for eBPF programs.
 //! API remains compatible with the previous simplified implementation.
 
+use alloc::vec;
 use alloc::vec::Vec;
 
 use kbpf_basic::linux_bpf::BpfMapType;
 use kbpf_basic::map::{BpfMapMeta, UnifiedMap, bpf_map_create};
 use kbpf_basic::{BpfError, KernelAuxiliaryOps};
 
-use crate::map_ops::{AxKernelAuxOps, DummyPerCpuOps, map_count, register_map, unregister_map};
+use crate::map_ops::{
+    AxKernelAuxOps, PerCpuOps, freeze_map, get_map_def, get_map_sizes, get_map_type, is_map_frozen,
+    map_count, map_keys, register_map, unregister_map, with_cpu_values, with_frozen_flag,
+    with_locked_map,
+};
+use crate::map_storage::{MapStorage, stored_value_size};
 
 /// Map type enumeration.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -20,14 +26,62 @@ pub enum MapType {
     HashMap,
     /// Hash table with LRU eviction.
     LruHash,
-    /// FIFO queue.
+    /// FIFO queue (key_size=0), see [`push_elem`] and [`pop_elem`].
     Queue,
+    /// LIFO stack (key_size=0), see [`MapType::Queue`].
+    Stack,
     /// Ring buffer for event streaming (key_size=0, value_size=0).
+    ///
+    /// `max_entries` is the ring size in bytes, a power of two of at least
+    /// 4096. Programs write records with the `bpf_ringbuf_*` helpers, see
+    /// [`ringbuf_consume`].
     RingBuf,
+    /// Program array for `bpf_tail_call` (key_size=4, value_size=4).
+    ///
+    /// Values are program IDs from `runtime::load_program`.
+    ProgArray,
+    /// Array with one value per CPU.
+    ///
+    /// Programs see the value of the CPU they run on; user-side lookups and
+    /// updates take all values, see [`percpu_value_stride`].
+    PerCpuArray,
+    /// Hash table with one value per CPU, see [`MapType::PerCpuArray`].
+    PerCpuHash,
+    /// Stack traces recorded by `bpf_get_stackid`.
+    ///
+    /// Keys are u32 stack IDs, values `value_size / 8` return addresses,
+    /// innermost first and zero-padded. Programs get lookups as a copy in
+    /// the helpers' lookup buffer, so `value_size` is at most
+    /// [`MAX_VALUE_SIZE`](crate::helpers::MAX_VALUE_SIZE) (64 frames). Only
+    /// programs store traces; the user side can look them up and delete
+    /// them.
+    StackTrace,
+    /// Longest-prefix-match trie.
+    ///
+    /// Keys are a u32 prefix length in bits followed by `key_size - 4`
+    /// bytes of data, see [`lpm_trie_key`]. Lookups return the value of the
+    /// longest stored prefix of the key; programs get it as a copy in the
+    /// helpers' lookup buffer, so `value_size` is at most
+    /// [`MAX_VALUE_SIZE`](crate::helpers::MAX_VALUE_SIZE).
+    LpmTrie,
+    /// Perf event array for `bpf_perf_event_output` (key_size=4,
+    /// value_size=4).
+    ///
+    /// Holds one perf ring per CPU; `max_entries` is the CPU count (0 =
+    /// `platform::num_cpus()`). Slots cannot be read or written, see
+    /// [`perf_event_read`].
+    PerfEventArray,
+    /// Bloom filter over values (key_size=0), sized for `max_entries`
+    /// values.
+    ///
+    /// Values are added with [`push_elem`] and tested with
+    /// [`bloom_filter_contains`]; tests can give false positives but never
+    /// false negatives.
+    BloomFilter,
 }
 
 /// Map definition for creating new maps.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct MapDef {
     /// Type of map.
     pub map_type: MapType,
@@ -37,8 +91,20 @@ pub struct MapDef {
     pub value_size: u32,
     /// Maximum number of entries.
     pub max_entries: u32,
+    /// `BPF_F_*` map flags, 0 for none.
+    pub map_flags: u32,
 }
 
+/// Map flag: allocate hash map elements on insert.
+///
+/// Accepted for HashMap, PerCpuHash and LpmTrie maps. Hash map values stay
+/// preallocated, since `bpf_map_lookup_elem` returns pointers into them.
+pub const BPF_F_NO_PREALLOC: u32 = 1 << 0;
+/// Map flag: programs can read the map but not change it.
+pub const BPF_F_RDONLY_PROG: u32 = 1 << 7;
+/// Map flag: programs can change the map but not read it.
+pub const BPF_F_WRONLY_PROG: u32 = 1 << 8;
+
 /// Error types for map operations.
 #[derive(Debug, Clone)]
 pub enum Error {
@@ -52,6 +118,12 @@ pub enum Error {
     InvalidArgument,
     /// Map type not supported.
     NotSupported,
+    /// Key already holds a different value.
+    KeyExists,
+    /// Map flags or a freeze forbid the operation.
+    PermissionDenied,
+    /// The map is used by a loaded program.
+    Busy,
 }
 
 impl core::fmt::Display for Error {
@@ -62,6 +134,9 @@ impl core::fmt::Display for Error {
             Self::NoSpace => write!(f, "Map is full"),
             Self::InvalidArgument => write!(f, "Invalid argument"),
             Self::NotSupported => write!(f, "Map type not supported"),
+            Self::KeyExists => write!(f, "Key already exists"),
+            Self::PermissionDenied => write!(f, "Operation not permitted on map"),
+            Self::Busy => write!(f, "Map is used by a loaded program"),
         }
     }
 }
@@ -86,19 +161,46 @@ fn to_bpf_map_type(map_type: MapType) -> BpfMapType {
     match map_type {
         MapType::Array => BpfMapType::BPF_MAP_TYPE_ARRAY,
         MapType::HashMap => BpfMapType::BPF_MAP_TYPE_HASH,
-        MapType::LruHash => BpfMapType::BPF_MAP_TYPE_LRU_HASH,
+        // Evicted by `crate::map_storage`, which holds the values
+        MapType::LruHash => BpfMapType::BPF_MAP_TYPE_HASH,
         MapType::Queue => BpfMapType::BPF_MAP_TYPE_QUEUE,
-        MapType::RingBuf => BpfMapType::BPF_MAP_TYPE_RINGBUF,
+        MapType::Stack => BpfMapType::BPF_MAP_TYPE_STACK,
+        // Placeholder for the registry; records live in `crate::ringbuf`
+        MapType::RingBuf => BpfMapType::BPF_MAP_TYPE_ARRAY,
+        // Stored as an array of encoded program IDs, see `encode_prog_id`
+        MapType::ProgArray => BpfMapType::BPF_MAP_TYPE_ARRAY,
+        MapType::PerCpuArray => BpfMapType::BPF_MAP_TYPE_PERCPU_ARRAY,
+        MapType::PerCpuHash => BpfMapType::BPF_MAP_TYPE_PERCPU_HASH,
+        // Keyed by stack ID, see `stack_map_store`
+        MapType::StackTrace => BpfMapType::BPF_MAP_TYPE_HASH,
+        MapType::LpmTrie => BpfMapType::BPF_MAP_TYPE_LPM_TRIE,
+        // Placeholder for the registry; rings live in `crate::perf_event`
+        MapType::PerfEventArray => BpfMapType::BPF_MAP_TYPE_ARRAY,
+        // Placeholder for the registry; bits live in `crate::bloom`
+        MapType::BloomFilter => BpfMapType::BPF_MAP_TYPE_ARRAY,
     }
 }
 
 /// Convert MapDef to kbpf-basic BpfMapMeta.
 fn to_bpf_map_meta(def: &MapDef) -> BpfMapMeta {
+    if matches!(def.map_type, MapType::RingBuf | MapType::BloomFilter) {
+        return BpfMapMeta {
+            map_type: BpfMapType::BPF_MAP_TYPE_ARRAY,
+            key_size: 4,
+            value_size: 8,
+            max_entries: 1,
+            ..Default::default()
+        };
+    }
     BpfMapMeta {
         map_type: to_bpf_map_type(def.map_type),
         key_size: def.key_size,
-        value_size: def.value_size,
-        max_entries: def.max_entries,
+        value_size: stored_value_size(def),
+        // Stack IDs are hashes masked to the bucket count
+        max_entries: match def.map_type {
+            MapType::StackTrace => def.max_entries.next_power_of_two(),
+            _ => def.max_entries,
+        },
         ..Default::default()
     }
 }
@@ -111,23 +213,82 @@ fn to_bpf_map_meta(def: &MapDef) -> BpfMapMeta {
 /// # Returns
 /// Map ID on success.
 pub fn create(def: &MapDef) -> Result<u32, Error> {
-    let meta = to_bpf_map_meta(def);
-
-    // RingBuf requires a PollWaker
-    let poll_waker = if def.map_type == MapType::RingBuf {
-        Some(crate::map_ops::TracePollWaker::new() as alloc::sync::Arc<dyn kbpf_basic::PollWaker>)
-    } else {
-        None
+    check_map_flags(def)?;
+    if def.map_type == MapType::ProgArray && (def.key_size != 4 || def.value_size != 4) {
+        return Err(Error::InvalidArgument);
+    }
+    if def.map_type == MapType::StackTrace
+        && (def.key_size != 4
+            || def.value_size % 8 != 0
+            || !(1..=crate::stack::MAX_STACK_DEPTH).contains(&(def.value_size / 8))
+            || def.value_size as usize > crate::helpers::MAX_VALUE_SIZE
+            || def.max_entries == 0
+            || def.max_entries > 1 << 31)
+    {
+        return Err(Error::InvalidArgument);
+    }
+    if def.map_type == MapType::LpmTrie
+        && (!(LPM_KEY_PREFIX + 1..=LPM_KEY_PREFIX + LPM_MAX_DATA_SIZE).contains(&def.key_size)
+            || def.value_size as usize > crate::helpers::MAX_VALUE_SIZE)
+    {
+        return Err(Error::InvalidArgument);
+    }
+    if def.map_type == MapType::RingBuf && (def.key_size != 0 || def.value_size != 0) {
+        return Err(Error::InvalidArgument);
+    }
+    if def.map_type == MapType::BloomFilter
+        && (def.key_size != 0 || def.value_size == 0 || def.max_entries == 0)
+    {
+        return Err(Error::InvalidArgument);
+    }
+    let def = &match def.map_type {
+        MapType::PerfEventArray => perf_event_array_def(def)?,
+        _ => def.clone(),
     };
+    let meta = to_bpf_map_meta(def);
 
     let unified_map =
-        bpf_map_create::<AxKernelAuxOps, DummyPerCpuOps>(meta, poll_waker).map_err(Error::from)?;
+        bpf_map_create::<AxKernelAuxOps, PerCpuOps>(meta, None).map_err(Error::from)?;
 
-    let id = register_map(unified_map);
+    let id = register_map(unified_map, def);
+    if def.map_type == MapType::RingBuf
+        && let Err(e) = crate::ringbuf::create(id, def.max_entries)
+    {
+        let _ = unregister_map(id);
+        return Err(e);
+    }
+    if def.map_type == MapType::PerfEventArray {
+        crate::perf_event::create(id, def.max_entries);
+    }
+    if def.map_type == MapType::BloomFilter {
+        crate::bloom::create(id, def.value_size, def.max_entries);
+    }
     log::debug!("Created map {} with type {:?}", id, def.map_type);
     Ok(id)
 }
 
+/// Check that the `map_flags` of `def` are known and apply to its map type,
+/// as Linux does.
+fn check_map_flags(def: &MapDef) -> Result<(), Error> {
+    let prog_access = BPF_F_RDONLY_PROG | BPF_F_WRONLY_PROG;
+    let allowed = match def.map_type {
+        MapType::HashMap | MapType::PerCpuHash | MapType::LpmTrie => {
+            BPF_F_NO_PREALLOC | prog_access
+        }
+        MapType::Array
+        | MapType::PerCpuArray
+        | MapType::LruHash
+        | MapType::Queue
+        | MapType::Stack
+        | MapType::BloomFilter => prog_access,
+        MapType::RingBuf | MapType::ProgArray | MapType::PerfEventArray | MapType::StackTrace => 0,
+    };
+    if def.map_flags & !allowed != 0 || def.map_flags & prog_access == prog_access {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(())
+}
+
 /// Lookup an element in a map.
 ///
 /// # Arguments
@@ -135,14 +296,46 @@ pub fn create(def: &MapDef) -> Result<u32, Error> {
 /// * `key` - Key bytes.
 ///
 /// # Returns
-/// Value bytes if found.
+/// Value bytes if found. Per-CPU maps return the values of all CPUs.
 pub fn lookup_elem(map_id: u32, key: &[u8]) -> Option<Vec<u8>> {
+    match get_map_type(map_id)? {
+        MapType::ProgArray => prog_array_get(map_id, key).map(|id| id.to_le_bytes().to_vec()),
+        MapType::RingBuf | MapType::PerfEventArray | MapType::BloomFilter => None,
+        map_type if is_percpu(map_type) => percpu_lookup(map_id, key),
+        _ => lookup_raw(map_id, key),
+    }
+}
+
+fn lookup_raw(map_id: u32, key: &[u8]) -> Option<Vec<u8>> {
+    with_locked_map(map_id, |store| {
+        store.lookup(key).ok().flatten().map(<[u8]>::to_vec)
+    })
+    .flatten()
+}
+
+/// Address range of a value stored in an array map.
+///
+/// Array values are preallocated when the map is created, so the range stays
+/// valid until the map is destroyed. Used to resolve global variable
+/// accesses to direct pointers.
+///
+/// # Arguments
+/// * `map_id` - Map ID of an Array map.
+/// * `index` - Array index.
+///
+/// # Returns
+/// Address range of the value, or None if the map is not an Array or the
+/// index is out of bounds.
+pub fn array_value_range(map_id: u32, index: u32) -> Option<core::ops::Range<u64>> {
+    if get_map_type(map_id) != Some(MapType::Array) {
+        return None;
+    }
     AxKernelAuxOps::get_unified_map_from_fd(map_id, |unified_map: &mut UnifiedMap| {
-        let result = unified_map.map_mut().lookup_elem(key)?;
-        match result {
-            Some(value) => Ok(Some(value.to_vec())),
-            None => Ok(None),
-        }
+        let value = unified_map.map_mut().lookup_elem(&index.to_le_bytes())?;
+        Ok(value.map(|v| {
+            let start = v.as_ptr() as u64;
+            start..start + v.len() as u64
+        }))
     })
     .ok()
     .flatten()
@@ -153,13 +346,40 @@ pub fn lookup_elem(map_id: u32, key: &[u8]) -> Option<Vec<u8>> {
 /// # Arguments
 /// * `map_id` - Map ID.
 /// * `key` - Key bytes.
-/// * `value` - Value bytes.
+/// * `value` - Value bytes (a little-endian program ID for ProgArray maps,
+///   the values of all CPUs for per-CPU maps).
 /// * `flags` - Update flags (0 = create or update).
 pub fn update_elem(map_id: u32, key: &[u8], value: &[u8], flags: u64) -> Result<(), Error> {
-    AxKernelAuxOps::get_unified_map_from_fd(map_id, |unified_map: &mut UnifiedMap| {
-        unified_map.map_mut().update_elem(key, value, flags)
-    })
-    .map_err(Error::from)
+    unless_frozen(map_id, || do_update_elem(map_id, key, value, flags))
+}
+
+fn do_update_elem(map_id: u32, key: &[u8], value: &[u8], flags: u64) -> Result<(), Error> {
+    let map_type = get_map_type(map_id);
+    if matches!(
+        map_type,
+        Some(
+            MapType::StackTrace | MapType::RingBuf | MapType::PerfEventArray | MapType::BloomFilter
+        )
+    ) {
+        return Err(Error::InvalidArgument);
+    }
+    if map_type.is_some_and(is_percpu) {
+        return percpu_update(map_id, key, value, flags);
+    }
+    if map_type == Some(MapType::ProgArray) {
+        let prog_id: [u8; 4] = value.try_into().map_err(|_| Error::InvalidArgument)?;
+        let prog_id = u32::from_le_bytes(prog_id);
+        if crate::runtime::get_program(prog_id).is_none() {
+            return Err(Error::InvalidArgument);
+        }
+        let encoded = encode_prog_id(Some(prog_id));
+        return update_raw(map_id, key, &encoded.to_le_bytes(), flags);
+    }
+    update_raw(map_id, key, value, flags)
+}
+
+fn update_raw(map_id: u32, key: &[u8], value: &[u8], flags: u64) -> Result<(), Error> {
+    with_locked_map(map_id, |store| store.update(key, value, flags)).ok_or(Error::NotFound)?
 }
 
 /// Delete an element from a map.
@@ -168,20 +388,535 @@ pub fn update_elem(map_id: u32, key: &[u8], value: &[u8], flags: u64) -> Result<
 /// * `map_id` - Map ID.
 /// * `key` - Key bytes.
 pub fn delete_elem(map_id: u32, key: &[u8]) -> Result<(), Error> {
+    unless_frozen(map_id, || do_delete_elem(map_id, key))
+}
+
+fn do_delete_elem(map_id: u32, key: &[u8]) -> Result<(), Error> {
+    match get_map_type(map_id) {
+        Some(MapType::ProgArray) => {
+            // Array slots cannot be removed, only cleared
+            prog_array_get(map_id, key).ok_or(Error::KeyNotFound)?;
+            update_raw(map_id, key, &encode_prog_id(None).to_le_bytes(), 0)
+        }
+        Some(MapType::RingBuf | MapType::PerfEventArray | MapType::BloomFilter) => {
+            Err(Error::InvalidArgument)
+        }
+        Some(map_type) if is_percpu(map_type) => percpu_delete(map_id, key),
+        _ => delete_raw(map_id, key),
+    }
+}
+
+fn delete_raw(map_id: u32, key: &[u8]) -> Result<(), Error> {
+    with_locked_map(map_id, |store| store.delete(key)).ok_or(Error::NotFound)?
+}
+
+// =============================================================================
+// Queue and Stack Maps
+// =============================================================================
+
+fn is_queue_or_stack(map_id: u32) -> bool {
+    matches!(get_map_type(map_id), Some(MapType::Queue | MapType::Stack))
+}
+
+/// Push a value onto a Queue or Stack map, or add it to a BloomFilter map.
+///
+/// # Arguments
+/// * `map_id` - Map ID of a Queue, Stack or BloomFilter map.
+/// * `value` - Value bytes.
+/// * `flags` - 0 to fail when the map is full, `BPF_EXIST` (2) to drop the
+///   oldest element instead. Must be 0 for bloom filters.
+pub fn push_elem(map_id: u32, value: &[u8], flags: u64) -> Result<(), Error> {
+    unless_frozen(map_id, || do_push_elem(map_id, value, flags))
+}
+
+fn do_push_elem(map_id: u32, value: &[u8], flags: u64) -> Result<(), Error> {
+    if crate::bloom::value_size(map_id).is_some() {
+        return crate::bloom::push(map_id, value, flags);
+    }
+    if !is_queue_or_stack(map_id) {
+        return Err(Error::InvalidArgument);
+    }
     AxKernelAuxOps::get_unified_map_from_fd(map_id, |unified_map: &mut UnifiedMap| {
-        unified_map.map_mut().delete_elem(key)
+        unified_map.map_mut().push_elem(value, flags)
     })
     .map_err(Error::from)
 }
 
+/// Remove and return the next value of a Queue or Stack map: the oldest
+/// for a Queue, the newest for a Stack.
+///
+/// # Returns
+/// Value bytes, or None if the map is empty, frozen or not a Queue or Stack.
+pub fn pop_elem(map_id: u32) -> Option<Vec<u8>> {
+    with_frozen_flag(map_id, |frozen| {
+        if frozen {
+            None
+        } else {
+            queue_or_stack_read(map_id, true)
+        }
+    })
+}
+
+/// Return the value [`pop_elem`] would remove, without removing it.
+///
+/// Bloom filters are tested with [`bloom_filter_contains`] instead.
+pub fn peek_elem(map_id: u32) -> Option<Vec<u8>> {
+    queue_or_stack_read(map_id, false)
+}
+
+fn queue_or_stack_read(map_id: u32, pop: bool) -> Option<Vec<u8>> {
+    if !is_queue_or_stack(map_id) {
+        return None;
+    }
+    let (_, value_size) = get_map_sizes(map_id)?;
+    let mut value = vec![0u8; value_size as usize];
+    AxKernelAuxOps::get_unified_map_from_fd(map_id, |unified_map: &mut UnifiedMap| {
+        if pop {
+            unified_map.map_mut().pop_elem(&mut value)
+        } else {
+            unified_map.map_mut().peek_elem(&mut value)
+        }
+    })
+    .ok()?;
+    Some(value)
+}
+
+// =============================================================================
+// Bloom Filter Maps
+// =============================================================================
+
+/// Test whether `value` was pushed to a BloomFilter map, as
+/// `bpf_map_peek_elem` does.
+///
+/// # Returns
+/// `Ok(false)` if it was not, `Ok(true)` if it probably was.
+pub fn bloom_filter_contains(map_id: u32, value: &[u8]) -> Result<bool, Error> {
+    crate::bloom::contains(map_id, value)
+}
+
+// =============================================================================
+// Ring Buffer Maps
+// =============================================================================
+
+/// Consume records submitted to a RingBuf map, oldest first.
+///
+/// # Arguments
+/// * `map_id` - Map ID of a RingBuf map.
+/// * `max_records` - Most records to return (0 = no limit).
+///
+/// # Returns
+/// Record data. Empty if the map has no records or is not a RingBuf.
+pub fn ringbuf_consume(map_id: u32, max_records: usize) -> Vec<Vec<u8>> {
+    crate::ringbuf::consume(map_id, max_records)
+}
+
+/// Write one record to a RingBuf map, as `bpf_ringbuf_output` does.
+pub fn ringbuf_output(map_id: u32, data: &[u8]) -> Result<(), Error> {
+    crate::ringbuf::output(map_id, data)
+}
+
+/// Bytes written to a RingBuf map and not consumed yet, including record
+/// headers and padding.
+pub fn ringbuf_available(map_id: u32) -> Option<u64> {
+    crate::ringbuf::query(map_id, crate::ringbuf::BPF_RB_AVAIL_DATA)
+}
+
+// =============================================================================
+// Perf Event Arrays
+// =============================================================================
+
+/// Definition a PerfEventArray map is created with: one slot per CPU unless
+/// `max_entries` says otherwise, as libbpf does.
+fn perf_event_array_def(def: &MapDef) -> Result<MapDef, Error> {
+    let cpus = crate::platform::num_cpus();
+    if def.key_size != 4 || def.value_size != 4 || def.max_entries > cpus {
+        return Err(Error::InvalidArgument);
+    }
+    Ok(MapDef {
+        max_entries: if def.max_entries == 0 {
+            cpus
+        } else {
+            def.max_entries
+        },
+        ..def.clone()
+    })
+}
+
+/// Consume the records written to one CPU's ring of a PerfEventArray map.
+///
+/// # Arguments
+/// * `map_id` - Map ID of a PerfEventArray map.
+/// * `cpu` - Slot of the ring.
+/// * `max_records` - Most records to return (0 = no limit).
+///
+/// # Returns
+/// Samples and lost-sample counts in ring order.
+pub fn perf_event_read(
+    map_id: u32,
+    cpu: u32,
+    max_records: usize,
+) -> Result<Vec<crate::perf_event::PerfRecord>, Error> {
+    crate::perf_event::consume(map_id, cpu, max_records)
+}
+
+/// `data_head` and `data_tail` of one CPU's ring of a PerfEventArray map,
+/// in bytes written and consumed.
+pub fn perf_event_positions(map_id: u32, cpu: u32) -> Option<(u64, u64)> {
+    crate::perf_event::positions(map_id, cpu)
+}
+
+// =============================================================================
+// LPM Trie Maps
+// =============================================================================
+
+/// Size of the prefix length at the start of an LPM trie key.
+const LPM_KEY_PREFIX: u32 = 4;
+
+/// Most data bytes an LPM trie key can have (`LPM_DATA_SIZE_MAX` in Linux).
+const LPM_MAX_DATA_SIZE: u32 = 256;
+
+/// Build an LPM trie key from a prefix length in bits and the data bytes,
+/// most significant first (e.g. a big-endian address).
+///
+/// For lookups, pass the full data length in bits as `prefix_len`.
+pub fn lpm_trie_key(prefix_len: u32, data: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(LPM_KEY_PREFIX as usize + data.len());
+    key.extend_from_slice(&prefix_len.to_le_bytes());
+    key.extend_from_slice(data);
+    key
+}
+
+// =============================================================================
+// Stack Trace Maps
+// =============================================================================
+
+/// Store a stack trace under the stack ID for `hash` and return the ID.
+///
+/// The ID is `hash` masked to the bucket count. If the bucket holds a
+/// different trace, it is replaced when `reuse` is set and the store fails
+/// with `KeyExists` otherwise.
+pub(crate) fn stack_map_store(
+    map_id: u32,
+    hash: u32,
+    trace: &[u8],
+    reuse: bool,
+) -> Result<u32, Error> {
+    let def = get_map_def(map_id).ok_or(Error::NotFound)?;
+    let id = hash & (def.max_entries - 1);
+    let key = id.to_le_bytes();
+    match lookup_raw(map_id, &key) {
+        Some(stored) if stored == trace => return Ok(id),
+        Some(_) if !reuse => return Err(Error::KeyExists),
+        _ => {}
+    }
+    update_raw(map_id, &key, trace, 0)?;
+    Ok(id)
+}
+
+// =============================================================================
+// Per-CPU Maps
+// =============================================================================
+
+/// Whether `map_type` keeps one value per CPU.
+fn is_percpu(map_type: MapType) -> bool {
+    matches!(map_type, MapType::PerCpuArray | MapType::PerCpuHash)
+}
+
+/// Bytes each CPU's value takes in user-side per-CPU buffers.
+///
+/// As in Linux, the values of CPU 0 to `platform::num_cpus() - 1` follow
+/// each other, each padded to a multiple of 8 bytes.
+pub fn percpu_value_stride(value_size: u32) -> usize {
+    (value_size as usize).next_multiple_of(8)
+}
+
+/// Values of all CPUs. CPUs that never stored `key` (per-CPU hash maps keep
+/// a key set per CPU) read as zero; None if no CPU has it.
+fn percpu_lookup(map_id: u32, key: &[u8]) -> Option<Vec<u8>> {
+    let (_, value_size) = get_map_sizes(map_id)?;
+    with_locked_map(map_id, |store| percpu_lookup_in(store, value_size, key)).flatten()
+}
+
+fn percpu_lookup_in(store: &mut MapStorage, value_size: u32, key: &[u8]) -> Option<Vec<u8>> {
+    let stride = percpu_value_stride(value_size);
+    let mut values = vec![0u8; stride * crate::platform::num_cpus() as usize];
+    let mut found = false;
+    for (cpu, slot) in values.chunks_mut(stride).enumerate() {
+        with_cpu_values(cpu as u32, || {
+            if let Ok(Some(value)) = store.lookup(key) {
+                slot[..value.len()].copy_from_slice(value);
+                found = true;
+            }
+        });
+    }
+    found.then_some(values)
+}
+
+/// Store one value per CPU. Not atomic: if a CPU's update fails, earlier
+/// CPUs keep their new value.
+fn percpu_update(map_id: u32, key: &[u8], values: &[u8], flags: u64) -> Result<(), Error> {
+    let (_, value_size) = get_map_sizes(map_id).ok_or(Error::NotFound)?;
+    with_locked_map(map_id, |store| {
+        percpu_update_in(store, value_size, key, values, flags)
+    })
+    .ok_or(Error::NotFound)?
+}
+
+fn percpu_update_in(
+    store: &mut MapStorage,
+    value_size: u32,
+    key: &[u8],
+    values: &[u8],
+    flags: u64,
+) -> Result<(), Error> {
+    let stride = percpu_value_stride(value_size);
+    if values.len() != stride * crate::platform::num_cpus() as usize {
+        return Err(Error::InvalidArgument);
+    }
+    for (cpu, slot) in values.chunks(stride).enumerate() {
+        let value = &slot[..value_size as usize];
+        with_cpu_values(cpu as u32, || store.update(key, value, flags))?;
+    }
+    Ok(())
+}
+
+/// Delete `key` on every CPU. Succeeds if any CPU had it.
+fn percpu_delete(map_id: u32, key: &[u8]) -> Result<(), Error> {
+    with_locked_map(map_id, |store| percpu_delete_in(store, key)).ok_or(Error::NotFound)?
+}
+
+fn percpu_delete_in(store: &mut MapStorage, key: &[u8]) -> Result<(), Error> {
+    let mut result = Err(Error::KeyNotFound);
+    for cpu in 0..crate::platform::num_cpus() {
+        match with_cpu_values(cpu, || store.delete(key)) {
+            Ok(()) => result = Ok(()),
+            Err(e) if result.is_err() => result = Err(e),
+            Err(_) => {}
+        }
+    }
+    result
+}
+
+// =============================================================================
+// Program-Side Access
+// =============================================================================
+
+/// Lookup as done by `bpf_map_lookup_elem`: per-CPU maps give the value of
+/// the current CPU.
+pub(crate) fn prog_lookup_elem(map_id: u32, key: &[u8]) -> Option<Vec<u8>> {
+    match get_map_type(map_id)? {
+        map_type if is_percpu(map_type) => lookup_raw(map_id, key),
+        _ => lookup_elem(map_id, key),
+    }
+}
+
+/// Whether `bpf_map_lookup_elem` on maps of `map_type` returns a pointer to
+/// the value in the map rather than to a copy.
+pub(crate) fn has_fixed_values(map_type: MapType) -> bool {
+    matches!(
+        map_type,
+        MapType::Array
+            | MapType::PerCpuArray
+            | MapType::HashMap
+            | MapType::LruHash
+            | MapType::PerCpuHash
+    )
+}
+
+/// Address of the value of `key` in a map with fixed values, the current
+/// CPU's for per-CPU maps. Programs may update the value through it.
+pub(crate) fn prog_lookup_ptr(map_id: u32, key: &[u8]) -> Option<*mut u8> {
+    with_locked_map(map_id, |store| {
+        let value = store.lookup(key).ok()??;
+        Some(value.as_ptr() as *mut u8)
+    })
+    .flatten()
+}
+
+/// Address ranges holding the values of a map with fixed values, of all
+/// CPUs for per-CPU maps. Pointers from [`prog_lookup_ptr`] point into
+/// them until the map is destroyed.
+pub(crate) fn value_regions(map_id: u32) -> Vec<core::ops::Range<u64>> {
+    let Some(def) = get_map_def(map_id).filter(|def| has_fixed_values(def.map_type)) else {
+        return Vec::new();
+    };
+    with_locked_map(map_id, |store| {
+        if let Some(range) = store.slots_range() {
+            return vec![range];
+        }
+        let cpus = if is_percpu(def.map_type) {
+            crate::platform::num_cpus()
+        } else {
+            1
+        };
+        (0..cpus)
+            .filter_map(|cpu| with_cpu_values(cpu, || array_values_range(store, def.max_entries)))
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// Range from the first to the last value of an array, the current CPU's
+/// for per-CPU arrays. kbpf-basic keeps the values of an array in one
+/// allocation.
+fn array_values_range(store: &mut MapStorage, max_entries: u32) -> Option<core::ops::Range<u64>> {
+    let last_index = max_entries.checked_sub(1)?;
+    let start = store.lookup(&0u32.to_le_bytes()).ok()??.as_ptr() as u64;
+    let last = store.lookup(&last_index.to_le_bytes()).ok()??;
+    Some(start..last.as_ptr() as u64 + last.len() as u64)
+}
+
+/// Whether programs may read the values of map `map_id`, i.e. it was not
+/// created with [`BPF_F_WRONLY_PROG`].
+pub(crate) fn prog_may_read(map_id: u32) -> bool {
+    get_map_def(map_id).is_some_and(|def| def.map_flags & BPF_F_WRONLY_PROG == 0)
+}
+
+/// Whether programs may change map `map_id`, i.e. it was not created with
+/// [`BPF_F_RDONLY_PROG`]. Only such maps can be frozen, see [`freeze`].
+pub(crate) fn prog_may_write(map_id: u32) -> bool {
+    get_map_def(map_id).is_some_and(|def| def.map_flags & BPF_F_RDONLY_PROG == 0)
+}
+
+/// Update as done by `bpf_map_update_elem`, see [`prog_lookup_elem`].
+pub(crate) fn prog_update_elem(
+    map_id: u32,
+    key: &[u8],
+    value: &[u8],
+    flags: u64,
+) -> Result<(), Error> {
+    if !prog_may_write(map_id) {
+        return Err(Error::PermissionDenied);
+    }
+    match get_map_type(map_id).ok_or(Error::NotFound)? {
+        map_type if is_percpu(map_type) => update_raw(map_id, key, value, flags),
+        _ => do_update_elem(map_id, key, value, flags),
+    }
+}
+
+/// Delete as done by `bpf_map_delete_elem`, see [`prog_lookup_elem`].
+pub(crate) fn prog_delete_elem(map_id: u32, key: &[u8]) -> Result<(), Error> {
+    if !prog_may_write(map_id) {
+        return Err(Error::PermissionDenied);
+    }
+    match get_map_type(map_id).ok_or(Error::NotFound)? {
+        map_type if is_percpu(map_type) => delete_raw(map_id, key),
+        _ => do_delete_elem(map_id, key),
+    }
+}
+
+/// Push as done by `bpf_map_push_elem`.
+pub(crate) fn prog_push_elem(map_id: u32, value: &[u8], flags: u64) -> Result<(), Error> {
+    if !prog_may_write(map_id) {
+        return Err(Error::PermissionDenied);
+    }
+    do_push_elem(map_id, value, flags)
+}
+
+/// Pop as done by `bpf_map_pop_elem`, which both reads and changes the map.
+pub(crate) fn prog_pop_elem(map_id: u32) -> Option<Vec<u8>> {
+    if !prog_may_read(map_id) || !prog_may_write(map_id) {
+        return None;
+    }
+    queue_or_stack_read(map_id, true)
+}
+
+/// Peek as done by `bpf_map_peek_elem` on a Queue or Stack map.
+pub(crate) fn prog_peek_elem(map_id: u32) -> Option<Vec<u8>> {
+    if !prog_may_read(map_id) {
+        return None;
+    }
+    queue_or_stack_read(map_id, false)
+}
+
+// =============================================================================
+// Frozen Maps
+// =============================================================================
+
+/// Freeze a map, like `BPF_MAP_FREEZE`.
+///
+/// From then on the user side can only read the map: updates, deletes,
+/// pushes and pops fail with [`Error::PermissionDenied`]. Only maps created
+/// with [`BPF_F_RDONLY_PROG`] can be frozen, other maps fail with
+/// [`Error::PermissionDenied`]; programs can never write those, so a frozen
+/// map stays constant. Freezing cannot be undone; freezing a frozen map
+/// again has no effect.
+pub fn freeze(map_id: u32) -> Result<(), Error> {
+    let def = get_map_def(map_id).ok_or(Error::NotFound)?;
+    if def.map_flags & BPF_F_RDONLY_PROG == 0 {
+        return Err(Error::PermissionDenied);
+    }
+    if freeze_map(map_id) {
+        Ok(())
+    } else {
+        Err(Error::NotFound)
+    }
+}
+
+/// Whether a map has been frozen, see [`freeze`].
+pub fn is_frozen(map_id: u32) -> bool {
+    is_map_frozen(map_id)
+}
+
+/// Run the user-side write `f` unless map `map_id` is frozen. The map
+/// cannot be frozen while `f` runs, so [`freeze`] never returns with a
+/// write still in flight.
+fn unless_frozen<R, E: From<Error>>(map_id: u32, f: impl FnOnce() -> Result<R, E>) -> Result<R, E> {
+    with_frozen_flag(map_id, |frozen| {
+        if frozen {
+            Err(Error::PermissionDenied.into())
+        } else {
+            f()
+        }
+    })
+}
+
+// =============================================================================
+// Program Arrays
+// =============================================================================
+
+/// Encode a program array slot. Array maps are zero-initialized, so 0 marks
+/// an empty slot and program IDs are stored off by one.
+fn encode_prog_id(prog_id: Option<u32>) -> u32 {
+    prog_id.map_or(0, |id| id.wrapping_add(1))
+}
+
+/// Get the program ID stored at `key` in a program array.
+///
+/// # Returns
+/// Program ID, or None if the slot is empty or the map is not a ProgArray.
+pub fn prog_array_get(map_id: u32, key: &[u8]) -> Option<u32> {
+    if get_map_type(map_id) != Some(MapType::ProgArray) {
+        return None;
+    }
+    let value = AxKernelAuxOps::get_unified_map_from_fd(map_id, |unified_map: &mut UnifiedMap| {
+        Ok(unified_map
+            .map_mut()
+            .lookup_elem(key)?
+            .and_then(|v| v.try_into().ok()))
+    })
+    .ok()
+    .flatten()?;
+    u32::from_le_bytes(value).checked_sub(1)
+}
+
 /// Get the number of maps in the registry.
 pub fn count() -> usize {
     map_count()
 }
 
 /// Delete a map by ID.
+///
+/// Fails with [`Error::Busy`] while a loaded program uses the map; unload
+/// the program first.
 pub fn destroy(map_id: u32) -> Result<(), Error> {
-    unregister_map(map_id).map_err(Error::from)?;
+    unregister_map(map_id).map_err(|e| match e {
+        BpfError::TryAgain => Error::Busy,
+        e => Error::from(e),
+    })?;
+    crate::ringbuf::destroy(map_id);
+    crate::perf_event::destroy(map_id);
+    crate::bloom::destroy(map_id);
     log::debug!("Destroyed map {}", map_id);
     Ok(())
 }
@@ -196,6 +931,11 @@ pub fn destroy(map_id: u32) -> Result<(), Error> {
 pub fn iter_entries(map_fd: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
     use crate::map_ops::iter_map_keys;
 
+    // Hash and array maps are read in one batch, a consistent snapshot
+    if let Ok(batch) = lookup_batch(map_fd, None, 0) {
+        return batch.entries;
+    }
+
     let mut entries = Vec::new();
 
     // Iterate all keys
@@ -210,3 +950,189 @@ pub fn iter_entries(map_fd: u32) -> Vec<(Vec<u8>, Vec<u8>)> {
 
     entries
 }
+
+// =============================================================================
+// Batch Operations
+// =============================================================================
+
+/// Where a batch walk stopped. Pass it to the next [`lookup_batch`] call to
+/// continue after the last key returned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BatchToken(Vec<u8>);
+
+/// Entries read by [`lookup_batch`] or [`lookup_and_delete_batch`].
+#[derive(Debug, Clone, Default)]
+pub struct Batch {
+    /// (key, value) pairs, values as returned by [`lookup_elem`].
+    pub entries: Vec<(Vec<u8>, Vec<u8>)>,
+    /// Token of the next batch, or None once the walk reached the end.
+    pub next: Option<BatchToken>,
+}
+
+/// Error of [`update_batch`] or [`delete_batch`].
+#[derive(Debug, Clone)]
+pub struct BatchError {
+    /// Elements processed before the failing one; their changes are kept.
+    pub processed: usize,
+    /// Error of the failing element.
+    pub error: Error,
+}
+
+impl core::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{} (after {} elements)", self.error, self.processed)
+    }
+}
+
+impl core::error::Error for BatchError {}
+
+impl From<Error> for BatchError {
+    fn from(error: Error) -> Self {
+        Self {
+            processed: 0,
+            error,
+        }
+    }
+}
+
+/// Check that batch operations support `map_type`. As in Linux, they cover
+/// hash and array maps, and array elements cannot be deleted.
+fn check_batch_type(map_type: MapType, delete: bool) -> Result<(), Error> {
+    match map_type {
+        MapType::HashMap | MapType::LruHash | MapType::PerCpuHash => Ok(()),
+        MapType::Array | MapType::PerCpuArray if !delete => Ok(()),
+        MapType::Array | MapType::PerCpuArray => Err(Error::InvalidArgument),
+        _ => Err(Error::NotSupported),
+    }
+}
+
+/// Read up to `max_count` entries (0 for no limit) in one pass over the map,
+/// like `BPF_MAP_LOOKUP_BATCH`.
+///
+/// The map is locked for the whole batch, so the entries are a consistent
+/// snapshot. Reading a map in several batches gives no such guarantee
+/// across batches.
+///
+/// # Arguments
+/// * `map_id` - Map ID of a hash or array map.
+/// * `start` - `next` of the previous batch, or None to start at the first
+///   key. If that key has been deleted since, where the walk resumes
+///   depends on the map, as for `get_next_key`.
+/// * `max_count` - Maximum number of entries.
+pub fn lookup_batch(
+    map_id: u32,
+    start: Option<&BatchToken>,
+    max_count: usize,
+) -> Result<Batch, Error> {
+    let start = start.map(|token| token.0.as_slice());
+    read_batch(map_id, start, max_count, false)
+}
+
+/// Read and delete up to `max_count` entries (0 for no limit), like
+/// `BPF_MAP_LOOKUP_AND_DELETE_BATCH`.
+///
+/// Each batch starts at the first remaining key, so draining a map is a
+/// matter of calling this until `next` is None. Entries added while the
+/// map is drained may be returned in a later batch.
+pub fn lookup_and_delete_batch(map_id: u32, max_count: usize) -> Result<Batch, Error> {
+    unless_frozen(map_id, || read_batch(map_id, None, max_count, true))
+}
+
+fn read_batch(
+    map_id: u32,
+    start: Option<&[u8]>,
+    max_count: usize,
+    delete: bool,
+) -> Result<Batch, Error> {
+    let def = get_map_def(map_id).ok_or(Error::NotFound)?;
+    check_batch_type(def.map_type, delete)?;
+    let limit = if max_count == 0 {
+        usize::MAX
+    } else {
+        max_count
+    };
+
+    with_locked_map(map_id, |store| {
+        let keys = map_keys(&mut store.map, def.map_type, def.key_size, start, limit);
+        // A short batch means the walk reached the end
+        let next = keys
+            .last()
+            .filter(|_| keys.len() == limit)
+            .cloned()
+            .map(BatchToken);
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in keys {
+            let value = if is_percpu(def.map_type) {
+                percpu_lookup_in(store, def.value_size, &key)
+            } else {
+                store.lookup(&key)?.map(<[u8]>::to_vec)
+            };
+            let Some(value) = value else {
+                continue;
+            };
+            if delete {
+                batch_delete_in(store, def.map_type, &key)?;
+            }
+            entries.push((key, value));
+        }
+        Ok(Batch { entries, next })
+    })
+    .ok_or(Error::NotFound)?
+}
+
+/// Store `entries` in one pass over the map, like `BPF_MAP_UPDATE_BATCH`.
+///
+/// Values are as for [`update_elem`], and `flags` applies to every entry.
+/// The map is locked for the whole batch. Updates stop at the first
+/// failing entry.
+pub fn update_batch(
+    map_id: u32,
+    entries: &[(Vec<u8>, Vec<u8>)],
+    flags: u64,
+) -> Result<(), BatchError> {
+    let def = get_map_def(map_id).ok_or(Error::NotFound)?;
+    check_batch_type(def.map_type, false)?;
+
+    unless_frozen(map_id, || {
+        with_locked_map(map_id, |store| {
+            for (processed, (key, value)) in entries.iter().enumerate() {
+                let result = if is_percpu(def.map_type) {
+                    percpu_update_in(store, def.value_size, key, value, flags)
+                } else {
+                    store.update(key, value, flags)
+                };
+                result.map_err(|error| BatchError { processed, error })?;
+            }
+            Ok(())
+        })
+        .ok_or(Error::NotFound)?
+    })
+}
+
+/// Delete `keys` in one pass over the map, like `BPF_MAP_DELETE_BATCH`.
+///
+/// The map is locked for the whole batch. Deletion stops at the first key
+/// that cannot be deleted.
+pub fn delete_batch(map_id: u32, keys: &[Vec<u8>]) -> Result<(), BatchError> {
+    let def = get_map_def(map_id).ok_or(Error::NotFound)?;
+    check_batch_type(def.map_type, true)?;
+
+    unless_frozen(map_id, || {
+        with_locked_map(map_id, |store| {
+            for (processed, key) in keys.iter().enumerate() {
+                batch_delete_in(store, def.map_type, key)
+                    .map_err(|error| BatchError { processed, error })?;
+            }
+            Ok(())
+        })
+        .ok_or(Error::NotFound)?
+    })
+}
+
+fn batch_delete_in(store: &mut MapStorage, map_type: MapType, key: &[u8]) -> Result<(), Error> {
+    if is_percpu(map_type) {
+        percpu_delete_in(store, key)
+    } else {
+        store.delete(key)
+    }
+}
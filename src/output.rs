@@ -2,6 +2,10 @@
 //!
 //! Provides structured output for eBPF program execution results.
 
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
 use crate::platform;
 
 /// Print structured eBPF execution result.
@@ -58,3 +62,42 @@ pub fn print_if_verbose(prog_name: &str, tp_name: &str, tp_id: u32, map_fd: u32)
         print_ebpf_result(prog_name, tp_name, &key, &value);
     }
 }
+
+/// Render a stack trace map value, one line per frame, innermost first.
+///
+/// Frames are symbolized with `symbols::lookup_symbol` when the symbol table
+/// is loaded, e.g. `handle_vmexit+0x3c [0xffff000040123abc]`, and printed as
+/// plain addresses otherwise. The zero padding after the last frame is
+/// dropped.
+pub fn format_stack_trace(value: &[u8]) -> Vec<String> {
+    value
+        .chunks_exact(8)
+        .map(|c| u64::from_le_bytes(c.try_into().unwrap_or([0; 8])))
+        .take_while(|&addr| addr != 0)
+        .map(format_frame)
+        .collect()
+}
+
+fn format_frame(addr: u64) -> String {
+    #[cfg(feature = "symbols")]
+    if let Some((name, _size, offset, _ty)) = crate::symbols::lookup_symbol(addr) {
+        return format!("{}+{:#x} [{:#x}]", name, offset, addr);
+    }
+    format!("{:#x}", addr)
+}
+
+/// Print the stack stored under `stack_id` in a stack trace map.
+///
+/// # Arguments
+/// * `map_fd` - StackTrace map FD
+/// * `stack_id` - Stack ID returned by `bpf_get_stackid`
+pub fn print_stack_trace(map_fd: u32, stack_id: u32) {
+    let Some(value) = crate::maps::lookup_elem(map_fd, &stack_id.to_le_bytes()) else {
+        log::warn!("[eBPF] stack {} not found in map {}", stack_id, map_fd);
+        return;
+    };
+    log::info!("[eBPF] stack {}:", stack_id);
+    for frame in format_stack_trace(&value) {
+        log::info!("    {}", frame);
+    }
+}
@@ -0,0 +1,237 @@
+//! Perf event array maps.
+//!
+//! In Linux, each slot of a `BPF_MAP_TYPE_PERF_EVENT_ARRAY` holds a perf
+//! event whose mmap'ed ring receives `bpf_perf_event_output` samples. There
+//! are no perf events here, so every slot of a [`MapType::PerfEventArray`]
+//! map owns a ring of its own, allocated when the map is created. Slot `i`
+//! is the buffer of CPU `i`.
+//!
+//! Rings use the perf layout: records start with a `perf_event_header`
+//! (u32 type, u16 misc, u16 size) and are 8-byte aligned, and they wrap
+//! around the end of the data area. Samples are `PERF_RECORD_SAMPLE`
+//! records with a u32 raw size and the raw data, zero-padded so the record
+//! stays aligned. Samples that do not fit are counted and reported with a
+//! `PERF_RECORD_LOST` record ahead of the next sample that fits.
+//!
+//! [`MapType::PerfEventArray`]: crate::maps::MapType::PerfEventArray
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use spin::{Mutex, RwLock};
+
+use crate::maps::Error;
+
+/// `bpf_perf_event_output` flags: slot index, or [`BPF_F_CURRENT_CPU`].
+pub const BPF_F_INDEX_MASK: u64 = 0xffff_ffff;
+/// `bpf_perf_event_output` flags: the slot of the current CPU.
+pub const BPF_F_CURRENT_CPU: u64 = BPF_F_INDEX_MASK;
+
+/// Record type of lost-sample counts.
+pub const PERF_RECORD_LOST: u32 = 2;
+/// Record type of samples.
+pub const PERF_RECORD_SAMPLE: u32 = 9;
+
+/// Size of `perf_event_header`.
+const HEADER_SIZE: u64 = 8;
+/// Size of a `PERF_RECORD_LOST` record (header, id, lost count).
+const LOST_RECORD_SIZE: u64 = HEADER_SIZE + 16;
+
+/// Data pages of each ring.
+pub const PERF_BUFFER_PAGES: u32 = 8;
+const PAGE_SIZE: u64 = 4096;
+
+const E2BIG: i64 = 7;
+const ENOSPC: i64 = 28;
+const EINVAL: i64 = 22;
+const EOPNOTSUPP: i64 = 95;
+
+/// One record read from a ring.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PerfRecord {
+    /// Raw data of a sample, zero-padded as stored.
+    Sample(Vec<u8>),
+    /// Number of samples dropped because the ring was full.
+    Lost(u64),
+}
+
+/// Ring of one slot. `data_head` and `data_tail` count bytes written and
+/// consumed, as in `perf_event_mmap_page`.
+struct PerfRing {
+    data: Vec<u8>,
+    data_head: u64,
+    data_tail: u64,
+    /// Samples dropped since the last `PERF_RECORD_LOST`.
+    lost: u64,
+}
+
+impl PerfRing {
+    fn new() -> Self {
+        Self {
+            data: vec![0; (PERF_BUFFER_PAGES as u64 * PAGE_SIZE) as usize],
+            data_head: 0,
+            data_tail: 0,
+            lost: 0,
+        }
+    }
+
+    fn free(&self) -> u64 {
+        self.data.len() as u64 - (self.data_head - self.data_tail)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        let mask = self.data.len() as u64 - 1;
+        for &b in bytes {
+            self.data[(self.data_head & mask) as usize] = b;
+            self.data_head += 1;
+        }
+    }
+
+    fn read(&self, pos: u64, len: usize) -> Vec<u8> {
+        let mask = self.data.len() as u64 - 1;
+        (0..len as u64)
+            .map(|i| self.data[((pos + i) & mask) as usize])
+            .collect()
+    }
+
+    fn write_header(&mut self, record_type: u32, size: u64) {
+        self.write(&record_type.to_le_bytes());
+        self.write(&0u16.to_le_bytes());
+        self.write(&(size as u16).to_le_bytes());
+    }
+
+    /// Append a sample, preceded by a lost record if samples were dropped.
+    fn output(&mut self, data: &[u8]) -> i64 {
+        let raw_size = (data.len() as u64 + 4).next_multiple_of(8) - 4;
+        let size = HEADER_SIZE + 4 + raw_size;
+        if size > u16::MAX as u64 {
+            return -E2BIG;
+        }
+        let needed = size + if self.lost != 0 { LOST_RECORD_SIZE } else { 0 };
+        if needed > self.free() {
+            self.lost += 1;
+            return -ENOSPC;
+        }
+
+        if self.lost != 0 {
+            self.write_header(PERF_RECORD_LOST, LOST_RECORD_SIZE);
+            self.write(&0u64.to_le_bytes());
+            self.write(&self.lost.to_le_bytes());
+            self.lost = 0;
+        }
+        self.write_header(PERF_RECORD_SAMPLE, size);
+        self.write(&(raw_size as u32).to_le_bytes());
+        self.write(data);
+        let padding = raw_size as usize - data.len();
+        self.write(&[0u8; 8][..padding]);
+        0
+    }
+
+    /// Pop the record at `data_tail`.
+    fn consume(&mut self) -> Option<PerfRecord> {
+        if self.data_tail == self.data_head {
+            return None;
+        }
+        let header = self.read(self.data_tail, HEADER_SIZE as usize);
+        let record_type = u32::from_le_bytes(header[..4].try_into().unwrap());
+        let size = u16::from_le_bytes(header[6..8].try_into().unwrap()) as u64;
+        let body = self.read(self.data_tail + HEADER_SIZE, (size - HEADER_SIZE) as usize);
+        self.data_tail += size;
+
+        Some(match record_type {
+            PERF_RECORD_LOST => {
+                PerfRecord::Lost(u64::from_le_bytes(body[8..16].try_into().unwrap()))
+            }
+            _ => PerfRecord::Sample(body[4..].to_vec()),
+        })
+    }
+}
+
+/// Rings of each map by map ID, indexed by slot. Each ring has a lock of its
+/// own, so CPUs writing to their own slot do not contend.
+static PERF_RINGS: RwLock<BTreeMap<u32, Vec<Mutex<PerfRing>>>> = RwLock::new(BTreeMap::new());
+
+// =============================================================================
+// Map Lifecycle
+// =============================================================================
+
+/// Allocate the rings of map `map_id`, one per slot.
+pub(crate) fn create(map_id: u32, slots: u32) {
+    let rings = (0..slots).map(|_| Mutex::new(PerfRing::new())).collect();
+    PERF_RINGS.write().insert(map_id, rings);
+}
+
+/// Free the rings of map `map_id`.
+pub(crate) fn destroy(map_id: u32) {
+    PERF_RINGS.write().remove(&map_id);
+}
+
+// =============================================================================
+// Producer Side
+// =============================================================================
+
+/// `bpf_perf_event_output(ctx, map, flags, data, size)`: append `data` as a
+/// sample to the ring selected by `flags`.
+///
+/// Returns 0, or `-E2BIG` if the slot is out of range or the sample too
+/// large, `-EOPNOTSUPP` if the slot belongs to another CPU (as for a perf
+/// event bound to another CPU in Linux), `-ENOSPC` if the ring is full and
+/// `-EINVAL` for unknown flags (including a context length) or a map that
+/// is not a perf event array.
+pub fn output(map_id: u32, flags: u64, data: &[u8]) -> i64 {
+    if flags & !BPF_F_INDEX_MASK != 0 {
+        return -EINVAL;
+    }
+    let cpu = crate::platform::cpu_id();
+    let index = match flags & BPF_F_INDEX_MASK {
+        BPF_F_CURRENT_CPU => cpu,
+        index => index as u32,
+    };
+    let maps = PERF_RINGS.read();
+    let Some(rings) = maps.get(&map_id) else {
+        return -EINVAL;
+    };
+    let Some(ring) = rings.get(index as usize) else {
+        return -E2BIG;
+    };
+    if index != cpu {
+        return -EOPNOTSUPP;
+    }
+    ring.lock().output(data)
+}
+
+// =============================================================================
+// Consumer Side
+// =============================================================================
+
+/// Pop up to `max_records` records (0 for no limit) from the ring of
+/// `slot`, oldest first.
+pub(crate) fn consume(
+    map_id: u32,
+    slot: u32,
+    max_records: usize,
+) -> Result<Vec<PerfRecord>, Error> {
+    let limit = if max_records == 0 {
+        usize::MAX
+    } else {
+        max_records
+    };
+    let maps = PERF_RINGS.read();
+    let rings = maps.get(&map_id).ok_or(Error::InvalidArgument)?;
+    let mut ring = rings.get(slot as usize).ok_or(Error::KeyNotFound)?.lock();
+    let mut records = Vec::new();
+    while records.len() < limit {
+        match ring.consume() {
+            Some(record) => records.push(record),
+            None => break,
+        }
+    }
+    Ok(records)
+}
+
+/// (`data_head`, `data_tail`) of the ring of `slot`.
+pub(crate) fn positions(map_id: u32, slot: u32) -> Option<(u64, u64)> {
+    let maps = PERF_RINGS.read();
+    let ring = maps.get(&map_id)?.get(slot as usize)?.lock();
+    Some((ring.data_head, ring.data_tail))
+}
@@ -3,7 +3,7 @@
 //! This module provides an abstraction over platform-specific operations
 //! (time, CPU ID) to allow testing in user space.
 
-use core::sync::atomic::{AtomicU64, Ordering};
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 
 /// Platform operations trait.
 ///
@@ -105,6 +105,31 @@ pub fn cpu_id() -> u32 {
     Platform::cpu_id()
 }
 
+// =============================================================================
+// CPU Count
+// =============================================================================
+
+/// Largest CPU count [`set_num_cpus`] accepts.
+pub const MAX_CPUS: u32 = 64;
+
+/// Number of CPUs per-CPU maps keep a value for.
+static NUM_CPUS: AtomicU32 = AtomicU32::new(1);
+
+/// Set the number of CPUs, clamped to `1..=MAX_CPUS`.
+///
+/// Per-CPU maps size their storage when they are created, so call this
+/// during boot before any are created. CPU IDs at or above the count share
+/// storage with lower ones.
+pub fn set_num_cpus(n: u32) {
+    NUM_CPUS.store(n.clamp(1, MAX_CPUS), Ordering::Relaxed);
+}
+
+/// Get the number of CPUs set by [`set_num_cpus`] (1 by default).
+#[inline]
+pub fn num_cpus() -> u32 {
+    NUM_CPUS.load(Ordering::Relaxed)
+}
+
 /// Get current VM ID.
 ///
 /// Returns 0 when in host context (not handling a VM).
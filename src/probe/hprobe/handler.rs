@@ -109,6 +109,12 @@ pub fn handle_breakpoint(regs_ptr: *mut u8, regs_size: usize, iss: u64) -> bool
         }
     };
 
+    // Slots whose program was detached during the hit can only go once the
+    // single step is done and the registry is unlocked again.
+    if handled && iss == KPROBES_BRK_SS_IMM {
+        super::manager::run_pending_detaches();
+    }
+
     // Write back modified registers (LR, PC, etc.) to TrapFrame
     if handled {
         unsafe {
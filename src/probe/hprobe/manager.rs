@@ -8,8 +8,10 @@ use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use spin::Mutex;
+use core::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use spin::{Mutex, MutexGuard};
 
+use crate::platform::MAX_CPUS;
 use crate::probe::hprobe::ops::AxKprobeOps;
 use crate::symbols;
 
@@ -39,11 +41,31 @@ fn arg_at(regs: &kprobe::PtRegs, idx: usize) -> u64 {
     }
 }
 
+/// Trapped program counter and frame pointer, for stack unwinding.
+#[cfg(feature = "runtime")]
+#[inline]
+fn frame_at(regs: &kprobe::PtRegs) -> (u64, u64) {
+    #[cfg(target_arch = "aarch64")]
+    {
+        return (regs.pc, regs.regs[29]);
+    }
+    #[cfg(target_arch = "x86_64")]
+    {
+        return (regs.rip as u64, regs.rbp as u64);
+    }
+    #[cfg(not(any(target_arch = "aarch64", target_arch = "x86_64")))]
+    {
+        let _ = regs;
+        (0, 0)
+    }
+}
+
 /// User data attached to each probe instance.
 /// Passed to callbacks via `ProbeData`, avoiding lock-table lookups.
 #[derive(Clone, Debug)]
 struct HprobeUserData {
-    prog_id: u32,
+    /// Shared with the slot so `replace_program` takes effect on the next hit.
+    prog_id: Arc<AtomicU32>,
     probe_addr: usize,
     symbol: String,
 }
@@ -71,7 +93,7 @@ struct ProbeSlot {
     /// Slot state.
     state: KprobeState,
     /// Associated eBPF program ID.
-    prog_id: u32,
+    prog_id: Arc<AtomicU32>,
     /// Handle to the underlying kprobe library object.
     handle: Option<ProbeHandle>,
 }
@@ -81,7 +103,7 @@ impl ProbeSlot {
         Self {
             hits: 0,
             state: KprobeState::Disabled,
-            prog_id,
+            prog_id: Arc::new(AtomicU32::new(prog_id)),
             handle: None,
         }
     }
@@ -102,6 +124,51 @@ struct ProbePairEntry {
 /// Global kprobe registry
 pub(super) static KPROBE_REGISTRY: Mutex<Option<KprobeRegistry>> = Mutex::new(None);
 
+/// Programs whose slots could not be removed because the registry was
+/// locked by a running handler, with their [`program_generation`]; see
+/// [`try_detach_program`].
+static PENDING_DETACH: Mutex<Vec<(u32, u64)>> = Mutex::new(Vec::new());
+
+/// Set while a CPU runs a program from a probe handler, which may hold
+/// [`KPROBE_REGISTRY`] on that CPU.
+static IN_HANDLER: [AtomicBool; MAX_CPUS as usize] =
+    [const { AtomicBool::new(false) }; MAX_CPUS as usize];
+
+fn in_handler() -> &'static AtomicBool {
+    &IN_HANDLER[crate::platform::cpu_id() as usize % MAX_CPUS as usize]
+}
+
+/// Lock the registry, or only try to from inside a probe handler, where
+/// waiting for it could deadlock.
+fn lock_registry() -> Option<MutexGuard<'static, Option<KprobeRegistry>>> {
+    if in_handler().load(Ordering::Relaxed) {
+        KPROBE_REGISTRY.try_lock()
+    } else {
+        Some(KPROBE_REGISTRY.lock())
+    }
+}
+
+/// Number of the load behind `prog_id`, which unlike the ID is never reused.
+#[cfg(feature = "runtime")]
+fn program_generation(prog_id: u32) -> Option<u64> {
+    crate::runtime::get_program(prog_id).map(|p| p.generation())
+}
+
+/// Without the runtime no handler runs programs, so nothing is queued.
+#[cfg(not(feature = "runtime"))]
+fn program_generation(_prog_id: u32) -> Option<u64> {
+    None
+}
+
+/// Run the program of a probe hit, marking this CPU as inside a handler.
+#[cfg(feature = "runtime")]
+fn run_from_handler(prog_id: u32, ctx: &mut [u8]) -> Result<u64, crate::runtime::Error> {
+    let outer = in_handler().swap(true, Ordering::Relaxed);
+    let result = crate::runtime::run_program(prog_id, Some(ctx));
+    in_handler().store(outer, Ordering::Relaxed);
+    result
+}
+
 /// Kprobe registry
 pub struct KprobeRegistry {
     /// Registered probe pairs by address.
@@ -203,7 +270,7 @@ impl KprobeRegistry {
         let slot_ro = Self::slot_ref(entry, is_ret)
             .as_ref()
             .ok_or("kprobe not found")?;
-        let prog_id = slot_ro.prog_id;
+        let prog_id = slot_ro.prog_id.clone();
         let already_enabled = slot_ro.state == KprobeState::Enabled;
         if already_enabled {
             return Ok(());
@@ -340,6 +407,21 @@ impl KprobeRegistry {
         Ok(())
     }
 
+    /// Unregister every slot that runs `prog_id`.
+    /// Returns the number of slots removed.
+    fn detach_program(&mut self, prog_id: u32) -> Result<usize, &'static str> {
+        let slots: Vec<(usize, bool)> = self
+            .list_flat()
+            .into_iter()
+            .filter(|slot| slot.5 == prog_id)
+            .map(|slot| (slot.1, slot.4))
+            .collect();
+        for &(addr, is_ret) in &slots {
+            self.unregister(addr, is_ret)?;
+        }
+        Ok(slots.len())
+    }
+
     /// Collect flat view used by shell command display.
     pub fn list_flat(&self) -> Vec<(String, usize, u64, bool, bool, u32)> {
         let mut out = Vec::new();
@@ -351,7 +433,7 @@ impl KprobeRegistry {
                     slot.hits,
                     slot.state == KprobeState::Enabled,
                     false,
-                    slot.prog_id,
+                    slot.prog_id.load(Ordering::Relaxed),
                 ));
             }
             if let Some(slot) = Self::slot_ref(entry, true).as_ref() {
@@ -361,13 +443,50 @@ impl KprobeRegistry {
                     slot.hits,
                     slot.state == KprobeState::Enabled,
                     true,
-                    slot.prog_id,
+                    slot.prog_id.load(Ordering::Relaxed),
                 ));
             }
         }
         out
     }
 
+    /// Point the slots of `name` that run `old_prog_id` at `new_prog_id`.
+    /// Returns the number of slots changed.
+    pub fn replace_program(
+        &mut self,
+        name: &str,
+        old_prog_id: u32,
+        new_prog_id: u32,
+    ) -> Result<usize, &'static str> {
+        let addr = self.get_addr_by_name(name).ok_or("kprobe not found")?;
+        let entry = self.probes.get(&addr).ok_or("kprobe not found")?;
+        let mut replaced = 0;
+        for slot in [&entry.entry_slot, &entry.ret_slot].into_iter().flatten() {
+            if slot
+                .prog_id
+                .compare_exchange(
+                    old_prog_id,
+                    new_prog_id,
+                    Ordering::Release,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                replaced += 1;
+            }
+        }
+        if replaced == 0 {
+            return Err("kprobe does not run this program");
+        }
+        log::info!(
+            "kprobe: {} now runs prog_id={} (was {})",
+            name,
+            new_prog_id,
+            old_prog_id
+        );
+        Ok(replaced)
+    }
+
     /// Record hits at breakpoint entry.
     /// Returns `(entry_slot_hit, ret_slot_hit)`.
     pub fn record_break_hit(&mut self, addr: usize) -> (bool, bool) {
@@ -403,15 +522,18 @@ fn kprobe_pre_handler(data: &dyn kprobe::ProbeData, pt_regs: &mut kprobe::PtRegs
 
     #[cfg(feature = "runtime")]
     {
+        let (_, fp) = frame_at(pt_regs);
         let ctx_bytes = unsafe {
             core::slice::from_raw_parts_mut(
                 pt_regs as *mut kprobe::PtRegs as *mut u8,
                 core::mem::size_of::<kprobe::PtRegs>(),
             )
         };
-        if let Err(e) = crate::runtime::run_program(ud.prog_id, Some(ctx_bytes)) {
+        crate::stack::set_current_frame(ctx_bytes.as_ptr() as u64, ud.probe_addr as u64, fp);
+        if let Err(e) = run_from_handler(ud.prog_id.load(Ordering::Acquire), ctx_bytes) {
             log::warn!("hprobe: eBPF execution failed at {:#x}: {:?}", ud.probe_addr, e);
         }
+        crate::stack::clear_current_frame();
 
         if crate::attach::is_verbose() {
             let regs = &*pt_regs;
@@ -442,15 +564,18 @@ fn kprobe_ret_handler(data: &dyn kprobe::ProbeData, pt_regs: &mut kprobe::PtRegs
 
     #[cfg(feature = "runtime")]
     {
+        let (pc, fp) = frame_at(pt_regs);
         let ctx_bytes = unsafe {
             core::slice::from_raw_parts_mut(
                 pt_regs as *mut kprobe::PtRegs as *mut u8,
                 core::mem::size_of::<kprobe::PtRegs>(),
             )
         };
-        if let Err(e) = crate::runtime::run_program(ud.prog_id, Some(ctx_bytes)) {
+        crate::stack::set_current_frame(ctx_bytes.as_ptr() as u64, pc, fp);
+        if let Err(e) = run_from_handler(ud.prog_id.load(Ordering::Acquire), ctx_bytes) {
             log::warn!("hretprobe: eBPF execution failed at {:#x}: {:?}", ud.probe_addr, e);
         }
+        crate::stack::clear_current_frame();
 
         if crate::attach::is_verbose() {
             let regs = &*pt_regs;
@@ -495,7 +620,15 @@ pub fn init() {
 }
 
 /// Register a kprobe by symbol name.
+///
+/// Fails if `prog_id` is not loaded.
 pub fn register(name: &str, prog_id: u32, is_ret: bool) -> Result<usize, &'static str> {
+    let _attach = crate::probe::lock_loaded(prog_id)?;
+    register_loaded(name, prog_id, is_ret)
+}
+
+/// [`register`] for a program already checked by `probe::lock_loaded`.
+fn register_loaded(name: &str, prog_id: u32, is_ret: bool) -> Result<usize, &'static str> {
     let mut registry = KPROBE_REGISTRY.lock();
     let registry = registry.as_mut().ok_or("kprobe subsystem not initialized")?;
     registry.register(name, prog_id, is_ret)
@@ -532,8 +665,17 @@ pub fn list_all() -> Vec<(String, usize, u64, bool, bool, u32)> {
 }
 
 /// Register and enable a kprobe by name.
+///
+/// Fails if `prog_id` is not loaded.
 pub fn attach(name: &str, prog_id: u32, is_ret: bool) -> Result<usize, &'static str> {
-    let addr = register(name, prog_id, is_ret)?;
+    let _attach = crate::probe::lock_loaded(prog_id)?;
+    attach_loaded(name, prog_id, is_ret)
+}
+
+/// [`attach`] for a program known to be loaded, with `attach::ATTACH_LOCK`
+/// held by the caller.
+pub(crate) fn attach_loaded(name: &str, prog_id: u32, is_ret: bool) -> Result<usize, &'static str> {
+    let addr = register_loaded(name, prog_id, is_ret)?;
     if let Err(e) = enable(addr, is_ret) {
         let _ = unregister(addr, is_ret);
         return Err(e);
@@ -548,6 +690,71 @@ pub fn detach(name: &str) -> Result<(), &'static str> {
     registry.unregister_by_name(name)
 }
 
+/// Unregister every slot that runs `prog_id`.
+///
+/// The registry is locked while an hprobe handler runs, so this does not
+/// wait for it from inside a handler (e.g. when the watchdog stops the
+/// program during its own probe hit). If the registry is busy there, the
+/// program is queued instead of deadlocking, and its slots are removed once
+/// the breakpoint handler has released the registry. Anywhere else it waits
+/// for the registry.
+///
+/// # Returns
+/// The number of slots removed now, 0 if the removal was deferred.
+pub fn try_detach_program(prog_id: u32) -> Result<usize, &'static str> {
+    let Some(mut registry) = lock_registry() else {
+        let generation = program_generation(prog_id).ok_or("program not loaded")?;
+        PENDING_DETACH.lock().push((prog_id, generation));
+        log::info!("kprobe: registry busy, detaching prog_id={} later", prog_id);
+        return Ok(0);
+    };
+    let registry = registry.as_mut().ok_or("kprobe subsystem not initialized")?;
+    registry.detach_program(prog_id)
+}
+
+/// Remove the slots of programs queued by [`try_detach_program`].
+///
+/// Called by the breakpoint handler once a hit is complete and the registry
+/// is unlocked, and by `runtime::unload_program` before it checks for
+/// attachments. Programs unloaded since they were queued are skipped, as
+/// their ID may belong to another program by now.
+pub(crate) fn run_pending_detaches() {
+    if PENDING_DETACH.lock().is_empty() {
+        return;
+    }
+    let Some(mut registry) = lock_registry() else {
+        return;
+    };
+    let Some(registry) = registry.as_mut() else {
+        return;
+    };
+    for (prog_id, generation) in core::mem::take(&mut *PENDING_DETACH.lock()) {
+        // A program cannot be unloaded while it has slots, and slots cannot
+        // be added while the registry is locked, so this check holds
+        if program_generation(prog_id) != Some(generation) {
+            continue;
+        }
+        match registry.detach_program(prog_id) {
+            Ok(n) => log::warn!("kprobe: detached prog_id={} from {} slots", prog_id, n),
+            Err(e) => log::warn!("kprobe: failed to detach prog_id={}: {}", prog_id, e),
+        }
+    }
+}
+
+/// Point the slots of `name` that run `old_prog_id` at `new_prog_id`.
+///
+/// Hits that already started finish on the old program; the next hit runs
+/// the new one.
+pub fn replace_program(
+    name: &str,
+    old_prog_id: u32,
+    new_prog_id: u32,
+) -> Result<usize, &'static str> {
+    let mut registry = KPROBE_REGISTRY.lock();
+    let registry = registry.as_mut().ok_or("kprobe subsystem not initialized")?;
+    registry.replace_program(name, old_prog_id, new_prog_id)
+}
+
 #[cfg(feature = "test-utils")]
 /// Test helper: register one slot using a synthetic address, bypassing symbol lookup.
 pub fn register_with_addr_for_test(
@@ -561,6 +768,30 @@ pub fn register_with_addr_for_test(
     registry.register_with_addr(name, addr, prog_id, is_ret)
 }
 
+#[cfg(feature = "test-utils")]
+/// Test helper: run the pre-handler of the entry slot at `addr` the way a
+/// breakpoint hit does, with the registry locked, then finish the hit as the
+/// single-step handler does.
+pub fn hit_for_test(addr: usize) -> Result<(), &'static str> {
+    {
+        let registry = KPROBE_REGISTRY.lock();
+        let registry = registry
+            .as_ref()
+            .ok_or("kprobe subsystem not initialized")?;
+        let entry = registry.probes.get(&addr).ok_or("kprobe not found")?;
+        let slot = entry.entry_slot.as_ref().ok_or("kprobe not found")?;
+        let data = HprobeUserData {
+            prog_id: slot.prog_id.clone(),
+            probe_addr: addr,
+            symbol: entry.name.clone(),
+        };
+        let mut pt_regs: kprobe::PtRegs = unsafe { core::mem::zeroed() };
+        kprobe_pre_handler(&data, &mut pt_regs);
+    }
+    run_pending_detaches();
+    Ok(())
+}
+
 #[cfg(all(feature = "test-utils", feature = "runtime", feature = "tracepoint-support"))]
 /// Test helper: emit one synthetic hretprobe event without trap handling.
 pub fn emit_hretprobe_event_for_test(probe_addr: usize, retval: u64) {
@@ -245,6 +245,32 @@ impl GuestKprobeRegistry {
             .or_else(|| self.probes.get(&(0, gva)))
     }
 
+    /// Point the probe at `vm_id:gva` from `old_prog_id` to `new_prog_id`.
+    pub fn replace_program(
+        &mut self,
+        vm_id: u32,
+        gva: u64,
+        old_prog_id: u32,
+        new_prog_id: u32,
+    ) -> Result<(), &'static str> {
+        let entry = self
+            .probes
+            .get_mut(&(vm_id, gva))
+            .ok_or("guest kprobe not found")?;
+        if entry.prog_id != old_prog_id {
+            return Err("guest kprobe does not run this program");
+        }
+        entry.prog_id = new_prog_id;
+        log::info!(
+            "guest_kprobe: vm{}:{:#x} now runs prog={} (was {})",
+            vm_id,
+            gva,
+            new_prog_id,
+            old_prog_id
+        );
+        Ok(())
+    }
+
     /// Record a hit.
     pub fn record_hit(&mut self, vm_id: u32, gva: u64) {
         if let Some(entry) = self.probes.get_mut(&(vm_id, gva)) {
@@ -355,12 +381,24 @@ pub fn clear_stage2_exec_hook_for_test() {
     *STAGE2_EXEC_HOOK.write() = None;
 }
 
+/// Register a guest kprobe. Fails if `prog_id` is not loaded.
 pub fn register(
     vm_id: u32,
     gva: u64,
     prog_id: u32,
     is_ret: bool,
     mode: KprobeMode,
+) -> Result<(), &'static str> {
+    let _attach = crate::probe::lock_loaded(prog_id)?;
+    register_loaded(vm_id, gva, prog_id, is_ret, mode)
+}
+
+fn register_loaded(
+    vm_id: u32,
+    gva: u64,
+    prog_id: u32,
+    is_ret: bool,
+    mode: KprobeMode,
 ) -> Result<(), &'static str> {
     let mut registry = GUEST_KPROBE_REGISTRY.lock();
     let registry = registry.as_mut().ok_or("guest kprobe not initialized")?;
@@ -385,6 +423,7 @@ pub fn unregister(vm_id: u32, gva: u64) -> Result<(), &'static str> {
     registry.unregister(vm_id, gva)
 }
 
+/// Register and enable a guest kprobe. Fails if `prog_id` is not loaded.
 pub fn attach(
     vm_id: u32,
     gva: u64,
@@ -392,7 +431,20 @@ pub fn attach(
     is_ret: bool,
     mode: KprobeMode,
 ) -> Result<(), &'static str> {
-    register(vm_id, gva, prog_id, is_ret, mode)?;
+    let _attach = crate::probe::lock_loaded(prog_id)?;
+    attach_loaded(vm_id, gva, prog_id, is_ret, mode)
+}
+
+/// [`attach`] for a program known to be loaded, with `attach::ATTACH_LOCK`
+/// held by the caller.
+pub(crate) fn attach_loaded(
+    vm_id: u32,
+    gva: u64,
+    prog_id: u32,
+    is_ret: bool,
+    mode: KprobeMode,
+) -> Result<(), &'static str> {
+    register_loaded(vm_id, gva, prog_id, is_ret, mode)?;
     if let Err(e) = enable(vm_id, gva) {
         let _ = unregister(vm_id, gva);
         return Err(e);
@@ -404,6 +456,46 @@ pub fn detach(vm_id: u32, gva: u64) -> Result<(), &'static str> {
     unregister(vm_id, gva)
 }
 
+/// Point the probe at `vm_id:gva` from `old_prog_id` to `new_prog_id`.
+///
+/// The handler looks the program up on every hit, so the next hit runs the
+/// new program while hits already running finish on the old one.
+pub fn replace_program(
+    vm_id: u32,
+    gva: u64,
+    old_prog_id: u32,
+    new_prog_id: u32,
+) -> Result<(), &'static str> {
+    let mut registry = GUEST_KPROBE_REGISTRY.lock();
+    let registry = registry.as_mut().ok_or("guest kprobe not initialized")?;
+    registry.replace_program(vm_id, gva, old_prog_id, new_prog_id)
+}
+
+/// Detach every probe that runs `prog_id`. Returns the number detached.
+pub fn detach_program(prog_id: u32) -> usize {
+    let keys: Vec<ProbeKey> = {
+        let registry = GUEST_KPROBE_REGISTRY.lock();
+        match registry.as_ref() {
+            Some(r) => r
+                .list()
+                .iter()
+                .filter(|e| e.prog_id == prog_id)
+                .map(|e| (e.vm_id, e.gva))
+                .collect(),
+            None => Vec::new(),
+        }
+    };
+    keys.iter()
+        .filter(|&&(vm_id, gva)| match detach(vm_id, gva) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("guest_kprobe: detach vm{}:{:#x} failed: {}", vm_id, gva, e);
+                false
+            }
+        })
+        .count()
+}
+
 /// Recover from stale BRK traps after probe detach.
 ///
 /// Returns `true` when a stale BRK trap was matched and recovered, and
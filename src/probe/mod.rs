@@ -13,6 +13,25 @@ pub mod hprobe;
 #[cfg(feature = "guest-kprobe")]
 pub mod kprobe;
 
+/// Check that `prog_id` is loaded before attaching it to a probe.
+///
+/// The returned guard holds `attach::ATTACH_LOCK`, so the program cannot be
+/// unloaded until the probe is in place.
+#[cfg(feature = "runtime")]
+pub(crate) fn lock_loaded(prog_id: u32) -> Result<spin::MutexGuard<'static, ()>, &'static str> {
+    let guard = crate::attach::ATTACH_LOCK.lock();
+    if crate::runtime::get_program(prog_id).is_none() {
+        return Err("program not loaded");
+    }
+    Ok(guard)
+}
+
+/// Without the runtime there are no programs to check.
+#[cfg(not(feature = "runtime"))]
+pub(crate) fn lock_loaded(_prog_id: u32) -> Result<(), &'static str> {
+    Ok(())
+}
+
 /// Probe type classification by privilege level and direction.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
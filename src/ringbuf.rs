@@ -0,0 +1,320 @@
+//! Ring buffer maps.
+//!
+//! Records of `MapType::RingBuf` maps are kept here rather than in
+//! kbpf-basic, so programs can reserve space with `bpf_ringbuf_reserve`
+//! and fill it in place. The record format follows Linux: an 8-byte header
+//! (the data length with busy and discard bits, then 4 unused bytes)
+//! followed by the data, padded to 8 bytes. Linux maps the data pages twice
+//! so records can wrap around the end; here a record that would wrap is
+//! placed at the start instead, after a discarded filler record.
+//!
+//! Records are consumed in order. A record that is reserved but not yet
+//! submitted holds back the ones after it, so the runtime discards any
+//! record a program leaves reserved when it returns.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ops::Range;
+use core::sync::atomic::{AtomicU32, Ordering};
+use spin::{Mutex, RwLock};
+
+use crate::maps::Error;
+use crate::platform::MAX_CPUS;
+
+/// Header bit of a record that is reserved but not submitted.
+pub const BPF_RINGBUF_BUSY_BIT: u32 = 1 << 31;
+/// Header bit of a discarded record.
+pub const BPF_RINGBUF_DISCARD_BIT: u32 = 1 << 30;
+/// Size of a record header.
+pub const BPF_RINGBUF_HDR_SZ: u32 = 8;
+
+/// Do not notify the consumer. Accepted; the consumer polls.
+pub const BPF_RB_NO_WAKEUP: u64 = 1;
+/// Notify the consumer. Accepted; the consumer polls.
+pub const BPF_RB_FORCE_WAKEUP: u64 = 2;
+
+/// `bpf_ringbuf_query`: bytes not consumed yet.
+pub const BPF_RB_AVAIL_DATA: u64 = 0;
+/// `bpf_ringbuf_query`: size of the data area.
+pub const BPF_RB_RING_SIZE: u64 = 1;
+/// `bpf_ringbuf_query`: consumer position.
+pub const BPF_RB_CONS_POS: u64 = 2;
+/// `bpf_ringbuf_query`: producer position.
+pub const BPF_RB_PROD_POS: u64 = 3;
+
+/// Smallest ring size, and the granularity of ring sizes.
+const PAGE_SIZE: u32 = 4096;
+
+/// One ring. Positions count bytes written since creation; the data offset
+/// is the position masked to the ring size.
+struct RingBuffer {
+    /// Data area, as u64 so records are 8-byte aligned.
+    data: Vec<u64>,
+    consumer_pos: u64,
+    producer_pos: u64,
+    /// Reserved records as (position, CPU).
+    pending: Vec<(u64, u32)>,
+}
+
+impl RingBuffer {
+    fn size(&self) -> u64 {
+        self.data.len() as u64 * 8
+    }
+
+    fn range(&self) -> Range<u64> {
+        let start = self.data.as_ptr() as u64;
+        start..start + self.size()
+    }
+
+    fn header(&mut self, pos: u64) -> &mut u32 {
+        let index = ((pos & (self.size() - 1)) / 8) as usize;
+        // SAFETY: the header is the low half of an aligned u64.
+        unsafe { &mut *(self.data.as_mut_ptr().add(index) as *mut u32) }
+    }
+
+    /// Reserve `len` bytes; returns the position of the record header.
+    fn reserve(&mut self, len: u32, cpu: u32) -> Option<u64> {
+        if len >= BPF_RINGBUF_DISCARD_BIT {
+            return None;
+        }
+        let size = self.size();
+        let total = (len as u64 + BPF_RINGBUF_HDR_SZ as u64).next_multiple_of(8);
+        let offset = self.producer_pos & (size - 1);
+        let pad = if offset + total > size {
+            size - offset
+        } else {
+            0
+        };
+        if self.producer_pos + pad + total - self.consumer_pos > size {
+            return None;
+        }
+
+        if pad != 0 {
+            let filler = (pad - BPF_RINGBUF_HDR_SZ as u64) as u32;
+            *self.header(self.producer_pos) = filler | BPF_RINGBUF_DISCARD_BIT;
+            self.producer_pos += pad;
+        }
+        let pos = self.producer_pos;
+        *self.header(pos) = len | BPF_RINGBUF_BUSY_BIT;
+        self.producer_pos += total;
+        self.pending.push((pos, cpu));
+        PENDING[cpu as usize].fetch_add(1, Ordering::Relaxed);
+        Some(pos)
+    }
+
+    /// Submit or discard the reserved record at `pos`.
+    fn commit(&mut self, pos: u64, discard: bool) -> bool {
+        let Some(i) = self.pending.iter().position(|&(p, _)| p == pos) else {
+            return false;
+        };
+        let (_, cpu) = self.pending.swap_remove(i);
+        PENDING[cpu as usize].fetch_sub(1, Ordering::Relaxed);
+
+        let header = self.header(pos);
+        *header &= !BPF_RINGBUF_BUSY_BIT;
+        if discard {
+            *header |= BPF_RINGBUF_DISCARD_BIT;
+        }
+        true
+    }
+
+    /// Pop the next submitted record, skipping discarded ones.
+    fn consume(&mut self) -> Option<Vec<u8>> {
+        while self.consumer_pos < self.producer_pos {
+            let pos = self.consumer_pos;
+            let header = *self.header(pos);
+            if header & BPF_RINGBUF_BUSY_BIT != 0 {
+                return None;
+            }
+            let len = header & !BPF_RINGBUF_DISCARD_BIT;
+            self.consumer_pos += (len as u64 + BPF_RINGBUF_HDR_SZ as u64).next_multiple_of(8);
+            if header & BPF_RINGBUF_DISCARD_BIT == 0 {
+                let start = self.record_ptr(pos);
+                // SAFETY: records never wrap, see `reserve`.
+                let data = unsafe { core::slice::from_raw_parts(start, len as usize) };
+                return Some(data.to_vec());
+            }
+        }
+        None
+    }
+
+    fn record_ptr(&mut self, pos: u64) -> *mut u8 {
+        let offset = (pos & (self.size() - 1)) + BPF_RINGBUF_HDR_SZ as u64;
+        unsafe { (self.data.as_mut_ptr() as *mut u8).add(offset as usize) }
+    }
+}
+
+/// A ring with a lock of its own, so producers on different rings do not
+/// contend. The data area never moves, so its range is kept outside the lock
+/// to find the ring of a reserved record.
+struct RingBufMap {
+    range: Range<u64>,
+    ring: Mutex<RingBuffer>,
+}
+
+/// Rings by map ID. Only written when rings are created or destroyed.
+static RINGBUFS: RwLock<BTreeMap<u32, RingBufMap>> = RwLock::new(BTreeMap::new());
+
+/// Records reserved and not yet committed, per CPU.
+static PENDING: [AtomicU32; MAX_CPUS as usize] = [const { AtomicU32::new(0) }; MAX_CPUS as usize];
+
+fn cpu_slot() -> u32 {
+    crate::platform::cpu_id() % MAX_CPUS
+}
+
+// =============================================================================
+// Map Lifecycle
+// =============================================================================
+
+/// Allocate the ring of map `map_id`. `size` must be a power of two and a
+/// multiple of the page size.
+pub(crate) fn create(map_id: u32, size: u32) -> Result<(), Error> {
+    if !size.is_power_of_two() || size < PAGE_SIZE {
+        return Err(Error::InvalidArgument);
+    }
+    let ring = RingBuffer {
+        data: vec![0u64; size as usize / 8],
+        consumer_pos: 0,
+        producer_pos: 0,
+        pending: Vec::new(),
+    };
+    let range = ring.range();
+    let ring = Mutex::new(ring);
+    RINGBUFS.write().insert(map_id, RingBufMap { range, ring });
+    Ok(())
+}
+
+/// Free the ring of map `map_id`.
+pub(crate) fn destroy(map_id: u32) {
+    if let Some(map) = RINGBUFS.write().remove(&map_id) {
+        for (_, cpu) in map.ring.into_inner().pending {
+            PENDING[cpu as usize].fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Address range of the data area, where reserved records live.
+///
+/// Programs that reserve records need the range allowed in their VM.
+pub(crate) fn data_range(map_id: u32) -> Option<Range<u64>> {
+    RINGBUFS.read().get(&map_id).map(|map| map.range.clone())
+}
+
+// =============================================================================
+// Producer Side
+// =============================================================================
+
+/// Reserve a `len`-byte record in map `map_id`.
+///
+/// # Returns
+/// Pointer to the record data, or None if the map is not a ring buffer or
+/// has no room. The record must be passed to [`commit`].
+pub(crate) fn reserve(map_id: u32, len: u32) -> Option<*mut u8> {
+    let rings = RINGBUFS.read();
+    let mut ring = rings.get(&map_id)?.ring.lock();
+    let pos = ring.reserve(len, cpu_slot())?;
+    Some(ring.record_ptr(pos))
+}
+
+/// Submit (or discard) a record returned by [`reserve`].
+///
+/// # Returns
+/// `false` if `data` is not a reserved record.
+pub(crate) fn commit(data: u64, discard: bool) -> bool {
+    let rings = RINGBUFS.read();
+    for RingBufMap { range, ring } in rings.values() {
+        if range.contains(&data) {
+            let mut ring = ring.lock();
+            let Some(header_off) = (data - range.start).checked_sub(BPF_RINGBUF_HDR_SZ as u64)
+            else {
+                return false;
+            };
+            let Some(&(pos, _)) = ring
+                .pending
+                .iter()
+                .find(|&&(p, _)| p & (ring.size() - 1) == header_off)
+            else {
+                return false;
+            };
+            return ring.commit(pos, discard);
+        }
+    }
+    false
+}
+
+/// Copy `data` into a new record of map `map_id`.
+pub(crate) fn output(map_id: u32, data: &[u8]) -> Result<(), Error> {
+    let len = u32::try_from(data.len()).map_err(|_| Error::InvalidArgument)?;
+    let rings = RINGBUFS.read();
+    let map = rings.get(&map_id).ok_or(Error::InvalidArgument)?;
+    let mut ring = map.ring.lock();
+    let pos = ring.reserve(len, cpu_slot()).ok_or(Error::NoSpace)?;
+    let dst = ring.record_ptr(pos);
+    // SAFETY: the record has room for `len` bytes.
+    unsafe { core::ptr::copy_nonoverlapping(data.as_ptr(), dst, data.len()) };
+    ring.commit(pos, false);
+    Ok(())
+}
+
+/// `bpf_ringbuf_query` value for `flag`, 0 for unknown flags.
+pub(crate) fn query(map_id: u32, flag: u64) -> Option<u64> {
+    let rings = RINGBUFS.read();
+    let ring = rings.get(&map_id)?.ring.lock();
+    Some(match flag {
+        BPF_RB_AVAIL_DATA => ring.producer_pos - ring.consumer_pos,
+        BPF_RB_RING_SIZE => ring.size(),
+        BPF_RB_CONS_POS => ring.consumer_pos,
+        BPF_RB_PROD_POS => ring.producer_pos,
+        _ => 0,
+    })
+}
+
+/// Discard the records left reserved on this CPU. Called by the runtime
+/// after each program run.
+pub(crate) fn discard_pending() {
+    let cpu = cpu_slot();
+    if PENDING[cpu as usize].load(Ordering::Relaxed) == 0 {
+        return;
+    }
+    let rings = RINGBUFS.read();
+    for map in rings.values() {
+        let mut ring = map.ring.lock();
+        let leaked: Vec<u64> = ring
+            .pending
+            .iter()
+            .filter(|&&(_, c)| c == cpu)
+            .map(|&(pos, _)| pos)
+            .collect();
+        for pos in leaked {
+            log::debug!("ringbuf: discarding record left reserved at {}", pos);
+            ring.commit(pos, true);
+        }
+    }
+}
+
+// =============================================================================
+// Consumer Side
+// =============================================================================
+
+/// Pop up to `max_records` submitted records (0 for no limit), oldest first.
+pub(crate) fn consume(map_id: u32, max_records: usize) -> Vec<Vec<u8>> {
+    let limit = if max_records == 0 {
+        usize::MAX
+    } else {
+        max_records
+    };
+    let mut records = Vec::new();
+    let rings = RINGBUFS.read();
+    let Some(map) = rings.get(&map_id) else {
+        return records;
+    };
+    let mut ring = map.ring.lock();
+    while records.len() < limit {
+        match ring.consume() {
+            Some(record) => records.push(record),
+            None => break,
+        }
+    }
+    records
+}
@@ -2,22 +2,28 @@
 //!
 //! Provides VM for running eBPF programs with registered helpers.
 
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
 use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
-use spin::Mutex;
+use core::sync::atomic::{AtomicU8, AtomicU64, AtomicUsize, Ordering};
+use rbpf::EbpfVmRaw;
+use spin::{Mutex, RwLock};
 
 use crate::helpers;
+use crate::section::{AttachTarget, ProgramType, SectionSpec};
+use crate::watchdog::{self, Budget, Watchdog};
 
 /// Error types for eBPF runtime operations.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum Error {
     /// The eBPF program is invalid or malformed.
     InvalidProgram,
     /// Program execution failed.
     ExecutionFailed,
-    /// Verification of the program failed.
-    VerificationFailed,
+    /// The static verifier rejected the program.
+    VerificationFailed(crate::verifier::VerifierError),
     /// Program not found in registry.
     NotFound,
     /// ELF parsing failed.
@@ -26,6 +32,15 @@ pub enum Error {
     MapCreationFailed,
     /// Relocation failed.
     RelocationFailed,
+    /// A global variable passed to `ObjectLoader::set_global` does not exist
+    /// or has a different size.
+    InvalidGlobal(String),
+    /// The invocation ran out of its instruction or time budget.
+    BudgetExceeded,
+    /// The watchdog disabled the program after repeated budget violations.
+    Disabled,
+    /// The program is still attached to this many tracepoints or probes.
+    Attached(usize),
 }
 
 impl core::fmt::Display for Error {
@@ -33,24 +48,38 @@ impl core::fmt::Display for Error {
         match self {
             Self::InvalidProgram => write!(f, "Invalid eBPF program"),
             Self::ExecutionFailed => write!(f, "eBPF execution failed"),
-            Self::VerificationFailed => write!(f, "eBPF verification failed"),
+            Self::VerificationFailed(e) => write!(f, "eBPF verification failed: {}", e),
             Self::NotFound => write!(f, "Program not found"),
             Self::ElfParseError => write!(f, "ELF parse error"),
             Self::MapCreationFailed => write!(f, "Map creation failed"),
             Self::RelocationFailed => write!(f, "Relocation failed"),
+            Self::InvalidGlobal(name) => write!(f, "Invalid global variable '{}'", name),
+            Self::BudgetExceeded => write!(f, "Execution budget exceeded"),
+            Self::Disabled => write!(f, "Program disabled by watchdog"),
+            Self::Attached(n) => write!(f, "Program still has {} attachments", n),
         }
     }
 }
 
 impl core::error::Error for Error {}
 
-/// Result of parsing ELF with Maps.
+/// One program extracted from an ELF object.
 #[derive(Debug)]
-struct ElfParseResult {
+struct ElfProgram {
+    /// Function name of the program.
+    name: String,
+    /// ELF section the program was found in (e.g. `kprobe/foo`).
+    section: String,
     /// Extracted bytecode (already patched if Maps present)
     bytecode: Vec<u8>,
-    /// Created Map FDs: (symbol_name, map_fd)
-    map_fds: Vec<(String, u32)>,
+}
+
+/// Result of parsing ELF with Maps.
+struct ElfParseResult {
+    /// All programs in the object, in aya-obj order.
+    programs: Vec<ElfProgram>,
+    /// Created Maps, shared by every program of the object.
+    maps: Arc<SharedMapFds>,
 }
 
 // =============================================================================
@@ -65,25 +94,91 @@ fn is_elf(data: &[u8]) -> bool {
     data.len() >= 4 && data[0..4] == ELF_MAGIC
 }
 
+/// Byte ranges of section names in a little-endian ELF64 file, indexed by
+/// section number. Returns an empty list if the headers are malformed.
+fn elf_section_name_ranges(data: &[u8]) -> Vec<core::ops::Range<usize>> {
+    let u16_at = |off: usize| -> Option<usize> {
+        Some(u16::from_le_bytes(data.get(off..off + 2)?.try_into().ok()?) as usize)
+    };
+    let u32_at = |off: usize| -> Option<usize> {
+        Some(u32::from_le_bytes(data.get(off..off + 4)?.try_into().ok()?) as usize)
+    };
+    let u64_at = |off: usize| -> Option<usize> {
+        Some(u64::from_le_bytes(data.get(off..off + 8)?.try_into().ok()?) as usize)
+    };
+
+    let read = || -> Option<Vec<core::ops::Range<usize>>> {
+        let shoff = u64_at(0x28)?;
+        let shentsize = u16_at(0x3a)?;
+        let shnum = u16_at(0x3c)?;
+        let shstrndx = u16_at(0x3e)?;
+
+        let header = |idx: usize| shoff.checked_add(idx.checked_mul(shentsize)?);
+        let strtab = u64_at(header(shstrndx)? + 0x18)?;
+
+        (0..shnum)
+            .map(|idx| {
+                let start = strtab.checked_add(u32_at(header(idx)?)?)?;
+                let len = data.get(start..)?.iter().position(|&b| b == 0)?;
+                Some(start..start + len)
+            })
+            .collect()
+    };
+
+    read().unwrap_or_default()
+}
+
+/// Read section names of an ELF file, indexed by section number.
+///
+/// aya-obj only keeps parsed program section kinds, so the raw names are read
+/// from the section header table directly.
+fn elf_section_names(data: &[u8]) -> Vec<String> {
+    elf_section_name_ranges(data)
+        .into_iter()
+        .map(|range| String::from_utf8_lossy(&data[range]).into_owned())
+        .collect()
+}
+
+/// Rename AxVisor-specific program sections to aliases aya-obj accepts.
+///
+/// Aliases have the same length, so names are patched in place.
+/// Returns the number of renamed sections.
+fn alias_section_names(data: &mut [u8]) -> usize {
+    let mut renamed = 0;
+    for range in elf_section_name_ranges(data) {
+        let name = &mut data[range];
+        for (prefix, alias) in crate::section::AYA_SECTION_ALIASES {
+            if name.starts_with(prefix) {
+                name[..alias.len()].copy_from_slice(alias);
+                renamed += 1;
+                break;
+            }
+        }
+    }
+    renamed
+}
+
 /// Parse ELF file using aya-obj with full relocation support.
 ///
 /// Handles:
 /// - `.text` section merging (memcpy/memmove/memset)
 /// - `R_BPF_64_64` map fd relocations
 /// - `R_BPF_64_32` function call relocations (BPF-to-BPF)
+/// - callback references (`lddw` of a function, as passed to `bpf_loop`)
 /// - BTF-defined and legacy map sections
+/// - `.data`/`.rodata`/`.bss` global variables as single-entry Array maps
+///
+/// Every program in the object is extracted; all of them reference the same
+/// set of Maps. `globals` overrides initial values of global variables.
 fn parse_elf_with_aya(
     elf_data: &[u8],
-    prog_name: Option<&str>,
+    globals: &[(String, Vec<u8>)],
+    reuse: Option<&SharedMapFds>,
 ) -> Result<ElfParseResult, Error> {
     use aya_obj::Object;
     use hashbrown::HashSet;
 
-    log::debug!(
-        "Parsing ELF with aya-obj, size={} bytes, prog_name={:?}",
-        elf_data.len(),
-        prog_name
-    );
+    log::debug!("Parsing ELF with aya-obj, size={} bytes", elf_data.len());
 
     // Phase 1: Parse ELF
     //
@@ -91,10 +186,28 @@ fn parse_elf_with_aya(
     // to `align_of::<FileHeader64>()` (8 bytes on aarch64). Data from
     // `include_bytes!()` is placed in .rodata with no alignment guarantee,
     // so we must copy to an aligned buffer when the pointer is misaligned.
+    //
+    // aya-obj also rejects section prefixes it does not know (`hprobe/` etc.),
+    // so those are renamed in a copy; the real names are read from `elf_data`.
+    let needs_alias = elf_section_names(elf_data).iter().any(|name| {
+        crate::section::AYA_SECTION_ALIASES
+            .iter()
+            .any(|(prefix, _)| name.as_bytes().starts_with(prefix))
+    });
+
     let aligned_buf;
-    let parse_data = if (elf_data.as_ptr() as usize) % 8 != 0 {
-        log::debug!("ELF data not 8-byte aligned (ptr={:#x}), copying to aligned buffer", elf_data.as_ptr() as usize);
-        aligned_buf = elf_data.to_vec();
+    let parse_data = if (elf_data.as_ptr() as usize) % 8 != 0 || needs_alias {
+        log::debug!(
+            "Copying ELF data (ptr={:#x}, needs_alias={})",
+            elf_data.as_ptr() as usize,
+            needs_alias
+        );
+        let mut buf = elf_data.to_vec();
+        if needs_alias {
+            let renamed = alias_section_names(&mut buf);
+            log::debug!("Renamed {} sections to aya-obj aliases", renamed);
+        }
+        aligned_buf = buf;
         aligned_buf.as_slice()
     } else {
         elf_data
@@ -112,25 +225,47 @@ fn parse_elf_with_aya(
         obj.functions.len()
     );
 
+    // Phase 1b: Patch global variables
+    //
+    // Must happen before the data section maps are created below, since
+    // their initial contents come from the patched section data.
+    for (name, value) in globals {
+        let mut patch = hashbrown::HashMap::new();
+        patch.insert(name.as_str(), (value.as_slice(), true));
+        obj.patch_map_data(patch).map_err(|e| {
+            log::warn!("Failed to set global '{}': {e:?}", name);
+            Error::InvalidGlobal(name.clone())
+        })?;
+        log::debug!("Set global '{}' ({} bytes)", name, value.len());
+    }
+
     // Phase 2: Create maps from aya-obj descriptors
-    let mut map_fds: Vec<(String, u32)> = Vec::new();
+    //
+    // Maps are owned by `SharedMapFds` from the start, so every error path
+    // below destroys the maps created so far when it returns. Maps named
+    // like one in `reuse` are shared with it instead, contents included.
+    let mut maps = SharedMapFds::default();
 
     for (name, map) in &obj.maps {
         let map_type = match map.map_type() {
             1 => crate::maps::MapType::HashMap,   // BPF_MAP_TYPE_HASH
             2 => crate::maps::MapType::Array,      // BPF_MAP_TYPE_ARRAY
+            3 => crate::maps::MapType::ProgArray,  // BPF_MAP_TYPE_PROG_ARRAY
+            4 => crate::maps::MapType::PerfEventArray, // BPF_MAP_TYPE_PERF_EVENT_ARRAY
+            5 => crate::maps::MapType::PerCpuHash, // BPF_MAP_TYPE_PERCPU_HASH
+            6 => crate::maps::MapType::PerCpuArray, // BPF_MAP_TYPE_PERCPU_ARRAY
+            7 => crate::maps::MapType::StackTrace, // BPF_MAP_TYPE_STACK_TRACE
             9 => crate::maps::MapType::LruHash,    // BPF_MAP_TYPE_LRU_HASH
+            11 => crate::maps::MapType::LpmTrie,   // BPF_MAP_TYPE_LPM_TRIE
             22 => crate::maps::MapType::Queue,     // BPF_MAP_TYPE_QUEUE
+            23 => crate::maps::MapType::Stack,     // BPF_MAP_TYPE_STACK
             27 => crate::maps::MapType::RingBuf,   // BPF_MAP_TYPE_RINGBUF
+            30 => crate::maps::MapType::BloomFilter, // BPF_MAP_TYPE_BLOOM_FILTER
             unsupported => {
                 log::warn!(
-                    "map '{}': unsupported BPF map type {} (supported: Hash=1, Array=2, LRU=9, Queue=22, RingBuf=27)",
+                    "map '{}': unsupported BPF map type {} (supported: Hash=1, Array=2, ProgArray=3, PerfEventArray=4, PerCpuHash=5, PerCpuArray=6, StackTrace=7, LRU=9, LpmTrie=11, Queue=22, Stack=23, RingBuf=27, BloomFilter=30)",
                     name, unsupported
                 );
-                // Cleanup already created maps
-                for (_, fd) in &map_fds {
-                    let _ = crate::maps::destroy(*fd);
-                }
                 return Err(Error::MapCreationFailed);
             }
         };
@@ -139,19 +274,55 @@ fn parse_elf_with_aya(
             map_type,
             key_size: map.key_size(),
             value_size: map.value_size(),
-            max_entries: map.max_entries(),
+            // Perf event arrays left unsized get one slot per CPU
+            max_entries: match (map_type, map.max_entries()) {
+                (crate::maps::MapType::PerfEventArray, 0) => crate::platform::num_cpus(),
+                (_, max_entries) => max_entries,
+            },
+            // `.rodata` sections come with BPF_F_RDONLY_PROG set
+            map_flags: map.map_flags(),
         };
 
+        if let Some(owner) = reuse.and_then(|r| r.owner(name)) {
+            if crate::map_ops::get_map_def(owner.fd).as_ref() != Some(&def) {
+                log::warn!(
+                    "map '{}' does not match map {} it would reuse",
+                    name,
+                    owner.fd
+                );
+                return Err(Error::MapCreationFailed);
+            }
+            log::info!("Reusing map '{}' with fd {}", name, owner.fd);
+            maps.map_fds.push((name.clone(), owner.fd));
+            maps.owners.push(owner.clone());
+            continue;
+        }
+
         match crate::maps::create(&def) {
             Ok(fd) => {
                 log::info!("Created map '{}' with fd {}", name, fd);
-                map_fds.push((name.clone(), fd));
+                maps.map_fds.push((name.clone(), fd));
+                maps.owners.push(Arc::new(OwnedMap {
+                    name: name.clone(),
+                    fd,
+                }));
+
+                // Data sections carry their initial contents (index 0)
+                if !map.data().is_empty() {
+                    crate::maps::update_elem(fd, &0u32.to_le_bytes(), map.data(), 0).map_err(
+                        |e| {
+                            log::warn!("Failed to initialize map '{}': {:?}", name, e);
+                            Error::MapCreationFailed
+                        },
+                    )?;
+                    // Read-only data stays constant, as libbpf freezes it
+                    if def.map_flags & crate::maps::BPF_F_RDONLY_PROG != 0 {
+                        crate::maps::freeze(fd).map_err(|_| Error::MapCreationFailed)?;
+                    }
+                }
             }
             Err(e) => {
                 log::warn!("Failed to create map '{}': {:?}", name, e);
-                for (_, fd) in &map_fds {
-                    let _ = crate::maps::destroy(*fd);
-                }
                 return Err(Error::MapCreationFailed);
             }
         }
@@ -168,13 +339,14 @@ fn parse_elf_with_aya(
         .map(|(section_index, _)| *section_index)
         .collect();
 
-    if !map_fds.is_empty() || !text_sections.is_empty() {
+    if !maps.map_fds.is_empty() || !text_sections.is_empty() {
         // Take maps out of obj to avoid borrow conflict:
         // relocate_maps needs &mut self, but also needs &Map references.
         // By taking maps out, we can pass &Map refs without borrowing obj immutably.
         let taken_maps = core::mem::take(&mut obj.maps);
 
-        let maps_for_reloc: Vec<(&str, core::ffi::c_int, &aya_obj::maps::Map)> = map_fds
+        let maps_for_reloc: Vec<(&str, core::ffi::c_int, &aya_obj::maps::Map)> = maps
+            .map_fds
             .iter()
             .filter_map(|(name, fd)| {
                 taken_maps.get(name.as_str()).map(|map| {
@@ -190,9 +362,6 @@ fn parse_elf_with_aya(
 
         reloc_result.map_err(|e| {
             log::warn!("aya-obj map relocation error: {e:?}");
-            for (_, fd) in &map_fds {
-                let _ = crate::maps::destroy(*fd);
-            }
             Error::RelocationFailed
         })?;
     }
@@ -204,51 +373,62 @@ fn parse_elf_with_aya(
     // imm fields with correct relative offsets.
     obj.relocate_calls(&text_sections).map_err(|e| {
         log::warn!("aya-obj call relocation error: {e:?}");
-        for (_, fd) in &map_fds {
-            let _ = crate::maps::destroy(*fd);
-        }
         Error::RelocationFailed
     })?;
 
-    // Phase 4: Select program and extract bytecode
-    let program = match prog_name {
-        Some(name) => obj.programs.get(name).ok_or_else(|| {
-            log::warn!("Program '{}' not found in ELF (available: {:?})",
-                name, obj.programs.keys().collect::<Vec<_>>());
-            Error::NotFound
-        })?,
-        None => obj.programs.values().next().ok_or_else(|| {
-            log::warn!("No programs found in ELF");
+    // Phase 4: Extract bytecode of every program
+    let section_names = elf_section_names(elf_data);
+    let mut programs = Vec::with_capacity(obj.programs.len());
+
+    for (name, program) in &obj.programs {
+        let func_key = (program.section_index, program.address);
+
+        let function = obj.functions.get(&func_key).ok_or_else(|| {
+            log::warn!(
+                "Function for program '{}' not found (key: {:?})",
+                name,
+                func_key
+            );
             Error::ElfParseError
-        })?,
-    };
+        })?;
 
-    let func_key = (program.section_index, program.address);
+        // Convert Vec<bpf_insn> to Vec<u8>
+        // bpf_insn is #[repr(C)], 8 bytes each, safe to reinterpret as bytes.
+        let insn_count = function.instructions.len();
+        let bytecode: Vec<u8> = unsafe {
+            core::slice::from_raw_parts(function.instructions.as_ptr() as *const u8, insn_count * 8)
+        }
+        .to_vec();
 
-    let function = obj.functions.get(&func_key).ok_or_else(|| {
-        log::warn!("Function for program not found (key: {:?})", func_key);
-        Error::ElfParseError
-    })?;
+        let section = section_names
+            .get(program.section_index)
+            .cloned()
+            .unwrap_or_default();
 
-    // Convert Vec<bpf_insn> to Vec<u8>
-    // bpf_insn is #[repr(C)], 8 bytes each, safe to reinterpret as bytes.
-    let insn_count = function.instructions.len();
-    let bytecode: Vec<u8> = unsafe {
-        core::slice::from_raw_parts(
-            function.instructions.as_ptr() as *const u8,
-            insn_count * 8,
-        )
+        log::debug!(
+            "Extracted program '{}' (section '{}'): {} instructions ({} bytes)",
+            name,
+            section,
+            insn_count,
+            bytecode.len()
+        );
+
+        programs.push(ElfProgram {
+            name: name.clone(),
+            section,
+            bytecode,
+        });
     }
-    .to_vec();
 
-    log::debug!(
-        "Extracted program bytecode: {} instructions ({} bytes), {} maps",
-        insn_count,
-        bytecode.len(),
-        map_fds.len()
-    );
+    if programs.is_empty() {
+        log::warn!("No programs found in ELF");
+        return Err(Error::ElfParseError);
+    }
 
-    Ok(ElfParseResult { bytecode, map_fds })
+    Ok(ElfParseResult {
+        programs,
+        maps: Arc::new(maps),
+    })
 }
 
 // =============================================================================
@@ -257,74 +437,743 @@ fn parse_elf_with_aya(
 
 /// Shared Map ownership for cloned programs.
 /// Maps are only destroyed when the last reference is dropped.
+#[derive(Default)]
 struct SharedMapFds {
     map_fds: Vec<(String, u32)>,
+    /// One owner per entry of `map_fds`; shared with replaced programs
+    /// whose maps were reused.
+    owners: Vec<Arc<OwnedMap>>,
+}
+
+impl SharedMapFds {
+    fn owner(&self, name: &str) -> Option<&Arc<OwnedMap>> {
+        self.owners.iter().find(|m| m.name == name)
+    }
+}
+
+/// A map created by the loader.
+struct OwnedMap {
+    name: String,
+    fd: u32,
+}
+
+impl Drop for OwnedMap {
+    fn drop(&mut self) {
+        // Auto-cleanup the Map when its last user is dropped
+        if let Err(e) = crate::maps::destroy(self.fd) {
+            log::warn!(
+                "Failed to destroy map '{}' (fd={}): {:?}",
+                self.name,
+                self.fd,
+                e
+            );
+        } else {
+            log::debug!("Destroyed map '{}' (fd={})", self.name, self.fd);
+        }
+    }
 }
 
-impl Drop for SharedMapFds {
+/// References on the maps a program loads with `lddw`, like Linux
+/// `used_maps`. The program has their value addresses baked in, so the maps
+/// cannot be destroyed while it is loaded.
+struct UsedMaps(Vec<u32>);
+
+impl UsedMaps {
+    /// Take a reference on every existing map `bytecode` refers to. Fds of
+    /// missing maps are left for the verifier to reject.
+    fn hold(bytecode: &[u8]) -> Self {
+        use crate::verifier::{INSN_SIZE, Insn, LD_DW_IMM, PSEUDO_MAP_FD, PSEUDO_MAP_VALUE};
+
+        let mut used = Self(Vec::new());
+        let len = bytecode.len() / INSN_SIZE;
+        let mut idx = 0;
+        while idx + 1 < len {
+            let insn = Insn::decode(bytecode, idx);
+            if insn.op != LD_DW_IMM {
+                idx += 1;
+                continue;
+            }
+            let fd = insn.imm as u32;
+            if matches!(insn.src, PSEUDO_MAP_FD | PSEUDO_MAP_VALUE)
+                && !used.0.contains(&fd)
+                && crate::map_ops::hold_map(fd)
+            {
+                used.0.push(fd);
+            }
+            idx += 2;
+        }
+        used
+    }
+}
+
+impl Drop for UsedMaps {
     fn drop(&mut self) {
-        // Auto-cleanup associated Maps when last reference is dropped
-        for (name, fd) in &self.map_fds {
-            if let Err(e) = crate::maps::destroy(*fd) {
-                log::warn!("Failed to destroy map '{}' (fd={}): {:?}", name, fd, e);
-            } else {
-                log::debug!("Destroyed map '{}' (fd={})", name, fd);
+        for &fd in &self.0 {
+            crate::map_ops::release_map(fd);
+        }
+    }
+}
+
+// =============================================================================
+// Execution Mode
+// =============================================================================
+
+/// How a loaded program is executed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExecMode {
+    /// Run in the rbpf interpreter.
+    Interpreter,
+    /// Compile to native code at load time.
+    ///
+    /// Falls back to the interpreter when the `jit` feature is disabled, the
+    /// target has no JIT backend, or compilation fails. A compiled program
+    /// still runs in the interpreter when its context is shorter than the
+    /// verifier found it may read.
+    Jit,
+}
+
+/// Execution mode used by `load_program` and `EbpfProgram::new`.
+static DEFAULT_EXEC_MODE: AtomicU8 = AtomicU8::new(ExecMode::Interpreter as u8);
+
+/// Set the execution mode for programs loaded without an explicit mode.
+///
+/// Already loaded programs keep the mode they were loaded with.
+pub fn set_default_exec_mode(mode: ExecMode) {
+    DEFAULT_EXEC_MODE.store(mode as u8, Ordering::Relaxed);
+}
+
+/// Get the execution mode for programs loaded without an explicit mode.
+pub fn default_exec_mode() -> ExecMode {
+    match DEFAULT_EXEC_MODE.load(Ordering::Relaxed) {
+        x if x == ExecMode::Jit as u8 => ExecMode::Jit,
+        _ => ExecMode::Interpreter,
+    }
+}
+
+/// Resolve global variable loads to direct pointers.
+///
+/// The verifier sees `lddw dst, map_value(fd, off)` and types `dst` as a
+/// pointer into the map value; rbpf would load `fd | off << 32` as a plain
+/// number. Each such load is rewritten to load the address of the value,
+/// and the value regions are returned so they can be allowed in the VM.
+///
+/// The data areas of RingBuf maps loaded with `lddw map_fd` are returned
+/// too, since programs fill reserved records in place, and so are the value
+/// storage of array and hash maps, whose values `bpf_map_lookup_elem`
+/// returns pointers to.
+fn resolve_map_values(code: &mut [u8]) -> Result<Vec<core::ops::Range<u64>>, Error> {
+    use crate::verifier::{INSN_SIZE, Insn, LD_DW_IMM, PSEUDO_MAP_FD, PSEUDO_MAP_VALUE};
+
+    let len = code.len() / INSN_SIZE;
+    let mut regions: Vec<core::ops::Range<u64>> = Vec::new();
+    let mut idx = 0;
+    while idx + 1 < len {
+        let insn = Insn::decode(code, idx);
+        if insn.op != LD_DW_IMM {
+            idx += 1;
+            continue;
+        }
+        if insn.src == PSEUDO_MAP_VALUE {
+            let fd = insn.imm as u32;
+            let off = Insn::decode(code, idx + 1).imm as u32 as u64;
+            let region = crate::maps::array_value_range(fd, 0).ok_or_else(|| {
+                log::warn!("Global variable map {} has no value storage", fd);
+                Error::RelocationFailed
+            })?;
+            let addr = region.start + off;
+
+            // Rewrite as a plain `lddw dst, addr`
+            let pc = idx * INSN_SIZE;
+            code[pc + 1] &= 0x0f;
+            code[pc + 4..pc + 8].copy_from_slice(&(addr as u32).to_le_bytes());
+            code[pc + 12..pc + 16].copy_from_slice(&((addr >> 32) as u32).to_le_bytes());
+            if !regions.contains(&region) {
+                regions.push(region);
+            }
+        } else if insn.src == PSEUDO_MAP_FD {
+            let fd = insn.imm as u32;
+            let map_regions = crate::ringbuf::data_range(fd)
+                .into_iter()
+                .chain(crate::maps::value_regions(fd));
+            for region in map_regions {
+                if !regions.contains(&region) {
+                    regions.push(region);
+                }
             }
         }
+        idx += 2;
+    }
+    Ok(regions)
+}
+
+/// FNV-1a hash of the bytecode.
+fn bytecode_tag(bytecode: &[u8]) -> u64 {
+    bytecode.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Instructions inserted around one original instruction.
+#[derive(Default)]
+struct Patch {
+    before: Vec<crate::verifier::Insn>,
+    after: Vec<crate::verifier::Insn>,
+}
+
+/// Make every `bpf_tail_call` site exit once the tail call is scheduled.
+///
+/// Adds `if r0 != 0 goto +1; exit` after each call. Returns the number of
+/// call sites.
+fn patch_tail_calls(code: &[u8], patches: &mut BTreeMap<usize, Patch>) -> usize {
+    use crate::verifier::{BPF_CALL, BPF_EXIT, BPF_JMP, BPF_JNE, INSN_SIZE, Insn, LD_DW_IMM};
+
+    let mut sites = 0;
+    let mut idx = 0;
+    while idx < code.len() / INSN_SIZE {
+        let insn = Insn::decode(code, idx);
+        if insn.op == LD_DW_IMM {
+            idx += 2;
+            continue;
+        }
+        if insn.op == BPF_JMP | BPF_CALL
+            && insn.src == 0
+            && insn.imm as u32 == helpers::id::TAIL_CALL
+        {
+            let skip_exit = Insn {
+                op: BPF_JMP | BPF_JNE,
+                dst: 0,
+                src: 0,
+                off: 1,
+                imm: 0,
+            };
+            let exit = Insn {
+                op: BPF_JMP | BPF_EXIT,
+                dst: 0,
+                src: 0,
+                off: 0,
+                imm: 0,
+            };
+            patches
+                .entry(idx)
+                .or_default()
+                .after
+                .extend([skip_exit, exit]);
+            sites += 1;
+        }
+        idx += 1;
+    }
+    sites
+}
+
+/// Link every `bpf_loop` call site to its callback.
+///
+/// Appends one loop function per callback and turns each call site into a
+/// BPF-to-BPF call to it. The loop runs in its own frame, so the caller's
+/// r6-r9 survive it:
+///
+/// ```text
+///         if r4 != 0 goto einval
+///         if r1 > MAX_LOOPS goto e2big
+///         r6 = r1; r7 = 0; r8 = r3
+/// loop:   if r7 >= r6 goto done
+///         r1 = r7; r2 = r8
+///         call callback
+///         r7 += 1
+///         if r0 == 0 goto loop
+/// done:   r0 = r7; exit
+/// e2big:  r0 = -E2BIG; exit
+/// einval: r0 = -EINVAL; exit
+/// ```
+fn link_loop_callbacks(
+    code: &mut Vec<u8>,
+    callbacks: &BTreeMap<usize, usize>,
+) -> Result<(), Error> {
+    use crate::verifier::{
+        BPF_ADD, BPF_ALU64, BPF_CALL, BPF_EXIT, BPF_JEQ, BPF_JGE, BPF_JGT, BPF_JMP, BPF_JNE,
+        BPF_MOV, BPF_X, INSN_SIZE, Insn, PSEUDO_CALL,
+    };
+    const E2BIG: i32 = 7;
+    const EINVAL: i32 = 22;
+
+    let insn = |op, dst, src, off, imm| Insn {
+        op,
+        dst,
+        src,
+        off,
+        imm,
+    };
+    let mov = |dst, src| insn(BPF_ALU64 | BPF_MOV | BPF_X, dst, src, 0, 0);
+    let mov_imm = |dst, imm| insn(BPF_ALU64 | BPF_MOV, dst, 0, 0, imm);
+    let exit = insn(BPF_JMP | BPF_EXIT, 0, 0, 0, 0);
+    let call = |from: usize, to: usize| {
+        let off = i32::try_from(to as i64 - from as i64 - 1).map_err(|_| Error::InvalidProgram)?;
+        Ok::<_, Error>(insn(BPF_JMP | BPF_CALL, 0, PSEUDO_CALL, 0, off))
+    };
+
+    // Start of the loop function of each callback
+    let mut loops = BTreeMap::new();
+    for (&site, &callback) in callbacks {
+        let start = match loops.get(&callback) {
+            Some(&start) => start,
+            None => {
+                let start = code.len() / INSN_SIZE;
+                let function = [
+                    insn(BPF_JMP | BPF_JNE, 4, 0, 14, 0),
+                    insn(BPF_JMP | BPF_JGT, 1, 0, 11, helpers::MAX_LOOPS as i32),
+                    mov(6, 1),
+                    mov_imm(7, 0),
+                    mov(8, 3),
+                    insn(BPF_JMP | BPF_JGE | BPF_X, 7, 6, 5, 0),
+                    mov(1, 7),
+                    mov(2, 8),
+                    call(start + 8, callback)?,
+                    insn(BPF_ALU64 | BPF_ADD, 7, 0, 0, 1),
+                    insn(BPF_JMP | BPF_JEQ, 0, 0, -6, 0),
+                    mov(0, 7),
+                    exit,
+                    mov_imm(0, -E2BIG),
+                    exit,
+                    mov_imm(0, -EINVAL),
+                    exit,
+                ];
+                for insn in function {
+                    code.extend_from_slice(&insn.encode());
+                }
+                loops.insert(callback, start);
+                start
+            }
+        };
+        code[site * INSN_SIZE..(site + 1) * INSN_SIZE]
+            .copy_from_slice(&call(site, start)?.encode());
+    }
+    Ok(())
+}
+
+/// Insert instructions into `code` and fix up jump and BPF-to-BPF call
+/// offsets.
+///
+/// Jumps to a patched instruction land on its `before` block, so checks
+/// inserted there run on every path. Offsets inside inserted instructions
+/// are left as they are.
+fn apply_patches(code: &mut Vec<u8>, patches: &BTreeMap<usize, Patch>) -> Result<(), Error> {
+    use crate::verifier::{
+        BPF_CALL, BPF_EXIT, BPF_JMP, BPF_JMP32, INSN_SIZE, Insn, LD_DW_IMM, OP_MASK, PSEUDO_CALL,
+    };
+
+    if patches.is_empty() {
+        return Ok(());
+    }
+
+    // New index of the `before` block of every original instruction (plus
+    // one past the end), and of the instruction itself
+    let len = code.len() / INSN_SIZE;
+    let mut new_idx = Vec::with_capacity(len + 1);
+    let mut own_idx = Vec::with_capacity(len);
+    let mut next = 0;
+    for idx in 0..len {
+        let patch = patches.get(&idx);
+        new_idx.push(next);
+        next += patch.map_or(0, |p| p.before.len());
+        own_idx.push(next);
+        next += 1 + patch.map_or(0, |p| p.after.len());
+    }
+    new_idx.push(next);
+
+    let relocate = |idx: usize, off: i64| -> Result<i64, Error> {
+        let target = usize::try_from(idx as i64 + 1 + off).map_err(|_| Error::InvalidProgram)?;
+        let target = *new_idx.get(target).ok_or(Error::InvalidProgram)?;
+        Ok(target as i64 - own_idx[idx] as i64 - 1)
+    };
+
+    let mut patched = Vec::with_capacity(next * INSN_SIZE);
+    let mut idx = 0;
+    while idx < len {
+        let patch = patches.get(&idx);
+        for insn in patch.map_or(&[][..], |p| &p.before) {
+            patched.extend_from_slice(&insn.encode());
+        }
+
+        let mut insn = Insn::decode(code, idx);
+        if insn.op == LD_DW_IMM {
+            patched.extend_from_slice(&code[idx * INSN_SIZE..(idx + 2) * INSN_SIZE]);
+            idx += 2;
+            continue;
+        }
+
+        if matches!(insn.class(), BPF_JMP | BPF_JMP32) {
+            match insn.op & OP_MASK {
+                BPF_CALL if insn.src == PSEUDO_CALL => {
+                    insn.imm = i32::try_from(relocate(idx, insn.imm as i64)?)
+                        .map_err(|_| Error::InvalidProgram)?;
+                }
+                BPF_CALL | BPF_EXIT => {}
+                _ => {
+                    insn.off = i16::try_from(relocate(idx, insn.off as i64)?)
+                        .map_err(|_| Error::InvalidProgram)?;
+                }
+            }
+        }
+        patched.extend_from_slice(&insn.encode());
+
+        for insn in patch.map_or(&[][..], |p| &p.after) {
+            patched.extend_from_slice(&insn.encode());
+        }
+        idx += 1;
+    }
+
+    *code = patched;
+    Ok(())
+}
+
+/// Bytecode together with the interpreter prepared for it at load time.
+///
+/// Helpers and allowed memory regions are registered once here, so running
+/// the program on a probe hit does no setup work.
+struct PreparedCode {
+    /// VM borrowing `code`. Declared first so it is dropped first.
+    vm: EbpfVmRaw<'static>,
+    /// Native image, if the program was JIT-compiled. Borrowed by `vm`.
+    #[cfg(feature = "jit")]
+    jit: Option<crate::jit::ExecRegion>,
+    /// Context bytes the program may read. Native code does no bounds
+    /// checks, so shorter contexts are run in the interpreter.
+    #[cfg(feature = "jit")]
+    ctx_size: usize,
+    /// Code run by `vm`: `bytecode` with `bpf_loop` calls linked, global
+    /// variable loads resolved, tail call sites patched and loops
+    /// instrumented. Heap-allocated; its
+    /// address is stable for the life of `vm`.
+    code: Box<[u8]>,
+    /// Verified bytecode as loaded.
+    bytecode: Box<[u8]>,
+    /// Whether the program calls `bpf_tail_call`.
+    tail_calls: bool,
+    /// Whether loop back-edges check the budget.
+    instrumented: bool,
+    /// Budget and violation count.
+    watchdog: Watchdog,
+    /// Run statistics.
+    stats: StatsCounters,
+    /// Maps whose memory `code` accesses. Declared last so they are
+    /// released after `vm` and `code` are gone.
+    used_maps: UsedMaps,
+}
+
+// SAFETY: the VM is only mutated while it is being prepared. Afterwards it is
+// shared read-only; execution takes `&self` and keeps all state on the stack.
+unsafe impl Send for PreparedCode {}
+unsafe impl Sync for PreparedCode {}
+
+impl PreparedCode {
+    fn new(
+        bytecode: Vec<u8>,
+        analysis: &crate::verifier::Analysis,
+        used_maps: UsedMaps,
+        mode: ExecMode,
+        budget: Budget,
+    ) -> Result<Self, Error> {
+        let mut code = bytecode.clone();
+        link_loop_callbacks(&mut code, &analysis.loop_callbacks)?;
+
+        let mut patches = BTreeMap::new();
+        let tail_calls = patch_tail_calls(&code, &mut patches) > 0;
+
+        let mut instrumented = false;
+        if budget.is_limited() {
+            let checks = watchdog::loop_checks(&code, analysis.stack_depth).ok_or_else(|| {
+                log::warn!("No free register or stack slot to save r0 at a loop back-edge");
+                Error::InvalidProgram
+            })?;
+            instrumented = !checks.is_empty();
+            for (idx, check) in checks {
+                patches.entry(idx).or_default().before = check;
+            }
+        }
+
+        apply_patches(&mut code, &patches)?;
+        let mut code = code.into_boxed_slice();
+        let globals = resolve_map_values(&mut code)?;
+
+        // SAFETY: the boxed slice is never mutated or reallocated and is
+        // dropped after `vm` (field order), so the borrow never dangles.
+        let prog: &'static [u8] = unsafe { core::slice::from_raw_parts(code.as_ptr(), code.len()) };
+
+        let mut vm = EbpfVmRaw::new(Some(prog)).map_err(|e| {
+            log::error!("Failed to create VM: {:?}", e);
+            Error::InvalidProgram
+        })?;
+
+        // The helper set is fixed for the lifetime of the program.
+        #[cfg(feature = "tracepoint-support")]
+        helpers::register_all_with_hypervisor_raw(&mut vm);
+        #[cfg(not(feature = "tracepoint-support"))]
+        helpers::register_all_raw(&mut vm);
+        if instrumented {
+            watchdog::register_ticks(&mut vm);
+        }
+
+        // Register LOOKUP_BUFFER so eBPF can access bpf_map_lookup_elem results
+        vm.register_allowed_memory(helpers::get_lookup_buffer_range());
+        // Register NAME_BUFFER so eBPF can access bpf_get_tracepoint_name results
+        vm.register_allowed_memory(helpers::get_name_buffer_range());
+        // Global variables, map values and ring buffer records are accessed
+        // in place
+        for region in globals {
+            vm.register_allowed_memory(region);
+        }
+
+        // rbpf's JIT clobbers r1-r5 on helper calls, which instrumented
+        // loops rely on being preserved.
+        #[cfg(feature = "jit")]
+        let jit = match mode {
+            ExecMode::Jit if instrumented => {
+                log::debug!("Program has a budget, using interpreter");
+                None
+            }
+            ExecMode::Jit => crate::jit::compile(&mut vm, code.len()),
+            ExecMode::Interpreter => None,
+        };
+        #[cfg(not(feature = "jit"))]
+        if mode == ExecMode::Jit {
+            log::debug!("JIT disabled at build time, using interpreter");
+        }
+
+        Ok(Self {
+            vm,
+            #[cfg(feature = "jit")]
+            jit,
+            #[cfg(feature = "jit")]
+            ctx_size: analysis.ctx_size as usize,
+            code,
+            bytecode: bytecode.into_boxed_slice(),
+            tail_calls,
+            instrumented,
+            watchdog: Watchdog::new(budget),
+            stats: StatsCounters::default(),
+            used_maps,
+        })
+    }
+
+    /// Mode the program actually runs in.
+    fn mode(&self) -> ExecMode {
+        #[cfg(feature = "jit")]
+        if self.jit.is_some() {
+            return ExecMode::Jit;
+        }
+        ExecMode::Interpreter
+    }
+
+    fn run(&self, ctx: &mut [u8]) -> Result<u64, Error> {
+        // SAFETY: the VM only accesses `ctx` for the duration of this call;
+        // the extended lifetime is never observed afterwards.
+        let mem: &'static mut [u8] =
+            unsafe { core::slice::from_raw_parts_mut(ctx.as_mut_ptr(), ctx.len()) };
+
+        #[cfg(feature = "jit")]
+        if self.jit.is_some() && mem.len() >= self.ctx_size {
+            // SAFETY: the native image was compiled from verified bytecode,
+            // whose context reads all end within `mem`, and its pages stay
+            // allocated as long as `self.jit` is alive.
+            return unsafe { self.vm.execute_program_jit(mem) }.map_err(|e| {
+                log::error!("eBPF JIT execution error: {:?}", e);
+                Error::ExecutionFailed
+            });
+        }
+
+        self.vm.execute_program(mem).map_err(|e| {
+            // A program stopped by the watchdog ends on an unknown helper
+            if self.instrumented && invocation_exceeded() {
+                return Error::BudgetExceeded;
+            }
+            log::error!("eBPF execution error: {:?}", e);
+            Error::ExecutionFailed
+        })
     }
 }
 
 /// eBPF program wrapper with helper support.
 ///
-/// Stores bytecode and provides execution methods with automatic helper registration.
-/// Maps are reference-counted and only destroyed when the last clone is dropped.
+/// Stores bytecode and a VM prepared at load time with all helpers registered.
+/// Clones share both the VM and the Maps; Maps are only destroyed when the
+/// last clone is dropped.
 #[derive(Clone)]
 pub struct EbpfProgram {
-    bytecode: Vec<u8>,
+    code: Arc<PreparedCode>,
     /// Shared Map FDs (reference counted, destroyed when last reference drops)
     shared_maps: Arc<SharedMapFds>,
+    /// Program name from the ELF object (empty for raw bytecode).
+    name: String,
+    /// ELF section the program came from (empty for raw bytecode).
+    section: String,
+    /// Program type and attach target inferred from `section`.
+    spec: SectionSpec,
+    /// `platform::time_ns()` when the program was loaded.
+    loaded_ns: u64,
+    /// Hash of the verified bytecode.
+    tag: u64,
+    /// Number of this load, see [`EbpfProgram::generation`].
+    generation: u64,
 }
 
+/// Source of [`EbpfProgram::generation`].
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
 impl EbpfProgram {
     /// Load eBPF bytecode into a program.
     ///
     /// Supports both raw bytecode and ELF format.
     /// If ELF contains Maps, they are automatically created and bytecode is patched.
+    /// The final bytecode is checked by the static verifier before it is accepted.
     ///
     /// # Arguments
     /// * `data` - Raw eBPF bytecode or ELF file containing eBPF program.
     ///
     /// # Returns
-    /// EbpfProgram on success, Error if bytecode is invalid.
+    /// EbpfProgram on success, Error if bytecode is invalid or fails verification.
     pub fn new(data: &[u8], prog_name: Option<&str>) -> Result<Self, Error> {
-        let (bytecode, map_fds) = if is_elf(data) {
-            log::debug!("Detected ELF format, parsing with aya-obj...");
-            let result = parse_elf_with_aya(data, prog_name)?;
-            (result.bytecode, result.map_fds)
-        } else {
-            (data.to_vec(), Vec::new())
+        Self::new_with_mode(data, prog_name, default_exec_mode())
+    }
+
+    /// Load eBPF bytecode into a program with an explicit execution mode.
+    ///
+    /// See [`EbpfProgram::new`]. With [`ExecMode::Jit`] the program is
+    /// compiled once here; use [`EbpfProgram::exec_mode`] to check whether
+    /// compilation succeeded.
+    pub fn new_with_mode(
+        data: &[u8],
+        prog_name: Option<&str>,
+        mode: ExecMode,
+    ) -> Result<Self, Error> {
+        if !is_elf(data) {
+            return Self::from_parts(
+                data.to_vec(),
+                Arc::new(SharedMapFds::default()),
+                String::new(),
+                String::new(),
+                mode,
+                watchdog::default_budget(),
+            );
+        }
+
+        log::debug!("Detected ELF format, parsing with aya-obj...");
+        let ElfParseResult { programs, maps } = parse_elf_with_aya(data, &[], None)?;
+
+        // Select the named program, or the first one
+        let program = match prog_name {
+            Some(name) => programs
+                .into_iter()
+                .find(|p| p.name == name)
+                .ok_or_else(|| {
+                    log::warn!("Program '{}' not found in ELF", name);
+                    Error::NotFound
+                })?,
+            None => programs.into_iter().next().ok_or(Error::ElfParseError)?,
         };
 
+        Self::from_parts(
+            program.bytecode,
+            maps,
+            program.name,
+            program.section,
+            mode,
+            watchdog::default_budget(),
+        )
+    }
+
+    /// Verify bytecode and prepare it for execution.
+    ///
+    /// Takes ownership of `maps` up front, so they are destroyed on rejection
+    /// unless another program of the same object still holds them.
+    fn from_parts(
+        bytecode: Vec<u8>,
+        maps: Arc<SharedMapFds>,
+        name: String,
+        section: String,
+        mode: ExecMode,
+        budget: Budget,
+    ) -> Result<Self, Error> {
         if bytecode.is_empty() || bytecode.len() % 8 != 0 {
             return Err(Error::InvalidProgram);
         }
 
+        // Held before verification, so the maps the verifier checks are the
+        // ones the program keeps
+        let used_maps = UsedMaps::hold(&bytecode);
+        let analysis = crate::verifier::analyze(&bytecode).map_err(|e| {
+            log::warn!("eBPF verifier rejected program '{}': {}", name, e);
+            Error::VerificationFailed(e)
+        })?;
+
         log::debug!(
-            "Loaded eBPF program: {} bytes ({} instructions), {} maps",
+            "Loaded eBPF program '{}': {} bytes ({} instructions), {} maps",
+            name,
             bytecode.len(),
             bytecode.len() / 8,
-            map_fds.len()
+            maps.map_fds.len()
         );
 
+        let tag = bytecode_tag(&bytecode);
         Ok(Self {
-            bytecode,
-            shared_maps: Arc::new(SharedMapFds { map_fds }),
+            code: Arc::new(PreparedCode::new(
+                bytecode, &analysis, used_maps, mode, budget,
+            )?),
+            shared_maps: maps,
+            name,
+            spec: SectionSpec::parse(&section),
+            section,
+            loaded_ns: crate::platform::time_ns(),
+            tag,
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
         })
     }
 
+    /// Get the program name (function name in the ELF object).
+    ///
+    /// Empty for programs loaded from raw bytecode.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Get the ELF section the program was loaded from.
+    ///
+    /// Empty for programs loaded from raw bytecode.
+    pub fn section(&self) -> &str {
+        &self.section
+    }
+
+    /// Get the program type inferred from the section name.
+    pub fn prog_type(&self) -> ProgramType {
+        self.spec.prog_type
+    }
+
+    /// Get the attach target inferred from the section name, if any.
+    pub fn attach_target(&self) -> Option<&AttachTarget> {
+        self.spec.target.as_ref()
+    }
+
+    /// Get the time the program was loaded, in `platform::time_ns()` units.
+    pub fn load_time_ns(&self) -> u64 {
+        self.loaded_ns
+    }
+
+    /// Get the bytecode hash.
+    ///
+    /// Equal tags mean identical bytecode, like Linux `bpf_prog_info.tag`.
+    pub fn tag(&self) -> u64 {
+        self.tag
+    }
+
+    /// Get the number of this load.
+    ///
+    /// Unlike program IDs, which are reused once a program is unloaded, no
+    /// two loads share a generation.
+    pub(crate) fn generation(&self) -> u64 {
+        self.generation
+    }
+
     /// Get the bytecode.
     pub fn bytecode(&self) -> &[u8] {
-        &self.bytecode
+        &self.code.bytecode
     }
 
     /// Get associated Map FDs.
@@ -332,23 +1181,37 @@ impl EbpfProgram {
         &self.shared_maps.map_fds
     }
 
+    /// Get the mode the program runs in.
+    pub fn exec_mode(&self) -> ExecMode {
+        self.code.mode()
+    }
+
+    /// Get the execution budget.
+    pub fn budget(&self) -> Budget {
+        self.code.watchdog.budget()
+    }
+
+    /// Get the number of runs that exceeded the budget.
+    pub fn budget_violations(&self) -> u64 {
+        self.code.watchdog.violations()
+    }
+
+    /// Whether the watchdog disabled the program.
+    pub fn is_disabled(&self) -> bool {
+        self.code.watchdog.is_disabled()
+    }
+
+    /// Get the run statistics, shared by all clones of the program.
+    pub fn stats(&self) -> ProgramStats {
+        self.code.stats.snapshot()
+    }
+
     /// Execute the program without input data.
     ///
     /// # Returns
     /// The return value of the eBPF program (r0 register).
     pub fn execute(&self) -> Result<u64, Error> {
-        use rbpf::EbpfVmNoData;
-
-        let mut vm = EbpfVmNoData::new(Some(&self.bytecode)).map_err(|_| Error::InvalidProgram)?;
-
-        helpers::register_all_nodata(&mut vm);
-
-        // Register LOOKUP_BUFFER so eBPF can access bpf_map_lookup_elem results
-        vm.register_allowed_memory(helpers::get_lookup_buffer_range());
-        // Register NAME_BUFFER so eBPF can access bpf_get_tracepoint_name results
-        vm.register_allowed_memory(helpers::get_name_buffer_range());
-
-        vm.execute_program().map_err(|_| Error::ExecutionFailed)
+        self.execute_with_context(&mut [])
     }
 
     /// Execute the program with memory context.
@@ -359,24 +1222,119 @@ impl EbpfProgram {
     /// # Returns
     /// The return value of the eBPF program (r0 register).
     pub fn execute_with_context(&self, ctx: &mut [u8]) -> Result<u64, Error> {
-        use rbpf::EbpfVmRaw;
+        let watchdog = &self.code.watchdog;
+        if watchdog.is_disabled() {
+            return Err(Error::Disabled);
+        }
 
-        let mut vm = EbpfVmRaw::new(Some(&self.bytecode)).map_err(|e| {
-            log::error!("Failed to create VM: {:?}", e);
-            Error::InvalidProgram
-        })?;
+        let (result, elapsed_ns) = self.run_timed(ctx);
+        self.code.stats.record(elapsed_ns, &result);
+        if matches!(result, Err(Error::BudgetExceeded)) && watchdog.record_violation() {
+            log::warn!(
+                "Program '{}' disabled after {} budget violations",
+                self.name,
+                watchdog.violations()
+            );
+        }
+        result
+    }
 
-        helpers::register_all_raw(&mut vm);
+    /// Run once within the budget, without any accounting.
+    ///
+    /// # Returns
+    /// The result and the run time in nanoseconds.
+    fn run_timed(&self, ctx: &mut [u8]) -> (Result<u64, Error>, u64) {
+        let budget = self.code.watchdog.budget();
+        let start_ns = crate::platform::time_ns();
+        let depth = run_depth_counter();
+        depth.fetch_add(1, Ordering::Relaxed);
+        let result = if self.code.tail_calls || self.code.instrumented {
+            run_invocation(&self.code, ctx, budget, start_ns)
+        } else {
+            self.code.run(ctx)
+        };
+        depth.fetch_sub(1, Ordering::Relaxed);
+        let elapsed_ns = crate::platform::time_ns().saturating_sub(start_ns);
+        crate::ringbuf::discard_pending();
+
+        // Programs without loops are only checked once they return
+        let result = match result {
+            Ok(_) if budget.max_time_ns != 0 && elapsed_ns > budget.max_time_ns => {
+                Err(Error::BudgetExceeded)
+            }
+            result => result,
+        };
+        (result, elapsed_ns)
+    }
+}
 
-        // Register LOOKUP_BUFFER so eBPF can access bpf_map_lookup_elem results
-        vm.register_allowed_memory(helpers::get_lookup_buffer_range());
-        // Register NAME_BUFFER so eBPF can access bpf_get_tracepoint_name results
-        vm.register_allowed_memory(helpers::get_name_buffer_range());
+// =============================================================================
+// Accounting
+// =============================================================================
 
-        vm.execute_program(ctx).map_err(|e| {
-            log::error!("eBPF execution error: {:?}", e);
-            Error::ExecutionFailed
-        })
+/// Run statistics of a program, like Linux `bpf_prog_info.run_cnt` and
+/// `run_time_ns`.
+///
+/// A tail-call chain counts as one run of the program it started with.
+#[derive(Debug, Clone, Default)]
+pub struct ProgramStats {
+    /// Number of runs.
+    pub run_cnt: u64,
+    /// Total run time in nanoseconds.
+    pub run_time_ns: u64,
+    /// Longest single run in nanoseconds.
+    pub max_run_time_ns: u64,
+    /// Number of runs that returned an error.
+    pub errors: u64,
+    /// Error of the most recent failed run.
+    pub last_error: Option<Error>,
+}
+
+impl ProgramStats {
+    /// Average run time in nanoseconds (0 before the first run).
+    pub fn avg_run_time_ns(&self) -> u64 {
+        self.run_time_ns.checked_div(self.run_cnt).unwrap_or(0)
+    }
+}
+
+/// Lock-free counters behind [`ProgramStats`]; only failed runs take a lock.
+#[derive(Default)]
+struct StatsCounters {
+    run_cnt: AtomicU64,
+    run_time_ns: AtomicU64,
+    max_run_time_ns: AtomicU64,
+    errors: AtomicU64,
+    last_error: Mutex<Option<Error>>,
+}
+
+impl StatsCounters {
+    fn record(&self, elapsed_ns: u64, result: &Result<u64, Error>) {
+        self.run_cnt.fetch_add(1, Ordering::Relaxed);
+        self.run_time_ns.fetch_add(elapsed_ns, Ordering::Relaxed);
+        self.max_run_time_ns
+            .fetch_max(elapsed_ns, Ordering::Relaxed);
+        if let Err(e) = result {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            *self.last_error.lock() = Some(e.clone());
+        }
+    }
+
+    fn snapshot(&self) -> ProgramStats {
+        ProgramStats {
+            run_cnt: self.run_cnt.load(Ordering::Relaxed),
+            run_time_ns: self.run_time_ns.load(Ordering::Relaxed),
+            max_run_time_ns: self.max_run_time_ns.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            last_error: self.last_error.lock().clone(),
+        }
+    }
+
+    fn reset(&self) {
+        self.run_cnt.store(0, Ordering::Relaxed);
+        self.run_time_ns.store(0, Ordering::Relaxed);
+        self.max_run_time_ns.store(0, Ordering::Relaxed);
+        self.errors.store(0, Ordering::Relaxed);
+        *self.last_error.lock() = None;
     }
 }
 
@@ -385,7 +1343,10 @@ impl EbpfProgram {
 // =============================================================================
 
 /// Global program registry.
-static PROGRAM_REGISTRY: Mutex<Vec<Option<EbpfProgram>>> = Mutex::new(Vec::new());
+///
+/// Probe handlers only take the read lock, so hits on different CPUs run
+/// concurrently; loading and unloading take the write lock.
+static PROGRAM_REGISTRY: RwLock<Vec<Option<EbpfProgram>>> = RwLock::new(Vec::new());
 
 /// Load a program into the registry.
 ///
@@ -395,27 +1356,184 @@ static PROGRAM_REGISTRY: Mutex<Vec<Option<EbpfProgram>>> = Mutex::new(Vec::new()
 /// # Returns
 /// Program ID on success.
 pub fn load_program(bytecode: &[u8], prog_name: Option<&str>) -> Result<u32, Error> {
-    let program = EbpfProgram::new(bytecode, prog_name)?;
-    let mut registry = PROGRAM_REGISTRY.lock();
+    load_program_with_mode(bytecode, prog_name, default_exec_mode())
+}
+
+/// Load a program into the registry with an explicit execution mode.
+///
+/// # Arguments
+/// * `bytecode` - Raw eBPF bytecode.
+/// * `mode` - Interpreter or JIT; JIT falls back to the interpreter.
+///
+/// # Returns
+/// Program ID on success.
+pub fn load_program_with_mode(
+    bytecode: &[u8],
+    prog_name: Option<&str>,
+    mode: ExecMode,
+) -> Result<u32, Error> {
+    let program = EbpfProgram::new_with_mode(bytecode, prog_name, mode)?;
+    let mut registry = PROGRAM_REGISTRY.write();
+    Ok(insert_program(&mut registry, program))
+}
+
+/// Store a program in the first free registry slot and return its ID.
+fn insert_program(registry: &mut Vec<Option<EbpfProgram>>, program: EbpfProgram) -> u32 {
+    let size = program.bytecode().len();
 
     // Find empty slot or append
-    for (i, slot) in registry.iter_mut().enumerate() {
-        if slot.is_none() {
-            *slot = Some(program);
-            log::debug!("Loaded program {} ({} bytes)", i, bytecode.len());
-            return Ok(i as u32);
+    let id = match registry.iter().position(|slot| slot.is_none()) {
+        Some(i) => {
+            registry[i] = Some(program);
+            i
+        }
+        None => {
+            registry.push(Some(program));
+            registry.len() - 1
+        }
+    };
+    log::debug!("Loaded program {} ({} bytes)", id, size);
+    id as u32
+}
+
+/// Load every program of an ELF object into the registry.
+///
+/// All programs share one set of Maps, so e.g. an entry/exit probe pair can
+/// exchange state through a common hash map. The Maps are destroyed when the
+/// last program of the object is unloaded. Loading is all-or-nothing: if any
+/// program fails verification, none are registered.
+///
+/// # Arguments
+/// * `elf_data` - ELF object containing one or more programs.
+///
+/// # Returns
+/// Vector of (program_name, prog_id) pairs.
+pub fn load_object(elf_data: &[u8]) -> Result<Vec<(String, u32)>, Error> {
+    load_object_with_mode(elf_data, default_exec_mode())
+}
+
+/// Load every program of an ELF object with an explicit execution mode.
+///
+/// See [`load_object`].
+pub fn load_object_with_mode(elf_data: &[u8], mode: ExecMode) -> Result<Vec<(String, u32)>, Error> {
+    ObjectLoader::new(elf_data).exec_mode(mode).load()
+}
+
+/// Loader for ELF objects with load-time configuration.
+///
+/// Global variables in `.rodata`/`.data` can be overridden before the object
+/// is relocated, so one compiled object can be configured per VM:
+///
+/// ```ignore
+/// let progs = ObjectLoader::new(elf)
+///     .set_global("TARGET_VM", &3u32.to_le_bytes())
+///     .load()?;
+/// ```
+pub struct ObjectLoader<'a> {
+    elf_data: &'a [u8],
+    globals: Vec<(String, Vec<u8>)>,
+    mode: ExecMode,
+    budget: Budget,
+    reuse_maps: Option<u32>,
+}
+
+impl<'a> ObjectLoader<'a> {
+    /// Create a loader for `elf_data` using the default execution mode.
+    pub fn new(elf_data: &'a [u8]) -> Self {
+        Self {
+            elf_data,
+            globals: Vec::new(),
+            mode: default_exec_mode(),
+            budget: watchdog::default_budget(),
+            reuse_maps: None,
         }
     }
 
-    let id = registry.len() as u32;
-    registry.push(Some(program));
-    log::debug!("Loaded program {} ({} bytes)", id, bytecode.len());
-    Ok(id)
+    /// Override the initial value of global variable `name`.
+    ///
+    /// `value` must have exactly the size of the variable; a missing symbol or
+    /// size mismatch fails the load with [`Error::InvalidGlobal`]. Setting the
+    /// same name again replaces the earlier value.
+    pub fn set_global(&mut self, name: &str, value: &[u8]) -> &mut Self {
+        self.globals.retain(|(n, _)| n != name);
+        self.globals.push((String::from(name), value.to_vec()));
+        self
+    }
+
+    /// Set the execution mode of the loaded programs.
+    pub fn exec_mode(&mut self, mode: ExecMode) -> &mut Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Set the execution budget of the loaded programs.
+    ///
+    /// Programs with loops and a limited budget run in the interpreter.
+    pub fn budget(&mut self, budget: Budget) -> &mut Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Share maps with loaded program `prog_id` instead of creating them.
+    ///
+    /// Every map of the object with the same name as one of the program's
+    /// maps uses that map, keeping its contents; the definitions must match.
+    /// Used to carry state over to a program that replaces `prog_id` with
+    /// `attach::replace`. Reused data sections ignore [`Self::set_global`].
+    pub fn reuse_maps(&mut self, prog_id: u32) -> &mut Self {
+        self.reuse_maps = Some(prog_id);
+        self
+    }
+
+    /// Load every program of the object into the registry.
+    ///
+    /// See [`load_object`].
+    pub fn load(&self) -> Result<Vec<(String, u32)>, Error> {
+        if !is_elf(self.elf_data) {
+            return Err(Error::ElfParseError);
+        }
+
+        let reuse = match self.reuse_maps {
+            Some(prog_id) => Some(get_program(prog_id).ok_or(Error::NotFound)?.shared_maps),
+            None => None,
+        };
+        let ElfParseResult { programs, maps } =
+            parse_elf_with_aya(self.elf_data, &self.globals, reuse.as_deref())?;
+        let programs = programs
+            .into_iter()
+            .map(|p| {
+                EbpfProgram::from_parts(
+                    p.bytecode,
+                    maps.clone(),
+                    p.name,
+                    p.section,
+                    self.mode,
+                    self.budget,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut registry = PROGRAM_REGISTRY.write();
+        let loaded: Vec<(String, u32)> = programs
+            .into_iter()
+            .map(|program| {
+                let name = String::from(program.name());
+                (name, insert_program(&mut registry, program))
+            })
+            .collect();
+
+        log::info!(
+            "Loaded object: {:?} sharing {} maps",
+            loaded,
+            maps.map_fds.len()
+        );
+        Ok(loaded)
+    }
 }
 
 /// Get a loaded program by ID.
 pub fn get_program(prog_id: u32) -> Option<EbpfProgram> {
-    let registry = PROGRAM_REGISTRY.lock();
+    let registry = PROGRAM_REGISTRY.read();
     registry.get(prog_id as usize)?.clone()
 }
 
@@ -431,9 +1549,60 @@ pub fn get_program_map_fds(prog_id: u32) -> Option<Vec<(String, u32)>> {
     Some(program.map_fds().to_vec())
 }
 
+/// Disassemble a loaded program.
+///
+/// Shows the verified bytecode as stored in the registry, after relocation
+/// and `.text` linking, with map loads and helper calls named. See
+/// [`crate::disasm`] for the format.
+pub fn disassemble(prog_id: u32) -> Result<Vec<String>, Error> {
+    let program = get_program(prog_id).ok_or(Error::NotFound)?;
+    Ok(crate::disasm::disassemble(
+        program.bytecode(),
+        program.map_fds(),
+    ))
+}
+
+/// Find a loaded program by name.
+///
+/// Names are not unique across objects; the lowest matching ID is returned.
+pub fn find_program_by_name(name: &str) -> Option<u32> {
+    let registry = PROGRAM_REGISTRY.read();
+    registry
+        .iter()
+        .position(|slot| slot.as_ref().is_some_and(|p| p.name() == name))
+        .map(|i| i as u32)
+}
+
 /// Unload a program from the registry.
+///
+/// Fails with [`Error::Attached`] while the program is attached to a
+/// tracepoint or probe; detach it first or use [`force_unload_program`].
 pub fn unload_program(prog_id: u32) -> Result<(), Error> {
-    let mut registry = PROGRAM_REGISTRY.lock();
+    // Attaching holds the same lock, so no attachment can be added between
+    // the check and the removal.
+    let _attach = crate::attach::ATTACH_LOCK.lock();
+    crate::attach::run_pending_detaches();
+    let attached = crate::attach::program_attachments(prog_id).len();
+    if attached > 0 {
+        return Err(Error::Attached(attached));
+    }
+    remove_program(prog_id)
+}
+
+/// Detach a program from all its tracepoints and probes, then unload it.
+pub fn force_unload_program(prog_id: u32) -> Result<(), Error> {
+    if get_program(prog_id).is_none() {
+        return Err(Error::NotFound);
+    }
+    let detached = crate::attach::detach_program(prog_id);
+    if detached > 0 {
+        log::info!("Detached program {} from {} attachments", prog_id, detached);
+    }
+    unload_program(prog_id)
+}
+
+fn remove_program(prog_id: u32) -> Result<(), Error> {
+    let mut registry = PROGRAM_REGISTRY.write();
     let slot = registry.get_mut(prog_id as usize).ok_or(Error::NotFound)?;
     if slot.is_none() {
         return Err(Error::NotFound);
@@ -445,6 +1614,9 @@ pub fn unload_program(prog_id: u32) -> Result<(), Error> {
 
 /// Run a loaded program by ID.
 ///
+/// Uses the VM prepared at load time; the registry lock is only held long
+/// enough to take a reference to it.
+///
 /// # Arguments
 /// * `prog_id` - Program ID returned by load_program().
 /// * `ctx` - Optional memory context for the program.
@@ -454,15 +1626,95 @@ pub fn unload_program(prog_id: u32) -> Result<(), Error> {
 pub fn run_program(prog_id: u32, ctx: Option<&mut [u8]>) -> Result<u64, Error> {
     let program = get_program(prog_id).ok_or(Error::NotFound)?;
 
-    match ctx {
+    let result = match ctx {
         Some(mem) => program.execute_with_context(mem),
         None => program.execute(),
+    };
+    if matches!(result, Err(Error::BudgetExceeded)) && program.is_disabled() {
+        let detached = crate::attach::detach_program(prog_id);
+        log::warn!(
+            "Watchdog detached program {} from {} attachments",
+            prog_id,
+            detached
+        );
     }
+    result
+}
+
+/// Output of [`test_run`].
+#[derive(Debug, Clone)]
+pub struct TestRunResult {
+    /// Return value (r0) of the last run.
+    pub retval: u64,
+    /// Context as left by the last run.
+    pub ctx_out: Vec<u8>,
+    /// Average run time in nanoseconds.
+    pub duration_ns: u64,
+}
+
+/// Dry-run a loaded program, like Linux `BPF_PROG_TEST_RUN`.
+///
+/// Runs the program `repeat` times (at least once), each time on a fresh
+/// copy of `ctx_in`, e.g. [`TraceContext::as_bytes`](crate::TraceContext::as_bytes)
+/// or the bytes of a `PtRegs`. The execution budget applies, but the run is
+/// invisible to the rest of the runtime: it is not counted in the program's
+/// statistics or budget violations, never detaches the program, and also
+/// works on a program the watchdog disabled. Maps, tail calls and output
+/// helpers behave as in a real run.
+///
+/// Stops at the first failing run and returns its error.
+pub fn test_run(prog_id: u32, ctx_in: &[u8], repeat: u32) -> Result<TestRunResult, Error> {
+    let program = get_program(prog_id).ok_or(Error::NotFound)?;
+    let repeat = repeat.max(1);
+
+    let mut ctx_out = Vec::new();
+    let mut retval = 0;
+    let mut total_ns = 0u64;
+    for _ in 0..repeat {
+        ctx_out = ctx_in.to_vec();
+        let (result, elapsed_ns) = program.run_timed(&mut ctx_out);
+        retval = result?;
+        total_ns = total_ns.saturating_add(elapsed_ns);
+    }
+
+    Ok(TestRunResult {
+        retval,
+        ctx_out,
+        duration_ns: total_ns / repeat as u64,
+    })
+}
+
+/// Change the execution budget of a loaded program.
+///
+/// Takes effect on the next invocation. Loops are only instrumented at load
+/// time, so for a program loaded without a budget only the time limit is
+/// enforced, once each run returns.
+pub fn set_program_budget(prog_id: u32, budget: Budget) -> Result<(), Error> {
+    let program = get_program(prog_id).ok_or(Error::NotFound)?;
+    program.code.watchdog.set_budget(budget);
+    Ok(())
+}
+
+/// Clear the budget violation count of a program and re-enable it if the
+/// watchdog disabled it.
+///
+/// The program is not re-attached.
+pub fn reset_watchdog(prog_id: u32) -> Result<(), Error> {
+    let program = get_program(prog_id).ok_or(Error::NotFound)?;
+    program.code.watchdog.reset();
+    Ok(())
+}
+
+/// Clear the run statistics of a loaded program.
+pub fn reset_program_stats(prog_id: u32) -> Result<(), Error> {
+    let program = get_program(prog_id).ok_or(Error::NotFound)?;
+    program.code.stats.reset();
+    Ok(())
 }
 
 /// Get the number of loaded programs.
 pub fn program_count() -> usize {
-    let registry = PROGRAM_REGISTRY.lock();
+    let registry = PROGRAM_REGISTRY.read();
     registry.iter().filter(|p| p.is_some()).count()
 }
 
@@ -471,8 +1723,30 @@ pub fn program_count() -> usize {
 pub struct ProgramInfo {
     /// Program ID.
     pub id: u32,
+    /// Program name (empty for raw bytecode).
+    pub name: String,
+    /// ELF section the program came from (empty for raw bytecode).
+    pub section: String,
+    /// Program type inferred from the section name.
+    pub prog_type: ProgramType,
+    /// Load time in `platform::time_ns()` units.
+    pub loaded_ns: u64,
+    /// Bytecode hash; see [`EbpfProgram::tag`].
+    pub tag: u64,
     /// Bytecode size in bytes.
     pub size: usize,
+    /// Associated Maps as (map_name, map_fd) pairs.
+    pub map_fds: Vec<(String, u32)>,
+    /// Tracepoints and probes the program is attached to.
+    pub attachments: Vec<AttachTarget>,
+    /// Mode the program runs in.
+    pub mode: ExecMode,
+    /// Runs that exceeded the execution budget.
+    pub budget_violations: u64,
+    /// Whether the watchdog disabled the program.
+    pub disabled: bool,
+    /// Run count, run time and errors.
+    pub stats: ProgramStats,
 }
 
 /// List all loaded programs.
@@ -480,19 +1754,162 @@ pub struct ProgramInfo {
 /// # Returns
 /// Vector of ProgramInfo for all loaded programs.
 pub fn list_programs() -> Vec<ProgramInfo> {
-    let registry = PROGRAM_REGISTRY.lock();
-    registry
-        .iter()
-        .enumerate()
-        .filter_map(|(i, slot)| {
-            slot.as_ref().map(|prog| ProgramInfo {
-                id: i as u32,
-                size: prog.bytecode().len(),
-            })
+    // Probe handlers hold their registry lock while running programs, so
+    // attachments are looked up after the program registry is released.
+    let programs: Vec<(u32, EbpfProgram)> = {
+        let registry = PROGRAM_REGISTRY.read();
+        registry
+            .iter()
+            .enumerate()
+            .filter_map(|(i, slot)| Some((i as u32, slot.clone()?)))
+            .collect()
+    };
+    programs
+        .into_iter()
+        .map(|(id, prog)| ProgramInfo {
+            id,
+            name: String::from(prog.name()),
+            section: String::from(prog.section()),
+            prog_type: prog.prog_type(),
+            loaded_ns: prog.load_time_ns(),
+            tag: prog.tag(),
+            size: prog.bytecode().len(),
+            map_fds: prog.map_fds().to_vec(),
+            attachments: crate::attach::program_attachments(id),
+            mode: prog.exec_mode(),
+            budget_violations: prog.budget_violations(),
+            disabled: prog.is_disabled(),
+            stats: prog.stats(),
         })
         .collect()
 }
 
+// =============================================================================
+// Invocations
+// =============================================================================
+
+/// Maximum number of tail calls in one invocation (same limit as Linux).
+pub const MAX_TAIL_CALL_CNT: u32 = 33;
+
+/// One invocation stack per CPU, so frames are never shared between CPUs.
+const INVOCATION_CPUS: usize = crate::platform::MAX_CPUS as usize;
+
+/// State of one running invocation, shared by the programs of a tail-call
+/// chain.
+struct Invocation {
+    /// Tail calls taken so far.
+    tail_calls: u32,
+    /// Program scheduled by `bpf_tail_call`, run once the current one exits.
+    next: Option<EbpfProgram>,
+    /// Budget of the first program of the chain.
+    budget: Budget,
+    /// `platform::time_ns()` when the invocation started.
+    start_ns: u64,
+    /// Instructions charged by loop back-edges so far.
+    insns: u64,
+    /// Whether the budget ran out.
+    exceeded: bool,
+}
+
+/// Running invocations per CPU, innermost last. Programs with tail call
+/// sites or instrumented loops push a frame on entry and pop it on exit, so
+/// when one of them calls `bpf_tail_call` or charges its budget, the top
+/// frame is its own. Other programs never take these locks.
+static INVOCATIONS: [Mutex<Vec<Invocation>>; INVOCATION_CPUS] =
+    [const { Mutex::new(Vec::new()) }; INVOCATION_CPUS];
+
+fn invocations() -> &'static Mutex<Vec<Invocation>> {
+    &INVOCATIONS[crate::platform::cpu_id() as usize % INVOCATION_CPUS]
+}
+
+/// Invocations running per CPU, counting all programs, nested or not.
+static RUN_DEPTH: [AtomicUsize; INVOCATION_CPUS] = [const { AtomicUsize::new(0) }; INVOCATION_CPUS];
+
+fn run_depth_counter() -> &'static AtomicUsize {
+    &RUN_DEPTH[crate::platform::cpu_id() as usize % INVOCATION_CPUS]
+}
+
+/// Number of invocations running on this CPU: 1 in a program that is not
+/// nested in another, 0 outside of programs.
+pub(crate) fn run_depth() -> usize {
+    run_depth_counter().load(Ordering::Relaxed)
+}
+
+/// Run a program and every program it tail-calls into.
+///
+/// The result is the return value of the last program in the chain.
+fn run_invocation(
+    code: &PreparedCode,
+    ctx: &mut [u8],
+    budget: Budget,
+    start_ns: u64,
+) -> Result<u64, Error> {
+    let frames = invocations();
+    frames.lock().push(Invocation {
+        tail_calls: 0,
+        next: None,
+        budget,
+        start_ns,
+        insns: 0,
+        exceeded: false,
+    });
+
+    let mut result = code.run(ctx);
+    while result.is_ok() {
+        // Invocations nested in this one have popped their frames by now
+        let next = frames.lock().last_mut().and_then(|f| f.next.take());
+        let Some(next) = next else {
+            break;
+        };
+        result = next.code.run(ctx);
+    }
+
+    frames.lock().pop();
+    result
+}
+
+/// Schedule a tail call for the innermost invocation on this CPU, the one
+/// of the calling program.
+///
+/// Backs the `bpf_tail_call` helper. Fails if the slot is empty, the target
+/// program is gone, or the invocation already took [`MAX_TAIL_CALL_CNT`]
+/// tail calls; the calling program then continues as in Linux.
+pub(crate) fn tail_call(map_fd: u32, index: u32) -> Result<(), &'static str> {
+    let prog_id = crate::maps::prog_array_get(map_fd, &index.to_le_bytes())
+        .ok_or("empty slot or not a program array")?;
+    let program = get_program(prog_id).ok_or("target program not loaded")?;
+
+    let mut frames = invocations().lock();
+    let frame = frames.last_mut().ok_or("no program running")?;
+    if frame.tail_calls >= MAX_TAIL_CALL_CNT {
+        return Err("tail call limit reached");
+    }
+    frame.tail_calls += 1;
+    frame.next = Some(program);
+    Ok(())
+}
+
+/// Charge `insns` instructions to the innermost invocation on this CPU.
+///
+/// Backs the watchdog tick helpers. Returns `false` once the budget has run
+/// out.
+pub(crate) fn charge_budget(insns: u64) -> bool {
+    let mut frames = invocations().lock();
+    let Some(frame) = frames.last_mut() else {
+        return true;
+    };
+    frame.insns = frame.insns.saturating_add(insns);
+    if !frame.exceeded && frame.budget.exceeded(frame.insns, frame.start_ns) {
+        frame.exceeded = true;
+    }
+    !frame.exceeded
+}
+
+/// Whether the innermost invocation on this CPU ran out of budget.
+fn invocation_exceeded() -> bool {
+    invocations().lock().last().is_some_and(|f| f.exceeded)
+}
+
 // =============================================================================
 // Initialization
 // =============================================================================
@@ -501,4 +1918,5 @@ pub fn list_programs() -> Vec<ProgramInfo> {
 pub fn init() {
     log::info!("Initializing eBPF runtime...");
     log::info!("  - {} helpers available", helpers::SUPPORTED_HELPERS.len());
+    log::info!("  - default exec mode: {:?}", default_exec_mode());
 }
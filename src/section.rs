@@ -0,0 +1,153 @@
+//! Program type and attach target inference from ELF section names.
+//!
+//! Follows the libbpf/aya naming conventions plus AxVisor-specific prefixes:
+//!
+//! | Section                    | Program type     | Attached with              |
+//! |----------------------------|------------------|----------------------------|
+//! | `kprobe/<sym>`             | `Kprobe`         | `hprobe_manager::attach`   |
+//! | `kretprobe/<sym>`          | `Kretprobe`      | `hprobe_manager::attach`   |
+//! | `hprobe/<sym>`             | `Hprobe`         | `hprobe_manager::attach`   |
+//! | `hretprobe/<sym>`          | `Hretprobe`      | `hprobe_manager::attach`   |
+//! | `tracepoint/<sub>/<event>` | `Tracepoint`     | `attach::attach`           |
+//! | `gkprobe/<vm>/<addr>`      | `GuestKprobe`    | guest `manager::attach`    |
+//! | `gkretprobe/<vm>/<addr>`   | `GuestKretprobe` | guest `manager::attach`    |
+//!
+//! The hypervisor is the "kernel" here, so `kprobe/` and `kretprobe/`
+//! programs probe hypervisor symbols just like `hprobe/` and `hretprobe/`.
+
+use alloc::string::{String, ToString};
+
+/// Program type inferred from a section name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramType {
+    /// `kprobe/<sym>`: hypervisor function entry.
+    Kprobe,
+    /// `kretprobe/<sym>`: hypervisor function return.
+    Kretprobe,
+    /// `hprobe/<sym>`: hypervisor function entry.
+    Hprobe,
+    /// `hretprobe/<sym>`: hypervisor function return.
+    Hretprobe,
+    /// `tracepoint/<subsystem>/<event>`: static tracepoint.
+    Tracepoint,
+    /// `gkprobe/<vm>/<addr>`: guest kernel function entry.
+    GuestKprobe,
+    /// `gkretprobe/<vm>/<addr>`: guest kernel function return.
+    GuestKretprobe,
+    /// Raw bytecode or a section name without a known prefix.
+    Unknown,
+}
+
+impl ProgramType {
+    /// Whether this is a return probe.
+    pub fn is_return_probe(&self) -> bool {
+        matches!(
+            self,
+            Self::Kretprobe | Self::Hretprobe | Self::GuestKretprobe
+        )
+    }
+
+    /// Short label for display.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::Kprobe => "kprobe",
+            Self::Kretprobe => "kretprobe",
+            Self::Hprobe => "hprobe",
+            Self::Hretprobe => "hretprobe",
+            Self::Tracepoint => "tracepoint",
+            Self::GuestKprobe => "gkprobe",
+            Self::GuestKretprobe => "gkretprobe",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+/// Where a program should be attached.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AttachTarget {
+    /// Hypervisor symbol for an hprobe.
+    Symbol(String),
+    /// Tracepoint name in "subsystem:event" format.
+    Tracepoint(String),
+    /// Guest virtual address inside a VM.
+    Guest {
+        /// Target VM ID.
+        vm_id: u32,
+        /// Guest virtual address of the probed instruction.
+        gva: u64,
+    },
+}
+
+/// Program type and attach target parsed from a section name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SectionSpec {
+    /// Inferred program type.
+    pub prog_type: ProgramType,
+    /// Attach target, if the section name carries one.
+    pub target: Option<AttachTarget>,
+}
+
+impl SectionSpec {
+    /// Spec for programs without a known section.
+    pub const UNKNOWN: Self = Self {
+        prog_type: ProgramType::Unknown,
+        target: None,
+    };
+
+    /// Parse a section name such as `kprobe/vcpu_run` or `tracepoint/vmm/vm_destroy`.
+    ///
+    /// Unknown prefixes yield [`ProgramType::Unknown`]; a known prefix with a
+    /// malformed target yields the type without a target.
+    pub fn parse(section: &str) -> Self {
+        let Some((kind, rest)) = section.split_once('/') else {
+            return Self::UNKNOWN;
+        };
+
+        let prog_type = match kind {
+            "kprobe" => ProgramType::Kprobe,
+            "kretprobe" => ProgramType::Kretprobe,
+            "hprobe" => ProgramType::Hprobe,
+            "hretprobe" => ProgramType::Hretprobe,
+            "tracepoint" => ProgramType::Tracepoint,
+            "gkprobe" => ProgramType::GuestKprobe,
+            "gkretprobe" => ProgramType::GuestKretprobe,
+            _ => return Self::UNKNOWN,
+        };
+
+        let target = match prog_type {
+            ProgramType::Tracepoint => rest
+                .split_once('/')
+                .filter(|(sub, event)| !sub.is_empty() && !event.is_empty())
+                .map(|(sub, event)| AttachTarget::Tracepoint(alloc::format!("{}:{}", sub, event))),
+            ProgramType::GuestKprobe | ProgramType::GuestKretprobe => {
+                rest.split_once('/').and_then(|(vm, addr)| {
+                    Some(AttachTarget::Guest {
+                        vm_id: vm.parse().ok()?,
+                        gva: parse_addr(addr)?,
+                    })
+                })
+            }
+            _ if !rest.is_empty() => Some(AttachTarget::Symbol(rest.to_string())),
+            _ => None,
+        };
+
+        Self { prog_type, target }
+    }
+}
+
+/// Parse a hex (`0x...`) or decimal address.
+fn parse_addr(s: &str) -> Option<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    }
+}
+
+/// Section prefixes aya-obj does not know, and the same-length aliases it
+/// accepts. The real names are kept for [`SectionSpec::parse`].
+pub(crate) const AYA_SECTION_ALIASES: &[(&[u8], &[u8])] = &[
+    (b"hprobe/", b"kprobe/"),
+    (b"hretprobe/", b"kretprobe/"),
+    (b"gkprobe/", b"kprobe/_"),
+    (b"gkretprobe/", b"kretprobe/_"),
+];
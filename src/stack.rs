@@ -0,0 +1,212 @@
+//! Stack traces for `bpf_get_stackid` and `bpf_get_stack`.
+//!
+//! Probe handlers that pass a `PtRegs` as program context register the
+//! trapped PC and frame pointer with [`set_current_frame`] for the duration
+//! of the run. The helpers then unwind the hypervisor stack from there by
+//! frame records: each frame pointer points at a pair of (caller's frame
+//! pointer, return address), the layout of both AArch64 (x29/x30) and
+//! x86_64 (rbp/return address) frames.
+//!
+//! Traces are lists of return addresses, innermost first, starting with the
+//! trapped PC. `bpf_get_stackid` stores them in a [`MapType::StackTrace`]
+//! map; see `output::format_stack_trace` for printing them symbolized.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use crate::maps::{self, MapType};
+use crate::platform::MAX_CPUS;
+
+/// Most frames a trace can hold (`PERF_MAX_STACK_DEPTH` in Linux).
+pub const MAX_STACK_DEPTH: u32 = 127;
+
+/// Number of innermost frames to skip.
+pub const BPF_F_SKIP_FIELD_MASK: u64 = 0xff;
+/// Unwind the user stack. Guest stacks cannot be unwound; always rejected.
+pub const BPF_F_USER_STACK: u64 = 1 << 8;
+/// Compare only the stack hash before reusing a stack ID. Accepted, but
+/// stacks are always compared in full.
+pub const BPF_F_FAST_STACK_CMP: u64 = 1 << 9;
+/// Replace the stack stored under a colliding stack ID.
+pub const BPF_F_REUSE_STACKID: u64 = 1 << 10;
+
+const EFAULT: i64 = 14;
+const EEXIST: i64 = 17;
+const EINVAL: i64 = 22;
+
+/// Frame records further than this above the trapped frame pointer are
+/// treated as corrupt and end the trace.
+const MAX_STACK_SPAN: u64 = 1 << 20;
+
+// =============================================================================
+// Current Frame
+// =============================================================================
+
+/// Frame a program on one CPU was entered from.
+struct CurrentFrame {
+    /// Address of the `PtRegs` passed as program context, 0 if none.
+    ctx: AtomicU64,
+    pc: AtomicU64,
+    fp: AtomicU64,
+}
+
+static CURRENT_FRAMES: [CurrentFrame; MAX_CPUS as usize] = [const {
+    CurrentFrame {
+        ctx: AtomicU64::new(0),
+        pc: AtomicU64::new(0),
+        fp: AtomicU64::new(0),
+    }
+}; MAX_CPUS as usize];
+
+fn current_slot() -> Option<&'static CurrentFrame> {
+    CURRENT_FRAMES.get(crate::platform::cpu_id() as usize)
+}
+
+/// Set the frame programs run next on this CPU can unwind from (called
+/// before eBPF program execution).
+///
+/// # Arguments
+/// * `ctx` - Address of the context the program is run with.
+/// * `pc` - Trapped program counter.
+/// * `fp` - Trapped frame pointer.
+pub fn set_current_frame(ctx: u64, pc: u64, fp: u64) {
+    if let Some(slot) = current_slot() {
+        slot.pc.store(pc, Ordering::Relaxed);
+        slot.fp.store(fp, Ordering::Relaxed);
+        slot.ctx.store(ctx, Ordering::Relaxed);
+    }
+}
+
+/// Clear the frame set by [`set_current_frame`].
+pub fn clear_current_frame() {
+    if let Some(slot) = current_slot() {
+        slot.ctx.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Trapped (pc, fp) if `ctx` is the context registered on this CPU.
+fn current_frame(ctx: u64) -> Option<(u64, u64)> {
+    let slot = current_slot()?;
+    if ctx == 0 || slot.ctx.load(Ordering::Relaxed) != ctx {
+        return None;
+    }
+    let pc = slot.pc.load(Ordering::Relaxed);
+    Some((pc, slot.fp.load(Ordering::Relaxed)))
+}
+
+// =============================================================================
+// Unwinding
+// =============================================================================
+
+/// Unwind at most `max_frames` frames starting at `pc`, `fp`.
+///
+/// The walk stops at a null or misaligned frame pointer, at a frame record
+/// that is not above the previous one or more than `MAX_STACK_SPAN` above
+/// the first, and at a null return address.
+///
+/// # Safety
+/// Every frame record reached this way must be readable. This holds for the
+/// frame pointer chain of the trapped hypervisor code.
+pub unsafe fn unwind(pc: u64, fp: u64, max_frames: usize) -> Vec<u64> {
+    let mut trace = Vec::new();
+    if max_frames == 0 {
+        return trace;
+    }
+    trace.push(pc);
+
+    let limit = fp.saturating_add(MAX_STACK_SPAN);
+    let mut fp = fp;
+    while trace.len() < max_frames && fp != 0 && fp % 8 == 0 && fp < limit {
+        let record = fp as *const u64;
+        let (next_fp, ret) = unsafe { (record.read(), record.add(1).read()) };
+        if ret == 0 {
+            break;
+        }
+        trace.push(ret);
+        if next_fp <= fp {
+            break;
+        }
+        fp = next_fp;
+    }
+    trace
+}
+
+/// Trace of the frame registered for `ctx`, without the `skip` innermost
+/// frames and at most `max_frames` long.
+fn trace_for(ctx: u64, skip: usize, max_frames: usize) -> Option<Vec<u64>> {
+    let (pc, fp) = current_frame(ctx)?;
+    let trace = unsafe { unwind(pc, fp, skip + max_frames) };
+    let trace: Vec<u64> = trace.into_iter().skip(skip).collect();
+    (!trace.is_empty()).then_some(trace)
+}
+
+// =============================================================================
+// Helpers
+// =============================================================================
+
+/// `bpf_get_stackid(ctx, map, flags)`: store the current stack in a stack
+/// trace map.
+///
+/// Returns the stack ID (the map key), or `-EFAULT` if the program was not
+/// run from a registered frame, `-EEXIST` if the ID is taken by another
+/// stack and `BPF_F_REUSE_STACKID` is not set, `-EINVAL` for bad flags or a
+/// map that is not a stack trace map.
+pub fn get_stackid(ctx: u64, map_id: u32, flags: u64) -> i64 {
+    let known = BPF_F_SKIP_FIELD_MASK | BPF_F_FAST_STACK_CMP | BPF_F_REUSE_STACKID;
+    if flags & !known != 0 || maps::get_map_type(map_id) != Some(MapType::StackTrace) {
+        return -EINVAL;
+    }
+    let Some((_, value_size)) = crate::map_ops::get_map_sizes(map_id) else {
+        return -EINVAL;
+    };
+    let skip = (flags & BPF_F_SKIP_FIELD_MASK) as usize;
+    let Some(trace) = trace_for(ctx, skip, value_size as usize / 8) else {
+        return -EFAULT;
+    };
+
+    let mut value = alloc::vec![0u8; value_size as usize];
+    for (slot, addr) in value.chunks_exact_mut(8).zip(&trace) {
+        slot.copy_from_slice(&addr.to_le_bytes());
+    }
+    let reuse = flags & BPF_F_REUSE_STACKID != 0;
+    match maps::stack_map_store(map_id, stack_hash(&trace), &value, reuse) {
+        Ok(id) => id as i64,
+        Err(maps::Error::KeyExists) => -EEXIST,
+        Err(_) => -EINVAL,
+    }
+}
+
+/// `bpf_get_stack(ctx, buf, size, flags)`: copy the current stack into
+/// `buf`.
+///
+/// Returns the number of bytes written, 8 per frame; the rest of `buf` is
+/// zeroed. On error `buf` is zeroed and `-EFAULT` or `-EINVAL` is returned,
+/// as for [`get_stackid`].
+///
+/// # Safety
+/// `buf` must be valid for `size` bytes of writes.
+pub unsafe fn get_stack(ctx: u64, buf: *mut u8, size: u32, flags: u64) -> i64 {
+    let buf = unsafe { core::slice::from_raw_parts_mut(buf, size as usize) };
+    buf.fill(0);
+    if flags & !BPF_F_SKIP_FIELD_MASK != 0 || size % 8 != 0 {
+        return -EINVAL;
+    }
+    let skip = (flags & BPF_F_SKIP_FIELD_MASK) as usize;
+    let Some(trace) = trace_for(ctx, skip, size as usize / 8) else {
+        return -EFAULT;
+    };
+    for (slot, addr) in buf.chunks_exact_mut(8).zip(&trace) {
+        slot.copy_from_slice(&addr.to_le_bytes());
+    }
+    (trace.len() * 8) as i64
+}
+
+/// FNV-1a over the return addresses of a trace.
+fn stack_hash(trace: &[u64]) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in trace.iter().flat_map(|addr| addr.to_le_bytes()) {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
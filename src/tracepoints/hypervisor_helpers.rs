@@ -59,6 +59,16 @@ pub fn get_hypervisor_helper(id: u32) -> Option<crate::helpers::HelperFn> {
     }
 }
 
+/// Get the name of a hypervisor helper by ID.
+pub fn hypervisor_helper_name(id: u32) -> Option<&'static str> {
+    match id {
+        hypervisor_helper_ids::GET_CURRENT_VM_ID => Some("bpf_get_current_vm_id"),
+        hypervisor_helper_ids::GET_CURRENT_VCPU_ID => Some("bpf_get_current_vcpu_id"),
+        hypervisor_helper_ids::GET_EXIT_REASON => Some("bpf_get_exit_reason"),
+        _ => None,
+    }
+}
+
 /// List of supported hypervisor helper IDs.
 pub const HYPERVISOR_HELPERS: &[u32] = &[
     hypervisor_helper_ids::GET_CURRENT_VM_ID,
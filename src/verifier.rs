@@ -0,0 +1,2214 @@
+//! Static eBPF verifier.
+//!
+//! Runs on every program before it enters the runtime registry. Programs
+//! execute at EL2, so anything accepted here can reach hypervisor memory.
+//!
+//! Verification happens in two passes:
+//!
+//! 1. A linear pass checks opcodes, register numbers, `lddw` pairs, and that
+//!    every jump and BPF-to-BPF call lands on an instruction boundary.
+//! 2. A path-sensitive walk simulates every reachable path, tracking register
+//!    types (scalar ranges, context/stack/map-value pointers), stack slot
+//!    initialization and helper argument types.
+//!
+//! Loops are accepted only if the walk proves they terminate: all paths must
+//! reach `exit` within [`MAX_PROCESSED_INSNS`] simulated instructions. A
+//! path that branches back into a state it was already in loops forever and
+//! is rejected. A path is only cut short when it branches into a state from
+//! which every path has already been checked.
+//!
+//! `bpf_loop` callbacks are verified as subprograms called from the helper
+//! call site, once with the caller's state at the call and once more with
+//! the state the first iteration leaves behind. They must return 0 or 1.
+//!
+//! Records from `bpf_ringbuf_reserve` are not reference-tracked: a program
+//! may return without submitting one, and the runtime discards it.
+
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::hash::{Hash, Hasher};
+
+use crate::helpers::{self, id};
+use crate::map_ops;
+use crate::maps::{BPF_F_RDONLY_PROG, BPF_F_WRONLY_PROG, MapType};
+
+/// Size of one eBPF instruction in bytes.
+pub const INSN_SIZE: usize = 8;
+
+/// Maximum number of instructions in a single program.
+pub const MAX_PROG_INSNS: usize = 65536;
+
+/// Maximum number of instructions simulated across all paths.
+pub const MAX_PROCESSED_INSNS: usize = 1_000_000;
+
+/// Stack size available to each frame, in bytes.
+pub const STACK_SIZE: usize = 512;
+
+/// Maximum BPF-to-BPF call depth (including the entry frame).
+pub const MAX_CALL_DEPTH: usize = 8;
+
+/// Upper bound for context accesses.
+///
+/// The exact context length (`TraceContext` or `PtRegs`) depends on the probe
+/// the program is attached to and is bounds-checked by the interpreter. The
+/// JIT does no bounds checks, so the verifier also records how far into the
+/// context a program reads, and the runtime only runs native code on
+/// contexts at least that long.
+pub const CTX_MAX_SIZE: u64 = 1024;
+
+/// Largest memory region a helper may read or write in one call.
+const MAX_HELPER_MEM_SIZE: u64 = 4096;
+
+/// Largest constant that may be added to a pointer.
+const MAX_PTR_OFFSET: u64 = 1 << 29;
+
+/// Number of fully explored states remembered per instruction for pruning.
+const MAX_STATES_PER_INSN: usize = 64;
+
+// =============================================================================
+// Instruction Encoding
+// =============================================================================
+
+// Instruction classes
+pub(crate) const BPF_LD: u8 = 0x00;
+pub(crate) const BPF_LDX: u8 = 0x01;
+pub(crate) const BPF_ST: u8 = 0x02;
+pub(crate) const BPF_STX: u8 = 0x03;
+pub(crate) const BPF_ALU: u8 = 0x04;
+pub(crate) const BPF_JMP: u8 = 0x05;
+pub(crate) const BPF_JMP32: u8 = 0x06;
+pub(crate) const BPF_ALU64: u8 = 0x07;
+
+// Memory sizes and modes
+pub(crate) const BPF_W: u8 = 0x00;
+pub(crate) const BPF_H: u8 = 0x08;
+pub(crate) const BPF_B: u8 = 0x10;
+pub(crate) const BPF_DW: u8 = 0x18;
+pub(crate) const BPF_MEM: u8 = 0x60;
+pub(crate) const BPF_ATOMIC: u8 = 0xc0;
+pub(crate) const SIZE_MASK: u8 = 0x18;
+pub(crate) const MODE_MASK: u8 = 0xe0;
+
+/// Source operand is a register (otherwise the immediate).
+pub(crate) const BPF_X: u8 = 0x08;
+pub(crate) const OP_MASK: u8 = 0xf0;
+
+// ALU operations
+pub(crate) const BPF_ADD: u8 = 0x00;
+pub(crate) const BPF_SUB: u8 = 0x10;
+pub(crate) const BPF_MUL: u8 = 0x20;
+pub(crate) const BPF_DIV: u8 = 0x30;
+pub(crate) const BPF_OR: u8 = 0x40;
+pub(crate) const BPF_AND: u8 = 0x50;
+pub(crate) const BPF_LSH: u8 = 0x60;
+pub(crate) const BPF_RSH: u8 = 0x70;
+pub(crate) const BPF_NEG: u8 = 0x80;
+pub(crate) const BPF_MOD: u8 = 0x90;
+pub(crate) const BPF_XOR: u8 = 0xa0;
+pub(crate) const BPF_MOV: u8 = 0xb0;
+pub(crate) const BPF_ARSH: u8 = 0xc0;
+pub(crate) const BPF_END: u8 = 0xd0;
+
+// Jump operations
+pub(crate) const BPF_JA: u8 = 0x00;
+pub(crate) const BPF_JEQ: u8 = 0x10;
+pub(crate) const BPF_JGT: u8 = 0x20;
+pub(crate) const BPF_JGE: u8 = 0x30;
+pub(crate) const BPF_JSET: u8 = 0x40;
+pub(crate) const BPF_JNE: u8 = 0x50;
+pub(crate) const BPF_JSGT: u8 = 0x60;
+pub(crate) const BPF_JSGE: u8 = 0x70;
+pub(crate) const BPF_CALL: u8 = 0x80;
+pub(crate) const BPF_EXIT: u8 = 0x90;
+pub(crate) const BPF_JLT: u8 = 0xa0;
+pub(crate) const BPF_JLE: u8 = 0xb0;
+pub(crate) const BPF_JSLT: u8 = 0xc0;
+pub(crate) const BPF_JSLE: u8 = 0xd0;
+
+/// `lddw dst, imm64` (first half of a 16-byte instruction).
+pub(crate) const LD_DW_IMM: u8 = BPF_LD | BPF_DW;
+
+/// `lddw` source marker: imm holds a map FD.
+pub const PSEUDO_MAP_FD: u8 = 1;
+
+/// `lddw` source marker: imm holds a map FD, the second imm an offset into
+/// the map's value (global variables in `.data`/`.rodata`/`.bss`).
+pub const PSEUDO_MAP_VALUE: u8 = 2;
+
+/// `lddw` source marker: imm holds the relative start of a callback function
+/// (`BPF_PSEUDO_FUNC`).
+pub const PSEUDO_FUNC: u8 = 4;
+
+/// `call` source marker: imm holds a relative BPF-to-BPF call target.
+pub const PSEUDO_CALL: u8 = 1;
+
+/// Frame pointer register (read-only).
+const FRAME_PTR: u8 = 10;
+
+/// One decoded eBPF instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Insn {
+    pub op: u8,
+    pub dst: u8,
+    pub src: u8,
+    pub off: i16,
+    pub imm: i32,
+}
+
+impl Insn {
+    /// Decode instruction `idx` from raw bytecode.
+    pub(crate) fn decode(prog: &[u8], idx: usize) -> Self {
+        let b = &prog[idx * INSN_SIZE..(idx + 1) * INSN_SIZE];
+        Self {
+            op: b[0],
+            dst: b[1] & 0x0f,
+            src: b[1] >> 4,
+            off: i16::from_le_bytes([b[2], b[3]]),
+            imm: i32::from_le_bytes([b[4], b[5], b[6], b[7]]),
+        }
+    }
+
+    /// Encode back to the 8-byte wire format.
+    pub(crate) fn encode(&self) -> [u8; INSN_SIZE] {
+        let mut b = [0u8; INSN_SIZE];
+        b[0] = self.op;
+        b[1] = (self.src << 4) | (self.dst & 0x0f);
+        b[2..4].copy_from_slice(&self.off.to_le_bytes());
+        b[4..8].copy_from_slice(&self.imm.to_le_bytes());
+        b
+    }
+
+    pub(crate) fn class(&self) -> u8 {
+        self.op & 0x07
+    }
+
+    pub(crate) fn uses_reg_src(&self) -> bool {
+        self.op & BPF_X != 0
+    }
+}
+
+pub(crate) fn access_size(op: u8) -> usize {
+    match op & SIZE_MASK {
+        BPF_W => 4,
+        BPF_H => 2,
+        BPF_B => 1,
+        _ => 8,
+    }
+}
+
+fn jump_target(idx: usize, off: i64) -> i64 {
+    idx as i64 + off + 1
+}
+
+// =============================================================================
+// Errors
+// =============================================================================
+
+/// Reason a program was rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Program is empty, not a multiple of 8 bytes, or too long.
+    ProgramSize(usize),
+    /// Unknown or unsupported opcode.
+    InvalidOpcode(u8),
+    /// Register number outside r0-r10.
+    InvalidRegister(u8),
+    /// `lddw` is missing its second half or the second half is malformed.
+    IncompleteLddw,
+    /// Jump or call target is outside the program.
+    JumpOutOfRange(i64),
+    /// Jump or call target is the second half of an `lddw`.
+    JumpIntoLddw(usize),
+    /// Execution can run past the last instruction.
+    FallThroughEnd,
+    /// Division or modulo by a constant zero.
+    DivisionByZero,
+    /// Shift amount is out of range for the operand width.
+    InvalidShift(i32),
+    /// Register is read before it is written.
+    UninitRegister(u8),
+    /// Instruction writes the read-only frame pointer r10.
+    FramePointerWrite,
+    /// Helper ID is not in the supported or hypervisor helper set.
+    UnknownHelper(u32),
+    /// Map FD does not refer to a live map.
+    InvalidMapFd(u64),
+    /// Helper argument has the wrong type or size.
+    InvalidHelperArg(u8),
+    /// Memory access through a register is out of bounds or not a pointer.
+    InvalidMemoryAccess { reg: u8, off: i64, size: usize },
+    /// Read from a stack slot that was never written.
+    UninitStackRead(i64),
+    /// Dereference of a pointer that may be null.
+    NullPointerDeref(u8),
+    /// Arithmetic that would produce an unbounded or meaningless pointer.
+    InvalidPointerArithmetic(u8),
+    /// Write through a pointer to read-only memory.
+    ReadOnlyWrite(u8),
+    /// Write to a map created with `BPF_F_RDONLY_PROG`.
+    MapWriteForbidden,
+    /// Read from a map created with `BPF_F_WRONLY_PROG`.
+    MapReadForbidden,
+    /// Subprogram returns a pointer into its own stack frame.
+    StackPointerEscape,
+    /// r0 is not initialized at `exit`.
+    UninitReturnValue,
+    /// BPF-to-BPF calls nest deeper than [`MAX_CALL_DEPTH`].
+    CallDepthExceeded,
+    /// `bpf_loop` callback may return something other than 0 or 1.
+    InvalidCallbackReturn,
+    /// A loop could not be proven to terminate.
+    UnboundedLoop,
+    /// Too many paths to explore within [`MAX_PROCESSED_INSNS`].
+    TooComplex,
+}
+
+impl core::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::ProgramSize(n) => write!(f, "invalid program size ({} instructions)", n),
+            Self::InvalidOpcode(op) => write!(f, "invalid opcode {:#04x}", op),
+            Self::InvalidRegister(r) => write!(f, "invalid register r{}", r),
+            Self::IncompleteLddw => write!(f, "incomplete lddw instruction"),
+            Self::JumpOutOfRange(t) => write!(f, "jump target {} out of range", t),
+            Self::JumpIntoLddw(t) => write!(f, "jump into the middle of lddw at {}", t),
+            Self::FallThroughEnd => write!(f, "execution falls off the end of the program"),
+            Self::DivisionByZero => write!(f, "division by zero"),
+            Self::InvalidShift(s) => write!(f, "invalid shift amount {}", s),
+            Self::UninitRegister(r) => write!(f, "r{} is not initialized", r),
+            Self::FramePointerWrite => write!(f, "frame pointer r10 is read-only"),
+            Self::UnknownHelper(h) => write!(f, "unknown helper {}", h),
+            Self::InvalidMapFd(fd) => write!(f, "invalid map fd {}", fd),
+            Self::InvalidHelperArg(r) => write!(f, "invalid helper argument r{}", r),
+            Self::InvalidMemoryAccess { reg, off, size } => {
+                write!(
+                    f,
+                    "invalid memory access via r{} (off={}, size={})",
+                    reg, off, size
+                )
+            }
+            Self::UninitStackRead(off) => write!(f, "read of uninitialized stack at {}", off),
+            Self::NullPointerDeref(r) => write!(f, "r{} may be null", r),
+            Self::InvalidPointerArithmetic(r) => write!(f, "invalid pointer arithmetic on r{}", r),
+            Self::ReadOnlyWrite(r) => write!(f, "write through r{} to read-only memory", r),
+            Self::MapWriteForbidden => write!(f, "write into map forbidden"),
+            Self::MapReadForbidden => write!(f, "read from map forbidden"),
+            Self::StackPointerEscape => write!(f, "subprogram returns a pointer to its own stack"),
+            Self::UninitReturnValue => write!(f, "r0 is not initialized at exit"),
+            Self::CallDepthExceeded => write!(f, "call depth exceeds {}", MAX_CALL_DEPTH),
+            Self::InvalidCallbackReturn => write!(f, "callback must return 0 or 1"),
+            Self::UnboundedLoop => write!(f, "loop may not terminate"),
+            Self::TooComplex => write!(f, "program too complex to verify"),
+        }
+    }
+}
+
+/// Verifier rejection with the offending instruction index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifierError {
+    /// Index of the rejected instruction.
+    pub insn: usize,
+    /// Why it was rejected.
+    pub kind: ErrorKind,
+}
+
+impl VerifierError {
+    fn new(insn: usize, kind: ErrorKind) -> Self {
+        Self { insn, kind }
+    }
+}
+
+impl core::fmt::Display for VerifierError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "insn {}: {}", self.insn, self.kind)
+    }
+}
+
+impl core::error::Error for VerifierError {}
+
+// =============================================================================
+// Abstract State
+// =============================================================================
+
+/// Unsigned value range of a scalar register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Range {
+    min: u64,
+    max: u64,
+}
+
+impl Range {
+    const UNKNOWN: Self = Self {
+        min: 0,
+        max: u64::MAX,
+    };
+    const U32: Self = Self {
+        min: 0,
+        max: u32::MAX as u64,
+    };
+
+    fn konst(v: u64) -> Self {
+        Self { min: v, max: v }
+    }
+
+    /// Range of a zero-extended load of `bytes` bytes.
+    fn of_size(bytes: usize) -> Self {
+        if bytes >= 8 {
+            Self::UNKNOWN
+        } else {
+            Self {
+                min: 0,
+                max: (1u64 << (bytes * 8)) - 1,
+            }
+        }
+    }
+
+    fn as_const(&self) -> Option<u64> {
+        (self.min == self.max).then_some(self.min)
+    }
+
+    fn trunc32(self) -> Self {
+        if self.max <= u32::MAX as u64 {
+            self
+        } else {
+            Self::U32
+        }
+    }
+}
+
+/// What a pointer register points to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum PtrKind {
+    /// Program context (`TraceContext` or `PtRegs`).
+    Ctx,
+    /// Stack of the given call frame.
+    Stack(usize),
+    /// Value returned by `bpf_map_lookup_elem`, or a global variable.
+    /// `map_flags` are those of the map, which may forbid reads or writes.
+    MapValue {
+        id: u32,
+        size: u32,
+        map_flags: u32,
+        nullable: bool,
+    },
+    /// Read-only helper buffer (e.g. tracepoint name).
+    Mem { id: u32, size: u32, nullable: bool },
+    /// Record returned by `bpf_ringbuf_reserve`.
+    Record { id: u32, size: u32, nullable: bool },
+}
+
+impl PtrKind {
+    fn null_id(&self) -> Option<u32> {
+        match *self {
+            Self::MapValue {
+                id, nullable: true, ..
+            }
+            | Self::Mem {
+                id, nullable: true, ..
+            }
+            | Self::Record {
+                id, nullable: true, ..
+            } => Some(id),
+            _ => None,
+        }
+    }
+
+    fn is_nullable(&self) -> bool {
+        self.null_id().is_some()
+    }
+
+    fn non_null(self) -> Self {
+        match self {
+            Self::MapValue {
+                id,
+                size,
+                map_flags,
+                ..
+            } => Self::MapValue {
+                id,
+                size,
+                map_flags,
+                nullable: false,
+            },
+            Self::Mem { id, size, .. } => Self::Mem {
+                id,
+                size,
+                nullable: false,
+            },
+            Self::Record { id, size, .. } => Self::Record {
+                id,
+                size,
+                nullable: false,
+            },
+            other => other,
+        }
+    }
+}
+
+/// Abstract type of one register.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum RegType {
+    Uninit,
+    Scalar(Range),
+    MapFd(u32),
+    /// Callback function starting at the given instruction.
+    Func(usize),
+    /// Pointer at `off..=off + var` bytes from the start of the region.
+    Ptr {
+        kind: PtrKind,
+        off: i64,
+        var: u64,
+    },
+}
+
+impl RegType {
+    fn ptr(kind: PtrKind) -> Self {
+        Self::Ptr {
+            kind,
+            off: 0,
+            var: 0,
+        }
+    }
+}
+
+/// Per-frame stack: initialized bytes plus spilled registers per 8-byte slot.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StackState {
+    init: [u64; STACK_SIZE / 64],
+    spills: [Option<RegType>; STACK_SIZE / 8],
+}
+
+impl StackState {
+    fn new() -> Self {
+        Self {
+            init: [0; STACK_SIZE / 64],
+            spills: [None; STACK_SIZE / 8],
+        }
+    }
+
+    fn is_init(&self, byte: usize) -> bool {
+        (self.init[byte / 64] >> (byte % 64)) & 1 == 1
+    }
+
+    fn mark_init(&mut self, byte: usize) {
+        self.init[byte / 64] |= 1 << (byte % 64);
+    }
+
+    /// Bytes from the lowest initialized byte up to the frame pointer.
+    fn depth(&self) -> usize {
+        let Some(word) = self.init.iter().position(|&w| w != 0) else {
+            return 0;
+        };
+        STACK_SIZE - (word * 64 + self.init[word].trailing_zeros() as usize)
+    }
+}
+
+/// Frame of a `bpf_loop` callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct LoopCallback {
+    /// First instruction of the callback.
+    entry: usize,
+    /// Type of the context argument passed to every iteration.
+    ctx: RegType,
+    /// Whether this is the second simulated iteration.
+    again: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Frame {
+    regs: [RegType; 11],
+    stack: StackState,
+    /// Instruction to resume at in the caller after `exit`.
+    ret_pc: usize,
+    /// Set if the frame is a `bpf_loop` callback.
+    callback: Option<LoopCallback>,
+}
+
+impl Frame {
+    fn new(frame_no: usize, ret_pc: usize) -> Self {
+        let mut regs = [RegType::Uninit; 11];
+        regs[FRAME_PTR as usize] = RegType::ptr(PtrKind::Stack(frame_no));
+        Self {
+            regs,
+            stack: StackState::new(),
+            ret_pc,
+            callback: None,
+        }
+    }
+
+    /// Frame for one iteration of a `bpf_loop` callback.
+    fn callback(frame_no: usize, ret_pc: usize, callback: LoopCallback) -> Self {
+        let mut frame = Self::new(frame_no, ret_pc);
+        frame.regs[1] = RegType::Scalar(Range {
+            min: 0,
+            max: helpers::MAX_LOOPS as u64 - 1,
+        });
+        frame.regs[2] = callback.ctx;
+        frame.callback = Some(callback);
+        frame
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct State {
+    pc: usize,
+    frames: Vec<Frame>,
+    next_id: u32,
+}
+
+impl State {
+    fn initial() -> Self {
+        let mut entry = Frame::new(0, 0);
+        entry.regs[1] = RegType::ptr(PtrKind::Ctx);
+        Self {
+            pc: 0,
+            frames: vec![entry],
+            next_id: 0,
+        }
+    }
+
+    fn cur(&self) -> &Frame {
+        self.frames.last().expect("verifier state has no frame")
+    }
+
+    fn cur_mut(&mut self) -> &mut Frame {
+        self.frames.last_mut().expect("verifier state has no frame")
+    }
+
+    fn set_reg(&mut self, reg: u8, ty: RegType) {
+        self.cur_mut().regs[reg as usize] = ty;
+    }
+
+    fn read_reg(&self, idx: usize, reg: u8) -> Result<RegType, VerifierError> {
+        match self.cur().regs[reg as usize] {
+            RegType::Uninit => Err(VerifierError::new(idx, ErrorKind::UninitRegister(reg))),
+            ty => Ok(ty),
+        }
+    }
+
+    fn fresh_id(&mut self) -> u32 {
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Resolve a null check on every copy of the pointer with `id`.
+    fn mark_null(&mut self, id: u32, is_null: bool) {
+        let resolve = |ty: &mut RegType| {
+            if let RegType::Ptr { kind, off, var } = *ty
+                && kind.null_id() == Some(id)
+            {
+                *ty = if is_null {
+                    RegType::Scalar(Range::konst(0))
+                } else {
+                    RegType::Ptr {
+                        kind: kind.non_null(),
+                        off,
+                        var,
+                    }
+                };
+            }
+        };
+        for frame in &mut self.frames {
+            frame.regs.iter_mut().for_each(resolve);
+            frame.stack.spills.iter_mut().flatten().for_each(resolve);
+        }
+    }
+}
+
+// =============================================================================
+// Helper Prototypes
+// =============================================================================
+
+/// Helper argument constraint.
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    /// Not read by the helper.
+    Unused,
+    /// Any initialized value.
+    Any,
+    /// Unmodified pointer to the program context.
+    Ctx,
+    /// Map FD (from `lddw` or a constant).
+    MapFd,
+    /// Pointer to `key_size` readable bytes of the preceding map.
+    MapKey,
+    /// Pointer to `value_size` readable bytes of the preceding map.
+    MapValue,
+    /// Pointer to `value_size` writable bytes of the preceding map, or
+    /// readable bytes if it is a bloom filter (`bpf_map_peek_elem` tests
+    /// the value instead of returning one).
+    UninitMapValue,
+    /// Pointer to readable memory, sized by the next argument.
+    Mem,
+    /// Pointer to writable memory, sized by the next argument.
+    UninitMem,
+    /// Bounded size of the preceding memory argument.
+    Size,
+    /// Constant, non-zero size of the returned record.
+    ConstSize,
+    /// Unmodified, non-null pointer to a reserved ring buffer record.
+    Record,
+    /// Callback function loaded with `lddw` (`BPF_PSEUDO_FUNC`).
+    Func,
+}
+
+/// Helper return type.
+#[derive(Debug, Clone, Copy)]
+enum Ret {
+    Scalar,
+    MapValueOrNull,
+    MemOrNull(u32),
+    /// Ring buffer record of the `ConstSize` argument's size.
+    RecordOrNull,
+}
+
+struct HelperProto {
+    args: [Arg; 5],
+    ret: Ret,
+}
+
+/// Whether `helper_id` may be called by programs.
+fn is_known_helper(helper_id: u32) -> bool {
+    if helpers::SUPPORTED_HELPERS.contains(&helper_id) {
+        return true;
+    }
+    #[cfg(feature = "tracepoint-support")]
+    if crate::tracepoints::hypervisor_helpers::HYPERVISOR_HELPERS.contains(&helper_id) {
+        return true;
+    }
+    false
+}
+
+/// Argument and return constraints for a helper.
+///
+/// Helpers without pointer arguments take no constraints and return a scalar.
+fn helper_proto(helper_id: u32) -> HelperProto {
+    use Arg::*;
+    let (args, ret) = match helper_id {
+        id::MAP_LOOKUP_ELEM => ([MapFd, MapKey, Unused, Unused, Unused], Ret::MapValueOrNull),
+        id::MAP_UPDATE_ELEM => ([MapFd, MapKey, MapValue, Any, Unused], Ret::Scalar),
+        id::MAP_DELETE_ELEM => ([MapFd, MapKey, Unused, Unused, Unused], Ret::Scalar),
+        id::MAP_PUSH_ELEM => ([MapFd, MapValue, Any, Unused, Unused], Ret::Scalar),
+        id::MAP_POP_ELEM | id::MAP_PEEK_ELEM => {
+            ([MapFd, UninitMapValue, Unused, Unused, Unused], Ret::Scalar)
+        }
+        id::PROBE_READ | id::PROBE_READ_KERNEL => {
+            ([UninitMem, Size, Any, Unused, Unused], Ret::Scalar)
+        }
+        id::TRACE_PRINTK => ([Any, Unused, Unused, Unused, Unused], Ret::Scalar),
+        id::TAIL_CALL => ([Ctx, MapFd, Any, Unused, Unused], Ret::Scalar),
+        id::PERF_EVENT_OUTPUT => ([Ctx, MapFd, Any, Mem, Size], Ret::Scalar),
+        id::GET_STACKID => ([Ctx, MapFd, Any, Unused, Unused], Ret::Scalar),
+        id::GET_STACK => ([Ctx, UninitMem, Size, Any, Unused], Ret::Scalar),
+        id::LOOP => ([Any, Func, Any, Any, Unused], Ret::Scalar),
+        id::RINGBUF_OUTPUT => ([MapFd, Mem, Size, Any, Unused], Ret::Scalar),
+        id::RINGBUF_RESERVE => ([MapFd, ConstSize, Any, Unused, Unused], Ret::RecordOrNull),
+        id::RINGBUF_SUBMIT | id::RINGBUF_DISCARD => {
+            ([Record, Any, Unused, Unused, Unused], Ret::Scalar)
+        }
+        id::RINGBUF_QUERY => ([MapFd, Any, Unused, Unused, Unused], Ret::Scalar),
+        id::GET_TRACEPOINT_NAME => (
+            [Any, Unused, Unused, Unused, Unused],
+            Ret::MemOrNull(helpers::MAX_NAME_SIZE as u32),
+        ),
+        _ => ([Unused; 5], Ret::Scalar),
+    };
+    HelperProto { args, ret }
+}
+
+// =============================================================================
+// Linear Pass
+// =============================================================================
+
+/// Check encoding and control-flow targets. Returns the `lddw` tail markers.
+fn check_structure(prog: &[u8], len: usize) -> Result<Vec<bool>, VerifierError> {
+    let mut lddw_tail = vec![false; len];
+    let mut idx = 0;
+    while idx < len {
+        if Insn::decode(prog, idx).op == LD_DW_IMM {
+            let tail_ok = idx + 1 < len && {
+                let next = Insn::decode(prog, idx + 1);
+                next.op == 0 && next.dst == 0 && next.src == 0 && next.off == 0
+            };
+            if !tail_ok {
+                return Err(VerifierError::new(idx, ErrorKind::IncompleteLddw));
+            }
+            lddw_tail[idx + 1] = true;
+            idx += 2;
+        } else {
+            idx += 1;
+        }
+    }
+
+    let check_target = |idx: usize, target: i64| -> Result<(), VerifierError> {
+        if target < 0 || target >= len as i64 {
+            return Err(VerifierError::new(idx, ErrorKind::JumpOutOfRange(target)));
+        }
+        if lddw_tail[target as usize] {
+            return Err(VerifierError::new(
+                idx,
+                ErrorKind::JumpIntoLddw(target as usize),
+            ));
+        }
+        Ok(())
+    };
+
+    for (idx, &is_tail) in lddw_tail.iter().enumerate() {
+        if is_tail {
+            continue;
+        }
+        let insn = Insn::decode(prog, idx);
+        let invalid = || Err(VerifierError::new(idx, ErrorKind::InvalidOpcode(insn.op)));
+        if insn.dst > FRAME_PTR {
+            return Err(VerifierError::new(
+                idx,
+                ErrorKind::InvalidRegister(insn.dst),
+            ));
+        }
+        if insn.src > FRAME_PTR {
+            return Err(VerifierError::new(
+                idx,
+                ErrorKind::InvalidRegister(insn.src),
+            ));
+        }
+
+        match insn.class() {
+            BPF_LD if insn.op == LD_DW_IMM => match insn.src {
+                0 | PSEUDO_MAP_FD | PSEUDO_MAP_VALUE => {}
+                PSEUDO_FUNC => check_target(idx, jump_target(idx, insn.imm as i64))?,
+                _ => return invalid(),
+            },
+            BPF_LDX | BPF_ST => {
+                if insn.op & MODE_MASK != BPF_MEM {
+                    return invalid();
+                }
+            }
+            BPF_STX => match insn.op & MODE_MASK {
+                BPF_MEM => {}
+                // Only plain atomic add (the classic XADD) is supported.
+                BPF_ATOMIC if matches!(insn.op & SIZE_MASK, BPF_W | BPF_DW) && insn.imm == 0 => {}
+                _ => return invalid(),
+            },
+            BPF_ALU | BPF_ALU64 => check_alu_encoding(idx, &insn)?,
+            BPF_JMP | BPF_JMP32 => {
+                let is32 = insn.class() == BPF_JMP32;
+                match insn.op & OP_MASK {
+                    BPF_JA if !is32 => check_target(idx, jump_target(idx, insn.off as i64))?,
+                    BPF_CALL if !is32 && !insn.uses_reg_src() => match insn.src {
+                        0 => {}
+                        PSEUDO_CALL => check_target(idx, jump_target(idx, insn.imm as i64))?,
+                        _ => return invalid(),
+                    },
+                    BPF_EXIT if !is32 && !insn.uses_reg_src() => {}
+                    BPF_JEQ | BPF_JGT | BPF_JGE | BPF_JSET | BPF_JNE | BPF_JSGT | BPF_JSGE
+                    | BPF_JLT | BPF_JLE | BPF_JSLT | BPF_JSLE => {
+                        check_target(idx, jump_target(idx, insn.off as i64))?
+                    }
+                    _ => return invalid(),
+                }
+            }
+            _ => return invalid(),
+        }
+    }
+
+    Ok(lddw_tail)
+}
+
+fn check_alu_encoding(idx: usize, insn: &Insn) -> Result<(), VerifierError> {
+    let is64 = insn.class() == BPF_ALU64;
+    let op = insn.op & OP_MASK;
+    let invalid = Err(VerifierError::new(idx, ErrorKind::InvalidOpcode(insn.op)));
+    match op {
+        BPF_ADD | BPF_SUB | BPF_MUL | BPF_DIV | BPF_OR | BPF_AND | BPF_LSH | BPF_RSH | BPF_MOD
+        | BPF_XOR | BPF_MOV | BPF_ARSH => {}
+        BPF_NEG if !insn.uses_reg_src() => {}
+        BPF_END if !is64 && matches!(insn.imm, 16 | 32 | 64) => return Ok(()),
+        _ => return invalid,
+    }
+
+    if insn.uses_reg_src() {
+        return Ok(());
+    }
+    let bits = if is64 { 64 } else { 32 };
+    match op {
+        BPF_DIV | BPF_MOD if insn.imm == 0 => {
+            Err(VerifierError::new(idx, ErrorKind::DivisionByZero))
+        }
+        BPF_LSH | BPF_RSH | BPF_ARSH if insn.imm < 0 || insn.imm >= bits => {
+            Err(VerifierError::new(idx, ErrorKind::InvalidShift(insn.imm)))
+        }
+        _ => Ok(()),
+    }
+}
+
+// =============================================================================
+// Scalar Arithmetic
+// =============================================================================
+
+fn eval_const(op: u8, x: u64, y: u64, is64: bool) -> Option<u64> {
+    let shift_mask = if is64 { 63 } else { 31 };
+    let v = match op {
+        BPF_ADD => x.wrapping_add(y),
+        BPF_SUB => x.wrapping_sub(y),
+        BPF_MUL => x.wrapping_mul(y),
+        BPF_DIV => x.checked_div(y)?,
+        BPF_MOD => x.checked_rem(y)?,
+        BPF_OR => x | y,
+        BPF_AND => x & y,
+        BPF_XOR => x ^ y,
+        BPF_LSH => x.wrapping_shl((y & shift_mask) as u32),
+        BPF_RSH => x.wrapping_shr((y & shift_mask) as u32),
+        BPF_ARSH if is64 => ((x as i64) >> (y & 63)) as u64,
+        BPF_ARSH => ((x as u32 as i32) >> (y & 31)) as u32 as u64,
+        _ => return None,
+    };
+    Some(if is64 { v } else { v as u32 as u64 })
+}
+
+fn scalar_alu(op: u8, a: Range, b: Range, is64: bool) -> Range {
+    let (a, b) = if is64 {
+        (a, b)
+    } else {
+        (a.trunc32(), b.trunc32())
+    };
+    if let (Some(x), Some(y)) = (a.as_const(), b.as_const())
+        && let Some(v) = eval_const(op, x, y, is64)
+    {
+        return Range::konst(v);
+    }
+
+    // Adding a negative constant is a subtraction (e.g. `r1 += -1` in loops).
+    let negated = b.as_const().and_then(|k| {
+        let k = if is64 {
+            k as i64
+        } else {
+            k as u32 as i32 as i64
+        };
+        (k < 0).then(|| Range::konst(k.unsigned_abs()))
+    });
+    let (op, b) = match (op, negated) {
+        (BPF_ADD, Some(n)) => (BPF_SUB, n),
+        (BPF_SUB, Some(n)) => (BPF_ADD, n),
+        _ => (op, b),
+    };
+
+    let sign_limit = if is64 {
+        i64::MAX as u64
+    } else {
+        i32::MAX as u64
+    };
+    let shift = b.as_const().filter(|&k| k < if is64 { 64 } else { 32 });
+    let r = match op {
+        BPF_ADD => match (a.min.checked_add(b.min), a.max.checked_add(b.max)) {
+            (Some(min), Some(max)) => Range { min, max },
+            _ => Range::UNKNOWN,
+        },
+        BPF_SUB if a.min >= b.max => Range {
+            min: a.min - b.max,
+            max: a.max - b.min,
+        },
+        BPF_MUL => match a.max.checked_mul(b.max) {
+            Some(max) => Range {
+                min: a.min * b.min,
+                max,
+            },
+            None => Range::UNKNOWN,
+        },
+        BPF_DIV if b.min > 0 => Range {
+            min: a.min / b.max,
+            max: a.max / b.min,
+        },
+        BPF_DIV => Range { min: 0, max: a.max },
+        BPF_MOD if b.min > 0 => Range {
+            min: 0,
+            max: a.max.min(b.max - 1),
+        },
+        BPF_MOD => Range { min: 0, max: a.max },
+        BPF_AND => Range {
+            min: 0,
+            max: a.max.min(b.max),
+        },
+        BPF_LSH => match shift {
+            Some(k) if a.max.leading_zeros() as u64 >= k => Range {
+                min: a.min << k,
+                max: a.max << k,
+            },
+            _ => Range::UNKNOWN,
+        },
+        BPF_RSH => match shift {
+            Some(k) => Range {
+                min: a.min >> k,
+                max: a.max >> k,
+            },
+            None => Range { min: 0, max: a.max },
+        },
+        BPF_ARSH if a.max <= sign_limit => match shift {
+            Some(k) => Range {
+                min: a.min >> k,
+                max: a.max >> k,
+            },
+            None => Range { min: 0, max: a.max },
+        },
+        _ => Range::UNKNOWN,
+    };
+    if is64 { r } else { r.trunc32() }
+}
+
+fn scalar_unary(insn: &Insn, a: Range, is64: bool) -> Range {
+    let op = insn.op & OP_MASK;
+    if op == BPF_NEG {
+        return match a.as_const() {
+            Some(x) if is64 => Range::konst(x.wrapping_neg()),
+            Some(x) => Range::konst((x as u32).wrapping_neg() as u64),
+            None if is64 => Range::UNKNOWN,
+            None => Range::U32,
+        };
+    }
+
+    // BPF_END: the source bit selects big-endian conversion.
+    let to_be = insn.uses_reg_src();
+    match a.as_const() {
+        Some(x) => Range::konst(match (insn.imm, to_be) {
+            (16, true) => (x as u16).swap_bytes() as u64,
+            (16, false) => x as u16 as u64,
+            (32, true) => (x as u32).swap_bytes() as u64,
+            (32, false) => x as u32 as u64,
+            (_, true) => x.swap_bytes(),
+            (_, false) => x,
+        }),
+        None => Range::of_size(insn.imm as usize / 8),
+    }
+}
+
+/// Offset a pointer by a scalar range. Returns `None` if the result is unsafe.
+fn ptr_offset(kind: PtrKind, off: i64, var: u64, b: Range, sub: bool) -> Option<RegType> {
+    if kind.is_nullable() {
+        return None;
+    }
+    // Constants are signed offsets (e.g. `r2 = r10; r2 += -8`).
+    if let Some(k) = b.as_const() {
+        let k = k as i64;
+        if k.unsigned_abs() > MAX_PTR_OFFSET {
+            return None;
+        }
+        let off = if sub { off - k } else { off + k };
+        return Some(RegType::Ptr { kind, off, var });
+    }
+    if b.max > MAX_PTR_OFFSET {
+        return None;
+    }
+    let span = b.max - b.min;
+    if matches!(kind, PtrKind::Stack(_)) && span != 0 {
+        return None;
+    }
+    let off = if sub {
+        off - b.max as i64
+    } else {
+        off + b.min as i64
+    };
+    Some(RegType::Ptr {
+        kind,
+        off,
+        var: var.checked_add(span)?,
+    })
+}
+
+// =============================================================================
+// Branch Refinement
+// =============================================================================
+
+type Edge = Option<(Range, Range)>;
+
+fn range_eq(a: Range, b: Range) -> Edge {
+    let r = Range {
+        min: a.min.max(b.min),
+        max: a.max.min(b.max),
+    };
+    (r.min <= r.max).then_some((r, r))
+}
+
+fn range_ne(a: Range, b: Range) -> Edge {
+    if a.as_const().is_some() && a == b {
+        return None;
+    }
+    let exclude = |r: Range, k: u64| {
+        if r.min == r.max {
+            r
+        } else if r.min == k {
+            Range {
+                min: k + 1,
+                max: r.max,
+            }
+        } else if r.max == k {
+            Range {
+                min: r.min,
+                max: k - 1,
+            }
+        } else {
+            r
+        }
+    };
+    let a2 = b.as_const().map_or(a, |k| exclude(a, k));
+    let b2 = a.as_const().map_or(b, |k| exclude(b, k));
+    Some((a2, b2))
+}
+
+/// Refine for `a > b`.
+fn range_gt(a: Range, b: Range) -> Edge {
+    if a.max <= b.min {
+        return None;
+    }
+    Some((
+        Range {
+            min: a.min.max(b.min + 1),
+            max: a.max,
+        },
+        Range {
+            min: b.min,
+            max: b.max.min(a.max - 1),
+        },
+    ))
+}
+
+/// Refine for `a >= b`.
+fn range_ge(a: Range, b: Range) -> Edge {
+    if a.max < b.min {
+        return None;
+    }
+    Some((
+        Range {
+            min: a.min.max(b.min),
+            max: a.max,
+        },
+        Range {
+            min: b.min,
+            max: b.max.min(a.max),
+        },
+    ))
+}
+
+/// Refined `(dst, src)` ranges for the taken and fall-through edges.
+/// `None` means the edge is impossible.
+fn compare(op: u8, d: Range, s: Range, is32: bool) -> (Edge, Edge) {
+    let both = (Some((d, s)), Some((d, s)));
+    let limit = if is32 { u32::MAX as u64 } else { u64::MAX };
+    if d.max > limit || s.max > limit {
+        return both;
+    }
+
+    // Signed comparisons match unsigned ones while both sides are non-negative.
+    let sign_limit = if is32 {
+        i32::MAX as u64
+    } else {
+        i64::MAX as u64
+    };
+    let signed_ok = d.max <= sign_limit && s.max <= sign_limit;
+    let op = match op {
+        BPF_JSGT | BPF_JSGE | BPF_JSLT | BPF_JSLE if !signed_ok => return both,
+        BPF_JSGT => BPF_JGT,
+        BPF_JSGE => BPF_JGE,
+        BPF_JSLT => BPF_JLT,
+        BPF_JSLE => BPF_JLE,
+        other => other,
+    };
+
+    let swap = |e: Edge| e.map(|(a, b)| (b, a));
+    match op {
+        BPF_JEQ => (range_eq(d, s), range_ne(d, s)),
+        BPF_JNE => (range_ne(d, s), range_eq(d, s)),
+        BPF_JGT => (range_gt(d, s), swap(range_ge(s, d))),
+        BPF_JGE => (range_ge(d, s), swap(range_gt(s, d))),
+        BPF_JLT => (swap(range_gt(s, d)), range_ge(d, s)),
+        BPF_JLE => (swap(range_ge(s, d)), range_gt(d, s)),
+        BPF_JSET => match (d.as_const(), s.as_const()) {
+            (Some(x), Some(y)) if x & y != 0 => (Some((d, s)), None),
+            (Some(_), Some(_)) => (None, Some((d, s))),
+            _ if d.max == 0 || s.max == 0 => (None, Some((d, s))),
+            _ => both,
+        },
+        _ => both,
+    }
+}
+
+// =============================================================================
+// Path Exploration
+// =============================================================================
+
+/// Memory access direction.
+#[derive(Debug, Clone, Copy)]
+enum Access {
+    Read,
+    /// Store; carries the stored register type for 8-byte spills.
+    Write(Option<RegType>),
+    /// Atomic read-modify-write.
+    Atomic,
+}
+
+/// A state at a branch, kept while paths from it are still explored.
+struct Checkpoint {
+    /// Taken when every path from the checkpoint has been explored.
+    state: Option<State>,
+    /// Checkpoint the path to this one branched off last.
+    parent: Option<usize>,
+    /// Number of checkpoints on the path to this one.
+    depth: usize,
+    /// Paths from here and child checkpoints not fully explored yet.
+    pending: usize,
+}
+
+/// FNV-1a, to look up checkpoints by state.
+struct Fnv(u64);
+
+impl Hasher for Fnv {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 = (self.0 ^ b as u64).wrapping_mul(0x100_0000_01b3);
+        }
+    }
+}
+
+fn state_key(state: &State) -> (usize, u64) {
+    let mut hasher = Fnv(0xcbf2_9ce4_8422_2325);
+    state.hash(&mut hasher);
+    (state.pc, hasher.finish())
+}
+
+enum Step {
+    /// Continue at `state.pc`.
+    Next,
+    /// Continue at `state.pc` and also explore the returned state.
+    Fork(State),
+    /// Path reached the final `exit`.
+    Done,
+}
+
+struct Verifier<'a> {
+    prog: &'a [u8],
+    len: usize,
+    processed: usize,
+    /// Most recent backward jump, reported when the budget runs out.
+    back_edge: Option<usize>,
+    /// Checkpoints of all branches taken so far.
+    checkpoints: Vec<Checkpoint>,
+    /// Checkpoints still being explored, by [`state_key`].
+    active: BTreeMap<(usize, u64), Vec<usize>>,
+    /// States from which every path has been explored, per instruction.
+    explored: Vec<Vec<State>>,
+    /// Callback of every `bpf_loop` call site.
+    loop_callbacks: BTreeMap<usize, usize>,
+    /// End of the furthest context read, see [`Analysis::ctx_size`].
+    ctx_size: u64,
+    /// Deepest stack use of any frame, see [`Analysis::stack_depth`].
+    stack_depth: usize,
+}
+
+impl Verifier<'_> {
+    fn explore(&mut self) -> Result<(), VerifierError> {
+        // Each path remembers the checkpoint it started from
+        let mut work = vec![(State::initial(), None)];
+        while let Some((mut state, mut from)) = work.pop() {
+            loop {
+                self.processed += 1;
+                if self.processed > MAX_PROCESSED_INSNS {
+                    return Err(match self.back_edge {
+                        Some(idx) => VerifierError::new(idx, ErrorKind::UnboundedLoop),
+                        None => VerifierError::new(state.pc, ErrorKind::TooComplex),
+                    });
+                }
+                let idx = state.pc;
+                let step = self.step(&mut state)?;
+                self.note_stack_depth(&state);
+                match step {
+                    Step::Next => {}
+                    Step::Fork(other) => {
+                        if let Some(cp) = self.checkpoint(from, &other, idx)? {
+                            work.push((other, Some(cp)));
+                        }
+                        let next = self.checkpoint(from, &state, idx)?;
+                        self.finish(from);
+                        match next {
+                            Some(cp) => from = Some(cp),
+                            None => break,
+                        }
+                    }
+                    Step::Done => {
+                        self.finish(from);
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Start a path at `state`, branched off at instruction `idx` from the
+    /// path that started at checkpoint `from`.
+    ///
+    /// Returns the new checkpoint, or None if every path from `state` has
+    /// already been explored. Fails if `state` is already on the path, as
+    /// the program would then loop forever.
+    fn checkpoint(
+        &mut self,
+        from: Option<usize>,
+        state: &State,
+        idx: usize,
+    ) -> Result<Option<usize>, VerifierError> {
+        if self.explored[state.pc].contains(state) {
+            return Ok(None);
+        }
+        let key = state_key(state);
+        if let Some(same) = self.active.get(&key) {
+            for &cp in same {
+                if self.checkpoints[cp].state.as_ref() == Some(state) && self.on_path(cp, from) {
+                    return Err(VerifierError::new(idx, ErrorKind::UnboundedLoop));
+                }
+            }
+        }
+
+        let depth = from.map_or(0, |p| self.checkpoints[p].depth + 1);
+        if let Some(p) = from {
+            self.checkpoints[p].pending += 1;
+        }
+        let cp = self.checkpoints.len();
+        self.checkpoints.push(Checkpoint {
+            state: Some(state.clone()),
+            parent: from,
+            depth,
+            pending: 1,
+        });
+        self.active.entry(key).or_default().push(cp);
+        Ok(Some(cp))
+    }
+
+    /// Whether checkpoint `cp` is on the path to checkpoint `from`.
+    fn on_path(&self, cp: usize, mut from: Option<usize>) -> bool {
+        let depth = self.checkpoints[cp].depth;
+        while let Some(p) = from {
+            if p == cp {
+                return true;
+            }
+            if self.checkpoints[p].depth <= depth {
+                return false;
+            }
+            from = self.checkpoints[p].parent;
+        }
+        false
+    }
+
+    /// End a path that started at checkpoint `from`. Checkpoints left with
+    /// no path to explore become explored states.
+    fn finish(&mut self, mut from: Option<usize>) {
+        while let Some(cp) = from {
+            let checkpoint = &mut self.checkpoints[cp];
+            checkpoint.pending -= 1;
+            if checkpoint.pending > 0 {
+                return;
+            }
+            from = checkpoint.parent;
+            let state = checkpoint.state.take().expect("checkpoint finished twice");
+            let key = state_key(&state);
+            if let Some(same) = self.active.get_mut(&key) {
+                same.retain(|&other| other != cp);
+                if same.is_empty() {
+                    self.active.remove(&key);
+                }
+            }
+            let explored = &mut self.explored[state.pc];
+            if explored.len() < MAX_STATES_PER_INSN {
+                explored.push(state);
+            }
+        }
+    }
+
+    /// Record how far a read through `reg + off`, already checked, may reach
+    /// into the context, including any variable offset.
+    fn note_ctx_read(&mut self, st: &State, reg: u8, off: i16, size: usize) {
+        if let RegType::Ptr {
+            kind: PtrKind::Ctx,
+            off: base,
+            var,
+        } = st.cur().regs[reg as usize]
+        {
+            let end = (base + off as i64) as u64 + var + size as u64;
+            self.ctx_size = self.ctx_size.max(end);
+        }
+    }
+
+    /// Record how deep the frames of `st` have used their stack.
+    fn note_stack_depth(&mut self, st: &State) {
+        for frame in &st.frames {
+            self.stack_depth = self.stack_depth.max(frame.stack.depth());
+        }
+    }
+
+    fn note_jump(&mut self, idx: usize, target: usize) {
+        if target <= idx {
+            self.back_edge = Some(idx);
+        }
+    }
+
+    fn step(&mut self, st: &mut State) -> Result<Step, VerifierError> {
+        let idx = st.pc;
+        if idx >= self.len {
+            return Err(VerifierError::new(self.len - 1, ErrorKind::FallThroughEnd));
+        }
+        let insn = Insn::decode(self.prog, idx);
+        match insn.class() {
+            BPF_LD => {
+                self.do_lddw(st, idx, &insn)?;
+                st.pc = idx + 2;
+            }
+            BPF_LDX => {
+                if insn.dst == FRAME_PTR {
+                    return Err(VerifierError::new(idx, ErrorKind::FramePointerWrite));
+                }
+                let size = access_size(insn.op);
+                let ty = check_access(st, idx, insn.src, insn.off, size, Access::Read)?;
+                self.note_ctx_read(st, insn.src, insn.off, size);
+                st.set_reg(insn.dst, ty);
+                st.pc = idx + 1;
+            }
+            BPF_ST => {
+                let size = access_size(insn.op);
+                let value = RegType::Scalar(Range::konst(insn.imm as i64 as u64));
+                check_access(
+                    st,
+                    idx,
+                    insn.dst,
+                    insn.off,
+                    size,
+                    Access::Write(Some(value)),
+                )?;
+                st.pc = idx + 1;
+            }
+            BPF_STX => {
+                let size = access_size(insn.op);
+                let value = st.read_reg(idx, insn.src)?;
+                let access = if insn.op & MODE_MASK == BPF_ATOMIC {
+                    if !matches!(value, RegType::Scalar(_)) {
+                        return Err(VerifierError::new(
+                            idx,
+                            ErrorKind::InvalidPointerArithmetic(insn.src),
+                        ));
+                    }
+                    Access::Atomic
+                } else {
+                    Access::Write(Some(value))
+                };
+                check_access(st, idx, insn.dst, insn.off, size, access)?;
+                st.pc = idx + 1;
+            }
+            BPF_ALU | BPF_ALU64 => {
+                do_alu(st, idx, &insn)?;
+                st.pc = idx + 1;
+            }
+            _ => return self.do_jmp(st, idx, &insn),
+        }
+        Ok(Step::Next)
+    }
+
+    fn do_lddw(&self, st: &mut State, idx: usize, insn: &Insn) -> Result<(), VerifierError> {
+        if insn.dst == FRAME_PTR {
+            return Err(VerifierError::new(idx, ErrorKind::FramePointerWrite));
+        }
+        let next = Insn::decode(self.prog, idx + 1);
+        let imm64 = (insn.imm as u32 as u64) | ((next.imm as u32 as u64) << 32);
+        let ty = match insn.src {
+            PSEUDO_MAP_FD => {
+                let fd = imm64 as u32;
+                if map_ops::get_map_sizes(fd).is_none() {
+                    return Err(VerifierError::new(idx, ErrorKind::InvalidMapFd(imm64)));
+                }
+                RegType::MapFd(fd)
+            }
+            PSEUDO_MAP_VALUE => {
+                let fd = insn.imm as u32;
+                let def = map_ops::get_map_def(fd)
+                    .ok_or(VerifierError::new(idx, ErrorKind::InvalidMapFd(fd as u64)))?;
+                let size = def.value_size;
+                let off = next.imm as u32 as i64;
+                if off >= size as i64 {
+                    return Err(VerifierError::new(
+                        idx,
+                        ErrorKind::InvalidMemoryAccess {
+                            reg: insn.dst,
+                            off,
+                            size: 0,
+                        },
+                    ));
+                }
+                let id = st.fresh_id();
+                RegType::Ptr {
+                    kind: PtrKind::MapValue {
+                        id,
+                        size,
+                        map_flags: def.map_flags,
+                        nullable: false,
+                    },
+                    off,
+                    var: 0,
+                }
+            }
+            PSEUDO_FUNC => RegType::Func(jump_target(idx, insn.imm as i64) as usize),
+            _ => RegType::Scalar(Range::konst(imm64)),
+        };
+        st.set_reg(insn.dst, ty);
+        Ok(())
+    }
+
+    fn do_jmp(&mut self, st: &mut State, idx: usize, insn: &Insn) -> Result<Step, VerifierError> {
+        let op = insn.op & OP_MASK;
+        match op {
+            BPF_JA => {
+                let target = jump_target(idx, insn.off as i64) as usize;
+                self.note_jump(idx, target);
+                st.pc = target;
+                Ok(Step::Next)
+            }
+            BPF_CALL if insn.src == PSEUDO_CALL => self.do_local_call(st, idx, insn),
+            BPF_CALL if insn.imm as u32 == id::LOOP => self.do_loop_call(st, idx),
+            BPF_CALL => {
+                do_helper_call(st, idx, insn.imm as u32)?;
+                st.pc = idx + 1;
+                Ok(Step::Next)
+            }
+            BPF_EXIT => do_exit(st, idx),
+            _ => self.do_cond_jmp(st, idx, insn, op),
+        }
+    }
+
+    fn do_local_call(
+        &mut self,
+        st: &mut State,
+        idx: usize,
+        insn: &Insn,
+    ) -> Result<Step, VerifierError> {
+        if st.frames.len() >= MAX_CALL_DEPTH {
+            return Err(VerifierError::new(idx, ErrorKind::CallDepthExceeded));
+        }
+        let target = jump_target(idx, insn.imm as i64) as usize;
+        self.note_jump(idx, target);
+
+        let mut callee = Frame::new(st.frames.len(), idx + 1);
+        callee.regs[1..=5].copy_from_slice(&st.cur().regs[1..=5]);
+        st.frames.push(callee);
+        st.pc = target;
+        Ok(Step::Next)
+    }
+
+    /// `bpf_loop`: continue after the call as if no iteration ran, and
+    /// explore the first iteration of the callback as a forked path.
+    ///
+    /// The runtime calls the callback directly from the call site, so every
+    /// site must always pass the same callback.
+    fn do_loop_call(&mut self, st: &mut State, idx: usize) -> Result<Step, VerifierError> {
+        // The runtime adds a frame for the loop itself
+        if st.frames.len() + 1 >= MAX_CALL_DEPTH {
+            return Err(VerifierError::new(idx, ErrorKind::CallDepthExceeded));
+        }
+        let bad = VerifierError::new(idx, ErrorKind::InvalidHelperArg(2));
+        let RegType::Func(entry) = st.read_reg(idx, 2)? else {
+            return Err(bad);
+        };
+        if *self.loop_callbacks.entry(idx).or_insert(entry) != entry {
+            return Err(bad);
+        }
+        let ctx = st.read_reg(idx, 3)?;
+        do_helper_call(st, idx, id::LOOP)?;
+        st.pc = idx + 1;
+        self.note_jump(idx, entry);
+
+        let mut first = st.clone();
+        let callback = LoopCallback {
+            entry,
+            ctx,
+            again: false,
+        };
+        first
+            .frames
+            .push(Frame::callback(st.frames.len(), idx + 1, callback));
+        first.pc = entry;
+        Ok(Step::Fork(first))
+    }
+
+    fn do_cond_jmp(
+        &mut self,
+        st: &mut State,
+        idx: usize,
+        insn: &Insn,
+        op: u8,
+    ) -> Result<Step, VerifierError> {
+        let is32 = insn.class() == BPF_JMP32;
+        let dst = st.read_reg(idx, insn.dst)?;
+        let src = if insn.uses_reg_src() {
+            st.read_reg(idx, insn.src)?
+        } else if is32 {
+            RegType::Scalar(Range::konst(insn.imm as u32 as u64))
+        } else {
+            RegType::Scalar(Range::konst(insn.imm as i64 as u64))
+        };
+        let target = jump_target(idx, insn.off as i64) as usize;
+        self.note_jump(idx, target);
+
+        // Null check on a pointer returned by a helper.
+        if !is32
+            && matches!(op, BPF_JEQ | BPF_JNE)
+            && src == RegType::Scalar(Range::konst(0))
+            && let RegType::Ptr { kind, .. } = dst
+            && let Some(id) = kind.null_id()
+        {
+            let mut taken = st.clone();
+            taken.pc = target;
+            taken.mark_null(id, op == BPF_JEQ);
+            st.pc = idx + 1;
+            st.mark_null(id, op == BPF_JNE);
+            return Ok(Step::Fork(taken));
+        }
+
+        let (taken, fall) = match (dst, src) {
+            (RegType::Scalar(d), RegType::Scalar(s)) => compare(op, d, s, is32),
+            _ => {
+                let any = Some((Range::UNKNOWN, Range::UNKNOWN));
+                (any, any)
+            }
+        };
+        let refine = matches!((dst, src), (RegType::Scalar(_), RegType::Scalar(_)));
+        let apply = |state: &mut State, (d, s): (Range, Range)| {
+            if refine {
+                state.set_reg(insn.dst, RegType::Scalar(d));
+                if insn.uses_reg_src() {
+                    state.set_reg(insn.src, RegType::Scalar(s));
+                }
+            }
+        };
+
+        match (taken, fall) {
+            (Some(t), Some(f)) => {
+                let mut other = st.clone();
+                other.pc = target;
+                apply(&mut other, t);
+                st.pc = idx + 1;
+                apply(st, f);
+                Ok(Step::Fork(other))
+            }
+            (Some(t), None) => {
+                st.pc = target;
+                apply(st, t);
+                Ok(Step::Next)
+            }
+            (None, Some(f)) => {
+                st.pc = idx + 1;
+                apply(st, f);
+                Ok(Step::Next)
+            }
+            (None, None) => Ok(Step::Done),
+        }
+    }
+}
+
+fn do_exit(st: &mut State, idx: usize) -> Result<Step, VerifierError> {
+    let r0 = st.cur().regs[0];
+    if r0 == RegType::Uninit {
+        return Err(VerifierError::new(idx, ErrorKind::UninitReturnValue));
+    }
+    if st.frames.len() == 1 {
+        return Ok(Step::Done);
+    }
+
+    let frame_no = st.frames.len() - 1;
+    if let RegType::Ptr {
+        kind: PtrKind::Stack(f),
+        ..
+    } = r0
+        && f == frame_no
+    {
+        return Err(VerifierError::new(idx, ErrorKind::StackPointerEscape));
+    }
+
+    let callee = st.frames.pop().expect("checked above");
+    let caller = st.cur_mut();
+    caller.regs[0] = r0;
+    caller.regs[1..=5].fill(RegType::Uninit);
+    st.pc = callee.ret_pc;
+
+    let Some(callback) = callee.callback else {
+        return Ok(Step::Next);
+    };
+    if !matches!(r0, RegType::Scalar(r) if r.max <= 1) {
+        return Err(VerifierError::new(idx, ErrorKind::InvalidCallbackReturn));
+    }
+    // Number of iterations run, or an error code
+    st.set_reg(0, RegType::Scalar(Range::UNKNOWN));
+    if callback.again {
+        return Ok(Step::Next);
+    }
+    // Later iterations see what the first one stored through the context
+    let mut again = st.clone();
+    let callback = LoopCallback {
+        again: true,
+        ..callback
+    };
+    again
+        .frames
+        .push(Frame::callback(frame_no, callee.ret_pc, callback));
+    again.pc = callback.entry;
+    Ok(Step::Fork(again))
+}
+
+fn do_alu(st: &mut State, idx: usize, insn: &Insn) -> Result<(), VerifierError> {
+    let is64 = insn.class() == BPF_ALU64;
+    let op = insn.op & OP_MASK;
+    if insn.dst == FRAME_PTR {
+        return Err(VerifierError::new(idx, ErrorKind::FramePointerWrite));
+    }
+
+    if matches!(op, BPF_NEG | BPF_END) {
+        let RegType::Scalar(a) = st.read_reg(idx, insn.dst)? else {
+            return Err(VerifierError::new(
+                idx,
+                ErrorKind::InvalidPointerArithmetic(insn.dst),
+            ));
+        };
+        st.set_reg(insn.dst, RegType::Scalar(scalar_unary(insn, a, is64)));
+        return Ok(());
+    }
+
+    let src = if insn.uses_reg_src() {
+        st.read_reg(idx, insn.src)?
+    } else if is64 {
+        RegType::Scalar(Range::konst(insn.imm as i64 as u64))
+    } else {
+        RegType::Scalar(Range::konst(insn.imm as u32 as u64))
+    };
+
+    let result = if op == BPF_MOV {
+        match src {
+            _ if is64 => src,
+            RegType::Scalar(r) => RegType::Scalar(r.trunc32()),
+            _ => RegType::Scalar(Range::U32),
+        }
+    } else {
+        let dst = st.read_reg(idx, insn.dst)?;
+        alu_binary(idx, insn.dst, op, dst, src, is64)?
+    };
+    st.set_reg(insn.dst, result);
+    Ok(())
+}
+
+fn alu_binary(
+    idx: usize,
+    reg: u8,
+    op: u8,
+    dst: RegType,
+    src: RegType,
+    is64: bool,
+) -> Result<RegType, VerifierError> {
+    let bad_ptr = VerifierError::new(idx, ErrorKind::InvalidPointerArithmetic(reg));
+    match (dst, src) {
+        (RegType::Scalar(a), RegType::Scalar(b)) => {
+            if matches!(op, BPF_DIV | BPF_MOD) && b.as_const() == Some(0) {
+                return Err(VerifierError::new(idx, ErrorKind::DivisionByZero));
+            }
+            Ok(RegType::Scalar(scalar_alu(op, a, b, is64)))
+        }
+        (RegType::Ptr { kind, off, var }, RegType::Scalar(b))
+            if is64 && matches!(op, BPF_ADD | BPF_SUB) =>
+        {
+            ptr_offset(kind, off, var, b, op == BPF_SUB).ok_or(bad_ptr)
+        }
+        (RegType::Scalar(a), RegType::Ptr { kind, off, var }) if is64 && op == BPF_ADD => {
+            ptr_offset(kind, off, var, a, false).ok_or(bad_ptr)
+        }
+        (
+            RegType::Ptr {
+                kind: k1,
+                off: o1,
+                var: 0,
+            },
+            RegType::Ptr {
+                kind: k2,
+                off: o2,
+                var: 0,
+            },
+        ) if is64 && op == BPF_SUB && k1 == k2 => {
+            Ok(RegType::Scalar(Range::konst(o1.wrapping_sub(o2) as u64)))
+        }
+        _ => Err(bad_ptr),
+    }
+}
+
+fn in_bounds(start: i64, var: u64, size: u64, limit: u64) -> bool {
+    start >= 0
+        && (start as u64)
+            .checked_add(var)
+            .and_then(|end| end.checked_add(size))
+            .is_some_and(|end| end <= limit)
+}
+
+/// Check a load/store through `reg + off`. Returns the loaded type for reads.
+fn check_access(
+    st: &mut State,
+    idx: usize,
+    reg: u8,
+    off: i16,
+    size: usize,
+    access: Access,
+) -> Result<RegType, VerifierError> {
+    let ty = st.read_reg(idx, reg)?;
+    let RegType::Ptr {
+        kind,
+        off: base,
+        var,
+    } = ty
+    else {
+        return Err(VerifierError::new(
+            idx,
+            ErrorKind::InvalidMemoryAccess {
+                reg,
+                off: off as i64,
+                size,
+            },
+        ));
+    };
+    let start = base + off as i64;
+    let bad = VerifierError::new(
+        idx,
+        ErrorKind::InvalidMemoryAccess {
+            reg,
+            off: start,
+            size,
+        },
+    );
+    let is_write = !matches!(access, Access::Read);
+    let loaded = RegType::Scalar(Range::of_size(size));
+    if let PtrKind::MapValue { map_flags, .. } = kind
+        && !kind.is_nullable()
+    {
+        let is_read = !matches!(access, Access::Write(_));
+        check_map_access(idx, map_flags, is_read, is_write)?;
+    }
+
+    match kind {
+        PtrKind::Stack(frame) => {
+            if var != 0 {
+                return Err(bad);
+            }
+            stack_access(st, idx, reg, frame, start, size, access)
+        }
+        _ if kind.is_nullable() => Err(VerifierError::new(idx, ErrorKind::NullPointerDeref(reg))),
+        PtrKind::Ctx | PtrKind::Mem { .. } if is_write => {
+            Err(VerifierError::new(idx, ErrorKind::ReadOnlyWrite(reg)))
+        }
+        PtrKind::Ctx => in_bounds(start, var, size as u64, CTX_MAX_SIZE)
+            .then_some(loaded)
+            .ok_or(bad),
+        PtrKind::MapValue { size: limit, .. }
+        | PtrKind::Mem { size: limit, .. }
+        | PtrKind::Record { size: limit, .. } => in_bounds(start, var, size as u64, limit as u64)
+            .then_some(loaded)
+            .ok_or(bad),
+    }
+}
+
+fn stack_access(
+    st: &mut State,
+    idx: usize,
+    reg: u8,
+    frame: usize,
+    start: i64,
+    size: usize,
+    access: Access,
+) -> Result<RegType, VerifierError> {
+    let bad = VerifierError::new(
+        idx,
+        ErrorKind::InvalidMemoryAccess {
+            reg,
+            off: start,
+            size,
+        },
+    );
+    if size == 0 || start < -(STACK_SIZE as i64) || start + size as i64 > 0 {
+        return Err(bad);
+    }
+    let stack = &mut st.frames.get_mut(frame).ok_or(bad)?.stack;
+    let base = (start + STACK_SIZE as i64) as usize;
+    let bytes = base..base + size;
+    let slots = base / 8..=(base + size - 1) / 8;
+    let aligned_dw = size == 8 && base.is_multiple_of(8);
+
+    match access {
+        Access::Read | Access::Atomic => {
+            if bytes.clone().any(|b| !stack.is_init(b)) {
+                return Err(VerifierError::new(idx, ErrorKind::UninitStackRead(start)));
+            }
+            if matches!(access, Access::Atomic) {
+                slots.for_each(|s| stack.spills[s] = None);
+                return Ok(RegType::Uninit);
+            }
+            match stack.spills[base / 8] {
+                Some(spilled) if aligned_dw => Ok(spilled),
+                _ => Ok(RegType::Scalar(Range::of_size(size))),
+            }
+        }
+        Access::Write(value) => {
+            bytes.for_each(|b| stack.mark_init(b));
+            slots.for_each(|s| stack.spills[s] = None);
+            if aligned_dw {
+                stack.spills[base / 8] = value;
+            }
+            Ok(RegType::Uninit)
+        }
+    }
+}
+
+/// Check a helper memory argument of `size` bytes. The context cannot be
+/// passed as memory.
+fn check_helper_mem(
+    st: &mut State,
+    idx: usize,
+    reg: u8,
+    size: u64,
+    write: bool,
+) -> Result<(), VerifierError> {
+    let ty = st.read_reg(idx, reg)?;
+    if size == 0 {
+        return Ok(());
+    }
+    let bad = VerifierError::new(idx, ErrorKind::InvalidHelperArg(reg));
+    match ty {
+        RegType::Ptr {
+            kind: PtrKind::Stack(frame),
+            off,
+            var: 0,
+        } => {
+            let access = if write {
+                Access::Write(None)
+            } else {
+                Access::Read
+            };
+            stack_access(st, idx, reg, frame, off, size as usize, access).map(|_| ())
+        }
+        RegType::Ptr { kind, off, var } if !kind.is_nullable() => {
+            if let PtrKind::MapValue { map_flags, .. } = kind {
+                check_map_access(idx, map_flags, !write, write)?;
+            }
+            let limit = match kind {
+                PtrKind::MapValue { size, .. } | PtrKind::Record { size, .. } => size as u64,
+                PtrKind::Mem { size, .. } if !write => size as u64,
+                // The context's real length is only known at run time
+                _ => return Err(bad),
+            };
+            in_bounds(off, var, size, limit).then_some(()).ok_or(bad)
+        }
+        _ => Err(bad),
+    }
+}
+
+/// Check a read or write of a map's values against the map's
+/// `BPF_F_RDONLY_PROG` and `BPF_F_WRONLY_PROG` flags.
+fn check_map_access(
+    idx: usize,
+    map_flags: u32,
+    read: bool,
+    write: bool,
+) -> Result<(), VerifierError> {
+    if write && map_flags & BPF_F_RDONLY_PROG != 0 {
+        return Err(VerifierError::new(idx, ErrorKind::MapWriteForbidden));
+    }
+    if read && map_flags & BPF_F_WRONLY_PROG != 0 {
+        return Err(VerifierError::new(idx, ErrorKind::MapReadForbidden));
+    }
+    Ok(())
+}
+
+/// Map of a map argument. Only maps loaded with `lddw` count, not constant
+/// scalars: the runtime allows access to map values only for those maps.
+fn map_fd_of(ty: RegType) -> Option<u32> {
+    match ty {
+        RegType::MapFd(fd) => Some(fd),
+        _ => None,
+    }
+}
+
+fn do_helper_call(st: &mut State, idx: usize, helper_id: u32) -> Result<(), VerifierError> {
+    if !is_known_helper(helper_id) {
+        return Err(VerifierError::new(idx, ErrorKind::UnknownHelper(helper_id)));
+    }
+    let proto = helper_proto(helper_id);
+
+    // (key_size, value_size) of the map argument, if any.
+    let mut map_sizes: Option<(u32, u32)> = None;
+    let mut map_type: Option<MapType> = None;
+    let mut map_flags = 0;
+    let mut record_size: Option<u32> = None;
+    for (i, arg) in proto.args.iter().enumerate() {
+        let reg = (i + 1) as u8;
+        let bad = VerifierError::new(idx, ErrorKind::InvalidHelperArg(reg));
+        match *arg {
+            Arg::Unused | Arg::Size => {}
+            Arg::Any => {
+                st.read_reg(idx, reg)?;
+            }
+            Arg::Ctx => {
+                if st.read_reg(idx, reg)? != RegType::ptr(PtrKind::Ctx) {
+                    return Err(bad);
+                }
+            }
+            Arg::Func => {
+                if !matches!(st.read_reg(idx, reg)?, RegType::Func(_)) {
+                    return Err(bad);
+                }
+            }
+            Arg::MapFd => {
+                let fd = map_fd_of(st.read_reg(idx, reg)?).ok_or(bad)?;
+                let sizes = map_ops::get_map_sizes(fd)
+                    .ok_or(VerifierError::new(idx, ErrorKind::InvalidMapFd(fd as u64)))?;
+                map_sizes = Some(sizes);
+                map_type = map_ops::get_map_type(fd);
+                map_flags = map_ops::get_map_def(fd).map_or(0, |def| def.map_flags);
+                // Lookups are allowed on write-only maps; reads through the
+                // returned pointer are not
+                let reads = matches!(helper_id, id::MAP_PEEK_ELEM | id::MAP_POP_ELEM);
+                let writes = matches!(
+                    helper_id,
+                    id::MAP_UPDATE_ELEM
+                        | id::MAP_DELETE_ELEM
+                        | id::MAP_PUSH_ELEM
+                        | id::MAP_POP_ELEM
+                );
+                check_map_access(idx, map_flags, reads, writes)?;
+            }
+            Arg::MapKey | Arg::MapValue | Arg::UninitMapValue => {
+                let (key_size, value_size) = map_sizes.ok_or(bad)?;
+                let size = if matches!(arg, Arg::MapKey) {
+                    key_size
+                } else {
+                    value_size
+                };
+                let write =
+                    matches!(arg, Arg::UninitMapValue) && map_type != Some(MapType::BloomFilter);
+                check_helper_mem(st, idx, reg, size as u64, write)?;
+            }
+            Arg::ConstSize => {
+                let size = match st.read_reg(idx, reg)? {
+                    RegType::Scalar(r) => r.as_const().filter(|&v| v > 0 && v <= u32::MAX as u64),
+                    _ => None,
+                };
+                record_size = Some(size.ok_or(bad)? as u32);
+            }
+            Arg::Record => {
+                let ty = st.read_reg(idx, reg)?;
+                if !matches!(
+                    ty,
+                    RegType::Ptr {
+                        kind: PtrKind::Record {
+                            nullable: false,
+                            ..
+                        },
+                        off: 0,
+                        var: 0,
+                    }
+                ) {
+                    return Err(bad);
+                }
+            }
+            Arg::Mem | Arg::UninitMem => {
+                let size_reg = reg + 1;
+                let size = match st.read_reg(idx, size_reg)? {
+                    RegType::Scalar(r) if r.max <= MAX_HELPER_MEM_SIZE => r.max,
+                    _ => {
+                        return Err(VerifierError::new(
+                            idx,
+                            ErrorKind::InvalidHelperArg(size_reg),
+                        ));
+                    }
+                };
+                check_helper_mem(st, idx, reg, size, matches!(arg, Arg::UninitMem))?;
+            }
+        }
+    }
+
+    for reg in 1..=5 {
+        st.set_reg(reg, RegType::Uninit);
+    }
+    let ret = match proto.ret {
+        Ret::Scalar => RegType::Scalar(Range::UNKNOWN),
+        Ret::MapValueOrNull => {
+            let (_, value_size) =
+                map_sizes.ok_or(VerifierError::new(idx, ErrorKind::InvalidHelperArg(1)))?;
+            let id = st.fresh_id();
+            RegType::ptr(PtrKind::MapValue {
+                id,
+                size: value_size,
+                map_flags,
+                nullable: true,
+            })
+        }
+        Ret::MemOrNull(size) => {
+            let id = st.fresh_id();
+            RegType::ptr(PtrKind::Mem {
+                id,
+                size,
+                nullable: true,
+            })
+        }
+        Ret::RecordOrNull => {
+            let size =
+                record_size.ok_or(VerifierError::new(idx, ErrorKind::InvalidHelperArg(2)))?;
+            let id = st.fresh_id();
+            RegType::ptr(PtrKind::Record {
+                id,
+                size,
+                nullable: true,
+            })
+        }
+    };
+    st.set_reg(0, ret);
+    Ok(())
+}
+
+// =============================================================================
+// Entry Point
+// =============================================================================
+
+/// What the runtime needs to know about a verified program.
+#[derive(Debug, Default)]
+pub(crate) struct Analysis {
+    /// Callback entry of every `bpf_loop` call site, by call instruction.
+    pub loop_callbacks: BTreeMap<usize, usize>,
+    /// Bytes of the context the program may read, from its start. Native
+    /// code only runs on contexts at least this long.
+    pub ctx_size: u64,
+    /// Bytes below the frame pointer that any frame of the program uses.
+    /// The rest of the stack is free for code the runtime inserts.
+    pub stack_depth: usize,
+}
+
+/// Verify raw eBPF bytecode.
+///
+/// Map FDs referenced by `lddw` must already exist, so ELF programs are
+/// verified after their maps are created and relocated.
+pub fn verify(prog: &[u8]) -> Result<(), VerifierError> {
+    analyze(prog).map(|_| ())
+}
+
+/// Verify raw eBPF bytecode and return what was learned about it.
+pub(crate) fn analyze(prog: &[u8]) -> Result<Analysis, VerifierError> {
+    let len = prog.len() / INSN_SIZE;
+    if len == 0 || len > MAX_PROG_INSNS || !prog.len().is_multiple_of(INSN_SIZE) {
+        return Err(VerifierError::new(0, ErrorKind::ProgramSize(len)));
+    }
+    check_structure(prog, len)?;
+
+    let mut verifier = Verifier {
+        prog,
+        len,
+        processed: 0,
+        back_edge: None,
+        checkpoints: Vec::new(),
+        active: BTreeMap::new(),
+        explored: vec![Vec::new(); len],
+        loop_callbacks: BTreeMap::new(),
+        ctx_size: 0,
+        stack_depth: 0,
+    };
+    verifier.explore()?;
+
+    log::debug!(
+        "Verified program: {} instructions, {} simulated",
+        len,
+        verifier.processed
+    );
+    Ok(Analysis {
+        loop_callbacks: verifier.loop_callbacks,
+        ctx_size: verifier.ctx_size,
+        stack_depth: verifier.stack_depth,
+    })
+}
+
+// =============================================================================
+// Register Liveness
+// =============================================================================
+
+/// Registers read and registers always written by `insn`, as bitmasks.
+///
+/// Errs on the safe side: reads may be over-reported, writes under-reported.
+fn reg_use_def(insn: &Insn) -> (u16, u16) {
+    const ARGS: u16 = 0b11_1110;
+    let bit = |reg: u8| 1u16 << reg;
+    let src = if insn.uses_reg_src() {
+        bit(insn.src)
+    } else {
+        0
+    };
+    match insn.class() {
+        BPF_LD => (0, bit(insn.dst)),
+        BPF_LDX => (bit(insn.src), bit(insn.dst)),
+        BPF_ST => (bit(insn.dst), 0),
+        BPF_STX => (bit(insn.dst) | bit(insn.src), 0),
+        BPF_ALU | BPF_ALU64 => match insn.op & OP_MASK {
+            BPF_MOV => (src, bit(insn.dst)),
+            BPF_NEG | BPF_END => (bit(insn.dst), bit(insn.dst)),
+            _ => (bit(insn.dst) | src, bit(insn.dst)),
+        },
+        _ => match insn.op & OP_MASK {
+            BPF_JA => (0, 0),
+            BPF_CALL => (ARGS, bit(0)),
+            BPF_EXIT => (bit(0), 0),
+            _ => (bit(insn.dst) | src, 0),
+        },
+    }
+}
+
+/// Registers live before each instruction of a verified program, as bitmasks
+/// (bit `n` for `rn`).
+///
+/// A register is live if some path may read it before overwriting it. Each
+/// function is treated on its own: `exit` reads only r0, and a BPF-to-BPF
+/// call reads r1-r5 and preserves r6-r9.
+pub(crate) fn live_regs(prog: &[u8]) -> Vec<u16> {
+    let len = prog.len() / INSN_SIZE;
+    let mut starts = Vec::with_capacity(len);
+    let mut idx = 0;
+    while idx < len {
+        starts.push(idx);
+        idx += if Insn::decode(prog, idx).op == LD_DW_IMM {
+            2
+        } else {
+            1
+        };
+    }
+
+    let mut live = vec![0u16; len];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &idx in starts.iter().rev() {
+            let insn = Insn::decode(prog, idx);
+            let next = |succ: usize| live.get(succ).copied().unwrap_or(0);
+            let out = match insn.class() {
+                BPF_LD => next(idx + 2),
+                BPF_JMP | BPF_JMP32 => match insn.op & OP_MASK {
+                    BPF_JA => next(jump_target(idx, insn.off as i64) as usize),
+                    BPF_CALL => next(idx + 1),
+                    BPF_EXIT => 0,
+                    _ => next(idx + 1) | next(jump_target(idx, insn.off as i64) as usize),
+                },
+                _ => next(idx + 1),
+            };
+            let (uses, defs) = reg_use_def(&insn);
+            let live_in = uses | (out & !defs);
+            if live_in != live[idx] {
+                live[idx] = live_in;
+                changed = true;
+            }
+        }
+    }
+    live
+}
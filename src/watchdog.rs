@@ -0,0 +1,310 @@
+//! Execution budgets for loaded programs.
+//!
+//! The verifier proves that loops terminate, not that they terminate soon.
+//! A program loaded with a [`Budget`] gets a check on every loop back-edge:
+//! a call to an internal tick helper that charges the loop body to the
+//! invocation and compares the elapsed time against the time budget. Once
+//! either runs out, the tick makes the program call a helper that does not
+//! exist, which stops the interpreter, and the run fails with
+//! `runtime::Error::BudgetExceeded`.
+//!
+//! rbpf's JIT clobbers r1-r5 on helper calls, so instrumented programs always
+//! run in the interpreter. Programs without loops are never instrumented: they
+//! cannot run more instructions than their length, and their run time is
+//! checked once they return.
+//!
+//! After [`Budget::detach_after`] violations the program is disabled and
+//! detached from its tracepoints and probes.
+
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use rbpf::EbpfVmRaw;
+use spin::Mutex;
+
+use crate::verifier::{
+    BPF_ALU64, BPF_CALL, BPF_DW, BPF_EXIT, BPF_JEQ, BPF_JMP, BPF_JMP32, BPF_LDX, BPF_MEM, BPF_MOV,
+    BPF_STX, BPF_X, INSN_SIZE, Insn, LD_DW_IMM, OP_MASK, STACK_SIZE,
+};
+
+/// Per-invocation limits for a program.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Budget {
+    /// Maximum instructions per invocation, 0 for no limit.
+    ///
+    /// Loop iterations are charged the length of the loop body; straight-line
+    /// code outside loops is not counted.
+    pub max_insns: u64,
+    /// Maximum run time per invocation in nanoseconds, 0 for no limit.
+    pub max_time_ns: u64,
+    /// Disable and detach the program after this many violations, 0 to never
+    /// detach.
+    pub detach_after: u32,
+}
+
+impl Budget {
+    /// No limits.
+    pub const UNLIMITED: Self = Self {
+        max_insns: 0,
+        max_time_ns: 0,
+        detach_after: 0,
+    };
+
+    /// Whether an instruction or time limit is set.
+    pub fn is_limited(&self) -> bool {
+        self.max_insns != 0 || self.max_time_ns != 0
+    }
+
+    /// Whether an invocation that started at `start_ns` and has been charged
+    /// `insns` instructions is over budget.
+    pub(crate) fn exceeded(&self, insns: u64, start_ns: u64) -> bool {
+        (self.max_insns != 0 && insns > self.max_insns)
+            || (self.max_time_ns != 0
+                && crate::platform::time_ns().saturating_sub(start_ns) > self.max_time_ns)
+    }
+}
+
+/// Budget given to programs loaded without an explicit one.
+static DEFAULT_BUDGET: Mutex<Budget> = Mutex::new(Budget::UNLIMITED);
+
+/// Set the budget for programs loaded without an explicit budget.
+///
+/// Already loaded programs keep their budget; use
+/// `runtime::set_program_budget` to change it.
+pub fn set_default_budget(budget: Budget) {
+    *DEFAULT_BUDGET.lock() = budget;
+}
+
+/// Get the budget for programs loaded without an explicit budget.
+pub fn default_budget() -> Budget {
+    *DEFAULT_BUDGET.lock()
+}
+
+// =============================================================================
+// Per-Program State
+// =============================================================================
+
+/// Budget and violation count of one loaded program.
+///
+/// Shared by all clones of the program, so limits can be changed while it
+/// is attached.
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    max_insns: AtomicU64,
+    max_time_ns: AtomicU64,
+    detach_after: AtomicU32,
+    violations: AtomicU64,
+    disabled: AtomicBool,
+}
+
+impl Watchdog {
+    pub(crate) fn new(budget: Budget) -> Self {
+        let watchdog = Self {
+            max_insns: AtomicU64::new(0),
+            max_time_ns: AtomicU64::new(0),
+            detach_after: AtomicU32::new(0),
+            violations: AtomicU64::new(0),
+            disabled: AtomicBool::new(false),
+        };
+        watchdog.set_budget(budget);
+        watchdog
+    }
+
+    pub(crate) fn budget(&self) -> Budget {
+        Budget {
+            max_insns: self.max_insns.load(Ordering::Relaxed),
+            max_time_ns: self.max_time_ns.load(Ordering::Relaxed),
+            detach_after: self.detach_after.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn set_budget(&self, budget: Budget) {
+        self.max_insns.store(budget.max_insns, Ordering::Relaxed);
+        self.max_time_ns
+            .store(budget.max_time_ns, Ordering::Relaxed);
+        self.detach_after
+            .store(budget.detach_after, Ordering::Relaxed);
+    }
+
+    pub(crate) fn violations(&self) -> u64 {
+        self.violations.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn is_disabled(&self) -> bool {
+        self.disabled.load(Ordering::Relaxed)
+    }
+
+    /// Count one violation. Returns `true` if this one disabled the program.
+    pub(crate) fn record_violation(&self) -> bool {
+        let count = self.violations.fetch_add(1, Ordering::Relaxed) + 1;
+        let limit = self.detach_after.load(Ordering::Relaxed);
+        limit != 0 && count >= limit as u64 && !self.disabled.swap(true, Ordering::Relaxed)
+    }
+
+    /// Clear the violation count and re-enable the program.
+    pub(crate) fn reset(&self) {
+        self.violations.store(0, Ordering::Relaxed);
+        self.disabled.store(false, Ordering::Relaxed);
+    }
+}
+
+// =============================================================================
+// Instrumentation
+// =============================================================================
+
+/// ID of the first tick helper. Tick `TICK_HELPER_BASE + k` charges `1 << k`
+/// instructions. The IDs are far outside the Linux range and not in
+/// `SUPPORTED_HELPERS`, so the verifier rejects programs that call them.
+const TICK_HELPER_BASE: u32 = 0x7fff_0000;
+
+/// Number of tick helpers; a loop body is at most `MAX_PROG_INSNS` (2^16).
+const TICK_HELPERS: usize = 17;
+
+/// Helper ID that is never registered. Calling it makes rbpf stop the
+/// program with an error.
+const ABORT_HELPER: u32 = 0x7fff_ffff;
+
+fn tick<const SHIFT: u32>(_r1: u64, _r2: u64, _r3: u64, _r4: u64, _r5: u64) -> u64 {
+    !crate::runtime::charge_budget(1 << SHIFT) as u64
+}
+
+const TICKS: [crate::helpers::HelperFn; TICK_HELPERS] = [
+    tick::<0>, tick::<1>, tick::<2>, tick::<3>, tick::<4>, tick::<5>, tick::<6>, tick::<7>,
+    tick::<8>, tick::<9>, tick::<10>, tick::<11>, tick::<12>, tick::<13>, tick::<14>, tick::<15>,
+    tick::<16>,
+];
+
+/// Register the tick helpers used by instrumented code.
+pub(crate) fn register_ticks(vm: &mut EbpfVmRaw<'static>) {
+    for (shift, &tick) in TICKS.iter().enumerate() {
+        if let Err(e) = vm.register_helper(TICK_HELPER_BASE + shift as u32, tick) {
+            log::warn!("Failed to register watchdog tick {}: {:?}", shift, e);
+        }
+    }
+}
+
+/// Target of instruction `idx` if it jumps backwards.
+fn back_edge_target(insn: &Insn, idx: usize) -> Option<usize> {
+    if !matches!(insn.class(), BPF_JMP | BPF_JMP32)
+        || matches!(insn.op & OP_MASK, BPF_CALL | BPF_EXIT)
+    {
+        return None;
+    }
+    let target = idx as i64 + 1 + insn.off as i64;
+    (target <= idx as i64).then_some(target as usize)
+}
+
+/// Where a check keeps r0 while it calls the tick helpers.
+#[derive(Clone, Copy)]
+enum Save {
+    /// A register that is dead at the back-edge.
+    Reg(u8),
+    /// The lowest doubleword of the stack, below anything the program uses.
+    Stack,
+}
+
+/// Build the budget check to insert before every loop back-edge.
+///
+/// Each check is
+///
+/// ```text
+/// mov rS, r0            ; only if r0 is live
+/// call tick<k>...       ; one call per set bit of the loop length
+/// jeq r0, 0, +1
+/// call ABORT_HELPER
+/// mov r0, rS            ; only if r0 is live
+/// ```
+///
+/// where `rS` is a register that is dead at the back-edge. If every register
+/// is live there, r0 is saved with `stxdw [r10 - 512], r0` and restored with
+/// `ldxdw r0, [r10 - 512]` instead; `stack_depth` is the stack the program
+/// itself uses, as reported by the verifier, and the slot must lie below it.
+///
+/// Returns the instruction index of each back-edge with its check (empty if
+/// the program has no loops), or `None` if r0 is live at a back-edge and
+/// neither a register nor the stack slot is free to save it.
+pub(crate) fn loop_checks(code: &[u8], stack_depth: usize) -> Option<Vec<(usize, Vec<Insn>)>> {
+    let len = code.len() / INSN_SIZE;
+    let mut checks = Vec::new();
+    let mut live = None;
+
+    let mut idx = 0;
+    while idx < len {
+        let insn = Insn::decode(code, idx);
+        if insn.op == LD_DW_IMM {
+            idx += 2;
+            continue;
+        }
+        let Some(target) = back_edge_target(&insn, idx) else {
+            idx += 1;
+            continue;
+        };
+
+        let live = live.get_or_insert_with(|| crate::verifier::live_regs(code));
+        let save = match live[idx] & 1 {
+            0 => None,
+            _ => match (1..10u8).find(|&r| live[idx] & (1 << r) == 0) {
+                Some(scratch) => Some(Save::Reg(scratch)),
+                None if stack_depth + 8 <= STACK_SIZE => Some(Save::Stack),
+                None => return None,
+            },
+        };
+
+        let mov = |dst: u8, src: u8| Insn {
+            op: BPF_ALU64 | BPF_MOV | BPF_X,
+            dst,
+            src,
+            off: 0,
+            imm: 0,
+        };
+        let call = |helper: u32| Insn {
+            op: BPF_JMP | BPF_CALL,
+            dst: 0,
+            src: 0,
+            off: 0,
+            imm: helper as i32,
+        };
+
+        let slot = -(STACK_SIZE as i16);
+
+        let mut check = Vec::new();
+        match save {
+            Some(Save::Reg(scratch)) => check.push(mov(scratch, 0)),
+            Some(Save::Stack) => check.push(Insn {
+                op: BPF_STX | BPF_MEM | BPF_DW,
+                dst: 10,
+                src: 0,
+                off: slot,
+                imm: 0,
+            }),
+            None => {}
+        }
+        let body = (idx - target + 1) as u32;
+        for shift in 0..TICK_HELPERS as u32 {
+            if body & (1 << shift) != 0 {
+                check.push(call(TICK_HELPER_BASE + shift));
+            }
+        }
+        check.push(Insn {
+            op: BPF_JMP | BPF_JEQ,
+            dst: 0,
+            src: 0,
+            off: 1,
+            imm: 0,
+        });
+        check.push(call(ABORT_HELPER));
+        match save {
+            Some(Save::Reg(scratch)) => check.push(mov(0, scratch)),
+            Some(Save::Stack) => check.push(Insn {
+                op: BPF_LDX | BPF_MEM | BPF_DW,
+                dst: 0,
+                src: 10,
+                off: slot,
+                imm: 0,
+            }),
+            None => {}
+        }
+        checks.push((idx, check));
+        idx += 1;
+    }
+    Some(checks)
+}
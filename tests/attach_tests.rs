@@ -2,6 +2,7 @@
 //!
 //! Tests attach, detach, and attachment registry operations.
 
+use axebpf::AttachTarget;
 use axebpf::attach::{self, Error};
 use axebpf::runtime;
 
@@ -159,6 +160,81 @@ fn test_list_attachments() {
     let _ = runtime::unload_program(prog_id2);
 }
 
+// =============================================================================
+// Unload Tests
+// =============================================================================
+
+#[test]
+fn test_unload_refuses_attached_program() {
+    let prog_id = runtime::load_program(PROG_RETURN_42, None).unwrap();
+    let tracepoint = "test:unload_attached";
+    attach::attach(tracepoint, prog_id, "test").unwrap();
+
+    let result = runtime::unload_program(prog_id);
+    assert!(matches!(result, Err(runtime::Error::Attached(1))));
+    assert_eq!(
+        attach::program_attachments(prog_id),
+        vec![AttachTarget::Tracepoint(tracepoint.to_string())]
+    );
+
+    attach::detach(tracepoint).unwrap();
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_force_unload_detaches() {
+    let prog_id = runtime::load_program(PROG_RETURN_42, None).unwrap();
+    let tp1 = "test:force_unload_1";
+    let tp2 = "test:force_unload_2";
+    attach::attach(tp1, prog_id, "test").unwrap();
+    attach::attach(tp2, prog_id, "test").unwrap();
+
+    runtime::force_unload_program(prog_id).unwrap();
+    assert!(runtime::get_program(prog_id).is_none());
+    assert!(attach::get_attached(tp1).is_none());
+    assert!(attach::get_attached(tp2).is_none());
+}
+
+// =============================================================================
+// Replace Tests
+// =============================================================================
+
+#[test]
+fn test_replace_tracepoint_program() {
+    let old = runtime::load_program(PROG_RETURN_42, None).unwrap();
+    let new = runtime::load_program(PROG_RETURN_ZERO, None).unwrap();
+    let tracepoint = "test:replace";
+    attach::attach(tracepoint, old, "old").unwrap();
+
+    let target = AttachTarget::Tracepoint(tracepoint.to_string());
+    attach::replace(&target, old, new).unwrap();
+    let info = attach::get_attached(tracepoint).unwrap();
+    assert_eq!(info.prog_id, new);
+    assert_eq!(runtime::run_program(info.prog_id, None).unwrap(), 0);
+
+    // The old program is no longer attached and can be unloaded
+    runtime::unload_program(old).unwrap();
+    attach::detach(tracepoint).unwrap();
+    runtime::unload_program(new).unwrap();
+}
+
+#[test]
+fn test_replace_requires_old_program() {
+    let prog_id = runtime::load_program(PROG_RETURN_42, None).unwrap();
+    let other = runtime::load_program(PROG_RETURN_ZERO, None).unwrap();
+    let tracepoint = "test:replace_wrong_old";
+    attach::attach(tracepoint, prog_id, "test").unwrap();
+
+    let target = AttachTarget::Tracepoint(tracepoint.to_string());
+    let result = attach::replace(&target, other, prog_id);
+    assert!(matches!(result, Err(Error::NotAttached(_))));
+    assert_eq!(attach::get_attached(tracepoint).unwrap().prog_id, prog_id);
+
+    attach::detach(tracepoint).unwrap();
+    let _ = runtime::unload_program(prog_id);
+    let _ = runtime::unload_program(other);
+}
+
 // =============================================================================
 // Attachment Count Tests
 // =============================================================================
@@ -255,3 +331,23 @@ fn test_attach_different_program_after_detach() {
     let _ = runtime::unload_program(prog_id1);
     let _ = runtime::unload_program(prog_id2);
 }
+
+// =============================================================================
+// Auto-Attach Tests
+// =============================================================================
+
+#[test]
+fn test_auto_attach_without_section() {
+    let prog_id = runtime::load_program(PROG_RETURN_42, None).unwrap();
+
+    let result = attach::auto_attach(prog_id);
+    assert!(matches!(result, Err(Error::NoAttachTarget(id)) if id == prog_id));
+
+    let _ = runtime::unload_program(prog_id);
+}
+
+#[test]
+fn test_auto_attach_program_not_found() {
+    let result = attach::auto_attach(99999);
+    assert!(matches!(result, Err(Error::ProgramNotFound(99999))));
+}
@@ -0,0 +1,81 @@
+//! Integration tests for the eBPF disassembler.
+
+use axebpf::disasm;
+use axebpf::runtime::{self, Error};
+
+/// Encode one instruction.
+fn insn(op: u8, dst: u8, src: u8, off: i16, imm: i32) -> [u8; 8] {
+    let mut b = [0u8; 8];
+    b[0] = op;
+    b[1] = (src << 4) | (dst & 0x0f);
+    b[2..4].copy_from_slice(&off.to_le_bytes());
+    b[4..8].copy_from_slice(&imm.to_le_bytes());
+    b
+}
+
+#[test]
+fn test_disassemble_basic_instructions() {
+    let code = [
+        insn(0xb7, 1, 0, 0, 0),   // r1 = 0
+        insn(0x7b, 10, 1, -8, 0), // *(u64 *)(r10 - 8) = r1
+        insn(0x79, 0, 10, -8, 0), // r0 = *(u64 *)(r10 - 8)
+        insn(0x15, 0, 0, 1, 0),   // if r0 == 0 goto +1
+        insn(0x04, 0, 0, 0, 1),   // w0 += 1
+        insn(0x95, 0, 0, 0, 0),   // exit
+    ]
+    .concat();
+
+    let lines = disasm::disassemble(&code, &[]);
+    assert_eq!(
+        lines,
+        [
+            "   0: (b7) r1 = 0",
+            "   1: (7b) *(u64 *)(r10 -8) = r1",
+            "   2: (79) r0 = *(u64 *)(r10 -8)",
+            "   3: (15) if r0 == 0x0 goto pc+1",
+            "   4: (04) w0 += 1",
+            "   5: (95) exit",
+        ]
+    );
+}
+
+#[test]
+fn test_disassemble_names_maps_and_helpers() {
+    let code = [
+        insn(0x18, 1, 1, 0, 7), // r1 = map[counts]
+        insn(0, 0, 0, 0, 0),
+        insn(0x18, 2, 2, 0, 9), // r2 = map[fd:9][0]+4
+        insn(0, 0, 0, 0, 4),
+        insn(0x85, 0, 0, 0, 1), // call bpf_map_lookup_elem
+        insn(0x95, 0, 0, 0, 0),
+    ]
+    .concat();
+
+    let lines = disasm::disassemble(&code, &[("counts".into(), 7)]);
+    assert_eq!(lines[0], "   0: (18) r1 = map[counts]");
+    assert_eq!(lines[1], "   2: (18) r2 = map[fd:9][0]+4");
+    assert_eq!(lines[2], "   4: (85) call bpf_map_lookup_elem#1");
+    assert_eq!(lines.len(), 4);
+}
+
+#[test]
+fn test_disassemble_loaded_program() {
+    let elf_bytes = include_bytes!("../../../target/bpf/kprobe_simple.o");
+    let prog_id = runtime::load_program(elf_bytes, None).unwrap();
+
+    let lines = runtime::disassemble(prog_id).unwrap();
+    assert!(lines.last().unwrap().ends_with("exit"));
+    // Every map the program loads belongs to its object
+    assert!(
+        lines
+            .iter()
+            .filter(|l| l.contains("map["))
+            .all(|l| !l.contains("[fd:"))
+    );
+
+    runtime::unload_program(prog_id).unwrap();
+    assert!(matches!(
+        runtime::disassemble(prog_id),
+        Err(Error::NotFound)
+    ));
+}
@@ -53,6 +53,19 @@ fn setup_mock_backends() {
     manager::clear_stale_brk_for_test();
 }
 
+/// Load a program for the probes to run; attaching checks that it exists.
+#[cfg(feature = "runtime")]
+fn loaded_prog() -> u32 {
+    // mov r0, 0; exit
+    let prog = [0xb7, 0, 0, 0, 0, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0];
+    axebpf::runtime::load_program(&prog, None).unwrap()
+}
+
+#[cfg(not(feature = "runtime"))]
+fn loaded_prog() -> u32 {
+    1
+}
+
 #[test]
 fn stage2_match_must_return_true() {
     manager::init();
@@ -61,7 +74,7 @@ fn stage2_match_must_return_true() {
     let gva = 0xffff_8000_8000_1000_u64;
     let _ = manager::detach(vm_id, gva);
 
-    manager::attach(vm_id, gva, 1, false, KprobeMode::Stage2Fault).unwrap();
+    manager::attach(vm_id, gva, loaded_prog(), false, KprobeMode::Stage2Fault).unwrap();
     let handled = handler::handle_stage2_exec_fault(vm_id, 0x1000, gva, true);
     assert!(handled, "matched stage2 fault must be handled");
 
@@ -76,7 +89,7 @@ fn guest_brk_match_must_return_true() {
     let pc = 0xffff_8000_8000_2000_u64;
     let _ = manager::detach(vm_id, pc);
 
-    manager::attach(vm_id, pc, 2, false, KprobeMode::BrkInject).unwrap();
+    manager::attach(vm_id, pc, loaded_prog(), false, KprobeMode::BrkInject).unwrap();
     let handled = handler::handle_guest_brk(vm_id, pc, 0x123);
     assert_eq!(
         handled,
@@ -95,7 +108,7 @@ fn stale_guest_brk_after_detach_must_request_retry() {
     let pc = 0xffff_8000_8000_3000_u64;
     let _ = manager::detach(vm_id, pc);
 
-    manager::attach(vm_id, pc, 3, false, KprobeMode::BrkInject).unwrap();
+    manager::attach(vm_id, pc, loaded_prog(), false, KprobeMode::BrkInject).unwrap();
     manager::detach(vm_id, pc).unwrap();
 
     let handled = handler::handle_guest_brk(vm_id, pc, 0);
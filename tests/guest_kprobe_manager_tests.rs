@@ -44,6 +44,19 @@ fn setup_stage2_backends() {
     manager::clear_stale_brk_for_test();
 }
 
+/// Load a program for the probes to run; attaching checks that it exists.
+#[cfg(feature = "runtime")]
+fn loaded_prog() -> u32 {
+    // mov r0, 0; exit
+    let prog = [0xb7, 0, 0, 0, 0, 0, 0, 0, 0x95, 0, 0, 0, 0, 0, 0, 0];
+    axebpf::runtime::load_program(&prog, None).unwrap()
+}
+
+#[cfg(not(feature = "runtime"))]
+fn loaded_prog() -> u32 {
+    1
+}
+
 static mut MOCK_GUEST_TEXT: [u8; 4] = [0x78, 0x56, 0x34, 0x12];
 
 fn mock_gva_to_hva(_gva: u64, _vm_id: u32) -> AxResult<usize> {
@@ -61,7 +74,7 @@ fn attach_must_rollback_when_enable_fails() {
 
     manager::install_mock_backend_fail_on_enable(vm_id, gva);
 
-    let ret = manager::attach(vm_id, gva, 1, false, KprobeMode::Stage2Fault);
+    let ret = manager::attach(vm_id, gva, loaded_prog(), false, KprobeMode::Stage2Fault);
     assert!(ret.is_err());
 
     assert!(manager::lookup_enabled(vm_id, gva).is_none());
@@ -80,12 +93,25 @@ fn duplicate_attach_same_key_returns_conflict() {
     let gva = 0x2000_u64;
     let _ = manager::detach(vm_id, gva);
 
-    manager::attach(vm_id, gva, 1, false, KprobeMode::Stage2Fault).unwrap();
-    assert!(manager::attach(vm_id, gva, 2, false, KprobeMode::Stage2Fault).is_err());
+    manager::attach(vm_id, gva, loaded_prog(), false, KprobeMode::Stage2Fault).unwrap();
+    assert!(manager::attach(vm_id, gva, loaded_prog(), false, KprobeMode::Stage2Fault).is_err());
 
     manager::detach(vm_id, gva).unwrap();
 }
 
+#[cfg(feature = "runtime")]
+#[test]
+fn attach_rejects_unloaded_program() {
+    manager::init();
+    setup_stage2_backends();
+    let vm_id = 5;
+    let gva = 0x5000_u64;
+    let _ = manager::detach(vm_id, gva);
+
+    assert!(manager::attach(vm_id, gva, u32::MAX, false, KprobeMode::Stage2Fault).is_err());
+    assert!(manager::lookup_enabled(vm_id, gva).is_none());
+}
+
 #[test]
 fn disable_and_detach_are_idempotent() {
     manager::init();
@@ -94,7 +120,7 @@ fn disable_and_detach_are_idempotent() {
     let gva = 0x3000_u64;
     let _ = manager::detach(vm_id, gva);
 
-    manager::attach(vm_id, gva, 1, false, KprobeMode::Stage2Fault).unwrap();
+    manager::attach(vm_id, gva, loaded_prog(), false, KprobeMode::Stage2Fault).unwrap();
 
     assert!(manager::disable(vm_id, gva).is_ok());
     assert!(manager::disable(vm_id, gva).is_ok());
@@ -116,7 +142,7 @@ fn brk_inject_enable_then_disable_restores_instruction() {
         MOCK_GUEST_TEXT = [0x78, 0x56, 0x34, 0x12];
     }
 
-    manager::attach(vm_id, gva, 7, false, KprobeMode::BrkInject).unwrap();
+    manager::attach(vm_id, gva, loaded_prog(), false, KprobeMode::BrkInject).unwrap();
 
     #[cfg(target_arch = "aarch64")]
     unsafe {
@@ -51,6 +51,13 @@ fn test_get_helper_cpu_id() {
     assert!(helper.is_some());
 }
 
+#[test]
+fn test_get_helper_tail_call() {
+    let helper = helpers::get_helper(id::TAIL_CALL);
+    assert!(helper.is_some());
+    assert!(SUPPORTED_HELPERS.contains(&id::TAIL_CALL));
+}
+
 #[test]
 fn test_get_helper_unsupported() {
     // Helper ID 999 should not exist
@@ -71,6 +78,7 @@ fn test_helper_ids_match_linux() {
     assert_eq!(id::KTIME_GET_NS, 5);
     assert_eq!(id::TRACE_PRINTK, 6);
     assert_eq!(id::GET_SMP_PROCESSOR_ID, 8);
+    assert_eq!(id::TAIL_CALL, 12);
 }
 
 // =============================================================================
@@ -85,6 +93,7 @@ fn test_map_helpers_integration() {
         key_size: 8,
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -113,6 +122,152 @@ fn test_map_helpers_integration() {
     assert_eq!(result, 0); // Not found
 }
 
+#[test]
+fn test_queue_helpers_integration() {
+    let def = MapDef {
+        map_type: MapType::Queue,
+        key_size: 0,
+        value_size: 8,
+        max_entries: 2,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+
+    let push_fn = helpers::get_helper(id::MAP_PUSH_ELEM).unwrap();
+    let pop_fn = helpers::get_helper(id::MAP_POP_ELEM).unwrap();
+    let peek_fn = helpers::get_helper(id::MAP_PEEK_ELEM).unwrap();
+
+    for value in [7u64, 8] {
+        let result = push_fn(map_id as u64, &value as *const u64 as u64, 0, 0, 0);
+        assert_eq!(result, 0);
+    }
+
+    let mut out = 0u64;
+    let out_ptr = &mut out as *mut u64 as u64;
+    assert_eq!(peek_fn(map_id as u64, out_ptr, 0, 0, 0), 0);
+    assert_eq!(out, 7);
+    assert_eq!(pop_fn(map_id as u64, out_ptr, 0, 0, 0), 0);
+    assert_eq!(out, 7);
+    assert_eq!(pop_fn(map_id as u64, out_ptr, 0, 0, 0), 0);
+    assert_eq!(out, 8);
+
+    // Empty
+    assert_eq!(pop_fn(map_id as u64, out_ptr, 0, 0, 0) as i64, -1);
+}
+
+#[test]
+fn test_bloom_filter_helpers_integration() {
+    let def = MapDef {
+        map_type: MapType::BloomFilter,
+        key_size: 0,
+        value_size: 8,
+        max_entries: 16,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+
+    let push_fn = helpers::get_helper(id::MAP_PUSH_ELEM).unwrap();
+    let peek_fn = helpers::get_helper(id::MAP_PEEK_ELEM).unwrap();
+    let pc = 0xffff_0000_4000_1000u64;
+    let pc_ptr = &pc as *const u64 as u64;
+
+    assert_eq!(peek_fn(map_id as u64, pc_ptr, 0, 0, 0) as i64, -2); // -ENOENT
+    assert_eq!(push_fn(map_id as u64, pc_ptr, 0, 0, 0), 0);
+    assert_eq!(peek_fn(map_id as u64, pc_ptr, 0, 0, 0), 0);
+    // The value is left as it was
+    assert_eq!(pc, 0xffff_0000_4000_1000);
+
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_map_helpers_respect_access_flags() {
+    let def = |map_flags| MapDef {
+        map_type: MapType::Array,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 4,
+        map_flags,
+    };
+    let rdonly = maps::create(&def(maps::BPF_F_RDONLY_PROG)).unwrap();
+    let writable = maps::create(&def(0)).unwrap();
+
+    let update_fn = helpers::get_helper(id::MAP_UPDATE_ELEM).unwrap();
+    let key = 1u32;
+    let value = 5u64;
+    let key_ptr = &key as *const u32 as u64;
+    let value_ptr = &value as *const u64 as u64;
+
+    // Programs cannot write a read-only map, the user side can
+    assert_eq!(
+        update_fn(rdonly as u64, key_ptr, value_ptr, 0, 0) as i64,
+        -1
+    );
+    maps::update_elem(rdonly, &key.to_le_bytes(), &value.to_le_bytes(), 0).unwrap();
+
+    // Maps programs can write cannot be frozen
+    assert!(matches!(
+        maps::freeze(writable),
+        Err(maps::Error::PermissionDenied)
+    ));
+    assert!(!maps::is_frozen(writable));
+    assert_eq!(update_fn(writable as u64, key_ptr, value_ptr, 0, 0), 0);
+
+    // A frozen read-only map is constant for both sides
+    maps::freeze(rdonly).unwrap();
+    assert_eq!(
+        update_fn(rdonly as u64, key_ptr, value_ptr, 0, 0) as i64,
+        -1
+    );
+    assert!(maps::update_elem(rdonly, &key.to_le_bytes(), &value.to_le_bytes(), 0).is_err());
+
+    maps::destroy(rdonly).unwrap();
+    maps::destroy(writable).unwrap();
+}
+
+#[test]
+fn test_ringbuf_helpers_integration() {
+    let def = MapDef {
+        map_type: MapType::RingBuf,
+        key_size: 0,
+        value_size: 0,
+        max_entries: 4096,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+
+    let output_fn = helpers::get_helper(id::RINGBUF_OUTPUT).unwrap();
+    let reserve_fn = helpers::get_helper(id::RINGBUF_RESERVE).unwrap();
+    let submit_fn = helpers::get_helper(id::RINGBUF_SUBMIT).unwrap();
+    let discard_fn = helpers::get_helper(id::RINGBUF_DISCARD).unwrap();
+    let query_fn = helpers::get_helper(id::RINGBUF_QUERY).unwrap();
+
+    // Reserved records hold back the ones after them until submitted
+    let first = reserve_fn(map_id as u64, 8, 0, 0, 0);
+    assert_ne!(first, 0);
+    let second = reserve_fn(map_id as u64, 8, 0, 0, 0);
+    unsafe { *(first as *mut u64) = 1 };
+    let value = 2u64;
+    let result = output_fn(map_id as u64, &value as *const u64 as u64, 8, 0, 0);
+    assert_eq!(result, 0);
+    assert_eq!(submit_fn(first, 0, 0, 0, 0), 0);
+    assert_eq!(maps::ringbuf_consume(map_id, 0), [1u64.to_le_bytes()]);
+
+    assert_eq!(discard_fn(second, 0, 0, 0, 0), 0);
+    assert_eq!(maps::ringbuf_consume(map_id, 0), [2u64.to_le_bytes()]);
+
+    // Positions: three 16-byte records written and consumed
+    assert_eq!(query_fn(map_id as u64, 0, 0, 0, 0), 0);
+    assert_eq!(query_fn(map_id as u64, 1, 0, 0, 0), 4096);
+    assert_eq!(query_fn(map_id as u64, 3, 0, 0, 0), 48);
+
+    // Too large, or flags set
+    assert_eq!(reserve_fn(map_id as u64, 4096, 0, 0, 0), 0);
+    assert_eq!(reserve_fn(map_id as u64, 8, 1, 0, 0), 0);
+
+    maps::destroy(map_id).unwrap();
+}
+
 #[test]
 fn test_trace_printk_helper() {
     let printk_fn = helpers::get_helper(id::TRACE_PRINTK).unwrap();
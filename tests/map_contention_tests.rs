@@ -0,0 +1,133 @@
+//! Contention tests for the map registry.
+//!
+//! Each map has a lock of its own, so threads working on different maps do
+//! not wait for each other the way threads sharing one map do. The
+//! benchmark is ignored by default; run it in release mode with
+//! `cargo test --release --test map_contention_tests -- --ignored --nocapture`.
+
+use std::sync::Barrier;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use axebpf::maps::{self, MapDef, MapType};
+
+const THREADS: usize = 4;
+const KEYS: u64 = 256;
+
+fn hash_map() -> u32 {
+    let def = MapDef {
+        map_type: MapType::HashMap,
+        key_size: 8,
+        value_size: 8,
+        max_entries: KEYS as u32,
+        map_flags: 0,
+    };
+    maps::create(&def).unwrap()
+}
+
+/// Run `ops` updates and lookups on each map in `map_ids`, one thread per
+/// entry, all starting together. Thread `t` stores `i * THREADS + t` under
+/// key `i % KEYS`. Returns the time until all threads finished.
+fn hammer(map_ids: &[u32], ops: u64) -> Duration {
+    let barrier = Barrier::new(map_ids.len());
+    let start = Instant::now();
+    thread::scope(|s| {
+        for (t, &map_id) in map_ids.iter().enumerate() {
+            let barrier = &barrier;
+            s.spawn(move || {
+                barrier.wait();
+                for i in 0..ops {
+                    let key = (i % KEYS).to_le_bytes();
+                    let value = i * THREADS as u64 + t as u64;
+                    maps::update_elem(map_id, &key, &value.to_le_bytes(), 0).unwrap();
+                    assert!(maps::lookup_elem(map_id, &key).is_some());
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+#[test]
+fn test_concurrent_updates_on_separate_maps() {
+    let map_ids: Vec<u32> = (0..THREADS).map(|_| hash_map()).collect();
+    let ops = KEYS * 4;
+    hammer(&map_ids, ops);
+
+    // Each map holds the last round of its own thread's values
+    for (t, &map_id) in map_ids.iter().enumerate() {
+        let entries = maps::iter_entries(map_id);
+        assert_eq!(entries.len(), KEYS as usize);
+        for (key, value) in entries {
+            let key = u64::from_le_bytes(key.try_into().unwrap());
+            let value = u64::from_le_bytes(value.try_into().unwrap());
+            let i = ops - KEYS + key;
+            assert_eq!(value, i * THREADS as u64 + t as u64);
+        }
+        maps::destroy(map_id).unwrap();
+    }
+}
+
+#[test]
+#[ignore = "benchmark"]
+fn test_bench_separate_maps_do_not_contend() {
+    const OPS: u64 = 200_000;
+
+    let separate: Vec<u32> = (0..THREADS).map(|_| hash_map()).collect();
+    let shared = hash_map();
+
+    let separate_time = hammer(&separate, OPS);
+    let shared_time = hammer(&[shared; THREADS], OPS);
+    println!(
+        "{} threads x {} update+lookup: separate maps {:?}, one shared map {:?}",
+        THREADS, OPS, separate_time, shared_time
+    );
+
+    // Only measurable when the threads really run in parallel
+    let cpus = thread::available_parallelism().map_or(1, |n| n.get());
+    if cpus >= THREADS {
+        assert!(separate_time < shared_time);
+    }
+
+    for map_id in separate.into_iter().chain([shared]) {
+        maps::destroy(map_id).unwrap();
+    }
+}
+
+#[test]
+fn test_freeze_waits_for_user_writes() {
+    let def = MapDef {
+        map_type: MapType::Array,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 1,
+        map_flags: maps::BPF_F_RDONLY_PROG,
+    };
+    let map_id = maps::create(&def).unwrap();
+    let key = 0u32.to_le_bytes();
+    let barrier = Barrier::new(2);
+
+    let (last_written, frozen_value) = thread::scope(|s| {
+        let writer = s.spawn(|| {
+            barrier.wait();
+            let mut last = 0u64;
+            for i in 1.. {
+                match maps::update_elem(map_id, &key, &u64::to_le_bytes(i), 0) {
+                    Ok(()) => last = i,
+                    Err(_) => break,
+                }
+            }
+            last
+        });
+        barrier.wait();
+        thread::sleep(Duration::from_millis(1));
+        maps::freeze(map_id).unwrap();
+        let frozen_value = maps::lookup_elem(map_id, &key).unwrap();
+        (writer.join().unwrap(), frozen_value)
+    });
+
+    // No write that started before the freeze lands after it
+    assert_eq!(frozen_value, last_written.to_le_bytes());
+
+    maps::destroy(map_id).unwrap();
+}
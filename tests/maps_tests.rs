@@ -2,6 +2,7 @@
 //!
 //! Tests map creation, CRUD operations, and different map types.
 
+use axebpf::map_ops;
 use axebpf::maps::{self, Error, MapDef, MapType};
 
 // =============================================================================
@@ -15,6 +16,7 @@ fn test_create_array_map() {
         key_size: 4,
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let result = maps::create(&def);
     assert!(result.is_ok());
@@ -27,6 +29,7 @@ fn test_create_hashmap() {
         key_size: 8,
         value_size: 8,
         max_entries: 64,
+        map_flags: 0,
     };
     let result = maps::create(&def);
     assert!(result.is_ok());
@@ -39,6 +42,7 @@ fn test_create_lru_hash() {
         key_size: 8,
         value_size: 16,
         max_entries: 32,
+        map_flags: 0,
     };
     let result = maps::create(&def);
     assert!(result.is_ok());
@@ -51,11 +55,95 @@ fn test_create_queue() {
         key_size: 0,
         value_size: 8,
         max_entries: 128,
+        map_flags: 0,
     };
     let result = maps::create(&def);
     assert!(result.is_ok());
 }
 
+#[test]
+fn test_create_stack() {
+    let def = MapDef {
+        map_type: MapType::Stack,
+        key_size: 0,
+        value_size: 8,
+        max_entries: 128,
+        map_flags: 0,
+    };
+    let result = maps::create(&def);
+    assert!(result.is_ok());
+}
+
+#[test]
+fn test_create_prog_array_requires_u32_values() {
+    let def = MapDef {
+        map_type: MapType::ProgArray,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 8,
+        map_flags: 0,
+    };
+    assert!(matches!(maps::create(&def), Err(Error::InvalidArgument)));
+}
+
+#[test]
+fn test_create_lpm_trie_requires_prefix_and_data() {
+    let def = MapDef {
+        map_type: MapType::LpmTrie,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 16,
+        map_flags: 0,
+    };
+    assert!(matches!(maps::create(&def), Err(Error::InvalidArgument)));
+
+    let def = MapDef {
+        key_size: 12,
+        ..def
+    };
+    assert!(maps::create(&def).is_ok());
+
+    // Values must fit in the lookup buffer
+    let def = MapDef {
+        value_size: axebpf::helpers::MAX_VALUE_SIZE as u32 + 8,
+        ..def
+    };
+    assert!(matches!(maps::create(&def), Err(Error::InvalidArgument)));
+}
+
+#[test]
+fn test_create_checks_map_flags() {
+    let def = |map_type, map_flags| MapDef {
+        map_type,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 16,
+        map_flags,
+    };
+    let rdonly = maps::create(&def(MapType::Array, maps::BPF_F_RDONLY_PROG)).unwrap();
+    assert_eq!(
+        map_ops::get_map_def(rdonly).unwrap().map_flags,
+        maps::BPF_F_RDONLY_PROG
+    );
+    assert!(maps::create(&def(MapType::HashMap, maps::BPF_F_NO_PREALLOC)).is_ok());
+
+    // Preallocated types, both access flags, flags of fd arrays, unknown bits
+    let both = maps::BPF_F_RDONLY_PROG | maps::BPF_F_WRONLY_PROG;
+    for (map_type, map_flags) in [
+        (MapType::Array, maps::BPF_F_NO_PREALLOC),
+        (MapType::LruHash, maps::BPF_F_NO_PREALLOC),
+        (MapType::HashMap, both),
+        (MapType::ProgArray, maps::BPF_F_RDONLY_PROG),
+        (MapType::HashMap, 1 << 20),
+    ] {
+        assert!(matches!(
+            maps::create(&def(map_type, map_flags)),
+            Err(Error::InvalidArgument)
+        ));
+    }
+    maps::destroy(rdonly).unwrap();
+}
+
 // =============================================================================
 // Map CRUD Tests
 // =============================================================================
@@ -67,6 +155,7 @@ fn test_update_and_lookup() {
         key_size: 4, // Array maps require 4-byte (u32) keys
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -93,6 +182,7 @@ fn test_lookup_nonexistent_key() {
         key_size: 8,
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -108,6 +198,7 @@ fn test_delete_elem() {
         key_size: 8,
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -133,6 +224,7 @@ fn test_delete_nonexistent_key() {
         key_size: 8,
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -150,6 +242,7 @@ fn test_update_existing_key() {
         key_size: 8,
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -170,6 +263,139 @@ fn test_update_existing_key() {
     assert_eq!(u64::from_le_bytes(buf), 200);
 }
 
+#[test]
+fn test_queue_and_stack_order() {
+    for (map_type, order) in [(MapType::Queue, [1u64, 2, 3]), (MapType::Stack, [3, 2, 1])] {
+        let def = MapDef {
+            map_type,
+            key_size: 0,
+            value_size: 8,
+            max_entries: 4,
+            map_flags: 0,
+        };
+        let map_id = maps::create(&def).unwrap();
+        for value in 1u64..=3 {
+            maps::push_elem(map_id, &value.to_le_bytes(), 0).unwrap();
+        }
+
+        let next = |elem: Option<Vec<u8>>| u64::from_le_bytes(elem.unwrap().try_into().unwrap());
+        assert_eq!(next(maps::peek_elem(map_id)), order[0]);
+        for expected in order {
+            assert_eq!(next(maps::pop_elem(map_id)), expected);
+        }
+        assert!(maps::pop_elem(map_id).is_none());
+
+        maps::destroy(map_id).unwrap();
+    }
+}
+
+#[test]
+fn test_bloom_filter_membership() {
+    let def = |key_size, value_size| MapDef {
+        map_type: MapType::BloomFilter,
+        key_size,
+        value_size,
+        max_entries: 100,
+        map_flags: 0,
+    };
+    assert!(maps::create(&def(4, 8)).is_err());
+    assert!(maps::create(&def(0, 0)).is_err());
+    let map_id = maps::create(&def(0, 8)).unwrap();
+
+    for value in 0u64..100 {
+        maps::push_elem(map_id, &(value * 3).to_le_bytes(), 0).unwrap();
+    }
+    // No false negatives, few false positives
+    for value in 0u64..100 {
+        assert!(maps::bloom_filter_contains(map_id, &(value * 3).to_le_bytes()).unwrap());
+    }
+    let false_positives = (1000u64..2000)
+        .filter(|value| maps::bloom_filter_contains(map_id, &value.to_le_bytes()).unwrap())
+        .count();
+    assert!(false_positives < 50, "{} false positives", false_positives);
+
+    // Values cannot be read back or removed
+    assert!(maps::peek_elem(map_id).is_none());
+    assert!(maps::delete_elem(map_id, &0u64.to_le_bytes()).is_err());
+    assert!(maps::push_elem(map_id, &0u64.to_le_bytes(), 2).is_err());
+    assert!(maps::bloom_filter_contains(map_id, &[0u8; 4]).is_err());
+    assert_eq!(map_ops::get_map_def(map_id).unwrap(), def(0, 8));
+
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_ringbuf_output_and_consume() {
+    let def = |max_entries| MapDef {
+        map_type: MapType::RingBuf,
+        key_size: 0,
+        value_size: 0,
+        max_entries,
+        map_flags: 0,
+    };
+    // The size must be a power of two of at least one page
+    assert!(maps::create(&def(6000)).is_err());
+    assert!(maps::create(&def(2048)).is_err());
+    let map_id = maps::create(&def(4096)).unwrap();
+    assert_eq!(map_ops::get_map_def(map_id).unwrap().max_entries, 4096);
+
+    maps::ringbuf_output(map_id, b"first").unwrap();
+    maps::ringbuf_output(map_id, &[7u8; 20]).unwrap();
+    // Two 8-byte headers, data padded to 8 bytes
+    assert_eq!(maps::ringbuf_available(map_id), Some(16 + 8 + 24));
+
+    let records = maps::ringbuf_consume(map_id, 0);
+    assert_eq!(records, [b"first".to_vec(), vec![7u8; 20]]);
+    assert_eq!(maps::ringbuf_available(map_id), Some(0));
+
+    // Records that would cross the end start over at the front
+    let record = [1u8; 1000];
+    for _ in 0..8 {
+        maps::ringbuf_output(map_id, &record).unwrap();
+        assert_eq!(maps::ringbuf_consume(map_id, 1), [record.to_vec()]);
+    }
+    for _ in 0..4 {
+        maps::ringbuf_output(map_id, &record).unwrap();
+    }
+    assert!(matches!(
+        maps::ringbuf_output(map_id, &record),
+        Err(Error::NoSpace)
+    ));
+    assert_eq!(maps::ringbuf_consume(map_id, 0).len(), 4);
+
+    maps::destroy(map_id).unwrap();
+    assert!(maps::ringbuf_consume(map_id, 0).is_empty());
+}
+
+#[test]
+fn test_lpm_trie_longest_prefix_match() {
+    let def = MapDef {
+        map_type: MapType::LpmTrie,
+        key_size: 4 + 8, // u32 prefix length + 64-bit address
+        value_size: 4,
+        max_entries: 16,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+
+    let region = |prefix_len, gpa: u64| maps::lpm_trie_key(prefix_len, &gpa.to_be_bytes());
+    let insert = |prefix_len, gpa, device: u32| {
+        maps::update_elem(map_id, &region(prefix_len, gpa), &device.to_le_bytes(), 0).unwrap()
+    };
+    insert(32, 0x0900_0000_0000_0000, 1);
+    insert(48, 0x0900_0000_0001_0000, 2);
+
+    let lookup = |gpa: u64| {
+        maps::lookup_elem(map_id, &region(64, gpa))
+            .map(|v| u32::from_le_bytes(v.try_into().unwrap()))
+    };
+    assert_eq!(lookup(0x0900_0000_0001_0040), Some(2));
+    assert_eq!(lookup(0x0900_0000_0002_0000), Some(1));
+    assert_eq!(lookup(0x0a00_0000_0000_0000), None);
+
+    maps::destroy(map_id).unwrap();
+}
+
 // =============================================================================
 // Map Capacity Tests
 // =============================================================================
@@ -181,6 +407,7 @@ fn test_map_full_error() {
         key_size: 4, // Array maps require 4-byte (u32) keys
         value_size: 8,
         max_entries: 2,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -205,6 +432,7 @@ fn test_lru_eviction() {
         key_size: 8,
         value_size: 8,
         max_entries: 2,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -225,6 +453,274 @@ fn test_lru_eviction() {
     assert!(lookup.is_none());
 }
 
+#[test]
+fn test_lru_map_evicts_least_recently_used() {
+    let def = MapDef {
+        map_type: MapType::LruHash,
+        key_size: 8,
+        value_size: 8,
+        max_entries: 3,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+    let update = |k: u64| maps::update_elem(map_id, &k.to_le_bytes(), &k.to_le_bytes(), 0);
+    for k in 0..3 {
+        update(k).unwrap();
+    }
+
+    // Lookups and updates count as uses; deleted keys free their slot
+    assert!(maps::lookup_elem(map_id, &0u64.to_le_bytes()).is_some());
+    maps::delete_elem(map_id, &1u64.to_le_bytes()).unwrap();
+    update(3).unwrap();
+    update(4).unwrap(); // evicts 2
+    update(0).unwrap();
+    update(5).unwrap(); // evicts 3
+
+    let mut keys: Vec<u64> = maps::iter_entries(map_id)
+        .into_iter()
+        .map(|(key, _)| u64::from_le_bytes(key.try_into().unwrap()))
+        .collect();
+    keys.sort();
+    assert_eq!(keys, [0, 4, 5]);
+
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_hash_map_full_until_delete() {
+    let def = MapDef {
+        map_type: MapType::HashMap,
+        key_size: 8,
+        value_size: 8,
+        max_entries: 2,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+    for i in 0u64..2 {
+        maps::update_elem(map_id, &i.to_le_bytes(), &i.to_le_bytes(), 0).unwrap();
+    }
+
+    let key = 2u64.to_le_bytes();
+    assert!(maps::update_elem(map_id, &key, &key, 0).is_err());
+    // Existing keys are still updated in place
+    maps::update_elem(map_id, &1u64.to_le_bytes(), &key, 0).unwrap();
+    assert_eq!(maps::lookup_elem(map_id, &1u64.to_le_bytes()).unwrap(), key);
+
+    // A deleted key's value storage is reused
+    maps::delete_elem(map_id, &0u64.to_le_bytes()).unwrap();
+    maps::update_elem(map_id, &key, &key, 0).unwrap();
+    assert_eq!(maps::lookup_elem(map_id, &key).unwrap(), key);
+    assert!(maps::lookup_elem(map_id, &0u64.to_le_bytes()).is_none());
+
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_prog_array_update_and_delete() {
+    let def = MapDef {
+        map_type: MapType::ProgArray,
+        key_size: 4,
+        value_size: 4,
+        max_entries: 8,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+    let key = 2u32.to_le_bytes();
+
+    // Empty slots read as missing
+    assert!(maps::lookup_elem(map_id, &key).is_none());
+
+    // Only loaded programs can be stored
+    let result = maps::update_elem(map_id, &key, &9999u32.to_le_bytes(), 0);
+    assert!(matches!(result, Err(Error::InvalidArgument)));
+
+    let prog: &[u8] = &[
+        0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r0, 0
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ];
+    let prog_id = axebpf::runtime::load_program(prog, None).unwrap();
+    maps::update_elem(map_id, &key, &prog_id.to_le_bytes(), 0).unwrap();
+    assert_eq!(
+        maps::lookup_elem(map_id, &key),
+        Some(prog_id.to_le_bytes().to_vec())
+    );
+    assert_eq!(maps::prog_array_get(map_id, &key), Some(prog_id));
+
+    maps::delete_elem(map_id, &key).unwrap();
+    assert!(maps::lookup_elem(map_id, &key).is_none());
+    assert!(matches!(
+        maps::delete_elem(map_id, &key),
+        Err(Error::KeyNotFound)
+    ));
+
+    axebpf::runtime::unload_program(prog_id).unwrap();
+    maps::destroy(map_id).unwrap();
+}
+
+// =============================================================================
+// Batch Operation Tests
+// =============================================================================
+
+fn hash_entries(keys: std::ops::Range<u64>) -> Vec<(Vec<u8>, Vec<u8>)> {
+    keys.map(|k| (k.to_le_bytes().to_vec(), (k * 10).to_le_bytes().to_vec()))
+        .collect()
+}
+
+#[test]
+fn test_hash_map_batch_lookup_and_drain() {
+    let def = MapDef {
+        map_type: MapType::HashMap,
+        key_size: 8,
+        value_size: 8,
+        max_entries: 16,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+    maps::update_batch(map_id, &hash_entries(0..10), 0).unwrap();
+
+    // Batches resume after the previous one and cover every entry once
+    let mut seen = Vec::new();
+    let mut token = None;
+    loop {
+        let batch = maps::lookup_batch(map_id, token.as_ref(), 4).unwrap();
+        assert!(batch.entries.len() <= 4);
+        seen.extend(batch.entries);
+        token = batch.next;
+        if token.is_none() {
+            break;
+        }
+    }
+    seen.sort();
+    assert_eq!(seen, hash_entries(0..10));
+
+    let mut drained = Vec::new();
+    loop {
+        let batch = maps::lookup_and_delete_batch(map_id, 3).unwrap();
+        drained.extend(batch.entries);
+        if batch.next.is_none() {
+            break;
+        }
+    }
+    drained.sort();
+    assert_eq!(drained, hash_entries(0..10));
+    assert!(maps::iter_entries(map_id).is_empty());
+
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_batch_update_stops_at_failure() {
+    let def = MapDef {
+        map_type: MapType::HashMap,
+        key_size: 8,
+        value_size: 8,
+        max_entries: 4,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+
+    // The fifth entry does not fit; the first four stay
+    let err = maps::update_batch(map_id, &hash_entries(0..6), 0).unwrap_err();
+    assert_eq!(err.processed, 4);
+    assert_eq!(maps::iter_entries(map_id).len(), 4);
+
+    let keys: Vec<Vec<u8>> = [1u64, 9, 2]
+        .iter()
+        .map(|k| k.to_le_bytes().to_vec())
+        .collect();
+    // Missing keys are skipped, as by delete_elem
+    maps::delete_batch(map_id, &keys).unwrap();
+    let mut entries = maps::iter_entries(map_id);
+    entries.sort();
+    assert_eq!(entries, [hash_entries(0..1), hash_entries(3..4)].concat());
+
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_array_map_batch() {
+    let def = MapDef {
+        map_type: MapType::Array,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 4,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+    let entries: Vec<_> = (0u32..4)
+        .map(|i| (i.to_le_bytes().to_vec(), vec![i as u8; 8]))
+        .collect();
+    maps::update_batch(map_id, &entries, 0).unwrap();
+
+    let batch = maps::lookup_batch(map_id, None, 0).unwrap();
+    assert_eq!(batch.entries, entries);
+    assert!(batch.next.is_none());
+
+    // Array elements cannot be deleted
+    assert!(matches!(
+        maps::lookup_and_delete_batch(map_id, 0),
+        Err(Error::InvalidArgument)
+    ));
+    assert!(maps::delete_batch(map_id, &[entries[0].0.clone()]).is_err());
+
+    maps::destroy(map_id).unwrap();
+}
+
+// =============================================================================
+// Frozen Map Tests
+// =============================================================================
+
+#[test]
+fn test_freeze_blocks_user_writes() {
+    let def = MapDef {
+        map_type: MapType::HashMap,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 16,
+        map_flags: maps::BPF_F_RDONLY_PROG,
+    };
+    let map_id = maps::create(&def).unwrap();
+    let key = 1u32.to_le_bytes();
+    let value = 7u64.to_le_bytes();
+    maps::update_elem(map_id, &key, &value, 0).unwrap();
+
+    assert!(!maps::is_frozen(map_id));
+    maps::freeze(map_id).unwrap();
+    maps::freeze(map_id).unwrap();
+    assert!(maps::is_frozen(map_id));
+
+    let denied = |result| matches!(result, Err(Error::PermissionDenied));
+    assert!(denied(maps::update_elem(map_id, &key, &value, 0)));
+    assert!(denied(maps::delete_elem(map_id, &key)));
+    assert!(maps::delete_batch(map_id, &[key.to_vec()]).is_err());
+    assert!(maps::lookup_and_delete_batch(map_id, 0).is_err());
+
+    // Reads still work
+    assert_eq!(maps::lookup_elem(map_id, &key), Some(value.to_vec()));
+    assert_eq!(maps::iter_entries(map_id).len(), 1);
+
+    maps::destroy(map_id).unwrap();
+    assert!(matches!(maps::freeze(map_id), Err(Error::NotFound)));
+}
+
+#[test]
+fn test_freeze_requires_rdonly_prog() {
+    let def = MapDef {
+        map_type: MapType::Array,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 4,
+        map_flags: 0,
+    };
+    let map_id = maps::create(&def).unwrap();
+
+    assert!(matches!(maps::freeze(map_id), Err(Error::PermissionDenied)));
+    assert!(!maps::is_frozen(map_id));
+    maps::update_elem(map_id, &0u32.to_le_bytes(), &1u64.to_le_bytes(), 0).unwrap();
+
+    maps::destroy(map_id).unwrap();
+}
+
 // =============================================================================
 // Map Destroy Tests
 // =============================================================================
@@ -236,6 +732,7 @@ fn test_destroy_map() {
         key_size: 4, // Array maps require 4-byte (u32) keys
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -260,6 +757,7 @@ fn test_invalid_key_size() {
         key_size: 4, // Array maps require 4-byte (u32) keys
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
@@ -277,6 +775,7 @@ fn test_invalid_value_size() {
         key_size: 4, // Array maps require 4-byte (u32) keys
         value_size: 8,
         max_entries: 16,
+        map_flags: 0,
     };
     let map_id = maps::create(&def).unwrap();
 
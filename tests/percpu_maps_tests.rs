@@ -0,0 +1,111 @@
+//! Integration tests for per-CPU maps.
+//!
+//! The CPU count and the mock CPU ID are global, so the tests in this file
+//! run one at a time.
+
+use std::sync::Mutex;
+
+use axebpf::helpers::{self, id};
+use axebpf::maps::{self, Error, MapDef, MapType};
+use axebpf::platform;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const CPUS: u32 = 4;
+
+fn create_percpu(map_type: MapType) -> u32 {
+    platform::set_num_cpus(CPUS);
+    let def = MapDef {
+        map_type,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 8,
+        map_flags: 0,
+    };
+    maps::create(&def).unwrap()
+}
+
+/// Add `delta` to the value of `key` on `cpu`, as a program would.
+fn add_on_cpu(map_id: u32, cpu: u32, key: u32, delta: u64) {
+    platform::set_mock_cpu_id(cpu);
+    let lookup = helpers::get_helper(id::MAP_LOOKUP_ELEM).unwrap();
+    let update = helpers::get_helper(id::MAP_UPDATE_ELEM).unwrap();
+
+    let key_ptr = &key as *const u32 as u64;
+    let ptr = lookup(map_id as u64, key_ptr, 0, 0, 0);
+    let old = if ptr == 0 {
+        0
+    } else {
+        unsafe { *(ptr as *const u64) }
+    };
+    let value = old + delta;
+    let ret = update(map_id as u64, key_ptr, &value as *const u64 as u64, 0, 0);
+    assert_eq!(ret, 0);
+}
+
+fn per_cpu_values(bytes: &[u8]) -> Vec<u64> {
+    bytes
+        .chunks(8)
+        .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+        .collect()
+}
+
+#[test]
+fn test_percpu_array_values_per_cpu() {
+    let _serial = SERIAL.lock().unwrap();
+    let map_id = create_percpu(MapType::PerCpuArray);
+
+    add_on_cpu(map_id, 0, 1, 5);
+    add_on_cpu(map_id, 2, 1, 7);
+    add_on_cpu(map_id, 2, 1, 1);
+
+    let values = maps::lookup_elem(map_id, &1u32.to_le_bytes()).unwrap();
+    assert_eq!(per_cpu_values(&values), [5, 0, 8, 0]);
+
+    platform::set_mock_cpu_id(0);
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_percpu_hash_user_update_and_delete() {
+    let _serial = SERIAL.lock().unwrap();
+    let map_id = create_percpu(MapType::PerCpuHash);
+    let key = 9u32.to_le_bytes();
+
+    // User-side updates take one 8-byte-aligned value per CPU
+    assert!(matches!(
+        maps::update_elem(map_id, &key, &[0u8; 8], 0),
+        Err(Error::InvalidArgument)
+    ));
+    let values: Vec<u8> = (1..=CPUS as u64).flat_map(u64::to_le_bytes).collect();
+    maps::update_elem(map_id, &key, &values, 0).unwrap();
+
+    // Programs only see their own CPU's value
+    add_on_cpu(map_id, 3, 9, 10);
+    let values = maps::lookup_elem(map_id, &key).unwrap();
+    assert_eq!(per_cpu_values(&values), [1, 2, 3, 14]);
+    let entries = maps::iter_entries(map_id);
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].0, key);
+
+    maps::delete_elem(map_id, &key).unwrap();
+    assert!(maps::lookup_elem(map_id, &key).is_none());
+
+    platform::set_mock_cpu_id(0);
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_percpu_hash_key_on_one_cpu() {
+    let _serial = SERIAL.lock().unwrap();
+    let map_id = create_percpu(MapType::PerCpuHash);
+
+    add_on_cpu(map_id, 1, 3, 42);
+
+    let values = maps::lookup_elem(map_id, &3u32.to_le_bytes()).unwrap();
+    assert_eq!(per_cpu_values(&values), [0, 42, 0, 0]);
+    assert!(maps::lookup_elem(map_id, &4u32.to_le_bytes()).is_none());
+
+    platform::set_mock_cpu_id(0);
+    maps::destroy(map_id).unwrap();
+}
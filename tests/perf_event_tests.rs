@@ -0,0 +1,112 @@
+//! Integration tests for perf event array maps and bpf_perf_event_output.
+//!
+//! The CPU count and the mock CPU ID are global, so the tests in this file
+//! run one at a time.
+
+use std::sync::Mutex;
+
+use axebpf::helpers::{self, id};
+use axebpf::map_ops;
+use axebpf::maps::{self, MapDef, MapType};
+use axebpf::perf_event::{BPF_F_CURRENT_CPU, PerfRecord};
+use axebpf::platform;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const CPUS: u32 = 4;
+
+fn perf_map(max_entries: u32) -> Result<u32, maps::Error> {
+    platform::set_num_cpus(CPUS);
+    maps::create(&MapDef {
+        map_type: MapType::PerfEventArray,
+        key_size: 4,
+        value_size: 4,
+        max_entries,
+        map_flags: 0,
+    })
+}
+
+/// Call bpf_perf_event_output as a program would.
+fn output(map_id: u32, flags: u64, data: &[u8]) -> i64 {
+    let output = helpers::get_helper(id::PERF_EVENT_OUTPUT).unwrap();
+    let ctx = [0u8; 16];
+    output(
+        ctx.as_ptr() as u64,
+        map_id as u64,
+        flags,
+        data.as_ptr() as u64,
+        data.len() as u64,
+    ) as i64
+}
+
+#[test]
+fn test_create_perf_event_array_one_slot_per_cpu() {
+    let _serial = SERIAL.lock().unwrap();
+    let map_id = perf_map(0).unwrap();
+    assert_eq!(map_ops::get_map_def(map_id).unwrap().max_entries, CPUS);
+
+    // Slots hold rings, not values
+    let key = 0u32.to_le_bytes();
+    assert!(maps::lookup_elem(map_id, &key).is_none());
+    assert!(maps::update_elem(map_id, &key, &key, 0).is_err());
+    assert!(maps::perf_event_read(map_id, CPUS, 0).is_err());
+    maps::destroy(map_id).unwrap();
+
+    assert!(perf_map(CPUS + 1).is_err());
+}
+
+#[test]
+fn test_perf_event_output_sample_layout() {
+    let _serial = SERIAL.lock().unwrap();
+    let map_id = perf_map(0).unwrap();
+    platform::set_mock_cpu_id(2);
+
+    assert_eq!(output(map_id, BPF_F_CURRENT_CPU, b"hello"), 0);
+    assert_eq!(output(map_id, 2, b"abcd"), 0);
+    // Other CPUs' rings and missing slots
+    assert_eq!(output(map_id, 1, b"x"), -95);
+    assert_eq!(output(map_id, 9, b"x"), -7);
+
+    // Header, u32 raw size, raw data padded to keep records aligned
+    assert_eq!(maps::perf_event_positions(map_id, 2), Some((24 + 16, 0)));
+    let records = maps::perf_event_read(map_id, 2, 0).unwrap();
+    assert_eq!(
+        records,
+        [
+            PerfRecord::Sample(b"hello\0\0\0\0\0\0\0".to_vec()),
+            PerfRecord::Sample(b"abcd".to_vec()),
+        ]
+    );
+    assert_eq!(maps::perf_event_positions(map_id, 2), Some((40, 40)));
+    assert!(maps::perf_event_read(map_id, 1, 0).unwrap().is_empty());
+
+    platform::set_mock_cpu_id(0);
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_perf_event_output_reports_lost_samples() {
+    let _serial = SERIAL.lock().unwrap();
+    let map_id = perf_map(1).unwrap();
+    platform::set_mock_cpu_id(0);
+    let sample = [3u8; 4004];
+
+    // Each sample takes 4016 bytes of the 32 KiB ring
+    for _ in 0..8 {
+        assert_eq!(output(map_id, BPF_F_CURRENT_CPU, &sample), 0);
+    }
+    assert_eq!(output(map_id, BPF_F_CURRENT_CPU, &sample), -28);
+    assert_eq!(output(map_id, BPF_F_CURRENT_CPU, &sample), -28);
+
+    // Freeing one sample makes room for the lost record and a sample, which
+    // wrap around the end of the ring
+    assert_eq!(maps::perf_event_read(map_id, 0, 1).unwrap().len(), 1);
+    assert_eq!(output(map_id, BPF_F_CURRENT_CPU, &sample), 0);
+
+    let records = maps::perf_event_read(map_id, 0, 0).unwrap();
+    assert_eq!(records.len(), 9);
+    assert_eq!(records[7], PerfRecord::Lost(2));
+    assert_eq!(records[8], PerfRecord::Sample(sample.to_vec()));
+
+    maps::destroy(map_id).unwrap();
+}
@@ -2,7 +2,9 @@
 //!
 //! Tests program loading, execution, and helper integration.
 
-use axebpf::runtime::{self, EbpfProgram};
+use axebpf::TraceContext;
+use axebpf::maps::{self, MapDef, MapType};
+use axebpf::runtime::{self, EbpfProgram, Error, ExecMode, ObjectLoader};
 
 /// Simple program: mov r0, 42; exit
 /// Returns constant 42.
@@ -77,6 +79,39 @@ fn test_execute_with_context() {
     assert!(result.is_ok());
 }
 
+#[test]
+fn test_program_interpreter_mode() {
+    let program = EbpfProgram::new_with_mode(PROG_RETURN_42, None, ExecMode::Interpreter).unwrap();
+    assert_eq!(program.exec_mode(), ExecMode::Interpreter);
+    assert_eq!(program.execute().unwrap(), 42);
+}
+
+#[test]
+fn test_program_jit_mode_falls_back() {
+    // JIT may be unavailable on the host; the result must match either way.
+    let program = EbpfProgram::new_with_mode(PROG_RETURN_42, None, ExecMode::Jit).unwrap();
+    assert_eq!(program.execute().unwrap(), 42);
+    let mut ctx = [0u8; 16];
+    assert_eq!(program.execute_with_context(&mut ctx).unwrap(), 42);
+}
+
+#[test]
+fn test_program_jit_mode_checks_short_context() {
+    // ldxdw r0, [r1 + 1000]; exit -- past the end of a 272-byte PtRegs
+    let code = [
+        [0x79, 0x10, 0xe8, 0x03, 0, 0, 0, 0],
+        [0x95, 0x00, 0, 0, 0, 0, 0, 0],
+    ]
+    .concat();
+    let program = EbpfProgram::new_with_mode(&code, None, ExecMode::Jit).unwrap();
+    let mut short = [0u8; 272];
+    assert!(program.execute_with_context(&mut short).is_err());
+
+    let mut ctx = [0u8; 1008];
+    ctx[1000..].copy_from_slice(&7u64.to_le_bytes());
+    assert_eq!(program.execute_with_context(&mut ctx).unwrap(), 7);
+}
+
 // =============================================================================
 // Program Registry Tests
 // =============================================================================
@@ -101,6 +136,26 @@ fn test_run_program() {
     assert_eq!(result, 42);
 }
 
+#[test]
+fn test_run_program_reuses_prepared_vm() {
+    let prog_id = runtime::load_program(PROG_RETURN_42, None).unwrap();
+    let mut ctx = [0u8; 16];
+    for _ in 0..100 {
+        assert_eq!(runtime::run_program(prog_id, None).unwrap(), 42);
+        assert_eq!(runtime::run_program(prog_id, Some(&mut ctx)).unwrap(), 42);
+    }
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_cloned_program_executes() {
+    let program = EbpfProgram::new(PROG_RETURN_42, None).unwrap();
+    let clone = program.clone();
+    drop(program);
+    assert_eq!(clone.execute().unwrap(), 42);
+    assert_eq!(clone.bytecode(), PROG_RETURN_42);
+}
+
 #[test]
 fn test_unload_program() {
     let prog_id = runtime::load_program(PROG_RETURN_42, None).unwrap();
@@ -116,6 +171,187 @@ fn test_run_unloaded_program() {
     assert!(result.is_err());
 }
 
+#[test]
+fn test_program_stats_count_runs() {
+    let prog_id = runtime::load_program(PROG_RETURN_42, None).unwrap();
+    for _ in 0..5 {
+        runtime::run_program(prog_id, None).unwrap();
+    }
+
+    let stats = runtime::get_program(prog_id).unwrap().stats();
+    assert_eq!(stats.run_cnt, 5);
+    assert_eq!(stats.errors, 0);
+    assert!(stats.last_error.is_none());
+    assert!(stats.max_run_time_ns <= stats.run_time_ns);
+
+    runtime::reset_program_stats(prog_id).unwrap();
+    assert_eq!(runtime::get_program(prog_id).unwrap().stats().run_cnt, 0);
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_list_programs_reports_errors() {
+    // ldxdw r0, [r1 + 512]; exit -- reads past a 16-byte context
+    let code = [
+        [0x79, 0x10, 0x00, 0x02, 0, 0, 0, 0],
+        [0x95, 0x00, 0, 0, 0, 0, 0, 0],
+    ]
+    .concat();
+    let prog_id = runtime::load_program(&code, None).unwrap();
+    let mut ctx = [0u8; 16];
+    assert!(runtime::run_program(prog_id, Some(&mut ctx)).is_err());
+
+    let info = runtime::list_programs()
+        .into_iter()
+        .find(|p| p.id == prog_id)
+        .unwrap();
+    assert_eq!(info.stats.run_cnt, 1);
+    assert_eq!(info.stats.errors, 1);
+    assert!(matches!(
+        info.stats.last_error,
+        Some(Error::ExecutionFailed)
+    ));
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_identical_bytecode_same_tag() {
+    let a = EbpfProgram::new(PROG_RETURN_42, None).unwrap();
+    let b = EbpfProgram::new(PROG_RETURN_42, None).unwrap();
+    let c = EbpfProgram::new(PROG_RETURN_ZERO, None).unwrap();
+    assert_eq!(a.tag(), b.tag());
+    assert_ne!(a.tag(), c.tag());
+}
+
+#[test]
+fn test_find_program_by_name() {
+    let elf_bytes = include_bytes!("../../../target/bpf/kprobe_noop.o");
+    let prog_id = runtime::load_program(elf_bytes, None).unwrap();
+    let name = String::from(runtime::get_program(prog_id).unwrap().name());
+
+    let found = runtime::find_program_by_name(&name).unwrap();
+    assert_eq!(runtime::get_program(found).unwrap().name(), name);
+    assert!(runtime::find_program_by_name("no_such_program").is_none());
+
+    let info = runtime::list_programs()
+        .into_iter()
+        .find(|p| p.id == prog_id)
+        .unwrap();
+    assert_eq!(info.name, name);
+    assert!(info.section.starts_with("kprobe/"));
+    assert!(info.attachments.is_empty());
+    runtime::unload_program(prog_id).unwrap();
+}
+
+// =============================================================================
+// Test Run
+// =============================================================================
+
+#[test]
+fn test_test_run_reads_context() {
+    // r0 = ctx->arg0; exit
+    let code = [
+        [0x79, 0x10, 24, 0, 0, 0, 0, 0],
+        [0x95, 0x00, 0, 0, 0, 0, 0, 0],
+    ]
+    .concat();
+    let prog_id = runtime::load_program(&code, None).unwrap();
+    let ctx = TraceContext::new(1).with_args(0xabcd, 0, 0, 0);
+
+    let out = runtime::test_run(prog_id, ctx.as_bytes(), 10).unwrap();
+    assert_eq!(out.retval, 0xabcd);
+    assert_eq!(out.ctx_out, ctx.as_bytes());
+    // Dry runs are not counted
+    assert_eq!(runtime::get_program(prog_id).unwrap().stats().run_cnt, 0);
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_test_run_reports_errors() {
+    assert!(matches!(
+        runtime::test_run(99999, &[], 1),
+        Err(Error::NotFound)
+    ));
+
+    // Reads past a 16-byte context
+    let code = [
+        [0x79, 0x10, 0x00, 0x02, 0, 0, 0, 0],
+        [0x95, 0x00, 0, 0, 0, 0, 0, 0],
+    ]
+    .concat();
+    let prog_id = runtime::load_program(&code, None).unwrap();
+    let result = runtime::test_run(prog_id, &[0u8; 16], 0);
+    assert!(matches!(result, Err(Error::ExecutionFailed)));
+    assert_eq!(runtime::get_program(prog_id).unwrap().stats().errors, 0);
+    runtime::unload_program(prog_id).unwrap();
+}
+
+// =============================================================================
+// bpf_loop
+// =============================================================================
+
+/// Sums loop indices through `bpf_loop`:
+///
+/// ```text
+///     *(u64 *)(r10 -8) = 0
+///     r1 = nr_loops; r2 = add_index; r3 = r10; r3 += -8; r4 = 0
+///     call bpf_loop
+///     r0 = *(u64 *)(r10 -8)    ; or r0 += 0 if return_count
+///     exit
+/// add_index:
+///     r3 = *(u64 *)(r2 +0); r3 += r1; *(u64 *)(r2 +0) = r3
+///     r0 = 0; if r1 != stop_at goto +1; r0 = 1
+///     exit
+/// ```
+fn sum_loop(nr_loops: i32, stop_at: i32, return_count: bool) -> Vec<u8> {
+    let [a, b, c, d] = nr_loops.to_le_bytes();
+    let [e, f, g, h] = stop_at.to_le_bytes();
+    let result = match return_count {
+        true => [0x07, 0x00, 0, 0, 0, 0, 0, 0],
+        false => [0x79, 0xa0, 0xf8, 0xff, 0, 0, 0, 0],
+    };
+    [
+        [0x7a, 0x0a, 0xf8, 0xff, 0, 0, 0, 0],
+        [0xb7, 0x01, 0, 0, a, b, c, d],
+        [0x18, 0x42, 0, 0, 7, 0, 0, 0],
+        [0x00, 0x00, 0, 0, 0, 0, 0, 0],
+        [0xbf, 0xa3, 0, 0, 0, 0, 0, 0],
+        [0x07, 0x03, 0, 0, 0xf8, 0xff, 0xff, 0xff],
+        [0xb7, 0x04, 0, 0, 0, 0, 0, 0],
+        [0x85, 0x00, 0, 0, 181, 0, 0, 0],
+        result,
+        [0x95, 0x00, 0, 0, 0, 0, 0, 0],
+        [0x79, 0x23, 0, 0, 0, 0, 0, 0],
+        [0x0f, 0x13, 0, 0, 0, 0, 0, 0],
+        [0x7b, 0x32, 0, 0, 0, 0, 0, 0],
+        [0xb7, 0x00, 0, 0, 0, 0, 0, 0],
+        [0x55, 0x01, 0x01, 0x00, e, f, g, h],
+        [0xb7, 0x00, 0, 0, 1, 0, 0, 0],
+        [0x95, 0x00, 0, 0, 0, 0, 0, 0],
+    ]
+    .concat()
+}
+
+#[test]
+fn test_bpf_loop_runs_callback() {
+    let prog_id = runtime::load_program(&sum_loop(5, -1, false), None).unwrap();
+    assert_eq!(runtime::run_program(prog_id, None).unwrap(), 1 + 2 + 3 + 4);
+    runtime::unload_program(prog_id).unwrap();
+
+    // Returning 1 from the callback stops the loop early
+    let prog_id = runtime::load_program(&sum_loop(5, 2, true), None).unwrap();
+    assert_eq!(runtime::run_program(prog_id, None).unwrap(), 3);
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_bpf_loop_caps_iterations() {
+    let prog_id = runtime::load_program(&sum_loop(1 << 24, -1, true), None).unwrap();
+    let result = runtime::run_program(prog_id, None).unwrap();
+    assert_eq!(result as i64, -7); // -E2BIG
+    runtime::unload_program(prog_id).unwrap();
+}
+
 // =============================================================================
 // ELF Loading Tests (Issue #4 verification)
 // =============================================================================
@@ -164,3 +400,371 @@ fn test_load_program_elf() {
     let prog_id = runtime::load_program(elf_bytes, None);
     assert!(prog_id.is_ok(), "load_program with ELF should work: {:?}", prog_id.err());
 }
+
+/// Load every program of kprobe_simple.o; all must share one set of maps.
+#[test]
+fn test_load_object_shares_maps() {
+    let elf_bytes = include_bytes!("../../../target/bpf/kprobe_simple.o");
+    let loaded = runtime::load_object(elf_bytes);
+    assert!(
+        loaded.is_ok(),
+        "load_object should work: {:?}",
+        loaded.err()
+    );
+    let loaded = loaded.unwrap();
+    assert!(!loaded.is_empty());
+
+    let first_maps = runtime::get_program_map_fds(loaded[0].1).unwrap();
+    assert!(!first_maps.is_empty());
+    for (name, prog_id) in &loaded {
+        let program = runtime::get_program(*prog_id).unwrap();
+        assert_eq!(program.name(), name);
+        assert_eq!(program.map_fds(), first_maps.as_slice());
+    }
+
+    // Maps stay alive until the last program of the object is unloaded
+    let (_, fd) = first_maps[0];
+    for (_, prog_id) in &loaded {
+        assert!(axebpf::map_ops::get_map_sizes(fd).is_some());
+        runtime::unload_program(*prog_id).unwrap();
+    }
+}
+
+/// A second load of the object can take over the maps of the first.
+#[test]
+fn test_object_loader_reuses_maps() {
+    let elf_bytes = include_bytes!("../../../target/bpf/kprobe_simple.o");
+    let old = runtime::load_object(elf_bytes).unwrap();
+    let old_maps = runtime::get_program_map_fds(old[0].1).unwrap();
+
+    let new = ObjectLoader::new(elf_bytes)
+        .reuse_maps(old[0].1)
+        .load()
+        .unwrap();
+    assert_eq!(runtime::get_program_map_fds(new[0].1).unwrap(), old_maps);
+
+    // The maps outlive the programs they were created for
+    for (_, prog_id) in &old {
+        runtime::unload_program(*prog_id).unwrap();
+    }
+    let (_, fd) = old_maps[0];
+    assert!(axebpf::map_ops::get_map_sizes(fd).is_some());
+    for (_, prog_id) in &new {
+        runtime::unload_program(*prog_id).unwrap();
+    }
+}
+
+#[test]
+fn test_load_object_rejects_raw_bytecode() {
+    assert!(runtime::load_object(PROG_RETURN_42).is_err());
+}
+
+/// Unknown globals fail the load instead of being silently ignored.
+#[test]
+fn test_object_loader_rejects_unknown_global() {
+    let elf_bytes = include_bytes!("../../../target/bpf/kprobe_simple.o");
+    let before = runtime::program_count();
+    let result = ObjectLoader::new(elf_bytes)
+        .set_global("NO_SUCH_GLOBAL", &3u32.to_le_bytes())
+        .load();
+    assert!(matches!(result, Err(Error::InvalidGlobal(ref name)) if name == "NO_SUCH_GLOBAL"));
+    assert_eq!(runtime::program_count(), before);
+}
+
+// =============================================================================
+// Global Variable Tests
+// =============================================================================
+
+/// Program writing and reading a global through `lddw r1, map_value(fd, 0)`.
+fn global_counter_prog(fd: u32) -> Vec<u8> {
+    let fd = fd.to_le_bytes();
+    let mut code = vec![
+        0x18, 0x21, 0x00, 0x00, fd[0], fd[1], fd[2], fd[3], // lddw r1, map_value(fd, 0)
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    code.extend_from_slice(&[
+        0x79, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // r0 = *(u64 *)(r1 + 0)
+        0x07, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // r0 += 1
+        0x7b, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // *(u64 *)(r1 + 0) = r0
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ]);
+    code
+}
+
+#[test]
+fn test_global_variable_updates_map_in_place() {
+    let def = MapDef {
+        map_type: MapType::Array,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 1,
+        map_flags: 0,
+    };
+    let fd = maps::create(&def).unwrap();
+    maps::update_elem(fd, &0u32.to_le_bytes(), &41u64.to_le_bytes(), 0).unwrap();
+
+    let prog_id = runtime::load_program(&global_counter_prog(fd), None).unwrap();
+    assert_eq!(runtime::run_program(prog_id, None).unwrap(), 42);
+    assert_eq!(runtime::run_program(prog_id, None).unwrap(), 43);
+
+    let value = maps::lookup_elem(fd, &0u32.to_le_bytes()).unwrap();
+    assert_eq!(value, 43u64.to_le_bytes());
+
+    // The loaded bytecode keeps the map reference
+    let program = runtime::get_program(prog_id).unwrap();
+    assert_eq!(program.bytecode(), global_counter_prog(fd).as_slice());
+
+    runtime::unload_program(prog_id).unwrap();
+    maps::destroy(fd).unwrap();
+}
+
+// =============================================================================
+// Map Value Pointer Tests
+// =============================================================================
+
+/// Look up u32 key 7 in `map` and increment the u64 value in place. Returns
+/// the new value, or 0 if the key is missing.
+fn map_counter_prog(map: u32) -> Vec<u8> {
+    let fd = map.to_le_bytes();
+    let mut code = vec![
+        0x62, 0x0a, 0xfc, 0xff, 0x07, 0x00, 0x00, 0x00, // *(u32 *)(r10 - 4) = 7
+        0xbf, 0xa2, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r2, r10
+        0x07, 0x02, 0x00, 0x00, 0xfc, 0xff, 0xff, 0xff, // r2 += -4
+        0x18, 0x11, 0x00, 0x00, fd[0], fd[1], fd[2], fd[3], // lddw r1, map_fd
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+    code.extend_from_slice(&[
+        0x85, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // call bpf_map_lookup_elem
+        0x15, 0x00, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00, // if r0 == 0 goto +4
+        0x79, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // r1 = *(u64 *)(r0 + 0)
+        0x07, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // r1 += 1
+        0x7b, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // *(u64 *)(r0 + 0) = r1
+        0xbf, 0x10, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r0, r1
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ]);
+    code
+}
+
+#[test]
+fn test_map_lookup_pointer_updates_value_in_place() {
+    for map_type in [MapType::HashMap, MapType::LruHash, MapType::Array] {
+        let fd = maps::create(&MapDef {
+            map_type,
+            key_size: 4,
+            value_size: 8,
+            max_entries: 8,
+            map_flags: 0,
+        })
+        .unwrap();
+        let key = 7u32.to_le_bytes();
+        let prog_id = runtime::load_program(&map_counter_prog(fd), None).unwrap();
+        if map_type != MapType::Array {
+            // Missing keys give a null pointer
+            assert_eq!(runtime::run_program(prog_id, None).unwrap(), 0);
+        }
+
+        maps::update_elem(fd, &key, &41u64.to_le_bytes(), 0).unwrap();
+        assert_eq!(runtime::run_program(prog_id, None).unwrap(), 42);
+        assert_eq!(runtime::run_program(prog_id, None).unwrap(), 43);
+        assert_eq!(maps::lookup_elem(fd, &key).unwrap(), 43u64.to_le_bytes());
+
+        // Keys added after the program was loaded live in the same storage
+        let _ = maps::delete_elem(fd, &key);
+        maps::update_elem(fd, &key, &99u64.to_le_bytes(), 0).unwrap();
+        assert_eq!(runtime::run_program(prog_id, None).unwrap(), 100);
+
+        runtime::unload_program(prog_id).unwrap();
+        maps::destroy(fd).unwrap();
+    }
+}
+
+#[test]
+fn test_destroy_refuses_maps_of_loaded_programs() {
+    let def = MapDef {
+        map_type: MapType::Array,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 8,
+        map_flags: 0,
+    };
+    let global = maps::create(&def).unwrap();
+    let values = maps::create(&def).unwrap();
+    let global_user = runtime::load_program(&global_counter_prog(global), None).unwrap();
+    let values_user = EbpfProgram::new(&map_counter_prog(values), None).unwrap();
+
+    // Raw bytecode has no ELF maps, but the programs still hold theirs
+    assert!(matches!(maps::destroy(global), Err(maps::Error::Busy)));
+    assert!(matches!(maps::destroy(values), Err(maps::Error::Busy)));
+    assert_eq!(runtime::run_program(global_user, None).unwrap(), 1);
+    assert_eq!(values_user.execute().unwrap(), 1);
+
+    runtime::unload_program(global_user).unwrap();
+    maps::destroy(global).unwrap();
+    drop(values_user);
+    maps::destroy(values).unwrap();
+}
+
+// =============================================================================
+// Ring Buffer Tests
+// =============================================================================
+
+/// Reserve an 8-byte record, store 42 in it and submit it. Returns 1 once
+/// submitted, 0 if the ring is full. With `submit` unset the record is left
+/// reserved.
+fn ringbuf_prog(ringbuf: u32, submit: bool) -> Vec<u8> {
+    let fd = ringbuf.to_le_bytes();
+    let mut code = vec![
+        0x18, 0x11, 0x00, 0x00, fd[0], fd[1], fd[2], fd[3], // lddw r1, map_fd
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xb7, 0x02, 0x00, 0x00, 0x08, 0x00, 0x00,
+        0x00, // mov r2, 8
+        0xb7, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r3, 0
+        0x85, 0x00, 0x00, 0x00, 0x83, 0x00, 0x00, 0x00, // call bpf_ringbuf_reserve
+        0x15, 0x00, 0x06, 0x00, 0x00, 0x00, 0x00, 0x00, // if r0 == 0 goto +6
+        0x7a, 0x00, 0x00, 0x00, 0x2a, 0x00, 0x00, 0x00, // *(u64 *)(r0 + 0) = 42
+    ];
+    if submit {
+        code.extend_from_slice(&[
+            0xbf, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r1, r0
+            0xb7, 0x02, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r2, 0
+            0x85, 0x00, 0x00, 0x00, 0x84, 0x00, 0x00, 0x00, // call bpf_ringbuf_submit
+        ]);
+    } else {
+        code.extend_from_slice(&[[0xb7, 0x01, 0, 0, 0, 0, 0, 0]; 3].concat()); // mov r1, 0
+    }
+    code.extend_from_slice(&[
+        0xb7, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // mov r0, 1
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+        0xb7, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // mov r0, 0
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ]);
+    code
+}
+
+#[test]
+fn test_ringbuf_records_from_program() {
+    let ringbuf = maps::create(&MapDef {
+        map_type: MapType::RingBuf,
+        key_size: 0,
+        value_size: 0,
+        max_entries: 4096,
+        map_flags: 0,
+    })
+    .unwrap();
+
+    let submitter = runtime::load_program(&ringbuf_prog(ringbuf, true), None).unwrap();
+    let leaker = runtime::load_program(&ringbuf_prog(ringbuf, false), None).unwrap();
+    assert_eq!(runtime::run_program(submitter, None).unwrap(), 1);
+    // A record left reserved is discarded when the program returns
+    assert_eq!(runtime::run_program(leaker, None).unwrap(), 1);
+    assert_eq!(runtime::run_program(submitter, None).unwrap(), 1);
+
+    let records = maps::ringbuf_consume(ringbuf, 0);
+    assert_eq!(records, [42u64.to_le_bytes(), 42u64.to_le_bytes()]);
+
+    runtime::unload_program(submitter).unwrap();
+    runtime::unload_program(leaker).unwrap();
+    maps::destroy(ringbuf).unwrap();
+}
+
+// =============================================================================
+// Tail Call Tests
+// =============================================================================
+
+fn create_prog_array() -> u32 {
+    let def = MapDef {
+        map_type: MapType::ProgArray,
+        key_size: 4,
+        value_size: 4,
+        max_entries: 4,
+        map_flags: 0,
+    };
+    maps::create(&def).unwrap()
+}
+
+/// Tail calls go to the innermost invocation of the CPU, and the mock
+/// platform runs every test thread as CPU 0.
+static TAIL_CALLS: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// `bpf_tail_call(ctx, prog_array, 0)`, then `return 7` if it fails.
+/// When `counter` is set, first increments the u64 global at map_value(counter, 0).
+fn tail_call_prog(prog_array: u32, counter: Option<u32>) -> Vec<u8> {
+    let mut code = Vec::new();
+    if let Some(fd) = counter {
+        let fd = fd.to_le_bytes();
+        code.extend_from_slice(&[
+            0x18, 0x26, 0x00, 0x00, fd[0], fd[1], fd[2], fd[3], // lddw r6, map_value(fd, 0)
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x79, 0x67, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, // r7 = *(u64 *)(r6 + 0)
+            0x07, 0x07, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, // r7 += 1
+            0x7b, 0x76, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // *(u64 *)(r6 + 0) = r7
+        ]);
+    }
+    let fd = prog_array.to_le_bytes();
+    code.extend_from_slice(&[
+        0x18, 0x12, 0x00, 0x00, fd[0], fd[1], fd[2], fd[3], // lddw r2, map_fd
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xb7, 0x03, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, // mov r3, 0
+        0x85, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00, // call bpf_tail_call
+        0xb7, 0x00, 0x00, 0x00, 0x07, 0x00, 0x00, 0x00, // mov r0, 7
+        0x95, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // exit
+    ]);
+    code
+}
+
+#[test]
+fn test_tail_call_jumps_to_target() {
+    let _serial = TAIL_CALLS.lock().unwrap();
+    let prog_array = create_prog_array();
+    let target = runtime::load_program(PROG_RETURN_42, None).unwrap();
+    let dispatcher = runtime::load_program(&tail_call_prog(prog_array, None), None).unwrap();
+    let mut ctx = [0u8; 16];
+
+    // Empty slot: the dispatcher falls through
+    assert_eq!(runtime::run_program(dispatcher, Some(&mut ctx)).unwrap(), 7);
+
+    maps::update_elem(prog_array, &0u32.to_le_bytes(), &target.to_le_bytes(), 0).unwrap();
+    assert_eq!(
+        runtime::run_program(dispatcher, Some(&mut ctx)).unwrap(),
+        42
+    );
+
+    // Swapping the slot changes the handler without reloading the dispatcher
+    let other = runtime::load_program(PROG_RETURN_ZERO, None).unwrap();
+    maps::update_elem(prog_array, &0u32.to_le_bytes(), &other.to_le_bytes(), 0).unwrap();
+    assert_eq!(runtime::run_program(dispatcher, Some(&mut ctx)).unwrap(), 0);
+
+    for id in [dispatcher, target, other] {
+        runtime::unload_program(id).unwrap();
+    }
+    maps::destroy(prog_array).unwrap();
+}
+
+#[test]
+fn test_tail_call_depth_limit() {
+    let _serial = TAIL_CALLS.lock().unwrap();
+    let prog_array = create_prog_array();
+    let counter = maps::create(&MapDef {
+        map_type: MapType::Array,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 1,
+        map_flags: 0,
+    })
+    .unwrap();
+
+    // A program that tail-calls itself
+    let prog_id = runtime::load_program(&tail_call_prog(prog_array, Some(counter)), None).unwrap();
+    maps::update_elem(prog_array, &0u32.to_le_bytes(), &prog_id.to_le_bytes(), 0).unwrap();
+
+    let mut ctx = [0u8; 16];
+    assert_eq!(runtime::run_program(prog_id, Some(&mut ctx)).unwrap(), 7);
+
+    // The initial run plus MAX_TAIL_CALL_CNT tail calls
+    let runs = maps::lookup_elem(counter, &0u32.to_le_bytes()).unwrap();
+    let expected = runtime::MAX_TAIL_CALL_CNT as u64 + 1;
+    assert_eq!(runs, expected.to_le_bytes());
+
+    runtime::unload_program(prog_id).unwrap();
+    maps::destroy(prog_array).unwrap();
+    maps::destroy(counter).unwrap();
+}
@@ -0,0 +1,81 @@
+//! Integration tests for section name parsing.
+//!
+//! Tests program type and attach target inference from ELF section names.
+
+use axebpf::section::{AttachTarget, ProgramType, SectionSpec};
+
+fn symbol(s: &str) -> Option<AttachTarget> {
+    Some(AttachTarget::Symbol(s.into()))
+}
+
+#[test]
+fn test_parse_kprobe_sections() {
+    let spec = SectionSpec::parse("kprobe/vcpu_run");
+    assert_eq!(spec.prog_type, ProgramType::Kprobe);
+    assert_eq!(spec.target, symbol("vcpu_run"));
+
+    let spec = SectionSpec::parse("kretprobe/vcpu_run");
+    assert_eq!(spec.prog_type, ProgramType::Kretprobe);
+    assert!(spec.prog_type.is_return_probe());
+    assert_eq!(spec.target, symbol("vcpu_run"));
+}
+
+#[test]
+fn test_parse_hprobe_sections() {
+    let spec = SectionSpec::parse("hprobe/handle_exit");
+    assert_eq!(spec.prog_type, ProgramType::Hprobe);
+    assert_eq!(spec.target, symbol("handle_exit"));
+
+    let spec = SectionSpec::parse("hretprobe/handle_exit");
+    assert_eq!(spec.prog_type, ProgramType::Hretprobe);
+    assert_eq!(spec.target, symbol("handle_exit"));
+}
+
+#[test]
+fn test_parse_tracepoint_section() {
+    let spec = SectionSpec::parse("tracepoint/vmm/vm_destroy");
+    assert_eq!(spec.prog_type, ProgramType::Tracepoint);
+    assert_eq!(
+        spec.target,
+        Some(AttachTarget::Tracepoint("vmm:vm_destroy".into()))
+    );
+
+    // Missing event name keeps the type but has no target.
+    let spec = SectionSpec::parse("tracepoint/vmm");
+    assert_eq!(spec.prog_type, ProgramType::Tracepoint);
+    assert_eq!(spec.target, None);
+}
+
+#[test]
+fn test_parse_guest_kprobe_sections() {
+    let spec = SectionSpec::parse("gkprobe/3/0xffff000008001000");
+    assert_eq!(spec.prog_type, ProgramType::GuestKprobe);
+    assert_eq!(
+        spec.target,
+        Some(AttachTarget::Guest {
+            vm_id: 3,
+            gva: 0xffff_0000_0800_1000
+        })
+    );
+
+    let spec = SectionSpec::parse("gkretprobe/1/4096");
+    assert_eq!(spec.prog_type, ProgramType::GuestKretprobe);
+    assert_eq!(
+        spec.target,
+        Some(AttachTarget::Guest {
+            vm_id: 1,
+            gva: 4096
+        })
+    );
+
+    let spec = SectionSpec::parse("gkprobe/x/0x1000");
+    assert_eq!(spec.prog_type, ProgramType::GuestKprobe);
+    assert_eq!(spec.target, None);
+}
+
+#[test]
+fn test_parse_unknown_sections() {
+    assert_eq!(SectionSpec::parse(""), SectionSpec::UNKNOWN);
+    assert_eq!(SectionSpec::parse("license"), SectionSpec::UNKNOWN);
+    assert_eq!(SectionSpec::parse("xdp/eth0"), SectionSpec::UNKNOWN);
+}
@@ -0,0 +1,128 @@
+//! Integration tests for stack trace maps and the stack helpers.
+//!
+//! The registered frame is per CPU and all tests run on mock CPU 0, so the
+//! tests that register one run one at a time.
+
+use std::sync::Mutex;
+
+use axebpf::helpers::{self, id};
+use axebpf::maps::{self, MapDef, MapType};
+use axebpf::{output, stack};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+const PC: u64 = 0xffff_0000_4000_1000;
+const RETURNS: [u64; 3] = [
+    0xffff_0000_4000_2004,
+    0xffff_0000_4000_3008,
+    0xffff_0000_4000_400c,
+];
+
+/// Three frame records chained by their frame pointers, as left by
+/// frame-pointer-enabled code. Returns the stack and the innermost record.
+fn fake_stack() -> (Box<[u64; 12]>, u64) {
+    let mut stack = Box::new([0u64; 12]);
+    let base = stack.as_ptr() as u64;
+    for (frame, ret) in RETURNS.iter().enumerate() {
+        let record = frame * 4;
+        let next = if frame + 1 < RETURNS.len() {
+            base + (record as u64 + 4) * 8
+        } else {
+            0
+        };
+        stack[record] = next;
+        stack[record + 1] = *ret;
+    }
+    (stack, base)
+}
+
+fn stack_map(depth: u32) -> u32 {
+    let def = MapDef {
+        map_type: MapType::StackTrace,
+        key_size: 4,
+        value_size: depth * 8,
+        max_entries: 16,
+        map_flags: 0,
+    };
+    maps::create(&def).unwrap()
+}
+
+#[test]
+fn test_create_stack_trace_map_rejects_bad_value_size() {
+    let def = MapDef {
+        map_type: MapType::StackTrace,
+        key_size: 4,
+        value_size: 12,
+        max_entries: 16,
+        map_flags: 0,
+    };
+    assert!(maps::create(&def).is_err());
+
+    // Deeper than the lookup buffer holds
+    let def = MapDef {
+        value_size: stack::MAX_STACK_DEPTH * 8,
+        ..def
+    };
+    assert!(maps::create(&def).is_err());
+    let def = MapDef {
+        value_size: helpers::MAX_VALUE_SIZE as u32,
+        ..def
+    };
+    let map_id = maps::create(&def).unwrap();
+    maps::destroy(map_id).unwrap();
+}
+
+#[test]
+fn test_get_stack_unwinds_frame_records() {
+    let _serial = SERIAL.lock().unwrap();
+    let (_stack, fp) = fake_stack();
+    let ctx = [0u8; 16];
+    let ctx_ptr = ctx.as_ptr() as u64;
+    let get_stack = helpers::get_helper(id::GET_STACK).unwrap();
+
+    stack::set_current_frame(ctx_ptr, PC, fp);
+    let mut buf = [0xffu64; 6];
+    let buf_ptr = buf.as_mut_ptr() as u64;
+    assert_eq!(get_stack(ctx_ptr, buf_ptr, 48, 0, 0), 32);
+    assert_eq!(buf, [PC, RETURNS[0], RETURNS[1], RETURNS[2], 0, 0]);
+
+    // Skip the trapped PC
+    assert_eq!(get_stack(ctx_ptr, buf_ptr, 48, 1, 0), 24);
+    assert_eq!(buf[..3], RETURNS);
+    stack::clear_current_frame();
+
+    // No frame registered for this context
+    assert_eq!(get_stack(ctx_ptr, buf_ptr, 48, 0, 0) as i64, -14);
+    assert_eq!(buf, [0; 6]);
+}
+
+#[test]
+fn test_get_stackid_stores_trace() {
+    let _serial = SERIAL.lock().unwrap();
+    let (_stack, fp) = fake_stack();
+    let map_id = stack_map(8);
+    let ctx = [0u8; 16];
+    let ctx_ptr = ctx.as_ptr() as u64;
+    let get_stackid = helpers::get_helper(id::GET_STACKID).unwrap();
+
+    stack::set_current_frame(ctx_ptr, PC, fp);
+    let first = get_stackid(ctx_ptr, map_id as u64, 0, 0, 0) as i64;
+    let second = get_stackid(ctx_ptr, map_id as u64, 0, 0, 0) as i64;
+    // Guest stacks cannot be unwound
+    let user = get_stackid(ctx_ptr, map_id as u64, stack::BPF_F_USER_STACK, 0, 0) as i64;
+    stack::clear_current_frame();
+
+    assert!((0..16).contains(&first));
+    assert_eq!(first, second);
+    assert_eq!(user, -22);
+
+    let value = maps::lookup_elem(map_id, &(first as u32).to_le_bytes()).unwrap();
+    assert_eq!(value.len(), 64);
+    let frames = output::format_stack_trace(&value);
+    assert_eq!(frames.len(), 4);
+    assert_eq!(frames[0], format!("{:#x}", PC));
+
+    // Stack trace maps are only written by programs
+    assert!(maps::update_elem(map_id, &0u32.to_le_bytes(), &value, 0).is_err());
+    maps::destroy(map_id).unwrap();
+}
@@ -0,0 +1,548 @@
+//! Integration tests for the static eBPF verifier.
+//!
+//! Tests structural checks, register/stack tracking, helper argument
+//! checks and loop termination.
+
+use axebpf::maps::{self, MapDef, MapType};
+use axebpf::runtime::{self, EbpfProgram, Error};
+use axebpf::verifier::{self, ErrorKind, VerifierError};
+
+/// Encode one instruction.
+fn insn(op: u8, dst: u8, src: u8, off: i16, imm: i32) -> [u8; 8] {
+    let mut b = [0u8; 8];
+    b[0] = op;
+    b[1] = (src << 4) | (dst & 0x0f);
+    b[2..4].copy_from_slice(&off.to_le_bytes());
+    b[4..8].copy_from_slice(&imm.to_le_bytes());
+    b
+}
+
+fn prog(insns: &[[u8; 8]]) -> Vec<u8> {
+    insns.concat()
+}
+
+fn mov64_imm(dst: u8, imm: i32) -> [u8; 8] {
+    insn(0xb7, dst, 0, 0, imm)
+}
+
+fn mov64_reg(dst: u8, src: u8) -> [u8; 8] {
+    insn(0xbf, dst, src, 0, 0)
+}
+
+fn add64_imm(dst: u8, imm: i32) -> [u8; 8] {
+    insn(0x07, dst, 0, 0, imm)
+}
+
+fn stdw_imm(dst: u8, off: i16, imm: i32) -> [u8; 8] {
+    insn(0x7a, dst, 0, off, imm)
+}
+
+fn ldxdw(dst: u8, src: u8, off: i16) -> [u8; 8] {
+    insn(0x79, dst, src, off, 0)
+}
+
+fn call(helper: i32) -> [u8; 8] {
+    insn(0x85, 0, 0, 0, helper)
+}
+
+fn exit() -> [u8; 8] {
+    insn(0x95, 0, 0, 0, 0)
+}
+
+/// `lddw dst, map_fd` as emitted after map relocation (two slots).
+fn ld_map_fd(dst: u8, fd: u32) -> [[u8; 8]; 2] {
+    [insn(0x18, dst, 1, 0, fd as i32), insn(0, 0, 0, 0, 0)]
+}
+
+/// `lddw dst, map_value(fd, off)` as emitted for global variables.
+fn ld_map_value(dst: u8, fd: u32, off: i32) -> [[u8; 8]; 2] {
+    [insn(0x18, dst, 2, 0, fd as i32), insn(0, 0, 0, 0, off)]
+}
+
+/// `lddw dst, callback` with the callback `off` instructions after it.
+fn ld_func(dst: u8, off: i32) -> [[u8; 8]; 2] {
+    [insn(0x18, dst, 4, 0, off), insn(0, 0, 0, 0, 0)]
+}
+
+/// `bpf_loop(4, callback, fp - 8, 0)` where the callback stores its index
+/// at the context and returns `ret`.
+fn loop_call(ret: i32) -> Vec<u8> {
+    let [ld0, ld1] = ld_func(2, 6);
+    prog(&[
+        mov64_imm(1, 4),
+        ld0,
+        ld1,
+        mov64_reg(3, 10),
+        add64_imm(3, -8),
+        mov64_imm(4, 0),
+        call(181),
+        exit(),
+        // callback(index, ctx)
+        insn(0x7b, 2, 1, 0, 0),
+        mov64_imm(0, ret),
+        exit(),
+    ])
+}
+
+fn rejected(code: &[u8]) -> VerifierError {
+    verifier::verify(code).expect_err("program should be rejected")
+}
+
+fn create_array_map() -> u32 {
+    let def = MapDef {
+        map_type: MapType::Array,
+        key_size: 4,
+        value_size: 8,
+        max_entries: 4,
+        map_flags: 0,
+    };
+    maps::create(&def).unwrap()
+}
+
+/// map_lookup_elem(fd, &key) with key 0 stored at fp-8, result in r0.
+fn lookup_prelude(fd: u32) -> Vec<[u8; 8]> {
+    let [ld0, ld1] = ld_map_fd(1, fd);
+    vec![
+        stdw_imm(10, -8, 0),
+        mov64_reg(2, 10),
+        add64_imm(2, -8),
+        ld0,
+        ld1,
+        call(1),
+    ]
+}
+
+// =============================================================================
+// Structural Checks
+// =============================================================================
+
+#[test]
+fn test_accepts_return_constant() {
+    let code = prog(&[mov64_imm(0, 42), exit()]);
+    assert!(verifier::verify(&code).is_ok());
+}
+
+#[test]
+fn test_rejects_jump_out_of_range() {
+    let code = prog(&[mov64_imm(0, 0), insn(0x05, 0, 0, 5, 0), exit()]);
+    let err = rejected(&code);
+    assert_eq!(err.insn, 1);
+    assert_eq!(err.kind, ErrorKind::JumpOutOfRange(7));
+}
+
+#[test]
+fn test_rejects_invalid_opcode() {
+    let code = prog(&[insn(0xff, 0, 0, 0, 0), exit()]);
+    assert_eq!(rejected(&code).kind, ErrorKind::InvalidOpcode(0xff));
+}
+
+#[test]
+fn test_rejects_fall_through_end() {
+    let code = prog(&[mov64_imm(0, 0)]);
+    assert_eq!(rejected(&code).kind, ErrorKind::FallThroughEnd);
+}
+
+#[test]
+fn test_rejects_division_by_zero_constant() {
+    let code = prog(&[mov64_imm(0, 1), insn(0x37, 0, 0, 0, 0), exit()]);
+    assert_eq!(rejected(&code).kind, ErrorKind::DivisionByZero);
+}
+
+// =============================================================================
+// Register and Stack Tracking
+// =============================================================================
+
+#[test]
+fn test_rejects_uninit_return_value() {
+    let code = prog(&[exit()]);
+    let err = rejected(&code);
+    assert_eq!(err.insn, 0);
+    assert_eq!(err.kind, ErrorKind::UninitReturnValue);
+}
+
+#[test]
+fn test_rejects_uninit_register_read() {
+    let code = prog(&[mov64_reg(0, 3), exit()]);
+    assert_eq!(rejected(&code).kind, ErrorKind::UninitRegister(3));
+}
+
+#[test]
+fn test_rejects_frame_pointer_write() {
+    let code = prog(&[mov64_imm(10, 0), mov64_imm(0, 0), exit()]);
+    assert_eq!(rejected(&code).kind, ErrorKind::FramePointerWrite);
+}
+
+#[test]
+fn test_accepts_stack_spill_and_fill() {
+    let code = prog(&[stdw_imm(10, -8, 7), ldxdw(0, 10, -8), exit()]);
+    assert!(verifier::verify(&code).is_ok());
+}
+
+#[test]
+fn test_rejects_stack_out_of_bounds() {
+    let code = prog(&[stdw_imm(10, -520, 0), mov64_imm(0, 0), exit()]);
+    let err = rejected(&code);
+    assert_eq!(err.insn, 0);
+    assert!(matches!(
+        err.kind,
+        ErrorKind::InvalidMemoryAccess { reg: 10, .. }
+    ));
+}
+
+#[test]
+fn test_rejects_uninit_stack_read() {
+    let code = prog(&[ldxdw(0, 10, -16), exit()]);
+    assert_eq!(rejected(&code).kind, ErrorKind::UninitStackRead(-16));
+}
+
+#[test]
+fn test_rejects_context_write() {
+    let code = prog(&[stdw_imm(1, 0, 0), mov64_imm(0, 0), exit()]);
+    assert_eq!(rejected(&code).kind, ErrorKind::ReadOnlyWrite(1));
+}
+
+#[test]
+fn test_rejects_scalar_dereference() {
+    let code = prog(&[mov64_imm(2, 0x1000), ldxdw(0, 2, 0), exit()]);
+    assert!(matches!(
+        rejected(&code).kind,
+        ErrorKind::InvalidMemoryAccess { reg: 2, .. }
+    ));
+}
+
+// =============================================================================
+// Loops
+// =============================================================================
+
+#[test]
+fn test_accepts_bounded_loop() {
+    // r0 = 0; r1 = 10; loop: r0 += 1; r1 -= 1; if r1 != 0 goto loop; exit
+    let code = prog(&[
+        mov64_imm(0, 0),
+        mov64_imm(1, 10),
+        add64_imm(0, 1),
+        add64_imm(1, -1),
+        insn(0x55, 1, 0, -3, 0),
+        exit(),
+    ]);
+    assert!(verifier::verify(&code).is_ok());
+}
+
+#[test]
+fn test_rejects_infinite_loop() {
+    let code = prog(&[mov64_imm(0, 0), insn(0x05, 0, 0, -1, 0), exit()]);
+    let err = rejected(&code);
+    assert_eq!(err.insn, 1);
+    assert_eq!(err.kind, ErrorKind::UnboundedLoop);
+}
+
+#[test]
+fn test_rejects_loop_on_helper_result() {
+    // 0: call bpf_ktime_get_ns; 1: if r0 != 0 goto 0; 2: exit
+    let code = prog(&[call(5), insn(0x55, 0, 0, -2, 0), exit()]);
+    let err = rejected(&code);
+    assert_eq!(err.insn, 1);
+    assert_eq!(err.kind, ErrorKind::UnboundedLoop);
+}
+
+#[test]
+fn test_rejects_loop_on_context_value() {
+    // while (*(u32 *)ctx) {}
+    let code = prog(&[
+        insn(0x61, 2, 1, 0, 0),
+        insn(0x55, 2, 0, -2, 0),
+        mov64_imm(0, 0),
+        exit(),
+    ]);
+    let err = rejected(&code);
+    assert_eq!(err.insn, 1);
+    assert_eq!(err.kind, ErrorKind::UnboundedLoop);
+}
+
+// =============================================================================
+// Helper Calls
+// =============================================================================
+
+#[test]
+fn test_rejects_unknown_helper() {
+    let code = prog(&[call(999), exit()]);
+    let err = rejected(&code);
+    assert_eq!(err.insn, 0);
+    assert_eq!(err.kind, ErrorKind::UnknownHelper(999));
+}
+
+#[test]
+fn test_accepts_known_helper() {
+    let code = prog(&[call(5), exit()]);
+    assert!(verifier::verify(&code).is_ok());
+}
+
+#[test]
+fn test_accepts_loop_callback() {
+    assert!(verifier::verify(&loop_call(0)).is_ok());
+}
+
+#[test]
+fn test_rejects_loop_callback_return_value() {
+    let err = rejected(&loop_call(2));
+    assert_eq!(err.insn, 10);
+    assert_eq!(err.kind, ErrorKind::InvalidCallbackReturn);
+}
+
+#[test]
+fn test_rejects_tail_call_with_moved_context() {
+    let fd = create_array_map();
+    let [ld0, ld1] = ld_map_fd(2, fd);
+    let code = prog(&[
+        add64_imm(1, 8),
+        ld0,
+        ld1,
+        mov64_imm(3, 0),
+        call(12),
+        mov64_imm(0, 0),
+        exit(),
+    ]);
+    assert_eq!(rejected(&code).kind, ErrorKind::InvalidHelperArg(1));
+    maps::destroy(fd).unwrap();
+}
+
+#[test]
+fn test_rejects_invalid_map_fd() {
+    let [ld0, ld1] = ld_map_fd(1, 0xdead);
+    let code = prog(&[ld0, ld1, mov64_imm(0, 0), exit()]);
+    assert_eq!(rejected(&code).kind, ErrorKind::InvalidMapFd(0xdead));
+}
+
+#[test]
+fn test_rejects_scalar_map_fd() {
+    let fd = create_array_map();
+    let code = prog(&[
+        stdw_imm(10, -8, 0),
+        mov64_reg(2, 10),
+        add64_imm(2, -8),
+        mov64_imm(1, fd as i32),
+        call(1),
+        mov64_imm(0, 0),
+        exit(),
+    ]);
+    assert_eq!(rejected(&code).kind, ErrorKind::InvalidHelperArg(1));
+    maps::destroy(fd).unwrap();
+}
+
+#[test]
+fn test_rejects_unchecked_map_value() {
+    let fd = create_array_map();
+    let mut insns = lookup_prelude(fd);
+    insns.extend([ldxdw(0, 0, 0), exit()]);
+    assert_eq!(rejected(&prog(&insns)).kind, ErrorKind::NullPointerDeref(0));
+    maps::destroy(fd).unwrap();
+}
+
+#[test]
+fn test_accepts_checked_map_value() {
+    let fd = create_array_map();
+    let mut insns = lookup_prelude(fd);
+    insns.extend([
+        insn(0x15, 0, 0, 2, 0), // if r0 == 0 goto +2
+        ldxdw(0, 0, 0),
+        exit(),
+        mov64_imm(0, 0),
+        exit(),
+    ]);
+    assert!(verifier::verify(&prog(&insns)).is_ok());
+    maps::destroy(fd).unwrap();
+}
+
+#[test]
+fn test_rejects_map_value_out_of_bounds() {
+    let fd = create_array_map();
+    let mut insns = lookup_prelude(fd);
+    insns.extend([
+        insn(0x15, 0, 0, 2, 0), // if r0 == 0 goto +2
+        ldxdw(0, 0, 8),
+        exit(),
+        mov64_imm(0, 0),
+        exit(),
+    ]);
+    assert!(matches!(
+        rejected(&prog(&insns)).kind,
+        ErrorKind::InvalidMemoryAccess {
+            reg: 0,
+            off: 8,
+            size: 8
+        }
+    ));
+    maps::destroy(fd).unwrap();
+}
+
+#[test]
+fn test_accepts_global_variable_access() {
+    let fd = create_array_map();
+    let [ld0, ld1] = ld_map_value(1, fd, 0);
+    let code = prog(&[ld0, ld1, stdw_imm(1, 0, 1), ldxdw(0, 1, 0), exit()]);
+    assert!(verifier::verify(&code).is_ok());
+    maps::destroy(fd).unwrap();
+}
+
+#[test]
+fn test_rejects_map_access_forbidden_by_flags() {
+    let map = |map_flags| {
+        let def = MapDef {
+            map_type: MapType::Array,
+            key_size: 4,
+            value_size: 8,
+            max_entries: 4,
+            map_flags,
+        };
+        maps::create(&def).unwrap()
+    };
+
+    let rdonly = map(maps::BPF_F_RDONLY_PROG);
+    let [ld0, ld1] = ld_map_value(1, rdonly, 0);
+    let read = prog(&[ld0, ld1, ldxdw(0, 1, 0), exit()]);
+    assert!(verifier::verify(&read).is_ok());
+    let write = prog(&[ld0, ld1, stdw_imm(1, 0, 1), mov64_imm(0, 0), exit()]);
+    assert_eq!(rejected(&write).kind, ErrorKind::MapWriteForbidden);
+    // bpf_map_update_elem(fd, &key, &key, 0)
+    let mut update = lookup_prelude(rdonly);
+    update.pop();
+    update.extend([mov64_reg(3, 2), mov64_imm(4, 0), call(2), exit()]);
+    assert_eq!(rejected(&prog(&update)).kind, ErrorKind::MapWriteForbidden);
+
+    let wronly = map(maps::BPF_F_WRONLY_PROG);
+    let [ld0, ld1] = ld_map_value(1, wronly, 0);
+    let read = prog(&[ld0, ld1, ldxdw(0, 1, 0), exit()]);
+    assert_eq!(rejected(&read).kind, ErrorKind::MapReadForbidden);
+    let write = prog(&[ld0, ld1, stdw_imm(1, 0, 1), mov64_imm(0, 0), exit()]);
+    assert!(verifier::verify(&write).is_ok());
+
+    maps::destroy(rdonly).unwrap();
+    maps::destroy(wronly).unwrap();
+}
+
+#[test]
+fn test_rejects_global_variable_out_of_bounds() {
+    let fd = create_array_map();
+    let [ld0, ld1] = ld_map_value(1, fd, 4);
+    let code = prog(&[ld0, ld1, ldxdw(0, 1, 0), exit()]);
+    assert!(matches!(
+        rejected(&code).kind,
+        ErrorKind::InvalidMemoryAccess {
+            reg: 1,
+            off: 4,
+            size: 8
+        }
+    ));
+    maps::destroy(fd).unwrap();
+}
+
+#[test]
+fn test_rejects_uninit_map_key() {
+    let fd = create_array_map();
+    let [ld0, ld1] = ld_map_fd(1, fd);
+    let code = prog(&[
+        mov64_reg(2, 10),
+        add64_imm(2, -8),
+        ld0,
+        ld1,
+        call(1),
+        mov64_imm(0, 0),
+        exit(),
+    ]);
+    assert_eq!(rejected(&code).kind, ErrorKind::UninitStackRead(-8));
+    maps::destroy(fd).unwrap();
+}
+
+#[test]
+fn test_rejects_context_as_helper_memory() {
+    // map_update_elem(fd, ctx, ctx, 0)
+    let fd = create_array_map();
+    let [ld0, ld1] = ld_map_fd(1, fd);
+    let code = prog(&[
+        mov64_reg(2, 1),
+        mov64_reg(3, 1),
+        ld0,
+        ld1,
+        mov64_imm(4, 0),
+        call(2),
+        mov64_imm(0, 0),
+        exit(),
+    ]);
+    assert_eq!(rejected(&code).kind, ErrorKind::InvalidHelperArg(2));
+    maps::destroy(fd).unwrap();
+
+    // ringbuf_output(ringbuf, ctx, 1024, 0)
+    let def = MapDef {
+        map_type: MapType::RingBuf,
+        key_size: 0,
+        value_size: 0,
+        max_entries: 4096,
+        map_flags: 0,
+    };
+    let ringbuf = maps::create(&def).unwrap();
+    let [ld0, ld1] = ld_map_fd(1, ringbuf);
+    let code = prog(&[
+        mov64_reg(2, 1),
+        ld0,
+        ld1,
+        mov64_imm(3, 1024),
+        mov64_imm(4, 0),
+        call(130),
+        mov64_imm(0, 0),
+        exit(),
+    ]);
+    assert_eq!(rejected(&code).kind, ErrorKind::InvalidHelperArg(2));
+    maps::destroy(ringbuf).unwrap();
+}
+
+#[test]
+fn test_bloom_filter_peek_reads_value() {
+    let def = MapDef {
+        map_type: MapType::BloomFilter,
+        key_size: 0,
+        value_size: 8,
+        max_entries: 16,
+        map_flags: 0,
+    };
+    let fd = maps::create(&def).unwrap();
+    let [ld0, ld1] = ld_map_fd(1, fd);
+    let peek = |init: bool| {
+        let mut insns = Vec::new();
+        if init {
+            insns.push(stdw_imm(10, -8, 7));
+        }
+        insns.extend([
+            mov64_reg(2, 10),
+            add64_imm(2, -8),
+            ld0,
+            ld1,
+            call(89),
+            exit(),
+        ]);
+        prog(&insns)
+    };
+
+    // The value is tested, not written, so it must be initialized
+    assert!(verifier::verify(&peek(true)).is_ok());
+    assert_eq!(rejected(&peek(false)).kind, ErrorKind::UninitStackRead(-8));
+    maps::destroy(fd).unwrap();
+}
+
+// =============================================================================
+// Loader Integration
+// =============================================================================
+
+#[test]
+fn test_program_new_rejects_unverified() {
+    let code = prog(&[call(999), exit()]);
+    let result = EbpfProgram::new(&code, None);
+    assert!(matches!(result, Err(Error::VerificationFailed(_))));
+}
+
+#[test]
+fn test_load_program_rejects_unverified() {
+    let before = runtime::program_count();
+    let code = prog(&[exit()]);
+    assert!(runtime::load_program(&code, None).is_err());
+    assert_eq!(runtime::program_count(), before);
+}
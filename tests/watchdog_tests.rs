@@ -0,0 +1,187 @@
+//! Integration tests for per-program execution budgets.
+//!
+//! The default budget and the per-CPU invocation state are global, so the
+//! tests in this file run one at a time.
+
+use std::sync::Mutex;
+
+use axebpf::attach;
+use axebpf::runtime::{self, Error};
+use axebpf::watchdog::{self, Budget};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+/// r0 = 0; r1 = n; loop: r0 += 1; r1 -= 1; if r1 != 0 goto loop; exit
+fn counting_loop(n: i32) -> Vec<u8> {
+    [
+        [0xb7, 0x00, 0, 0, 0, 0, 0, 0],
+        {
+            let [a, b, c, d] = n.to_le_bytes();
+            [0xb7, 0x01, 0, 0, a, b, c, d]
+        },
+        [0x07, 0x00, 0, 0, 1, 0, 0, 0],
+        [0x07, 0x01, 0, 0, 0xff, 0xff, 0xff, 0xff],
+        [0x55, 0x01, 0xfd, 0xff, 0, 0, 0, 0],
+        [0x95, 0x00, 0, 0, 0, 0, 0, 0],
+    ]
+    .concat()
+}
+
+/// r0 = 0; r1..r8 = 1; r9 = n; loop: r0 += r1; ... r0 += r8; r9 -= 1;
+/// if r9 != 0 goto loop; exit
+///
+/// Every register is live at the back-edge.
+fn all_regs_loop(n: i32) -> Vec<u8> {
+    let mut insns = vec![[0xb7, 0x00, 0, 0, 0, 0, 0, 0]];
+    for reg in 1..9 {
+        insns.push([0xb7, reg, 0, 0, 1, 0, 0, 0]);
+    }
+    let [a, b, c, d] = n.to_le_bytes();
+    insns.push([0xb7, 0x09, 0, 0, a, b, c, d]);
+    for reg in 1..9 {
+        insns.push([0x0f, reg << 4, 0, 0, 0, 0, 0, 0]);
+    }
+    insns.push([0x07, 0x09, 0, 0, 0xff, 0xff, 0xff, 0xff]);
+    insns.push([0x55, 0x09, 0xf6, 0xff, 0, 0, 0, 0]);
+    insns.push([0x95, 0x00, 0, 0, 0, 0, 0, 0]);
+    insns.concat()
+}
+
+/// Load `code` with `budget` as the default budget.
+fn load_with_budget(code: &[u8], budget: Budget) -> u32 {
+    watchdog::set_default_budget(budget);
+    let prog_id = runtime::load_program(code, None);
+    watchdog::set_default_budget(Budget::UNLIMITED);
+    prog_id.unwrap()
+}
+
+fn insn_budget(max_insns: u64, detach_after: u32) -> Budget {
+    Budget {
+        max_insns,
+        max_time_ns: 0,
+        detach_after,
+    }
+}
+
+#[test]
+fn test_loop_within_budget() {
+    let _serial = SERIAL.lock().unwrap();
+    let prog_id = load_with_budget(&counting_loop(10), insn_budget(1000, 0));
+
+    assert_eq!(runtime::run_program(prog_id, None).unwrap(), 10);
+    let program = runtime::get_program(prog_id).unwrap();
+    assert_eq!(program.budget_violations(), 0);
+    assert_eq!(program.exec_mode(), runtime::ExecMode::Interpreter);
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_loop_over_budget_fails() {
+    let _serial = SERIAL.lock().unwrap();
+    let prog_id = load_with_budget(&counting_loop(1000), insn_budget(100, 0));
+
+    let result = runtime::run_program(prog_id, None);
+    assert!(matches!(result, Err(Error::BudgetExceeded)));
+    let info = runtime::list_programs()
+        .into_iter()
+        .find(|p| p.id == prog_id)
+        .unwrap();
+    assert_eq!(info.budget_violations, 1);
+    assert!(!info.disabled);
+
+    // Raising the limit at run time lets the same program finish
+    runtime::set_program_budget(prog_id, insn_budget(10_000, 0)).unwrap();
+    assert_eq!(runtime::run_program(prog_id, None).unwrap(), 1000);
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_loop_with_all_registers_live() {
+    let _serial = SERIAL.lock().unwrap();
+    let prog_id = load_with_budget(&all_regs_loop(10), insn_budget(1000, 0));
+
+    // r0 is kept on the stack across the budget check
+    assert_eq!(runtime::run_program(prog_id, None).unwrap(), 80);
+    runtime::set_program_budget(prog_id, insn_budget(50, 0)).unwrap();
+    let result = runtime::run_program(prog_id, None);
+    assert!(matches!(result, Err(Error::BudgetExceeded)));
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_unlimited_program_not_charged() {
+    let _serial = SERIAL.lock().unwrap();
+    let prog_id = runtime::load_program(&counting_loop(1000), None).unwrap();
+
+    assert_eq!(runtime::run_program(prog_id, None).unwrap(), 1000);
+    assert_eq!(
+        runtime::get_program(prog_id).unwrap().budget(),
+        Budget::UNLIMITED
+    );
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_watchdog_detaches_after_violations() {
+    let _serial = SERIAL.lock().unwrap();
+    let prog_id = load_with_budget(&counting_loop(1000), insn_budget(100, 2));
+    attach::attach("test:watchdog", prog_id, "loop").unwrap();
+
+    for _ in 0..2 {
+        let result = runtime::run_program(prog_id, None);
+        assert!(matches!(result, Err(Error::BudgetExceeded)));
+    }
+    assert!(attach::get_attached("test:watchdog").is_none());
+    assert!(matches!(
+        runtime::run_program(prog_id, None),
+        Err(Error::Disabled)
+    ));
+
+    runtime::reset_watchdog(prog_id).unwrap();
+    let program = runtime::get_program(prog_id).unwrap();
+    assert!(!program.is_disabled());
+    assert_eq!(program.budget_violations(), 0);
+    runtime::unload_program(prog_id).unwrap();
+}
+
+#[test]
+fn test_test_run_leaves_watchdog_alone() {
+    let _serial = SERIAL.lock().unwrap();
+    let prog_id = load_with_budget(&counting_loop(1000), insn_budget(100, 1));
+    attach::attach("test:watchdog_test_run", prog_id, "loop").unwrap();
+
+    let result = runtime::test_run(prog_id, &[], 3);
+    assert!(matches!(result, Err(Error::BudgetExceeded)));
+    assert!(attach::get_attached("test:watchdog_test_run").is_some());
+    let program = runtime::get_program(prog_id).unwrap();
+    assert_eq!(program.budget_violations(), 0);
+    assert!(!program.is_disabled());
+
+    runtime::force_unload_program(prog_id).unwrap();
+}
+
+#[cfg(all(feature = "hprobe", feature = "test-utils"))]
+#[test]
+fn test_watchdog_detaches_hprobe_from_probe_hit() {
+    use axebpf::hprobe_manager;
+
+    let _serial = SERIAL.lock().unwrap();
+    hprobe_manager::init();
+    let prog_id = load_with_budget(&counting_loop(1000), insn_budget(100, 1));
+    let addr = 0x3000usize;
+    let symbol = "test_watchdog_hprobe_symbol";
+    let _ = hprobe_manager::detach(symbol);
+    hprobe_manager::register_with_addr_for_test(symbol, addr, prog_id, false).unwrap();
+
+    // The program runs with the registry locked, so the slot is removed
+    // once the hit is complete
+    hprobe_manager::hit_for_test(addr).unwrap();
+    assert!(runtime::get_program(prog_id).unwrap().is_disabled());
+    assert!(
+        !hprobe_manager::list_all()
+            .iter()
+            .any(|slot| slot.0 == symbol || slot.5 == prog_id)
+    );
+
+    runtime::unload_program(prog_id).unwrap();
+}